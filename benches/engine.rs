@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use rust_decimal::Decimal;
+use toy_processor::account::AccountMap;
+use toy_processor::deposit_store::{DepositKey, StoredDeposit};
+use toy_processor::generate::{self, GenerateConfig};
+use toy_processor::transactions::{DepositTx, DisputeTx, WithdrawalTx};
+use toy_processor::{ProcessorBuilder, TransactionRow};
+
+// Engine benchmarks, to measure a refactor against the code before it:
+//
+//   cargo bench --bench engine -- --save-baseline before
+//   (change the code)
+//   cargo bench --bench engine -- --baseline before
+//
+// `process` times single transactions on a small, warm state, without the reader or the
+// workers. `pipeline` runs a generated 1M-row input through a `Processor`, from parsed rows
+// and from CSV bytes.
+
+const PIPELINE_ROWS: u64 = 1_000_000;
+
+type State = (AccountMap, HashMap<DepositKey, StoredDeposit>);
+
+// Clients 1 to 100, each with a settled deposit of 100 under its own tx id
+fn funded() -> State {
+    let mut accounts = AccountMap::new();
+    let mut deposits = HashMap::new();
+    for client in 1..=100 {
+        DepositTx::new(client, u32::from(client), Decimal::ONE_HUNDRED)
+            .process(&mut accounts, &mut deposits)
+            .unwrap();
+    }
+    (accounts, deposits)
+}
+
+fn process(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process");
+    let amount = Decimal::new(1250, 2);
+
+    group.bench_function("deposit", |b| {
+        let tx = DepositTx::new(42, 1_000, amount);
+        b.iter_batched_ref(
+            funded,
+            |(accounts, deposits)| tx.process(accounts, deposits).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("withdrawal", |b| {
+        let tx = WithdrawalTx::new(42, 1_000, amount);
+        b.iter_batched_ref(
+            funded,
+            |(accounts, deposits)| tx.process(accounts, deposits).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("dispute", |b| {
+        let tx = DisputeTx::new(42, 42);
+        b.iter_batched_ref(
+            funded,
+            |(accounts, deposits)| tx.process(accounts, deposits).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+fn pipeline(c: &mut Criterion) {
+    let workload = GenerateConfig {
+        rows: PIPELINE_ROWS,
+        clients: 5_000,
+        malformed_rate: 0.0,
+        seed: 42,
+        ..GenerateConfig::default()
+    };
+    let mut csv = Vec::new();
+    generate::generate(&workload, &mut csv).unwrap();
+    let rows: Vec<TransactionRow> = csv::Reader::from_reader(csv.as_slice())
+        .into_deserialize()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    let mut group = c.benchmark_group("pipeline");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(PIPELINE_ROWS));
+
+    group.bench_function("rows", |b| {
+        b.iter(|| {
+            ProcessorBuilder::new()
+                .build()
+                .run("bench", rows.iter().cloned().map(Ok))
+                .unwrap()
+        })
+    });
+    group.bench_function("csv", |b| {
+        b.iter(|| {
+            let rows = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(csv.as_slice())
+                .into_deserialize();
+            ProcessorBuilder::new().build().run("bench", rows).unwrap()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, process, pipeline);
+criterion_main!(benches);
@@ -0,0 +1,49 @@
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use rust_decimal::Decimal;
+use toy_processor::{ProcessorBuilder, TransactionRow};
+
+const CLIENTS: u64 = 5_000;
+
+// Deterministic synthetic workload: mostly deposits, a fifth withdrawals, and disputes with
+// resolves on a small share of earlier deposits. Rows are produced lazily so 10M rows do not
+// have to sit in memory; generation is a handful of integer ops per row, negligible next to
+// the engine.
+fn workload(rows: u64) -> impl Iterator<Item = Result<TransactionRow, csv::Error>> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    (1..=rows).map(move |tx| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let client = (state % CLIENTS) as u16;
+        let amount = Some(Decimal::new((state % 100_000) as i64, 2));
+        let row = match state % 100 {
+            0 => TransactionRow::new("dispute", client, (tx / 2).max(1) as u32, None),
+            1 => TransactionRow::new("resolve", client, (tx / 2).max(1) as u32, None),
+            2..=21 => TransactionRow::new("withdrawal", client, tx as u32, amount),
+            _ => TransactionRow::new("deposit", client, tx as u32, amount),
+        };
+        Ok(row)
+    })
+}
+
+fn throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("engine");
+    group.sample_size(10);
+
+    for rows in [1_000_000u64, 10_000_000] {
+        group.throughput(Throughput::Elements(rows));
+        group.bench_function(format!("{}_rows", rows), |b| {
+            b.iter(|| {
+                ProcessorBuilder::new()
+                    .build()
+                    .run("bench", workload(rows))
+                    .unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -1,13 +1,79 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+#[cfg(feature = "serde")]
+use std::io::{self, Read, Write};
+use std::str::FromStr;
 
 use rust_decimal::Decimal;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
+use crate::amount::{Funds, decimal, fits, funds};
+use crate::config::Config;
 use crate::error::Error;
+use crate::policy::AccountPolicy;
+
+// Which account a transaction applies to: a client's main account (sub 0) or one of its
+// sub-accounts, each with balances and a status of its own. The client stays the unit of
+// sharding and filtering, so all of a client's sub-accounts live on the same worker.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AccountKey {
+    pub client: u16,
+    pub sub: u16,
+}
+
+impl AccountKey {
+    pub fn new(client: u16, sub: u16) -> Self {
+        Self { client, sub }
+    }
+}
 
-#[derive(Default)]
+// A bare client id names its main account
+impl From<u16> for AccountKey {
+    fn from(client: u16) -> Self {
+        Self::new(client, 0)
+    }
+}
+
+impl fmt::Display for AccountKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.sub {
+            0 => write!(f, "{}", self.client),
+            sub => write!(f, "{}/{}", self.client, sub),
+        }
+    }
+}
+
+#[derive(Default, Clone, PartialEq, Eq)]
 pub struct AccountMap {
-    clients: HashMap<u16, Account>,
+    clients: HashMap<AccountKey, Account>,
+    // Keys of the sub-accounts, so listings can walk them in order without sorting the map
+    subs: BTreeSet<AccountKey>,
+}
+
+// Which accounts a listing returns; the default matches all
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccountFilter {
+    pub locked: Option<bool>,
+    // Only accounts with funds held by open disputes
+    pub held_only: bool,
+}
+
+impl AccountFilter {
+    #[allow(dead_code)]
+    pub fn matches(&self, account: &Account) -> bool {
+        self.locked.is_none_or(|locked| account.locked() == locked)
+            && (!self.held_only || !account.held.is_zero())
+    }
+}
+
+// One page of a listing in client order, each client's main account before its
+// sub-accounts. `next` is the cursor for the following page, `None` on the last one.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct AccountPage<'a> {
+    pub accounts: Vec<&'a Account>,
+    pub next: Option<AccountKey>,
 }
 
 impl AccountMap {
@@ -24,129 +90,629 @@ impl AccountMap {
         self.clients.is_empty()
     }
 
-    pub fn get_or_create(&mut self, client: u16) -> &mut Account {
-        self.clients
-            .entry(client)
-            .or_insert_with(|| Account::new(client))
+    // Whether any account is a sub-account, i.e. the output needs the `sub` column
+    pub fn has_sub_accounts(&self) -> bool {
+        !self.subs.is_empty()
+    }
+
+    pub fn get_or_create(&mut self, key: impl Into<AccountKey>) -> &mut Account {
+        let key = key.into();
+        if key.sub != 0 {
+            self.subs.insert(key);
+        }
+        self.clients.entry(key).or_insert_with(|| Account::new(key))
     }
 
-    pub fn get_mut(&mut self, client: u16) -> Result<&mut Account, Error> {
+    #[allow(dead_code)]
+    pub fn get(&self, key: impl Into<AccountKey>) -> Option<&Account> {
+        self.clients.get(&key.into())
+    }
+
+    // Takes an account out
+    #[allow(dead_code)]
+    pub fn remove(&mut self, key: impl Into<AccountKey>) -> Option<Account> {
+        let key = key.into();
+        self.subs.remove(&key);
+        self.clients.remove(&key)
+    }
+
+    // Takes all of a client's accounts out, e.g. to hand them to another shard
+    pub fn remove_client(&mut self, client: u16) -> Vec<Account> {
+        let subs: Vec<_> = self
+            .subs
+            .range(AccountKey::new(client, 1)..=AccountKey::new(client, u16::MAX))
+            .copied()
+            .collect();
+        std::iter::once(AccountKey::from(client))
+            .chain(subs)
+            .filter_map(|key| self.remove(key))
+            .collect()
+    }
+
+    pub fn insert(&mut self, account: Account) {
+        if account.sub != 0 {
+            self.subs.insert(account.key());
+        }
+        self.clients.insert(account.key(), account);
+    }
+
+    pub fn get_mut(&mut self, key: impl Into<AccountKey>) -> Result<&mut Account, Error> {
+        let key = key.into();
         self.clients
-            .get_mut(&client)
-            .ok_or(Error::AccountNotFound(client))
+            .get_mut(&key)
+            .ok_or(Error::AccountNotFound(key.client))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Account> {
+        self.clients.values()
+    }
+
+    // Funds available over every account, sub-accounts included
+    #[allow(dead_code)]
+    pub fn total_available(&self) -> Decimal {
+        self.iter().map(Account::available).sum()
+    }
+
+    // Funds held by disputes and pending deposits over every account
+    #[allow(dead_code)]
+    pub fn total_held(&self) -> Decimal {
+        self.iter().map(Account::held).sum()
+    }
+
+    // Clients with an account locked by a chargeback, main or sub-account, in order. Frozen
+    // and closed accounts are not locked; see `Account::status`.
+    #[allow(dead_code)]
+    pub fn locked_clients(&self) -> Vec<u16> {
+        let locked: BTreeSet<u16> = self
+            .iter()
+            .filter(|account| account.locked())
+            .map(Account::client)
+            .collect();
+        locked.into_iter().collect()
     }
 
     pub fn into_iter_sorted(self) -> impl Iterator<Item = Account> {
         let mut accounts: Vec<_> = self.clients.into_values().collect();
-        accounts.sort_by_key(|a| a.client);
+        accounts.sort_by_key(|a| a.key());
         accounts.into_iter()
     }
 
-    pub fn merge(&mut self, other: AccountMap) {
+    pub fn into_iter_sorted_by(
+        self,
+        compare: impl FnMut(&Account, &Account) -> std::cmp::Ordering,
+    ) -> impl Iterator<Item = Account> {
+        let mut accounts: Vec<_> = self.clients.into_values().collect();
+        accounts.sort_by(compare);
+        accounts.into_iter()
+    }
+
+    // Up to `limit` accounts matching `filter` with keys after `cursor` (from the start when
+    // `None`). The cursor is the last key returned, so it stays valid while accounts are added
+    // or removed. Walks the client id space rather than sorting the map, so a page costs at
+    // most one lookup per possible client plus its sub-accounts, and nothing is materialized
+    // beyond the page.
+    #[allow(dead_code)]
+    pub fn list_accounts(
+        &self,
+        cursor: Option<AccountKey>,
+        limit: usize,
+        filter: &AccountFilter,
+    ) -> AccountPage<'_> {
+        let limit = limit.max(1);
+        let start = cursor.map_or(0, |cursor| cursor.client);
+        let mut accounts = Vec::with_capacity(limit.min(self.clients.len()));
+        let mut matching = (start..=u16::MAX)
+            .flat_map(|client| {
+                let subs = self
+                    .subs
+                    .range(AccountKey::new(client, 1)..=AccountKey::new(client, u16::MAX));
+                std::iter::once(AccountKey::from(client)).chain(subs.copied())
+            })
+            .filter(|key| cursor.is_none_or(|cursor| *key > cursor))
+            .filter_map(|key| self.clients.get(&key))
+            .filter(|account| filter.matches(account));
+        accounts.extend(matching.by_ref().take(limit));
+        let next = match matching.next() {
+            Some(_) => accounts.last().map(|account| account.key()),
+            None => None,
+        };
+        AccountPage { accounts, next }
+    }
+
+    // Keeps only the accounts of the given clients
+    pub fn retain_clients(&mut self, clients: &HashSet<u16>) {
+        self.clients.retain(|key, _| clients.contains(&key.client));
+        self.subs.retain(|key| clients.contains(&key.client));
+    }
+
+    // Shards own disjoint clients, so an account present on both sides means the sharding is
+    // broken. Nothing is merged in that case.
+    pub fn merge(&mut self, other: AccountMap) -> Result<(), Error> {
+        if let Some(key) = other.clients.keys().find(|k| self.clients.contains_key(k)) {
+            return Err(Error::ShardCollision(key.client));
+        }
         self.clients.extend(other.clients);
+        self.subs.extend(other.subs);
+        Ok(())
+    }
+}
+
+// A snapshot lists the accounts in client order, so the same state always serializes the same
+#[cfg(feature = "serde")]
+impl Serialize for AccountMap {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut accounts: Vec<&Account> = self.clients.values().collect();
+        accounts.sort_by_key(|a| a.key());
+        serializer.collect_seq(accounts)
     }
 }
 
-#[derive(Default, Debug)]
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for AccountMap {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut accounts = AccountMap::new();
+        for account in Vec::<Account>::deserialize(deserializer)? {
+            if accounts.clients.contains_key(&account.key()) {
+                return Err(serde::de::Error::custom(format!(
+                    "duplicate account {}",
+                    account.key()
+                )));
+            }
+            accounts.insert(account);
+        }
+        Ok(accounts)
+    }
+}
+
+// JSON snapshots for embedders persisting state between runs
+#[cfg(feature = "serde")]
+#[allow(dead_code)]
+impl AccountMap {
+    pub fn to_snapshot(&self, writer: impl Write) -> Result<(), Error> {
+        serde_json::to_writer(writer, self).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    pub fn from_snapshot(reader: impl Read) -> Result<Self, Error> {
+        Ok(serde_json::from_reader(reader).map_err(io::Error::from)?)
+    }
+}
+
+// What an account still accepts. `Frozen` and `Closed` are set by the admin `freeze` and
+// `close` rows, `Locked` by a chargeback.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    // Deposits still land, nothing leaves
+    Frozen,
+    Locked,
+    // Takes nothing at all, disputes included
+    Closed,
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Frozen => "frozen",
+            AccountStatus::Locked => "locked",
+            AccountStatus::Closed => "closed",
+        }
+    }
+
+    pub fn accepts_deposits(&self) -> bool {
+        matches!(self, AccountStatus::Active | AccountStatus::Frozen)
+    }
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AccountStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(AccountStatus::Active),
+            "frozen" => Ok(AccountStatus::Frozen),
+            "locked" => Ok(AccountStatus::Locked),
+            "closed" => Ok(AccountStatus::Closed),
+            other => Err(Error::InvalidArgument(format!(
+                "unknown account status {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Account {
     client: u16,
-    available: Decimal,
-    held: Decimal,
-    locked: bool,
+    // Left out of snapshots for main accounts, so those read the same as before sub-accounts
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_main"))]
+    sub: u16,
+    available: Funds,
+    held: Funds,
+    status: AccountStatus,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AccountOutput {
     client: u16,
+    // Only written when the run has sub-accounts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sub: Option<u16>,
     available: String,
     held: String,
     total: String,
+    // Locked or closed
     locked: bool,
+    // Only written when some account is frozen or closed, which `locked` cannot tell apart
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<AccountStatus>,
+    // Only written when the run allows overdrafts, so other runs keep the original columns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    overdraft: Option<bool>,
 }
 
-impl From<Account> for AccountOutput {
-    fn from(account: Account) -> Self {
+impl AccountOutput {
+    #[allow(dead_code)]
+    pub const HEADER: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+    pub fn new(account: Account, config: &Config) -> Self {
         Self {
             client: account.client,
-            available: format!("{:.4}", account.available),
-            held: format!("{:.4}", account.held),
-            total: format!("{:.4}", account.total()),
-            locked: account.locked,
+            sub: None,
+            available: config.format(account.available()),
+            held: config.format(account.held()),
+            total: config.format(account.total()),
+            locked: !account.status.accepts_deposits(),
+            status: None,
+            overdraft: None,
         }
     }
+
+    // Adds the `sub` column
+    pub fn with_sub(mut self, sub: u16) -> Self {
+        self.sub = Some(sub);
+        self
+    }
+
+    // Adds the `status` column
+    pub fn with_status(mut self, status: AccountStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    // Adds the `overdraft` column, set for accounts with available below zero
+    pub fn with_overdraft(mut self, in_overdraft: bool) -> Self {
+        self.overdraft = Some(in_overdraft);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    #[allow(dead_code)]
+    pub fn sub(&self) -> Option<u16> {
+        self.sub
+    }
+
+    // Amounts are already formatted at the run's precision
+    #[allow(dead_code)]
+    pub fn available(&self) -> &str {
+        &self.available
+    }
+
+    #[allow(dead_code)]
+    pub fn held(&self) -> &str {
+        &self.held
+    }
+
+    #[allow(dead_code)]
+    pub fn total(&self) -> &str {
+        &self.total
+    }
+
+    #[allow(dead_code)]
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    #[allow(dead_code)]
+    pub fn status(&self) -> Option<AccountStatus> {
+        self.status
+    }
+
+    #[allow(dead_code)]
+    pub fn overdraft(&self) -> Option<bool> {
+        self.overdraft
+    }
+}
+
+impl From<Account> for AccountOutput {
+    fn from(account: Account) -> Self {
+        AccountOutput::new(account, &Config::default())
+    }
+}
+
+// An account read back from a run's output, for `--load-accounts`. `locked` without a
+// `status` column is a chargeback lock, as the output leaves the column out unless some
+// account is frozen or closed. The total must add up, so a hand-edited row is caught.
+impl TryFrom<AccountOutput> for Account {
+    type Error = String;
+
+    fn try_from(output: AccountOutput) -> Result<Self, Self::Error> {
+        let amount = |column: &str, value: &str| {
+            Decimal::from_str(value)
+                .ok()
+                .filter(|amount| fits(*amount))
+                .ok_or_else(|| format!("invalid {} {}", column, value))
+        };
+        let available = amount("available", &output.available)?;
+        let held = amount("held", &output.held)?;
+        let total = amount("total", &output.total)?;
+        if available + held != total {
+            return Err(format!(
+                "total {} is not available {} plus held {}",
+                total, available, held
+            ));
+        }
+        let status = match (output.status, output.locked) {
+            (Some(status), _) => status,
+            (None, true) => AccountStatus::Locked,
+            (None, false) => AccountStatus::Active,
+        };
+        Ok(Account {
+            client: output.client,
+            sub: output.sub.unwrap_or_default(),
+            available: funds(available),
+            held: funds(held),
+            status,
+        })
+    }
 }
 
 impl Account {
-    pub fn new(client: u16) -> Self {
+    pub fn new(key: impl Into<AccountKey>) -> Self {
+        let key = key.into();
         Self {
-            client,
+            client: key.client,
+            sub: key.sub,
             ..Default::default()
         }
     }
 
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    // 0 for the client's main account
+    pub fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    pub fn key(&self) -> AccountKey {
+        AccountKey::new(self.client, self.sub)
+    }
+
     pub fn total(&self) -> Decimal {
-        self.available + self.held
+        decimal(self.available + self.held)
     }
 
-    #[allow(dead_code)]
     pub fn available(&self) -> Decimal {
-        self.available
+        decimal(self.available)
     }
 
     #[allow(dead_code)]
     pub fn held(&self) -> Decimal {
-        self.held
+        decimal(self.held)
+    }
+
+    // Locked by a chargeback; see `status` for frozen and closed accounts
+    pub fn locked(&self) -> bool {
+        self.status == AccountStatus::Locked
+    }
+
+    pub fn status(&self) -> AccountStatus {
+        self.status
+    }
+
+    pub fn in_overdraft(&self) -> bool {
+        self.available < Funds::ZERO
     }
 
     pub fn deposit(&mut self, amount: Decimal) -> Result<(), Error> {
         self.throw_locked()?;
-        self.available += amount;
+        self.available = self.credit(self.available, amount)?;
+        Ok(())
+    }
+
+    // A deposit that has not settled yet: accepted like any deposit, but held until released
+    pub fn deposit_pending(&mut self, amount: Decimal) -> Result<(), Error> {
+        self.throw_locked()?;
+        self.held = self.credit(self.held, amount)?;
         Ok(())
     }
 
+    // Moves a settled pending deposit to available. Never fails: the money has arrived,
+    // whatever happened to the account in the meantime.
+    pub fn release(&mut self, amount: Decimal) {
+        let amount = funds(amount);
+        self.held -= amount;
+        self.available += amount;
+    }
+
     // Allows available to go negative. This is clawback semantics -
     // if client deposited 100, withdrew 80, then deposit is disputed, we hold the full 100
     // and available becomes -80. The client owes this amount.
     pub fn dispute(&mut self, amount: Decimal) -> Result<(), Error> {
+        self.check_open()?;
+        let amount = funds(amount);
         self.available -= amount;
         self.held += amount;
         Ok(())
     }
 
-    pub fn withdraw(&mut self, amount: Decimal) -> Result<(), Error> {
-        self.throw_locked()?;
-        if self.available < amount {
+    // `overdraft_limit` is how far below zero available may end up
+    // Credits fees collected from other accounts. Unlike a deposit this never fails: the fees
+    // were already taken, so a locked house account still receives them.
+    pub fn collect_fees(&mut self, amount: Decimal) {
+        self.available += funds(amount);
+    }
+
+    pub fn withdraw(&mut self, amount: Decimal, overdraft_limit: Decimal) -> Result<(), Error> {
+        self.throw_frozen()?;
+        // In `Decimal`, the limit may be beyond what `Funds` can hold
+        if self.available() + overdraft_limit < amount {
             return Err(Error::InsufficientFunds {
                 client: self.client,
-                available: self.available,
+                available: self.available(),
                 requested: amount,
             });
         }
-        self.available -= amount;
+        self.available -= funds(amount);
         Ok(())
     }
 
-    pub fn resolve(&mut self, amount: Decimal) -> Result<(), Error> {
+    pub fn resolve(&mut self, amount: Decimal, policy: AccountPolicy) -> Result<(), Error> {
+        self.check_resolve(policy)?;
+        let amount = funds(amount);
         self.held -= amount;
         self.available += amount;
         Ok(())
     }
 
-    pub fn chargeback(&mut self, amount: Decimal) -> Result<(), Error> {
-        self.held -= amount;
-        self.locked = true;
+    pub fn chargeback(&mut self, amount: Decimal, policy: AccountPolicy) -> Result<(), Error> {
+        self.check_chargeback(policy)?;
+        self.held -= funds(amount);
+        self.status = AccountStatus::Locked;
         Ok(())
     }
 
-    fn throw_locked(&self) -> Result<(), Error> {
-        if self.locked {
-            Err(Error::AccountLocked(self.client))
+    // Returns charged back funds; a reversal is administrative, so it applies to the locked
+    // account the chargeback left behind
+    pub fn reverse_chargeback(&mut self, amount: Decimal, unlock: bool) {
+        self.available += funds(amount);
+        if unlock && self.status == AccountStatus::Locked {
+            self.status = AccountStatus::Active;
+        }
+    }
+
+    // The policy checks on their own, so callers can validate before moving deposit state
+    pub fn check_resolve(&self, policy: AccountPolicy) -> Result<(), Error> {
+        if policy.allows_resolve() {
+            self.check_open()
         } else {
-            Ok(())
+            self.throw_locked()
+        }
+    }
+
+    pub fn check_chargeback(&self, policy: AccountPolicy) -> Result<(), Error> {
+        if policy.allows_chargeback() {
+            self.check_open()
+        } else {
+            self.throw_locked()
+        }
+    }
+
+    // Administrative reversal, allowed on locked accounts like disputes
+    pub fn void(&mut self, amount: Decimal) -> Result<(), Error> {
+        self.check_open()?;
+        self.available -= funds(amount);
+        Ok(())
+    }
+
+    // Signed adjustment from a correction row. Locked accounts take none, and a debit may not
+    // overdraw, like a withdrawal.
+    pub fn correct(&mut self, delta: Decimal) -> Result<(), Error> {
+        if delta < Decimal::ZERO {
+            self.throw_frozen()?;
+        } else {
+            self.throw_locked()?;
+        }
+        if delta < Decimal::ZERO && self.available < funds(-delta) {
+            return Err(Error::InsufficientFunds {
+                client: self.client,
+                available: self.available(),
+                requested: -delta,
+            });
+        }
+        self.available = self.credit(self.available, delta)?;
+        Ok(())
+    }
+
+    // Operator adjustment. Applies to frozen and locked accounts too, and a debit may take
+    // available below zero: ops are correcting the books, not moving client money.
+    pub fn adjust(&mut self, delta: Decimal) -> Result<(), Error> {
+        self.check_open()?;
+        self.available = self.credit(self.available, delta)?;
+        Ok(())
+    }
+
+    // `balance` moved by `amount`, refused when it or the account's total would outgrow
+    // `Funds`. Only reachable with `minor-units`, `Decimal` goes far beyond any input.
+    fn credit(&self, balance: Funds, amount: Decimal) -> Result<Funds, Error> {
+        let amount = funds(amount);
+        self.available
+            .checked_add(self.held)
+            .and_then(|total| total.checked_add(amount))
+            .and(balance.checked_add(amount))
+            .ok_or(Error::BalanceOverflow(self.client))
+    }
+
+    // Admin `freeze`: only an active account can be frozen
+    pub fn freeze(&mut self) -> Result<(), Error> {
+        self.throw_frozen()?;
+        self.status = AccountStatus::Frozen;
+        Ok(())
+    }
+
+    // Admin `close`, final. Funds still on the account stay there.
+    pub fn close(&mut self) -> Result<(), Error> {
+        self.check_open()?;
+        self.status = AccountStatus::Closed;
+        Ok(())
+    }
+
+    // Administrative changes (disputes, voids, reversals) only stop at a closed account
+    pub fn check_open(&self) -> Result<(), Error> {
+        match self.status {
+            AccountStatus::Closed => Err(Error::AccountClosed(self.client)),
+            _ => Ok(()),
+        }
+    }
+
+    // Whether funds may come in, see `AccountStatus::accepts_deposits`
+    fn throw_locked(&self) -> Result<(), Error> {
+        match self.status {
+            AccountStatus::Locked => Err(Error::AccountLocked(self.client)),
+            _ => self.check_open(),
+        }
+    }
+
+    // Whether funds may go out
+    fn throw_frozen(&self) -> Result<(), Error> {
+        match self.status {
+            AccountStatus::Frozen => Err(Error::AccountFrozen(self.client)),
+            _ => self.throw_locked(),
         }
     }
 }
 
+#[cfg(feature = "serde")]
+fn is_main(sub: &u16) -> bool {
+    *sub == 0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -155,22 +721,206 @@ mod tests {
         Decimal::new(n, 0)
     }
 
+    #[test]
+    fn merge_disjoint_shards() {
+        let mut accounts = AccountMap::new();
+        accounts.get_or_create(1).deposit(dec(10)).unwrap();
+        let mut shard = AccountMap::new();
+        shard.get_or_create(2).deposit(dec(20)).unwrap();
+
+        accounts.merge(shard).unwrap();
+
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts.get(2).unwrap().available(), dec(20));
+    }
+
+    #[test]
+    fn totals_and_locked_clients() {
+        let mut accounts = AccountMap::new();
+        accounts.get_or_create(7).deposit(dec(100)).unwrap();
+        accounts.get_or_create(7).dispute(dec(100)).unwrap();
+        accounts
+            .get_or_create(7)
+            .chargeback(dec(100), AccountPolicy::Settle)
+            .unwrap();
+        accounts.get_or_create(2).deposit(dec(50)).unwrap();
+        accounts.get_or_create(2).dispute(dec(20)).unwrap();
+        accounts
+            .get_or_create(AccountKey::new(2, 1))
+            .deposit(dec(5))
+            .unwrap();
+        accounts.get_or_create(3).deposit(dec(1)).unwrap();
+        accounts.get_or_create(3).close().unwrap();
+
+        assert_eq!(accounts.total_available(), dec(36));
+        assert_eq!(accounts.total_held(), dec(20));
+        assert_eq!(accounts.locked_clients(), [7]);
+        assert!(AccountMap::new().locked_clients().is_empty());
+    }
+
+    #[test]
+    fn list_accounts_pages_in_client_order() {
+        let mut accounts = AccountMap::new();
+        for client in [9, 3, 700, 1, 65535] {
+            accounts.get_or_create(client).deposit(dec(10)).unwrap();
+        }
+        accounts.get_or_create(3).dispute(dec(4)).unwrap();
+        let all = AccountFilter::default();
+        let clients =
+            |page: &AccountPage| -> Vec<u16> { page.accounts.iter().map(|a| a.client()).collect() };
+
+        let first = accounts.list_accounts(None, 2, &all);
+        let second = accounts.list_accounts(first.next, 2, &all);
+        let third = accounts.list_accounts(second.next, 2, &all);
+        let held = accounts.list_accounts(
+            None,
+            10,
+            &AccountFilter {
+                held_only: true,
+                ..all
+            },
+        );
+
+        assert_eq!((clients(&first), first.next), (vec![1, 3], Some(3.into())));
+        assert_eq!(
+            (clients(&second), second.next),
+            (vec![9, 700], Some(700.into()))
+        );
+        assert_eq!((clients(&third), third.next), (vec![65535], None));
+        assert_eq!(clients(&held), [3]);
+    }
+
+    #[test]
+    fn sub_accounts_are_separate_and_listed_after_their_client() {
+        let mut accounts = AccountMap::new();
+        accounts.get_or_create(1).deposit(dec(10)).unwrap();
+        accounts
+            .get_or_create(AccountKey::new(1, 2))
+            .deposit(dec(5))
+            .unwrap();
+        accounts
+            .get_or_create(AccountKey::new(0, 7))
+            .deposit(dec(1))
+            .unwrap();
+        accounts.get_or_create(3).deposit(dec(3)).unwrap();
+        let all = AccountFilter::default();
+        let keys = |page: &AccountPage| -> Vec<String> {
+            page.accounts.iter().map(|a| a.key().to_string()).collect()
+        };
+
+        let first = accounts.list_accounts(None, 2, &all);
+        let second = accounts.list_accounts(first.next, 2, &all);
+
+        assert_eq!(keys(&first), ["0/7", "1"]);
+        assert_eq!(keys(&second), ["1/2", "3"]);
+        assert_eq!(second.next, None);
+        let moved = accounts.remove_client(1);
+        assert_eq!(moved.len(), 2);
+        assert_eq!(moved[1].available(), dec(5));
+        assert_eq!(accounts.len(), 2);
+        assert!(accounts.has_sub_accounts());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshot_round_trips() {
+        let mut accounts = AccountMap::new();
+        accounts.get_or_create(7).deposit(dec(10)).unwrap();
+        accounts.get_or_create(7).dispute(dec(4)).unwrap();
+        let locked = accounts.get_or_create(2);
+        locked.deposit(dec(5)).unwrap();
+        locked.status = AccountStatus::Locked;
+
+        let mut snapshot = Vec::new();
+        accounts.to_snapshot(&mut snapshot).unwrap();
+        let restored = AccountMap::from_snapshot(snapshot.as_slice()).unwrap();
+
+        assert!(restored == accounts);
+        assert!(
+            String::from_utf8(snapshot)
+                .unwrap()
+                .starts_with(r#"[{"client":2,"#)
+        );
+        let duplicate = r#"[{"client":1,"available":"1","held":"0","status":"active"},
+                            {"client":1,"available":"2","held":"0","status":"active"}]"#;
+        assert!(AccountMap::from_snapshot(duplicate.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn merge_rejects_colliding_shards() {
+        let mut accounts = AccountMap::new();
+        accounts.get_or_create(1).deposit(dec(10)).unwrap();
+        let mut shard = AccountMap::new();
+        shard.get_or_create(1).deposit(dec(20)).unwrap();
+        shard.get_or_create(2).deposit(dec(30)).unwrap();
+
+        let result = accounts.merge(shard);
+
+        assert!(matches!(result, Err(Error::ShardCollision(1))));
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts.get(1).unwrap().available(), dec(10));
+    }
+
+    #[test]
+    fn withdraw_into_overdraft_up_to_limit() {
+        let mut account = Account::new(1);
+        account.deposit(dec(50)).unwrap();
+
+        account.withdraw(dec(80), dec(30)).unwrap();
+        let result = account.withdraw(dec(1), dec(30));
+
+        assert_eq!(account.available(), dec(-30));
+        assert!(account.in_overdraft());
+        assert!(matches!(result, Err(Error::InsufficientFunds { .. })));
+    }
+
     #[test]
     fn withdraw_insufficient_funds() {
         let mut account = Account::new(1);
         account.deposit(dec(50)).unwrap();
 
-        let result = account.withdraw(dec(100));
+        let result = account.withdraw(dec(100), Decimal::ZERO);
 
         assert!(matches!(result, Err(Error::InsufficientFunds { .. })));
     }
 
+    #[test]
+    fn frozen_takes_deposits_only_and_closed_nothing() {
+        let mut account = Account::new(1);
+        account.deposit(dec(100)).unwrap();
+        account.freeze().unwrap();
+
+        account.deposit(dec(10)).unwrap();
+        assert!(matches!(
+            account.withdraw(dec(5), Decimal::ZERO),
+            Err(Error::AccountFrozen(1))
+        ));
+        assert!(matches!(
+            account.correct(dec(-5)),
+            Err(Error::AccountFrozen(1))
+        ));
+        assert!(matches!(account.freeze(), Err(Error::AccountFrozen(1))));
+
+        account.close().unwrap();
+        assert!(matches!(
+            account.deposit(dec(1)),
+            Err(Error::AccountClosed(1))
+        ));
+        assert!(matches!(
+            account.dispute(dec(1)),
+            Err(Error::AccountClosed(1))
+        ));
+        assert!(matches!(account.close(), Err(Error::AccountClosed(1))));
+        assert_eq!(account.available(), dec(110));
+        assert_eq!(account.status(), AccountStatus::Closed);
+    }
+
     #[test]
     fn deposit_on_locked_account() {
         let mut account = Account::new(1);
         account.deposit(dec(100)).unwrap();
         account.dispute(dec(100)).unwrap();
-        account.chargeback(dec(100)).unwrap(); // locks account
+        account.chargeback(dec(100), AccountPolicy::Settle).unwrap(); // locks account
 
         let result = account.deposit(dec(50));
 
@@ -182,9 +932,9 @@ mod tests {
         let mut account = Account::new(1);
         account.deposit(dec(100)).unwrap();
         account.dispute(dec(100)).unwrap();
-        account.chargeback(dec(100)).unwrap(); // locks account
+        account.chargeback(dec(100), AccountPolicy::Settle).unwrap(); // locks account
 
-        let result = account.withdraw(dec(10));
+        let result = account.withdraw(dec(10), Decimal::ZERO);
 
         assert!(matches!(result, Err(Error::AccountLocked(1))));
     }
@@ -194,13 +944,13 @@ mod tests {
         let mut account = Account::new(1);
         account.deposit(dec(200)).unwrap();
         account.dispute(dec(100)).unwrap();
-        account.chargeback(dec(100)).unwrap(); // locks account, 100 available remains
+        account.chargeback(dec(100), AccountPolicy::Settle).unwrap(); // locks account, 100 available remains
 
         // Dispute should still work on locked accounts
         let result = account.dispute(dec(50));
 
         assert!(result.is_ok());
-        assert_eq!(account.available, dec(50));
-        assert_eq!(account.held, dec(50));
+        assert_eq!(account.available(), dec(50));
+        assert_eq!(account.held(), dec(50));
     }
 }
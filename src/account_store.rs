@@ -0,0 +1,138 @@
+#[cfg(feature = "redis")]
+use std::collections::HashMap;
+
+use crate::account::AccountMap;
+use crate::error::Error;
+
+// Where account state lives between runs, so that several processor instances (say, one per
+// Kafka partition) can work on the same accounts and any of them can write the final output.
+// A run loads every account before its first row and saves back the ones it changed, see
+// `changed`. Deposits are not shared: a dispute must reach the instance that saw its deposit.
+pub trait AccountStore {
+    fn load(&mut self) -> Result<AccountMap, Error>;
+
+    // Inserts or replaces each of `accounts`, leaving the others as they are
+    fn save(&mut self, accounts: &AccountMap) -> Result<(), Error>;
+}
+
+// The store of a single process, e.g. to run the engine over several inputs in turn
+impl AccountStore for AccountMap {
+    fn load(&mut self) -> Result<AccountMap, Error> {
+        Ok(self.clone())
+    }
+
+    fn save(&mut self, accounts: &AccountMap) -> Result<(), Error> {
+        for account in accounts.iter() {
+            self.insert(account.clone());
+        }
+        Ok(())
+    }
+}
+
+// The accounts of `after` that are new or differ from `before`. Saving only these keeps a run
+// from overwriting what another instance saved meanwhile for accounts this run never touched.
+pub fn changed(before: &AccountMap, after: &AccountMap) -> AccountMap {
+    let mut changed = AccountMap::new();
+    for account in after.iter() {
+        if before.get(account.key()) != Some(account) {
+            changed.insert(account.clone());
+        }
+    }
+    changed
+}
+
+// Hash field under which every account is stored, keyed by `AccountKey` ("1", or "1/2" for a
+// sub-account), with the account's JSON snapshot as the value
+#[cfg(feature = "redis")]
+pub const REDIS_KEY: &str = "toy-processor:accounts";
+
+// Accounts in a Redis hash, for `--account-store redis://host:port/db`. A save is a single
+// HSET, so other instances see all of a run's accounts or none of them. Two instances changing
+// the same account concurrently still race: the last save wins, as for any shared store
+// without locking, so partition the input by client.
+#[cfg(feature = "redis")]
+pub struct RedisAccountStore {
+    connection: redis::Connection,
+}
+
+#[cfg(feature = "redis")]
+impl RedisAccountStore {
+    pub fn open(url: &str) -> Result<Self, Error> {
+        let connection = redis::Client::open(url)?.get_connection()?;
+        Ok(Self { connection })
+    }
+}
+
+#[cfg(feature = "redis")]
+impl AccountStore for RedisAccountStore {
+    fn load(&mut self) -> Result<AccountMap, Error> {
+        let fields: HashMap<String, String> = redis::cmd("HGETALL")
+            .arg(REDIS_KEY)
+            .query(&mut self.connection)?;
+        let mut accounts = AccountMap::new();
+        for (field, json) in fields {
+            let account = serde_json::from_str(&json).map_err(|e| {
+                let detail = format!("account {} in {}: {}", field, REDIS_KEY, e);
+                std::io::Error::new(std::io::ErrorKind::InvalidData, detail)
+            })?;
+            accounts.insert(account);
+        }
+        Ok(accounts)
+    }
+
+    fn save(&mut self, accounts: &AccountMap) -> Result<(), Error> {
+        if accounts.is_empty() {
+            return Ok(());
+        }
+        let mut hset = redis::cmd("HSET");
+        hset.arg(REDIS_KEY);
+        for account in accounts.iter() {
+            let json = serde_json::to_string(account).map_err(std::io::Error::from)?;
+            hset.arg(account.key().to_string()).arg(json);
+        }
+        hset.query::<()>(&mut self.connection)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn saves_only_what_a_run_changed() {
+        let mut store = AccountMap::new();
+        store.get_or_create(1).deposit(Decimal::TEN).unwrap();
+        store.get_or_create(2).deposit(Decimal::TEN).unwrap();
+
+        let before = store.load().unwrap();
+        let mut after = before.clone();
+        after
+            .get_or_create(1)
+            .withdraw(Decimal::ONE, Decimal::ZERO)
+            .unwrap();
+        after.get_or_create(3).deposit(Decimal::ONE).unwrap();
+        // Meanwhile another instance moves client 2
+        let mut other = AccountMap::new();
+        other.get_or_create(2).deposit(Decimal::ONE).unwrap();
+        store.save(&other).unwrap();
+
+        let changed = changed(&before, &after);
+        assert_eq!(changed.len(), 2);
+        store.save(&changed).unwrap();
+        let balances: Vec<_> = store
+            .into_iter_sorted()
+            .map(|account| (account.client(), account.available()))
+            .collect();
+        assert_eq!(
+            balances,
+            [
+                (1, Decimal::new(9, 0)),
+                (2, Decimal::ONE),
+                (3, Decimal::ONE)
+            ]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use std::io::Write;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::ledger::Balances;
+use crate::transactions::AdjustmentTx;
+
+pub const HEADER: [&str; 7] = [
+    "tx",
+    "client",
+    "amount",
+    "reason",
+    "outcome",
+    "available",
+    "held",
+];
+
+// Audit trail of operator adjustments, as CSV with one line per adjustment row the engine
+// tried to apply:
+//
+//   tx,client,amount,reason,outcome,available,held
+//   7,1,-2.5000,FX_FIX,applied,97.5000,0.0000
+//
+// The outcome is `applied`, or the reject reason (e.g. `account_closed`) of one that was
+// refused. The balances are the client's after the row, so an auditor can follow an account
+// through its adjustments without the rest of the run.
+pub struct AdjustmentReport<W: Write> {
+    inner: csv::Writer<W>,
+    config: Config,
+}
+
+impl<W: Write> AdjustmentReport<W> {
+    // Amounts are written at the precision of `config`
+    pub fn new(inner: W, config: &Config) -> Result<Self, Error> {
+        let mut inner = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::Any(b'\n'))
+            .from_writer(inner);
+        inner.write_record(HEADER)?;
+        Ok(Self {
+            inner,
+            config: *config,
+        })
+    }
+
+    pub fn record(
+        &mut self,
+        tx: &AdjustmentTx,
+        outcome: Result<(), &Error>,
+        after: Balances,
+    ) -> Result<(), Error> {
+        let outcome = outcome.map_or_else(|e| e.reason(), |()| "applied");
+        self.inner.write_record([
+            tx.id().to_string().as_str(),
+            &tx.account().to_string(),
+            &self.config.format(tx.amount()),
+            tx.reason(),
+            outcome,
+            &self.config.format(after.available),
+            &self.config.format(after.held),
+        ])?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn records_applied_and_refused_adjustments() {
+        let mut out = Vec::new();
+        let mut report = AdjustmentReport::new(&mut out, &Config::default()).unwrap();
+        let after = Balances {
+            available: Decimal::new(975, 1),
+            held: Decimal::ZERO,
+        };
+        let credit = AdjustmentTx::new(1, 7, Decimal::new(-25, 1), "FX_FIX".to_string());
+        report.record(&credit, Ok(()), after).unwrap();
+        let refused = AdjustmentTx::new(2, 8, Decimal::TEN, "backfill, 2024-03".to_string());
+        let closed = Error::AccountClosed(2);
+        report
+            .record(&refused, Err(&closed), Balances::default())
+            .unwrap();
+        report.flush().unwrap();
+        drop(report);
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "tx,client,amount,reason,outcome,available,held
+7,1,-2.5000,FX_FIX,applied,97.5000,0.0000
+8,2,10.0000,\"backfill, 2024-03\",account_closed,0.0000,0.0000
+"
+        );
+    }
+}
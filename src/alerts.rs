@@ -0,0 +1,253 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::account::AccountKey;
+use crate::config::Config;
+use crate::error::Error;
+use crate::ledger::Balances;
+
+// For each of connecting, sending and the reply, so a hung endpoint can't stall a worker long
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Balances accounts are watched against during the run, `--alert-below` and
+// `--alert-held-above`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlertThresholds {
+    pub available_below: Option<Decimal>,
+    pub held_above: Option<Decimal>,
+}
+
+// An account's balances crossing a threshold, after transaction `tx`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alert {
+    // `available_below` or `held_above`
+    pub kind: &'static str,
+    pub account: AccountKey,
+    pub tx: u32,
+    pub balances: Balances,
+    pub threshold: Decimal,
+}
+
+impl AlertThresholds {
+    pub fn is_empty(&self) -> bool {
+        self.available_below.is_none() && self.held_above.is_none()
+    }
+
+    // The thresholds a transaction took the account across, from `before` to `after`. Only
+    // the crossing alerts: an account that stays beyond a threshold alerts again only once it
+    // came back and crossed anew. An account that doesn't exist yet starts at zero.
+    pub fn crossed(
+        &self,
+        account: AccountKey,
+        tx: u32,
+        before: Balances,
+        after: Balances,
+    ) -> Vec<Alert> {
+        let alert = |kind, threshold| Alert {
+            kind,
+            account,
+            tx,
+            balances: after,
+            threshold,
+        };
+        let mut alerts = Vec::new();
+        if let Some(threshold) = self.available_below
+            && before.available >= threshold
+            && after.available < threshold
+        {
+            alerts.push(alert("available_below", threshold));
+        }
+        if let Some(threshold) = self.held_above
+            && before.held <= threshold
+            && after.held > threshold
+        {
+            alerts.push(alert("held_above", threshold));
+        }
+        alerts
+    }
+}
+
+// Where alert records go: stderr, or POSTed one by one to a webhook
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum AlertTarget {
+    #[default]
+    Stderr,
+    Webhook {
+        // `host:port`, as connected to
+        addr: String,
+        host: String,
+        path: String,
+    },
+}
+
+impl FromStr for AlertTarget {
+    type Err = Error;
+
+    // Plain `http://` only; an `https://` endpoint needs a relay on the host
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(rest) = s.strip_prefix("http://") else {
+            return Err(Error::InvalidArgument(format!(
+                "alert webhook must be an http:// URL: {}",
+                s
+            )));
+        };
+        let (host, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "alert webhook without a host: {}",
+                s
+            )));
+        }
+        let addr = match host.contains(':') {
+            true => host.to_string(),
+            false => format!("{}:80", host),
+        };
+        Ok(AlertTarget::Webhook {
+            addr,
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+}
+
+// An alert as it is sent
+#[derive(Serialize)]
+struct AlertRecord {
+    alert: &'static str,
+    client: u16,
+    sub: u16,
+    tx: u32,
+    available: String,
+    held: String,
+    threshold: String,
+}
+
+// Writes alerts as JSON records, one per line on stderr or one per request to a webhook:
+//
+//   {"alert":"available_below","client":3,"sub":0,"tx":17,"available":"-40.0000","held":"0.0000","threshold":"0.0000"}
+//
+// Amounts are strings at the precision of the run, like the JSON output. Shared by the
+// workers, so a client's alerts come in the order its transactions were applied.
+pub struct AlertWriter {
+    target: AlertTarget,
+    config: Config,
+}
+
+impl AlertWriter {
+    pub fn new(target: AlertTarget, config: &Config) -> Self {
+        Self {
+            target,
+            config: *config,
+        }
+    }
+
+    pub fn send(&mut self, alert: &Alert) -> Result<(), Error> {
+        let record = serde_json::to_string(&AlertRecord {
+            alert: alert.kind,
+            client: alert.account.client,
+            sub: alert.account.sub,
+            tx: alert.tx,
+            available: self.config.format(alert.balances.available),
+            held: self.config.format(alert.balances.held),
+            threshold: self.config.format(alert.threshold),
+        })
+        .map_err(io::Error::from)?;
+        match &self.target {
+            AlertTarget::Stderr => writeln!(io::stderr().lock(), "{}", record)?,
+            AlertTarget::Webhook { addr, host, path } => post(addr, host, path, &record)?,
+        }
+        Ok(())
+    }
+}
+
+// A minimal HTTP/1.1 POST; any 2xx reply is success
+fn post(addr: &str, host: &str, path: &str, body: &str) -> io::Result<()> {
+    let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", host))
+    })?;
+    let mut stream = TcpStream::connect_timeout(&addr, WEBHOOK_TIMEOUT)?;
+    stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+    stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    )?;
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!(
+            "webhook {} answered {}",
+            host,
+            status.trim()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alerts_only_on_crossing() {
+        let thresholds = AlertThresholds {
+            available_below: Some(Decimal::ZERO),
+            held_above: Some(Decimal::TEN),
+        };
+        let balances = |available: i64, held: i64| Balances {
+            available: Decimal::from(available),
+            held: Decimal::from(held),
+        };
+        let kinds = |before, after| -> Vec<&str> {
+            thresholds
+                .crossed(AccountKey::new(1, 0), 7, before, after)
+                .iter()
+                .map(|alert| alert.kind)
+                .collect()
+        };
+
+        assert_eq!(
+            kinds(balances(5, 0), balances(-5, 20)),
+            ["available_below", "held_above"]
+        );
+        assert!(kinds(balances(-5, 20), balances(-8, 30)).is_empty());
+        assert!(kinds(balances(0, 10), balances(0, 10)).is_empty());
+        assert!(kinds(balances(-5, 0), balances(0, 0)).is_empty());
+    }
+
+    #[test]
+    fn parses_webhook_urls() {
+        assert_eq!(
+            "http://alerts.local:8080/hooks/balances"
+                .parse::<AlertTarget>()
+                .unwrap(),
+            AlertTarget::Webhook {
+                addr: "alerts.local:8080".to_string(),
+                host: "alerts.local:8080".to_string(),
+                path: "/hooks/balances".to_string(),
+            }
+        );
+        assert_eq!(
+            "http://alerts.local".parse::<AlertTarget>().unwrap(),
+            AlertTarget::Webhook {
+                addr: "alerts.local:80".to_string(),
+                host: "alerts.local".to_string(),
+                path: "/".to_string(),
+            }
+        );
+        assert!("https://alerts.local/".parse::<AlertTarget>().is_err());
+        assert!("http:///hooks".parse::<AlertTarget>().is_err());
+    }
+}
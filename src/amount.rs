@@ -0,0 +1,198 @@
+// The type account balances and stored deposits are kept in. `Decimal` by default; with the
+// `minor-units` feature, a plain i64 count of ten-thousandths, which is smaller and faster to
+// add up. Rows are parsed, and amounts formatted, as `Decimal` either way: `funds` and
+// `decimal` convert at the edge of the engine.
+
+#[cfg(not(feature = "minor-units"))]
+pub use rust_decimal::Decimal as Funds;
+
+#[cfg(feature = "minor-units")]
+pub use minor::{MinorUnits as Funds, SCALE};
+
+use rust_decimal::Decimal;
+
+#[cfg(not(feature = "minor-units"))]
+#[inline]
+pub fn funds(amount: Decimal) -> Funds {
+    amount
+}
+
+#[cfg(not(feature = "minor-units"))]
+#[inline]
+pub fn decimal(funds: Funds) -> Decimal {
+    funds
+}
+
+// Whether `amount` can be held as `Funds`; always with `Decimal`
+#[cfg(not(feature = "minor-units"))]
+#[inline]
+pub fn fits(_amount: Decimal) -> bool {
+    true
+}
+
+#[cfg(feature = "minor-units")]
+#[inline]
+pub fn funds(amount: Decimal) -> Funds {
+    Funds::from_decimal(amount)
+}
+
+#[cfg(feature = "minor-units")]
+#[inline]
+pub fn fits(amount: Decimal) -> bool {
+    Funds::try_from(amount).is_ok()
+}
+
+#[cfg(feature = "minor-units")]
+#[inline]
+pub fn decimal(funds: Funds) -> Decimal {
+    funds.to_decimal()
+}
+
+#[cfg(feature = "minor-units")]
+mod minor {
+    use std::fmt;
+    use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+
+    use rust_decimal::Decimal;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    // Decimal places kept, the most `--precision` allows with the feature
+    pub const SCALE: u32 = 4;
+
+    // An amount in ten-thousandths of a unit, up to about 922 trillion units rather than
+    // `Decimal`'s 7.9e28. Rows beyond that are rejected by `fits`, and balances that would
+    // grow beyond it by the `checked_` adds; the operators panic on overflow like `Decimal`'s.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct MinorUnits(i64);
+
+    impl MinorUnits {
+        pub const ZERO: Self = Self(0);
+
+        // For amounts known to fit, such as those of accepted rows; see `TryFrom` otherwise
+        pub fn from_decimal(amount: Decimal) -> Self {
+            Self::try_from(amount).expect("amount out of range for minor units")
+        }
+
+        pub fn to_decimal(self) -> Decimal {
+            Decimal::new(self.0, SCALE)
+        }
+
+        pub fn is_zero(self) -> bool {
+            self.0 == 0
+        }
+
+        pub fn checked_add(self, other: Self) -> Option<Self> {
+            self.0.checked_add(other.0).map(Self)
+        }
+    }
+
+    // Amounts with more places are rounded half to even, like `Decimal::round_dp`; the
+    // engine has already rounded them to the run's precision, so nothing is lost. An amount
+    // too large to count in ten-thousandths comes back as the error.
+    impl TryFrom<Decimal> for MinorUnits {
+        type Error = Decimal;
+
+        fn try_from(amount: Decimal) -> Result<Self, Decimal> {
+            let rounded = amount.round_dp(SCALE);
+            let minor = rounded.mantissa() * 10_i128.pow(SCALE - rounded.scale());
+            i64::try_from(minor).map(Self).map_err(|_| amount)
+        }
+    }
+
+    impl Add for MinorUnits {
+        type Output = Self;
+
+        fn add(self, other: Self) -> Self {
+            Self(self.0.checked_add(other.0).expect("addition overflowed"))
+        }
+    }
+
+    impl Sub for MinorUnits {
+        type Output = Self;
+
+        fn sub(self, other: Self) -> Self {
+            Self(self.0.checked_sub(other.0).expect("subtraction overflowed"))
+        }
+    }
+
+    impl AddAssign for MinorUnits {
+        fn add_assign(&mut self, other: Self) {
+            *self = *self + other;
+        }
+    }
+
+    impl SubAssign for MinorUnits {
+        fn sub_assign(&mut self, other: Self) {
+            *self = *self - other;
+        }
+    }
+
+    impl Neg for MinorUnits {
+        type Output = Self;
+
+        fn neg(self) -> Self {
+            Self(-self.0)
+        }
+    }
+
+    impl fmt::Display for MinorUnits {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.to_decimal().fmt(f)
+        }
+    }
+
+    // Snapshots and stores hold the same decimal strings as without the feature
+    impl Serialize for MinorUnits {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            Serialize::serialize(&self.to_decimal(), serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for MinorUnits {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let amount = <Decimal as Deserialize>::deserialize(deserializer)?;
+            Self::try_from(amount).map_err(|amount| {
+                serde::de::Error::custom(format!("{} is out of range for minor units", amount))
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn converts_at_four_places() {
+            let amount = |s: &str| s.parse::<Decimal>().unwrap();
+            for s in ["0", "1", "-2.5", "1234.5678", "0.0001"] {
+                let minor = MinorUnits::from_decimal(amount(s));
+                assert_eq!(minor.to_decimal(), amount(s));
+            }
+            assert_eq!(MinorUnits::from_decimal(amount("1.5")), MinorUnits(15_000));
+            assert_eq!(
+                MinorUnits::from_decimal(amount("0.00005")),
+                MinorUnits::ZERO
+            );
+            assert_eq!(MinorUnits::from_decimal(amount("0.00015")), MinorUnits(2));
+
+            let (a, b) = (MinorUnits(15_000), MinorUnits(2_500));
+            assert_eq!(
+                (a + b, a - b, -a),
+                (MinorUnits(17_500), MinorUnits(12_500), MinorUnits(-15_000))
+            );
+            assert!(b - a < MinorUnits::ZERO);
+        }
+
+        #[test]
+        fn refuses_amounts_beyond_i64() {
+            let max = Decimal::new(i64::MAX, SCALE);
+            assert_eq!(MinorUnits::try_from(max), Ok(MinorUnits(i64::MAX)));
+            let beyond = Decimal::from(1_000_000_000_000_000_i64);
+            assert_eq!(MinorUnits::try_from(beyond), Err(beyond));
+            assert!(!crate::amount::fits(beyond));
+
+            assert_eq!(MinorUnits(i64::MAX).checked_add(MinorUnits(1)), None);
+            assert!(serde_json::from_str::<MinorUnits>("\"1000000000000000\"").is_err());
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, BooleanArray, Decimal128Array, RecordBatch, UInt16Array};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema};
+use rust_decimal::Decimal;
+
+use crate::account::AccountOutput;
+use crate::config::Config;
+use crate::error::Error;
+use crate::output::OutputSink;
+
+// Widest Decimal128; any rust_decimal mantissa fits
+const DECIMAL_PRECISION: u8 = 38;
+
+// Writes the accounts as an Arrow IPC stream with a single batch. Amounts are Decimal128 at
+// the run's precision rather than strings, so pandas/polars load them without parsing.
+// Columns are built whole, so accounts are buffered until `finish`. The `sub` column is only
+// written when the accounts carry one, like in the CSV.
+pub struct ArrowSink<W: Write> {
+    sink: Option<W>,
+    scale: u32,
+    clients: Vec<u16>,
+    subs: Vec<Option<u16>>,
+    available: Vec<i128>,
+    held: Vec<i128>,
+    total: Vec<i128>,
+    locked: Vec<bool>,
+}
+
+impl<W: Write> ArrowSink<W> {
+    pub fn new(sink: W, config: &Config) -> Self {
+        Self {
+            sink: Some(sink),
+            scale: config.precision,
+            clients: Vec::new(),
+            subs: Vec::new(),
+            available: Vec::new(),
+            held: Vec::new(),
+            total: Vec::new(),
+            locked: Vec::new(),
+        }
+    }
+
+    // The formatted amount as an integer count of 10^-precision units
+    fn mantissa(&self, amount: &str) -> Result<i128, Error> {
+        let mut amount = Decimal::from_str(amount)
+            .map_err(|_| Error::InvalidArgument(format!("invalid amount {}", amount)))?;
+        amount.rescale(self.scale);
+        Ok(amount.mantissa())
+    }
+}
+
+impl<W: Write> OutputSink for ArrowSink<W> {
+    fn write_account(&mut self, account: &AccountOutput) -> Result<(), Error> {
+        self.available.push(self.mantissa(account.available())?);
+        self.held.push(self.mantissa(account.held())?);
+        self.total.push(self.mantissa(account.total())?);
+        self.clients.push(account.client());
+        self.subs.push(account.sub());
+        self.locked.push(account.locked());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        let Some(sink) = self.sink.take() else {
+            return Ok(());
+        };
+        let scale = self.scale as i8;
+        let amount = |name| Field::new(name, DataType::Decimal128(DECIMAL_PRECISION, scale), false);
+        let mut fields = vec![Field::new("client", DataType::UInt16, false)];
+        let mut columns: Vec<ArrayRef> = vec![Arc::new(UInt16Array::from_iter_values(
+            std::mem::take(&mut self.clients),
+        ))];
+        if self.subs.iter().any(Option::is_some) {
+            fields.push(Field::new("sub", DataType::UInt16, false));
+            let subs = self.subs.drain(..).map(Option::unwrap_or_default);
+            columns.push(Arc::new(UInt16Array::from_iter_values(subs)));
+        }
+        fields.extend([
+            amount("available"),
+            amount("held"),
+            amount("total"),
+            Field::new("locked", DataType::Boolean, false),
+        ]);
+        let schema = Arc::new(Schema::new(fields));
+
+        let column = |values: &mut Vec<i128>| {
+            Decimal128Array::from_iter_values(std::mem::take(values))
+                .with_precision_and_scale(DECIMAL_PRECISION, scale)
+        };
+        columns.extend([
+            Arc::new(column(&mut self.available)?) as ArrayRef,
+            Arc::new(column(&mut self.held)?),
+            Arc::new(column(&mut self.total)?),
+            Arc::new(BooleanArray::from_iter(self.locked.drain(..).map(Some))),
+        ]);
+        let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+        let mut writer = StreamWriter::try_new(sink, &schema)?;
+        writer.write(&batch)?;
+        // Finishes the stream before handing the sink back
+        writer.into_inner()?.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Decimal128Type, UInt16Type};
+    use arrow_ipc::reader::StreamReader;
+
+    use super::*;
+    use crate::account::Account;
+
+    #[test]
+    fn roundtrips_through_stream_reader() {
+        let config = Config {
+            precision: 2,
+            ..Config::default()
+        };
+        let mut accounts = [Account::new(2), Account::new(1)];
+        accounts[0].deposit(Decimal::new(40, 0)).unwrap();
+        accounts[1].deposit(Decimal::new(15_125, 3)).unwrap();
+
+        let mut buffer = Vec::new();
+        let mut sink = ArrowSink::new(&mut buffer, &config);
+        for account in accounts {
+            sink.write_account(&AccountOutput::new(account, &config))
+                .unwrap();
+        }
+        sink.finish().unwrap();
+
+        let batches: Vec<_> = StreamReader::try_new(buffer.as_slice(), None)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let batch = &batches[0];
+        let clients = batch.column(0).as_primitive::<UInt16Type>();
+        let available = batch.column(1).as_primitive::<Decimal128Type>();
+        // In the order written
+        assert_eq!(clients.values(), &[2, 1]);
+        assert_eq!(available.value_as_string(0), "40.00");
+        // 15.125 rounds half-even like the CSV output
+        assert_eq!(available.value_as_string(1), "15.12");
+        assert!(!batch.column(4).as_boolean().value(0));
+    }
+}
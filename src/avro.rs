@@ -0,0 +1,605 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde_json::Value as Json;
+
+use crate::error::Error;
+use crate::input::REQUIRED_COLUMNS;
+use crate::transactions::TransactionRow;
+
+const MAGIC: &[u8; 4] = b"Obj\x01";
+
+// The fields read into a row, like the CSV columns; any others are skipped
+const COLUMNS: [&str; 8] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "timestamp",
+    "reason",
+    "pending_until",
+    "sub",
+];
+
+type Row = Result<TransactionRow, csv::Error>;
+
+// Transactions archived as Avro object container files, for `--format avro`. The writer
+// schema is the one embedded in the file's header, so files from producers on a schema
+// registry read without the registry. It must be a record with `type`, `client` and `tx`
+// fields; the other columns are read when present, by name, and any other field is skipped.
+// Every field may also be a union with null:
+//
+//   type                      string or enum
+//   client, tx, sub           int or long
+//   amount                    decimal (bytes or fixed), string, double, int or long
+//   timestamp, pending_until  long seconds, or timestamp-millis / timestamp-micros
+//   reason                    string
+//
+// Blocks compressed with `deflate` need the `gzip` feature, `zstandard` the `zstd` feature.
+// A row's line is its record number in the file. A record with a value out of range is
+// passed on as a bad row, while one that doesn't decode ends the file: the records after it
+// can't be found.
+pub struct AvroRows<R> {
+    reader: R,
+    codec: Codec,
+    sync: [u8; 16],
+    fields: Vec<(String, Schema)>,
+    block: io::Cursor<Vec<u8>>,
+    // Records left in the block
+    remaining: u64,
+    record: u64,
+    done: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Codec {
+    Null,
+    #[cfg(feature = "gzip")]
+    Deflate,
+    #[cfg(feature = "zstd")]
+    Zstandard,
+}
+
+#[derive(Debug, Clone)]
+enum Schema {
+    Null,
+    Boolean,
+    // int and long share their encoding
+    Long,
+    // A long in units of 1/n seconds
+    Timestamp(i64),
+    Float,
+    Double,
+    Bytes,
+    String,
+    Decimal { fixed: Option<usize>, scale: u32 },
+    Fixed(usize),
+    Enum(Vec<String>),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Record(Vec<(String, Schema)>),
+}
+
+// A decoded field, as far as rows need it
+#[derive(Debug, PartialEq)]
+enum Value {
+    Null,
+    Long(i64),
+    Double(f64),
+    String(String),
+    // Mantissa and scale
+    Decimal(i128, u32),
+    Skipped,
+}
+
+impl<R: Read> AvroRows<R> {
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not an Avro object container file").into());
+        }
+        let mut metadata = HashMap::new();
+        read_blocks(&mut reader, |reader| {
+            let key = string(reader)?;
+            metadata.insert(key, bytes(reader)?);
+            Ok(())
+        })?;
+        let mut sync = [0; 16];
+        reader.read_exact(&mut sync)?;
+
+        let codec = match metadata.get("avro.codec").map(Vec::as_slice) {
+            None | Some(b"null") => Codec::Null,
+            #[cfg(feature = "gzip")]
+            Some(b"deflate") => Codec::Deflate,
+            #[cfg(feature = "zstd")]
+            Some(b"zstandard") => Codec::Zstandard,
+            Some(other) => {
+                return Err(Error::InvalidArgument(format!(
+                    "Avro codec {} is not supported by this build",
+                    String::from_utf8_lossy(other)
+                )));
+            }
+        };
+        let schema = metadata
+            .get("avro.schema")
+            .ok_or_else(|| invalid("Avro file without a schema"))?;
+        let schema: Json = serde_json::from_slice(schema).map_err(io::Error::from)?;
+        let fields = match parse(&schema, &mut HashMap::new()).map_err(invalid)? {
+            Schema::Record(fields) => fields,
+            _ => return Err(invalid("Avro schema is not a record").into()),
+        };
+        for column in REQUIRED_COLUMNS {
+            if !fields.iter().any(|(name, _)| name == column) {
+                return Err(invalid(format!("missing field {}", column)).into());
+            }
+        }
+        Ok(Self {
+            reader,
+            codec,
+            sync,
+            fields,
+            block: io::Cursor::new(Vec::new()),
+            remaining: 0,
+            record: 0,
+            done: false,
+        })
+    }
+
+    // Reads the next block into `block`; false at the end of the file
+    fn next_block(&mut self) -> io::Result<bool> {
+        let mut first = [0];
+        if self.reader.read(&mut first)? == 0 {
+            return Ok(false);
+        }
+        let count = long(&mut first.chain(&mut self.reader))?;
+        let size = length(&mut self.reader)?;
+        let data = exactly(&mut self.reader, size)?;
+        let mut sync = [0; 16];
+        self.reader.read_exact(&mut sync)?;
+        if sync != self.sync {
+            return Err(invalid("sync marker mismatch after a block"));
+        }
+        let data = match self.codec {
+            Codec::Null => data,
+            #[cfg(feature = "gzip")]
+            Codec::Deflate => {
+                let mut inflated = Vec::new();
+                flate2::read::DeflateDecoder::new(data.as_slice()).read_to_end(&mut inflated)?;
+                inflated
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstandard => zstd::stream::decode_all(data.as_slice())?,
+        };
+        self.remaining = u64::try_from(count).map_err(|_| invalid("negative block count"))?;
+        self.block = io::Cursor::new(data);
+        Ok(true)
+    }
+
+    fn row(&self, values: Vec<Value>) -> Result<TransactionRow, String> {
+        let mut found: HashMap<&str, Value> = self
+            .fields
+            .iter()
+            .zip(values)
+            .filter(|((name, _), _)| COLUMNS.contains(&name.as_str()))
+            .map(|((name, _), value)| (name.as_str(), value))
+            .collect();
+        let mut take = |name| found.remove(name).unwrap_or(Value::Null);
+
+        let tx_type = match take("type") {
+            Value::String(tx_type) => tx_type,
+            _ => return Err("type is not a string".to_string()),
+        };
+        let client = integer(take("client"), "client")?;
+        let tx = integer(take("tx"), "tx")?;
+        let amount = match take("amount") {
+            Value::Null => None,
+            Value::Decimal(mantissa, scale) => Some(
+                Decimal::try_from_i128_with_scale(mantissa, scale)
+                    .map_err(|_| "amount out of range".to_string())?,
+            ),
+            Value::String(amount) => {
+                Some(Decimal::from_str(&amount).map_err(|_| format!("invalid amount {}", amount))?)
+            }
+            Value::Double(amount) => {
+                Some(Decimal::try_from(amount).map_err(|_| format!("invalid amount {}", amount))?)
+            }
+            Value::Long(amount) => Some(Decimal::from(amount)),
+            Value::Skipped => return Err("amount is not a number".to_string()),
+        };
+        let mut row = TransactionRow::new(tx_type.as_str(), client, tx, amount);
+        row.set_timestamp(optional(take("timestamp"), "timestamp")?);
+        row.set_reason(match take("reason") {
+            Value::Null => None,
+            Value::String(reason) => Some(reason),
+            _ => return Err("reason is not a string".to_string()),
+        });
+        row.set_pending_until(optional(take("pending_until"), "pending_until")?);
+        row.set_sub(optional(take("sub"), "sub")?);
+        row.set_line(self.record);
+        Ok(row)
+    }
+}
+
+impl<R: Read> Iterator for AvroRows<R> {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining == 0 {
+            if self.done {
+                return None;
+            }
+            match self.next_block() {
+                Ok(true) => {}
+                Ok(false) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+        self.remaining -= 1;
+        self.record += 1;
+        let values = self
+            .fields
+            .iter()
+            .map(|(_, schema)| decode(schema, &mut self.block))
+            .collect::<io::Result<Vec<_>>>();
+        let row = match values {
+            Ok(values) => self.row(values),
+            Err(e) => {
+                (self.done, self.remaining) = (true, 0);
+                Err(e.to_string())
+            }
+        };
+        Some(row.map_err(|detail| invalid(format!("record {}: {}", self.record, detail)).into()))
+    }
+}
+
+// A schema from its JSON form; `names` collects the named types for later references
+fn parse(json: &Json, names: &mut HashMap<String, Schema>) -> Result<Schema, String> {
+    let object = match json {
+        Json::String(name) => {
+            return primitive(name)
+                .or_else(|| names.get(name).cloned())
+                .ok_or_else(|| format!("unknown Avro type {}", name));
+        }
+        Json::Array(branches) => {
+            return branches
+                .iter()
+                .map(|branch| parse(branch, names))
+                .collect::<Result<_, _>>()
+                .map(Schema::Union);
+        }
+        Json::Object(object) => object,
+        other => return Err(format!("invalid Avro schema {}", other)),
+    };
+    let kind = object.get("type").ok_or("Avro schema without a type")?;
+    let number = |key: &str| object.get(key).and_then(Json::as_u64);
+    let size = || {
+        number("size")
+            .ok_or("fixed without a size")
+            .map(|n| n as usize)
+    };
+    let logical = object.get("logicalType").and_then(Json::as_str);
+    let schema = match (kind.as_str(), logical) {
+        (Some(kind @ ("bytes" | "fixed")), Some("decimal")) => Schema::Decimal {
+            fixed: match kind {
+                "fixed" => Some(size()?),
+                _ => None,
+            },
+            scale: number("scale").unwrap_or(0) as u32,
+        },
+        (Some("long"), Some("timestamp-millis")) => Schema::Timestamp(1_000),
+        (Some("long"), Some("timestamp-micros")) => Schema::Timestamp(1_000_000),
+        (Some("fixed"), _) => Schema::Fixed(size()?),
+        (Some("enum"), _) => Schema::Enum(
+            object
+                .get("symbols")
+                .and_then(Json::as_array)
+                .ok_or("enum without symbols")?
+                .iter()
+                .map(|symbol| symbol.as_str().unwrap_or_default().to_string())
+                .collect(),
+        ),
+        (Some("array"), _) => Schema::Array(Box::new(parse(
+            object.get("items").ok_or("array without items")?,
+            names,
+        )?)),
+        (Some("map"), _) => Schema::Map(Box::new(parse(
+            object.get("values").ok_or("map without values")?,
+            names,
+        )?)),
+        (Some("record" | "error"), _) => {
+            let fields = object
+                .get("fields")
+                .and_then(Json::as_array)
+                .ok_or("record without fields")?;
+            let mut parsed = Vec::with_capacity(fields.len());
+            for field in fields {
+                let name = field.get("name").and_then(Json::as_str).unwrap_or_default();
+                let schema = field.get("type").ok_or("field without a type")?;
+                parsed.push((name.to_string(), parse(schema, names)?));
+            }
+            Schema::Record(parsed)
+        }
+        // `{"type": "string"}` and the like
+        _ => parse(kind, names)?,
+    };
+    if let Some(name) = object.get("name").and_then(Json::as_str) {
+        if let Some(namespace) = object.get("namespace").and_then(Json::as_str) {
+            names.insert(format!("{}.{}", namespace, name), schema.clone());
+        }
+        names.insert(name.to_string(), schema.clone());
+    }
+    Ok(schema)
+}
+
+fn primitive(name: &str) -> Option<Schema> {
+    Some(match name {
+        "null" => Schema::Null,
+        "boolean" => Schema::Boolean,
+        "int" | "long" => Schema::Long,
+        "float" => Schema::Float,
+        "double" => Schema::Double,
+        "bytes" => Schema::Bytes,
+        "string" => Schema::String,
+        _ => return None,
+    })
+}
+
+fn decode(schema: &Schema, reader: &mut impl Read) -> io::Result<Value> {
+    Ok(match schema {
+        Schema::Null => Value::Null,
+        Schema::Boolean => {
+            exactly(reader, 1)?;
+            Value::Skipped
+        }
+        Schema::Long => Value::Long(long(reader)?),
+        Schema::Timestamp(per_second) => Value::Long(long(reader)?.div_euclid(*per_second)),
+        Schema::Float => {
+            let mut float = [0; 4];
+            reader.read_exact(&mut float)?;
+            Value::Double(f32::from_le_bytes(float).into())
+        }
+        Schema::Double => {
+            let mut double = [0; 8];
+            reader.read_exact(&mut double)?;
+            Value::Double(f64::from_le_bytes(double))
+        }
+        Schema::Bytes => {
+            bytes(reader)?;
+            Value::Skipped
+        }
+        Schema::String => Value::String(string(reader)?),
+        Schema::Decimal { fixed, scale } => {
+            let raw = match fixed {
+                Some(size) => exactly(reader, *size)?,
+                None => bytes(reader)?,
+            };
+            Value::Decimal(unscaled(&raw)?, *scale)
+        }
+        Schema::Fixed(size) => {
+            exactly(reader, *size)?;
+            Value::Skipped
+        }
+        Schema::Enum(symbols) => {
+            let index = length(reader)?;
+            let symbol = symbols
+                .get(index)
+                .ok_or_else(|| invalid(format!("enum index {} out of range", index)))?;
+            Value::String(symbol.clone())
+        }
+        Schema::Array(items) => {
+            read_blocks(reader, |reader| decode(items, reader).map(drop))?;
+            Value::Skipped
+        }
+        Schema::Map(values) => {
+            read_blocks(reader, |reader| {
+                string(reader)?;
+                decode(values, reader).map(drop)
+            })?;
+            Value::Skipped
+        }
+        Schema::Union(branches) => {
+            let index = length(reader)?;
+            let branch = branches
+                .get(index)
+                .ok_or_else(|| invalid(format!("union index {} out of range", index)))?;
+            decode(branch, reader)?
+        }
+        Schema::Record(fields) => {
+            for (_, field) in fields {
+                decode(field, reader)?;
+            }
+            Value::Skipped
+        }
+    })
+}
+
+// The items of an array or map, or the file metadata: blocks of a count, negative when
+// followed by the block's size in bytes, up to a count of zero
+fn read_blocks<R: Read>(
+    reader: &mut R,
+    mut item: impl FnMut(&mut R) -> io::Result<()>,
+) -> io::Result<()> {
+    loop {
+        let count = match long(reader)? {
+            0 => return Ok(()),
+            count if count < 0 => {
+                long(reader)?;
+                count.unsigned_abs()
+            }
+            count => count as u64,
+        };
+        for _ in 0..count {
+            item(reader)?;
+        }
+    }
+}
+
+// Zigzag varint, for int and long alike
+fn long(reader: &mut impl Read) -> io::Result<i64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+        }
+    }
+    Err(invalid("varint longer than a long"))
+}
+
+fn length(reader: &mut impl Read) -> io::Result<usize> {
+    let length = long(reader)?;
+    usize::try_from(length).map_err(|_| invalid(format!("negative length {}", length)))
+}
+
+// Read up to `size` at a time, so a corrupt length can't allocate more than the input holds
+fn exactly(reader: &mut impl Read, size: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    reader.take(size as u64).read_to_end(&mut buf)?;
+    if buf.len() != size {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(buf)
+}
+
+fn bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let size = length(reader)?;
+    exactly(reader, size)
+}
+
+fn string(reader: &mut impl Read) -> io::Result<String> {
+    String::from_utf8(bytes(reader)?).map_err(|_| invalid("string is not UTF-8"))
+}
+
+// A decimal's unscaled value: big-endian two's complement
+fn unscaled(raw: &[u8]) -> io::Result<i128> {
+    if raw.len() > 16 {
+        return Err(invalid("decimal wider than 128 bits"));
+    }
+    let negative = raw.first().is_some_and(|byte| byte & 0x80 != 0);
+    let mut bytes = [if negative { 0xff } else { 0 }; 16];
+    bytes[16 - raw.len()..].copy_from_slice(raw);
+    Ok(i128::from_be_bytes(bytes))
+}
+
+fn integer<T: TryFrom<i64>>(value: Value, name: &str) -> Result<T, String> {
+    match value {
+        Value::Long(n) => T::try_from(n).map_err(|_| format!("{} {} out of range", name, n)),
+        Value::Null => Err(format!("{} is null", name)),
+        _ => Err(format!("{} is not an integer", name)),
+    }
+}
+
+fn optional<T: TryFrom<i64>>(value: Value, name: &str) -> Result<Option<T>, String> {
+    match value {
+        Value::Null => Ok(None),
+        value => integer(value, name).map(Some),
+    }
+}
+
+fn invalid(detail: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, detail.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn long(buf: &mut Vec<u8>, n: i64) {
+        let mut zigzag = ((n << 1) ^ (n >> 63)) as u64;
+        while zigzag >= 0x80 {
+            buf.push(zigzag as u8 | 0x80);
+            zigzag >>= 7;
+        }
+        buf.push(zigzag as u8);
+    }
+
+    fn bytes(buf: &mut Vec<u8>, data: &[u8]) {
+        long(buf, data.len() as i64);
+        buf.extend_from_slice(data);
+    }
+
+    // A container file with a single block of `records`, already encoded
+    fn container(schema: &str, count: i64, records: &[u8]) -> Vec<u8> {
+        let sync = [7; 16];
+        let mut file = MAGIC.to_vec();
+        long(&mut file, 1);
+        bytes(&mut file, b"avro.schema");
+        bytes(&mut file, schema.as_bytes());
+        long(&mut file, 0);
+        file.extend_from_slice(&sync);
+        long(&mut file, count);
+        bytes(&mut file, records);
+        file.extend_from_slice(&sync);
+        file
+    }
+
+    #[test]
+    fn reads_records_into_rows() {
+        let schema = r#"{"type": "record", "name": "Tx", "fields": [
+            {"name": "type", "type": {"type": "enum", "name": "Kind", "symbols": ["deposit", "dispute"]}},
+            {"name": "client", "type": "int"},
+            {"name": "tx", "type": "long"},
+            {"name": "tags", "type": {"type": "array", "items": "string"}},
+            {"name": "amount", "type": ["null", {"type": "bytes", "logicalType": "decimal", "precision": 18, "scale": 4}]},
+            {"name": "timestamp", "type": ["null", {"type": "long", "logicalType": "timestamp-millis"}]}
+        ]}"#;
+        let mut records = Vec::new();
+        // deposit, 1, 1, ["web"], 1.5000, 1700000000123 ms
+        for n in [0, 1, 1, 1] {
+            long(&mut records, n);
+        }
+        bytes(&mut records, b"web");
+        long(&mut records, 0);
+        long(&mut records, 1);
+        bytes(&mut records, &15000i32.to_be_bytes()[2..]);
+        long(&mut records, 1);
+        long(&mut records, 1_700_000_000_123);
+        // dispute, 70000, 2, [], null, null: a client out of range
+        for n in [1, 70000, 2, 0, 0, 0] {
+            long(&mut records, n);
+        }
+        // dispute, 1, 1, [], null, null
+        for n in [1, 1, 1, 0, 0, 0] {
+            long(&mut records, n);
+        }
+
+        let rows: Vec<_> = AvroRows::new(container(schema, 3, &records).as_slice())
+            .unwrap()
+            .collect();
+
+        assert_eq!(rows.len(), 3);
+        let deposit = rows[0].as_ref().unwrap();
+        assert_eq!((deposit.client(), deposit.tx()), (1, 1));
+        assert_eq!(deposit.amount(), Some(Decimal::new(15, 1)));
+        assert_eq!(deposit.timestamp(), Some(1_700_000_000));
+        assert_eq!(deposit.line(), Some(1));
+        assert!(
+            rows[1]
+                .as_ref()
+                .unwrap_err()
+                .to_string()
+                .contains("record 2")
+        );
+        let dispute = rows[2].as_ref().unwrap();
+        assert_eq!((dispute.tx(), dispute.amount()), (1, None));
+    }
+
+    #[test]
+    fn requires_the_row_columns() {
+        let schema = r#"{"type": "record", "name": "Tx", "fields": [
+            {"name": "type", "type": "string"}, {"name": "client", "type": "int"}
+        ]}"#;
+        let error = AvroRows::new(container(schema, 0, &[]).as_slice()).err();
+        assert!(error.unwrap().to_string().contains("missing field tx"));
+        assert!(AvroRows::new(&b"type,client,tx\n"[..]).is_err());
+    }
+}
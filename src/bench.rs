@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::generate::{self, GenerateConfig};
+use crate::processor::ProcessorBuilder;
+use crate::transactions::TransactionRow;
+
+pub const DEFAULT_ROWS: u64 = 500_000;
+pub const DEFAULT_FAIL_THRESHOLD: f64 = 10.0;
+// Each scenario is timed this many times and the fastest run kept, which filters out
+// scheduler noise better than an average
+const RUNS: usize = 3;
+
+// Rows per second by scenario name, also the format of baseline files
+pub type BenchResults = BTreeMap<String, f64>;
+
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    pub rows: u64,
+    pub baseline: Option<String>,
+    // Where to write this run's results, e.g. to record a new baseline
+    pub save: Option<String>,
+    // Percent of baseline throughput a scenario may lose before the run fails
+    pub fail_threshold: f64,
+    pub config: Config,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            rows: DEFAULT_ROWS,
+            baseline: None,
+            save: None,
+            fail_threshold: DEFAULT_FAIL_THRESHOLD,
+            config: Config::default(),
+        }
+    }
+}
+
+// The built-in workloads, all generated with a fixed seed so runs are comparable
+fn scenarios(rows: u64) -> Vec<(&'static str, GenerateConfig)> {
+    let base = GenerateConfig {
+        rows,
+        malformed_rate: 0.0,
+        ..GenerateConfig::default()
+    };
+    vec![
+        (
+            "mixed",
+            GenerateConfig {
+                clients: 5_000,
+                ..base
+            },
+        ),
+        (
+            "dispute_heavy",
+            GenerateConfig {
+                clients: 5_000,
+                dispute_rate: 0.2,
+                ..base
+            },
+        ),
+        // Everything lands on a handful of clients, so most workers idle
+        ("few_clients", GenerateConfig { clients: 3, ..base }),
+    ]
+}
+
+pub fn run(options: &BenchOptions) -> Result<BenchResults, Error> {
+    let mut results = BenchResults::new();
+    for (name, workload) in scenarios(options.rows) {
+        let mut csv = Vec::new();
+        generate::generate(&workload, &mut csv)?;
+        let rows: Vec<TransactionRow> = csv::Reader::from_reader(csv.as_slice())
+            .into_deserialize()
+            .collect::<Result<_, _>>()?;
+
+        let mut best = 0f64;
+        for _ in 0..RUNS {
+            let output = ProcessorBuilder::new()
+                .config(options.config)
+                .build()
+                .run(name, rows.iter().cloned().map(Ok))?;
+            best = best.max(output.metrics.rows_per_sec());
+        }
+        results.insert(name.to_string(), best);
+    }
+    Ok(results)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub name: String,
+    pub rows_per_sec: f64,
+    pub baseline: Option<f64>,
+    // Percent change against the baseline, negative when slower
+    pub change: Option<f64>,
+    pub regressed: bool,
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<16} {:>12.0} rows/s", self.name, self.rows_per_sec)?;
+        if let (Some(baseline), Some(change)) = (self.baseline, self.change) {
+            write!(f, "  baseline {:>12.0}  {:+6.1}%", baseline, change)?;
+        }
+        if self.regressed {
+            write!(f, "  REGRESSED")?;
+        }
+        Ok(())
+    }
+}
+
+// Scenarios missing from the baseline are reported without a verdict, so adding a scenario
+// does not fail the gate before a new baseline is recorded
+pub fn compare(current: &BenchResults, baseline: &BenchResults, threshold: f64) -> Vec<Comparison> {
+    current
+        .iter()
+        .map(|(name, &rows_per_sec)| {
+            let base = baseline.get(name).copied().filter(|b| *b > 0.0);
+            let change = base.map(|b| (rows_per_sec - b) / b * 100.0);
+            Comparison {
+                name: name.clone(),
+                rows_per_sec,
+                baseline: base,
+                change,
+                regressed: change.is_some_and(|c| c < -threshold),
+            }
+        })
+        .collect()
+}
+
+// "10%" or "10"
+pub fn parse_percent(s: &str) -> Result<f64, Error> {
+    s.trim_end_matches('%')
+        .parse::<f64>()
+        .ok()
+        .filter(|p| p.is_finite() && *p >= 0.0)
+        .ok_or_else(|| Error::InvalidArgument(format!("invalid percentage {}", s)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_only_drops_beyond_threshold() {
+        let baseline = BenchResults::from([
+            ("a".to_string(), 1000.0),
+            ("b".to_string(), 1000.0),
+            ("c".to_string(), 1000.0),
+        ]);
+        let current = BenchResults::from([
+            ("a".to_string(), 950.0),
+            ("b".to_string(), 850.0),
+            ("c".to_string(), 1200.0),
+            ("new".to_string(), 10.0),
+        ]);
+
+        let regressed: Vec<_> = compare(&current, &baseline, parse_percent("10%").unwrap())
+            .into_iter()
+            .filter(|c| c.regressed)
+            .map(|c| c.name)
+            .collect();
+
+        assert_eq!(regressed, ["b"]);
+        assert!(parse_percent("-5").is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::account::AccountMap;
+use crate::config::Config;
+use crate::deposit_store::{DepositKey, StoredDeposit};
+use crate::transactions::{Transaction, TransactionRow};
+
+pub const DEFAULT_MAX_REJECT_RATE: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CanaryConfig {
+    pub rows: usize,
+    pub max_reject_rate: f64,
+}
+
+// Rejected rows are structurally broken (unparseable or invalid); failed rows parsed fine but
+// were refused by the engine (insufficient funds etc.), which is normal business traffic and
+// therefore not counted against the reject rate.
+#[derive(Debug, Default)]
+pub struct CanaryReport {
+    rows: usize,
+    rejected: usize,
+    failed: usize,
+    violations: Vec<String>,
+}
+
+impl CanaryReport {
+    pub fn reject_rate(&self) -> f64 {
+        if self.rows == 0 {
+            0.0
+        } else {
+            self.rejected as f64 / self.rows as f64
+        }
+    }
+
+    pub fn passed(&self, config: &CanaryConfig) -> bool {
+        self.violations.is_empty() && self.reject_rate() <= config.max_reject_rate
+    }
+}
+
+impl fmt::Display for CanaryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rows={} rejected={} failed={} reject_rate={:.4}",
+            self.rows,
+            self.rejected,
+            self.failed,
+            self.reject_rate()
+        )?;
+        for violation in &self.violations {
+            write!(f, "; {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+// Runs the first `config.rows` rows through an isolated, single-threaded engine. Nothing here
+// touches the state of the real run, it only decides whether the real run should happen.
+pub fn run<I, E>(rows: I, config: &CanaryConfig, engine_config: &Config) -> CanaryReport
+where
+    I: IntoIterator<Item = Result<TransactionRow, E>>,
+{
+    let mut accounts = AccountMap::new();
+    let mut deposits: HashMap<DepositKey, StoredDeposit> = HashMap::new();
+    let mut report = CanaryReport::default();
+
+    for result in rows.into_iter().take(config.rows) {
+        report.rows += 1;
+        let Some(transaction) = result
+            .ok()
+            .and_then(|row| Transaction::from_row(row, engine_config).ok())
+        else {
+            report.rejected += 1;
+            continue;
+        };
+        if transaction.process(&mut accounts, &mut deposits).is_err() {
+            report.failed += 1;
+        }
+    }
+
+    check_invariants(&accounts, &deposits, &mut report);
+    report
+}
+
+fn check_invariants(
+    accounts: &AccountMap,
+    deposits: &HashMap<DepositKey, StoredDeposit>,
+    report: &mut CanaryReport,
+) {
+    let mut total_held = Decimal::ZERO;
+    for account in accounts.iter() {
+        if account.held() < Decimal::ZERO {
+            report.violations.push(format!(
+                "client {} has negative held {}",
+                account.key(),
+                account.held()
+            ));
+        }
+        total_held += account.held();
+    }
+
+    let disputed: Decimal = deposits.values().map(|d| d.held()).sum();
+    if disputed != total_held {
+        report.violations.push(format!(
+            "held funds {} do not match disputed and pending deposits {}",
+            total_held, disputed
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(input: &str) -> Vec<Result<TransactionRow, csv::Error>> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .deserialize()
+            .collect()
+    }
+
+    #[test]
+    fn clean_input_passes() {
+        let config = CanaryConfig {
+            rows: 10,
+            max_reject_rate: 0.0,
+        };
+        let report = run(
+            rows("type,client,tx,amount\ndeposit,1,1,10\ndispute,1,1,\nwithdrawal,1,2,5\n"),
+            &config,
+            &Config::default(),
+        );
+
+        assert!(report.passed(&config));
+        assert_eq!(report.failed, 1); // withdrawal while funds are held
+    }
+
+    #[test]
+    fn reject_rate_above_threshold_fails() {
+        let config = CanaryConfig {
+            rows: 10,
+            max_reject_rate: 0.25,
+        };
+        let report = run(
+            rows("type,client,tx,amount\ndeposit,1,1,10\nbogus,1,2,5\ndeposit,x,3,1\n"),
+            &config,
+            &Config::default(),
+        );
+
+        assert_eq!(report.rejected, 2);
+        assert!(!report.passed(&config));
+    }
+
+    #[test]
+    fn only_first_rows_are_sampled() {
+        let config = CanaryConfig {
+            rows: 1,
+            max_reject_rate: 0.0,
+        };
+        let report = run(
+            rows("type,client,tx,amount\ndeposit,1,1,10\nbogus,1,2,5\n"),
+            &config,
+            &Config::default(),
+        );
+
+        assert_eq!(report.rows, 1);
+        assert!(report.passed(&config));
+    }
+}
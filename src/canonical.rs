@@ -0,0 +1,101 @@
+use std::io::Write;
+
+use crate::account::AccountOutput;
+use crate::error::Error;
+use crate::transactions::TransactionRow;
+
+// The one way tools that rewrite transaction files put rows back on disk. Canonical means a
+// fixed header and column order, amounts as plain decimals (`.` separator, no grouping, no
+// exponent, no trailing zeros) whatever the host locale, empty fields for absent values,
+// quoting only where CSV needs it and `\n` line endings. Reading the output back with the
+// engine's reader yields the rows that were written.
+pub const TRANSACTION_HEADER: [&str; 8] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "timestamp",
+    "reason",
+    "pending_until",
+    "sub",
+];
+
+fn csv_writer<W: Write>(inner: W) -> csv::Writer<W> {
+    csv::WriterBuilder::new()
+        .has_headers(false)
+        .terminator(csv::Terminator::Any(b'\n'))
+        .quote_style(csv::QuoteStyle::Necessary)
+        .from_writer(inner)
+}
+
+pub struct TransactionWriter<W: Write> {
+    inner: csv::Writer<W>,
+}
+
+impl<W: Write> TransactionWriter<W> {
+    // Writes the header straight away, so even an empty file parses
+    pub fn new(inner: W) -> Result<Self, Error> {
+        let mut inner = csv_writer(inner);
+        inner.write_record(TRANSACTION_HEADER)?;
+        Ok(Self { inner })
+    }
+
+    pub fn write(&mut self, row: &TransactionRow) -> Result<(), Error> {
+        let amount = row
+            .amount()
+            .map(|amount| amount.normalize().to_string())
+            .unwrap_or_default();
+        let timestamp = row.timestamp().map(|ts| ts.to_string()).unwrap_or_default();
+        let pending_until = row
+            .pending_until()
+            .map(|ts| ts.to_string())
+            .unwrap_or_default();
+        let sub = row.sub().map(|sub| sub.to_string()).unwrap_or_default();
+        self.inner.write_record([
+            row.tx_type().as_str(),
+            &row.client().to_string(),
+            &row.tx().to_string(),
+            &amount,
+            &timestamp,
+            row.reason().unwrap_or_default(),
+            &pending_until,
+            &sub,
+        ])?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn into_inner(self) -> Result<W, Error> {
+        self.inner.into_inner().map_err(|e| e.into_error().into())
+    }
+}
+
+// Account states as the CLI prints them; `AccountOutput` already carries the amounts
+// formatted at the run's precision
+#[allow(dead_code)]
+pub struct AccountWriter<W: Write> {
+    inner: csv::Writer<W>,
+}
+
+#[allow(dead_code)]
+impl<W: Write> AccountWriter<W> {
+    pub fn new(inner: W) -> Result<Self, Error> {
+        let mut inner = csv_writer(inner);
+        inner.write_record(AccountOutput::HEADER)?;
+        Ok(Self { inner })
+    }
+
+    pub fn write(&mut self, account: &AccountOutput) -> Result<(), Error> {
+        self.inner.serialize(account)?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> Result<W, Error> {
+        self.inner.into_inner().map_err(|e| e.into_error().into())
+    }
+}
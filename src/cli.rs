@@ -0,0 +1,654 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+
+use crate::alerts::{AlertTarget, AlertThresholds};
+use crate::bench::{self, BenchOptions};
+use crate::canary::{CanaryConfig, DEFAULT_MAX_REJECT_RATE};
+use crate::config::{Config, parse_duration, parse_rate};
+use crate::dedup::{DEFAULT_BLOOM_FP_RATE, DEFAULT_EXPECTED_TRANSACTIONS};
+use crate::error::Error;
+use crate::generate::GenerateConfig;
+use crate::input::{InputEncoding, InputFormat, InputOptions};
+use crate::output::{AccountOrder, OutputCompat, OutputFormat, OutputTarget};
+use crate::policy::{DedupStrategy, FeePolicy};
+use crate::run_config::RunConfig;
+
+// Kept here rather than in the feature gated server so the flag parses in every build
+const DEFAULT_GRPC_ADDR: ([u8; 4], u16) = ([127, 0, 0, 1], 50051);
+// Seconds between account snapshots of a followed input
+const DEFAULT_SNAPSHOT_INTERVAL: u64 = 5;
+
+#[derive(Debug)]
+pub enum Command {
+    Process(Box<Args>),
+    Replay { wal: String, config: Config },
+    Generate(GenerateConfig),
+    GrpcServe { addr: SocketAddr, config: Config },
+    Bench(BenchOptions),
+    // Two account outputs, old then new
+    Diff { old: String, new: String },
+    // An age identity file and an output written with `--encrypt-output`
+    Decrypt { identity: String, input: String },
+}
+
+impl Command {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Error> {
+        let mut args = args.into_iter().peekable();
+        match args.peek().map(String::as_str) {
+            Some("replay") => {
+                args.next();
+                let mut wal = None;
+                let mut config = Config::default();
+                while let Some(arg) = args.next() {
+                    if !parse_config_flag(&arg, &mut args, &mut config)? {
+                        wal = Some(arg);
+                    }
+                }
+                Ok(Command::Replay {
+                    wal: wal.ok_or(Error::MissingArgument)?,
+                    config,
+                })
+            }
+            Some("generate") => {
+                args.next();
+                let mut config = GenerateConfig::default();
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--rows" => config.rows = value(&arg, args.next())?,
+                        "--clients" => config.clients = value(&arg, args.next())?,
+                        "--dispute-rate" => config.dispute_rate = value(&arg, args.next())?,
+                        "--malformed-rate" => config.malformed_rate = value(&arg, args.next())?,
+                        "--seed" => config.seed = value(&arg, args.next())?,
+                        _ => {
+                            return Err(Error::InvalidArgument(format!(
+                                "unknown generate option {}",
+                                arg
+                            )));
+                        }
+                    }
+                }
+                Ok(Command::Generate(config))
+            }
+            Some("grpc-serve") => {
+                args.next();
+                let mut addr = SocketAddr::from(DEFAULT_GRPC_ADDR);
+                let mut config = Config::default();
+                while let Some(arg) = args.next() {
+                    if parse_config_flag(&arg, &mut args, &mut config)? {
+                        continue;
+                    }
+                    match arg.as_str() {
+                        "--addr" => addr = value(&arg, args.next())?,
+                        _ => {
+                            return Err(Error::InvalidArgument(format!(
+                                "unknown grpc-serve option {}",
+                                arg
+                            )));
+                        }
+                    }
+                }
+                Ok(Command::GrpcServe { addr, config })
+            }
+            Some("bench") => {
+                args.next();
+                let mut options = BenchOptions::default();
+                while let Some(arg) = args.next() {
+                    if parse_config_flag(&arg, &mut args, &mut options.config)? {
+                        continue;
+                    }
+                    match arg.as_str() {
+                        "--rows" => options.rows = value(&arg, args.next())?,
+                        "--baseline" => options.baseline = Some(value(&arg, args.next())?),
+                        "--save" => options.save = Some(value(&arg, args.next())?),
+                        "--fail-threshold" => {
+                            let threshold: String = value(&arg, args.next())?;
+                            options.fail_threshold = bench::parse_percent(&threshold)?;
+                        }
+                        _ => {
+                            return Err(Error::InvalidArgument(format!(
+                                "unknown bench option {}",
+                                arg
+                            )));
+                        }
+                    }
+                }
+                Ok(Command::Bench(options))
+            }
+            Some("diff") => {
+                args.next();
+                let (Some(old), Some(new), None) = (args.next(), args.next(), args.next()) else {
+                    return Err(Error::InvalidArgument(
+                        "diff takes two account files: diff <old.csv> <new.csv>".to_string(),
+                    ));
+                };
+                Ok(Command::Diff { old, new })
+            }
+            Some("decrypt") => {
+                args.next();
+                let (mut identity, mut input) = (None, None);
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--identity" => identity = Some(value(&arg, args.next())?),
+                        flag if flag.starts_with("--") => {
+                            return Err(Error::InvalidArgument(format!(
+                                "unknown decrypt option {}",
+                                flag
+                            )));
+                        }
+                        _ if input.is_none() => input = Some(arg),
+                        _ => {
+                            return Err(Error::InvalidArgument(
+                                "decrypt takes one input: decrypt --identity <key.txt> <file>"
+                                    .to_string(),
+                            ));
+                        }
+                    }
+                }
+                let (Some(identity), Some(input)) = (identity, input) else {
+                    return Err(Error::InvalidArgument(
+                        "decrypt takes one input: decrypt --identity <key.txt> <file>".to_string(),
+                    ));
+                };
+                Ok(Command::Decrypt { identity, input })
+            }
+            _ => Args::parse(args).map(|args| Command::Process(Box::new(args))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Args {
+    // Paths and globs, see `input::resolve_inputs`
+    pub inputs: Vec<String>,
+    pub input_dir: Option<String>,
+    // Unix domain socket to take rows from instead of input files, see `SocketRows`
+    pub listen_unix: Option<String>,
+    pub canary: Option<CanaryConfig>,
+    pub wal: Option<String>,
+    pub ledger: Option<String>,
+    pub adjustments: Option<String>,
+    pub conflicts: Option<String>,
+    pub dead_letters: Option<String>,
+    // Alerts on accounts crossing the thresholds, sent to stderr unless a webhook is given
+    pub alerts: AlertThresholds,
+    pub alert_target: AlertTarget,
+    pub locked_report: Option<String>,
+    pub quarantine: Option<String>,
+    pub metrics_json: Option<String>,
+    pub mapping: Option<String>,
+    pub script: Option<String>,
+    pub plugin: Option<String>,
+    pub workers: Option<usize>,
+    pub channel_capacity: Option<usize>,
+    pub batch_size: Option<usize>,
+    // Rows sent to the workers per second, see `TokenBucket`
+    pub max_tps: Option<u64>,
+    // Threads deserializing rows of the buffered reader, see `ParallelRows`
+    pub parse_threads: Option<usize>,
+    pub max_deposits_per_worker: Option<usize>,
+    pub error_log_limit: Option<u64>,
+    pub output: OutputTarget,
+    pub output_deposits: bool,
+    pub output_compat: OutputCompat,
+    pub output_format: OutputFormat,
+    // age recipient (`age1...`) the stdout or file output is encrypted to
+    pub encrypt_output: Option<String>,
+    pub order: AccountOrder,
+    // Only emit these accounts; empty means all
+    pub clients: HashSet<u16>,
+    // Client list files; rows of other clients are dropped before they are processed
+    pub only_clients: Option<String>,
+    pub exclude_clients: Option<String>,
+    pub strict: bool,
+    pub auto_tune: bool,
+    pub rebalance: bool,
+    pub evict_terminal: bool,
+    // Run the input a second time on a different number of workers and compare the accounts
+    pub verify_deterministic: bool,
+    // Check the merged state for invariants the engine must keep
+    pub assert_invariants: bool,
+    // Print a hash of the final accounts, see `state_hash`
+    pub state_hash: bool,
+    // Check the rows and report the invalid ones instead of processing
+    pub validate_only: bool,
+    // Seconds between rewrites of the output while following the input
+    pub snapshot_interval: u64,
+    pub seen_store: Option<String>,
+    // `redis://` URL of the accounts shared with other instances
+    pub account_store: Option<String>,
+    // State carried over from an earlier run, see `incremental`
+    pub load_accounts: Option<String>,
+    pub load_deposits: Option<String>,
+    pub save_deposits: Option<String>,
+    // Sizing of a new bloom filter, see `Deduplicator::bloom`
+    pub expected_transactions: usize,
+    pub bloom_fp_rate: f64,
+    // Bloom filter of an earlier run to start from, and where to write this run's
+    pub bloom_load: Option<String>,
+    pub bloom_save: Option<String>,
+    pub overdraft_limits: Option<String>,
+    pub mmap: bool,
+    // Memory-mapped like `mmap`, split with SIMD instead of `csv_core`
+    pub simd: bool,
+    pub progress: bool,
+    // `-` for stderr
+    pub summary: Option<String>,
+    pub input_options: InputOptions,
+    pub config: Config,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Error> {
+        let mut inputs = Vec::new();
+        let mut input_dir = None;
+        let mut listen_unix = None;
+        let mut canary_rows = None;
+        let mut canary_max_reject_rate = DEFAULT_MAX_REJECT_RATE;
+        let mut wal = None;
+        let mut ledger = None;
+        let mut adjustments = None;
+        let mut conflicts = None;
+        let mut dead_letters = None;
+        let mut alerts = AlertThresholds::default();
+        let mut alert_target = None;
+        let mut locked_report = None;
+        let mut quarantine = None;
+        let mut metrics_json = None;
+        let mut mapping = None;
+        let mut script = None;
+        let mut plugin = None;
+        let mut workers = None;
+        let mut channel_capacity = None;
+        let mut batch_size = None;
+        let mut max_tps = None;
+        let mut parse_threads = None;
+        let mut max_deposits_per_worker = None;
+        let mut error_log_limit = None;
+        let mut output = OutputTarget::default();
+        let mut output_deposits = false;
+        let mut output_compat = OutputCompat::default();
+        let mut output_format = OutputFormat::default();
+        let mut encrypt_output = None;
+        let mut order = AccountOrder::default();
+        let mut clients = HashSet::new();
+        let mut only_clients = None;
+        let mut exclude_clients = None;
+        let mut strict = false;
+        let mut auto_tune = false;
+        let mut rebalance = false;
+        let mut evict_terminal = false;
+        let mut verify_deterministic = false;
+        let mut assert_invariants = false;
+        let mut state_hash = false;
+        let mut validate_only = false;
+        let mut snapshot_interval = DEFAULT_SNAPSHOT_INTERVAL;
+        let mut seen_store = None;
+        let mut account_store = None;
+        let mut load_accounts = None;
+        let mut load_deposits = None;
+        let mut save_deposits = None;
+        let mut expected_transactions = DEFAULT_EXPECTED_TRANSACTIONS;
+        let mut bloom_fp_rate = DEFAULT_BLOOM_FP_RATE;
+        let mut bloom_load = None;
+        let mut bloom_save = None;
+        let mut overdraft_limits = None;
+        let mut mmap = false;
+        let mut simd = false;
+        let mut progress = false;
+        let mut summary = None;
+        let mut input_options = InputOptions::default();
+        let mut config = Config::default();
+
+        // The file's settings go first, so flags on the command line override them
+        let mut args: Vec<String> = args.into_iter().collect();
+        if let Some(at) = args.iter().position(|arg| arg == "--config") {
+            let path: String = value("--config", args.get(at + 1).cloned())?;
+            args.drain(at..at + 2);
+            args.splice(0..0, RunConfig::load(&path)?.to_args());
+        }
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if parse_config_flag(&arg, &mut args, &mut config)? {
+                continue;
+            }
+            match arg.as_str() {
+                "--config" => {
+                    return Err(Error::InvalidArgument(
+                        "--config can only be given once".to_string(),
+                    ));
+                }
+                "--input" => inputs.push(value(&arg, args.next())?),
+                "--input-dir" => input_dir = Some(value(&arg, args.next())?),
+                "--listen-unix" => listen_unix = Some(value(&arg, args.next())?),
+                "--canary" => canary_rows = Some(value(&arg, args.next())?),
+                "--canary-max-reject-rate" => canary_max_reject_rate = value(&arg, args.next())?,
+                "--wal" => wal = Some(value(&arg, args.next())?),
+                "--ledger" => ledger = Some(value(&arg, args.next())?),
+                "--adjustments" => adjustments = Some(value(&arg, args.next())?),
+                "--conflicts" => conflicts = Some(value(&arg, args.next())?),
+                "--dead-letters" => dead_letters = Some(value(&arg, args.next())?),
+                "--alert-below" => alerts.available_below = Some(value(&arg, args.next())?),
+                "--alert-held-above" => alerts.held_above = Some(value(&arg, args.next())?),
+                "--alert-webhook" => {
+                    let url: String = value(&arg, args.next())?;
+                    alert_target = Some(url.parse()?);
+                }
+                "--locked-report" => locked_report = Some(value(&arg, args.next())?),
+                "--quarantine" => quarantine = Some(value(&arg, args.next())?),
+                "--metrics-json" => metrics_json = Some(value(&arg, args.next())?),
+                "--mapping" => mapping = Some(value(&arg, args.next())?),
+                "--script" => script = Some(value(&arg, args.next())?),
+                "--plugin" => plugin = Some(value(&arg, args.next())?),
+                "--workers" => {
+                    let count = value(&arg, args.next())?;
+                    if count == 0 {
+                        return Err(Error::InvalidArgument(
+                            "--workers must be at least 1".to_string(),
+                        ));
+                    }
+                    workers = Some(count);
+                }
+                "--channel-capacity" => channel_capacity = Some(value(&arg, args.next())?),
+                "--batch-size" => {
+                    let size = value(&arg, args.next())?;
+                    if size == 0 {
+                        return Err(Error::InvalidArgument(
+                            "--batch-size must be at least 1".to_string(),
+                        ));
+                    }
+                    batch_size = Some(size);
+                }
+                "--max-tps" => {
+                    let rate = value(&arg, args.next())?;
+                    if rate == 0 {
+                        return Err(Error::InvalidArgument(
+                            "--max-tps must be at least 1".to_string(),
+                        ));
+                    }
+                    max_tps = Some(rate);
+                }
+                "--parse-threads" => {
+                    let threads = value(&arg, args.next())?;
+                    if threads == 0 {
+                        return Err(Error::InvalidArgument(
+                            "--parse-threads must be at least 1".to_string(),
+                        ));
+                    }
+                    parse_threads = Some(threads);
+                }
+                "--max-deposits-per-worker" => {
+                    max_deposits_per_worker = Some(value(&arg, args.next())?)
+                }
+                "--error-log-limit" => error_log_limit = Some(value(&arg, args.next())?),
+                "--output" => output = value(&arg, args.next())?,
+                "--output-deposits" => output_deposits = true,
+                "--output-compat" => output_compat = value(&arg, args.next())?,
+                "--output-format" => output_format = value(&arg, args.next())?,
+                "--encrypt-output" => encrypt_output = Some(value(&arg, args.next())?),
+                "--sort-by" => order.key = value(&arg, args.next())?,
+                "--desc" => order.descending = true,
+                "--client" => {
+                    clients.insert(value(&arg, args.next())?);
+                }
+                "--clients" => {
+                    let list: String = value(&arg, args.next())?;
+                    for client in list.split(',') {
+                        clients.insert(value(&arg, Some(client.trim().to_string()))?);
+                    }
+                }
+                "--only-clients" => only_clients = Some(value(&arg, args.next())?),
+                "--exclude-clients" => exclude_clients = Some(value(&arg, args.next())?),
+                "--strict" => strict = true,
+                "--auto-tune" => auto_tune = true,
+                "--rebalance" => rebalance = true,
+                "--evict-terminal" => evict_terminal = true,
+                "--verify-deterministic" => verify_deterministic = true,
+                "--assert-invariants" => assert_invariants = true,
+                "--state-hash" => state_hash = true,
+                "--validate-only" => validate_only = true,
+                "--follow" => input_options.follow = true,
+                "--snapshot-interval" => {
+                    let interval: String = value(&arg, args.next())?;
+                    snapshot_interval = parse_duration(&interval)?;
+                    if snapshot_interval == 0 {
+                        return Err(Error::InvalidArgument(
+                            "--snapshot-interval must be at least a second".to_string(),
+                        ));
+                    }
+                }
+                "--seen-store" => seen_store = Some(value(&arg, args.next())?),
+                "--account-store" => account_store = Some(value(&arg, args.next())?),
+                "--load-accounts" => load_accounts = Some(value(&arg, args.next())?),
+                "--load-deposits" => load_deposits = Some(value(&arg, args.next())?),
+                "--save-deposits" => save_deposits = Some(value(&arg, args.next())?),
+                "--expected-transactions" => expected_transactions = value(&arg, args.next())?,
+                "--bloom-fp-rate" => bloom_fp_rate = value(&arg, args.next())?,
+                "--bloom-load" => bloom_load = Some(value(&arg, args.next())?),
+                "--bloom-save" => bloom_save = Some(value(&arg, args.next())?),
+                "--overdraft-limits" => overdraft_limits = Some(value(&arg, args.next())?),
+                "--mmap" => mmap = true,
+                "--simd" => simd = true,
+                "--progress" => progress = true,
+                "--summary" => summary = Some(value(&arg, args.next())?),
+                "--format" => input_options.format = value(&arg, args.next())?,
+                "--encoding" => input_options.encoding = value(&arg, args.next())?,
+                "--compression" => input_options.compression = Some(value(&arg, args.next())?),
+                flag if flag.starts_with("--") => {
+                    return Err(Error::InvalidArgument(format!("unknown option {}", flag)));
+                }
+                _ => inputs.push(arg),
+            }
+        }
+        if listen_unix.is_some() && (!inputs.is_empty() || input_dir.is_some()) {
+            return Err(Error::InvalidArgument(
+                "--listen-unix takes the place of input files".to_string(),
+            ));
+        }
+        if inputs.is_empty() && input_dir.is_none() && listen_unix.is_none() {
+            return Err(Error::MissingArgument);
+        }
+        if alert_target.is_some() && alerts.is_empty() {
+            return Err(Error::InvalidArgument(
+                "--alert-webhook requires --alert-below or --alert-held-above".to_string(),
+            ));
+        }
+        if auto_tune && workers.is_some() {
+            return Err(Error::InvalidArgument(
+                "--workers does not combine with --auto-tune".to_string(),
+            ));
+        }
+        if config.withdrawal_fee != FeePolicy::None && config.fee_account.is_none() {
+            return Err(Error::InvalidArgument(
+                "--withdrawal-fee requires --fee-account".to_string(),
+            ));
+        }
+        if let (Some(min), Some(max)) = (config.min_amount, config.max_amount)
+            && min > max
+        {
+            return Err(Error::InvalidArgument(
+                "--min-amount is above --max-amount".to_string(),
+            ));
+        }
+        // Balances keep a fixed number of places with `minor-units`
+        #[cfg(feature = "minor-units")]
+        if config.precision > crate::amount::SCALE {
+            return Err(Error::InvalidArgument(format!(
+                "--precision above {} requires a build without the `minor-units` feature",
+                crate::amount::SCALE
+            )));
+        }
+        let bloom_tuned = expected_transactions != DEFAULT_EXPECTED_TRANSACTIONS
+            || bloom_fp_rate != DEFAULT_BLOOM_FP_RATE;
+        if (bloom_tuned || bloom_load.is_some() || bloom_save.is_some())
+            && config.dedup != DedupStrategy::Bloom
+        {
+            return Err(Error::InvalidArgument(
+                "bloom filter options require --dedup bloom".to_string(),
+            ));
+        }
+        // Avro is binary; strings in it are always UTF-8
+        if input_options.format == InputFormat::Avro
+            && input_options.encoding != InputEncoding::Utf8
+        {
+            return Err(Error::InvalidArgument(
+                "--encoding applies to CSV input, not --format avro".to_string(),
+            ));
+        }
+        if load_accounts.is_some() && account_store.is_some() {
+            return Err(Error::InvalidArgument(
+                "--load-accounts cannot be combined with --account-store".to_string(),
+            ));
+        }
+        // The funds loaded deposits hold are in the balances of the same run
+        if load_deposits.is_some() && load_accounts.is_none() {
+            return Err(Error::InvalidArgument(
+                "--load-deposits requires --load-accounts".to_string(),
+            ));
+        }
+        if seen_store.is_some() && (bloom_load.is_some() || bloom_save.is_some()) {
+            return Err(Error::InvalidArgument(
+                "--seen-store cannot be combined with --bloom-load or --bloom-save".to_string(),
+            ));
+        }
+        // A loaded filter keeps the size it was created with
+        if bloom_tuned && bloom_load.is_some() {
+            return Err(Error::InvalidArgument(
+                "--expected-transactions and --bloom-fp-rate cannot resize a loaded filter"
+                    .to_string(),
+            ));
+        }
+
+        Ok(Self {
+            inputs,
+            input_dir,
+            listen_unix,
+            canary: canary_rows.map(|rows| CanaryConfig {
+                rows,
+                max_reject_rate: canary_max_reject_rate,
+            }),
+            wal,
+            ledger,
+            adjustments,
+            conflicts,
+            dead_letters,
+            alerts,
+            alert_target: alert_target.unwrap_or_default(),
+            locked_report,
+            quarantine,
+            metrics_json,
+            mapping,
+            script,
+            plugin,
+            workers,
+            channel_capacity,
+            batch_size,
+            max_tps,
+            parse_threads,
+            max_deposits_per_worker,
+            error_log_limit,
+            output,
+            output_deposits,
+            output_compat,
+            output_format,
+            encrypt_output,
+            order,
+            clients,
+            only_clients,
+            exclude_clients,
+            strict,
+            auto_tune,
+            rebalance,
+            evict_terminal,
+            verify_deterministic,
+            assert_invariants,
+            state_hash,
+            validate_only,
+            snapshot_interval,
+            seen_store,
+            account_store,
+            load_accounts,
+            load_deposits,
+            save_deposits,
+            expected_transactions,
+            bloom_fp_rate,
+            bloom_load,
+            bloom_save,
+            overdraft_limits,
+            mmap,
+            simd,
+            progress,
+            summary,
+            input_options,
+            config,
+        })
+    }
+}
+
+// Engine settings shared by every subcommand. Returns false if `arg` is not one of them.
+fn parse_config_flag(
+    arg: &str,
+    args: &mut impl Iterator<Item = String>,
+    config: &mut Config,
+) -> Result<bool, Error> {
+    match arg {
+        "--precision" => config.precision = value(arg, args.next())?,
+        "--rounding" => config.rounding = value(arg, args.next())?,
+        "--dispute-window" => {
+            let window: String = value(arg, args.next())?;
+            config.dispute_window = Some(parse_duration(&window)?);
+        }
+        "--as-of" => config.as_of = Some(value(arg, args.next())?),
+        "--require-monotonic" => config.ordering = value(arg, args.next())?,
+        "--dedup" => config.dedup = value(arg, args.next())?,
+        "--dedupe-key" => config.dedup_key = value(arg, args.next())?,
+        "--dispute-overdraft" => config.dispute_overdraft = value(arg, args.next())?,
+        "--zero-amount" => config.zero_amount = value(arg, args.next())?,
+        "--locked-dispute" => config.locked_dispute = value(arg, args.next())?,
+        "--locked-account" => config.locked_account = value(arg, args.next())?,
+        "--void" => config.void = value(arg, args.next())?,
+        "--chargeback-reversal" => config.chargeback_reversal = value(arg, args.next())?,
+        "--withdrawal-fee" => config.withdrawal_fee = value(arg, args.next())?,
+        "--accrue-interest" => {
+            let rate: String = value(arg, args.next())?;
+            config.interest_rate = Some(parse_rate(&rate)?);
+        }
+        "--fee-account" => config.fee_account = Some(value(arg, args.next())?),
+        "--overdraft-limit" => {
+            let limit: Decimal = value(arg, args.next())?;
+            if limit.is_sign_negative() {
+                return Err(Error::InvalidArgument(format!(
+                    "invalid value for {}: {}",
+                    arg, limit
+                )));
+            }
+            config.overdraft_limit = limit;
+        }
+        "--min-amount" | "--max-amount" => {
+            let bound: Decimal = value(arg, args.next())?;
+            if bound.is_sign_negative() {
+                return Err(Error::InvalidArgument(format!(
+                    "invalid value for {}: {}",
+                    arg, bound
+                )));
+            }
+            match arg {
+                "--min-amount" => config.min_amount = Some(bound),
+                _ => config.max_amount = Some(bound),
+            }
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, Error> {
+    let value =
+        value.ok_or_else(|| Error::InvalidArgument(format!("{} requires a value", flag)))?;
+    value
+        .parse()
+        .map_err(|_| Error::InvalidArgument(format!("invalid value for {}: {}", flag, value)))
+}
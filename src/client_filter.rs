@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Read};
+
+use crate::error::Error;
+
+// Which clients' rows are let into a run, e.g. to reprocess only the clients an incident
+// touched. Lists are files with one client id per line; blank lines and `#` comments are
+// skipped, so is a `client` header before the first id. A client on both lists is excluded.
+#[derive(Debug, Default, Clone)]
+pub struct ClientFilter {
+    // None lets every client in
+    only: Option<HashSet<u16>>,
+    exclude: HashSet<u16>,
+}
+
+impl ClientFilter {
+    pub fn only(mut self, clients: HashSet<u16>) -> Self {
+        self.only = Some(clients);
+        self
+    }
+
+    pub fn exclude(mut self, clients: HashSet<u16>) -> Self {
+        self.exclude = clients;
+        self
+    }
+
+    pub fn allows(&self, client: u16) -> bool {
+        self.only.as_ref().is_none_or(|only| only.contains(&client))
+            && !self.exclude.contains(&client)
+    }
+
+    pub fn read_list<R: Read>(reader: R) -> Result<HashSet<u16>, Error> {
+        let mut clients = HashSet::new();
+        let mut first = true;
+        for (index, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                continue;
+            }
+            if std::mem::take(&mut first) && entry == "client" {
+                continue;
+            }
+            let client = entry.parse().map_err(|_| {
+                Error::InvalidArgument(format!(
+                    "invalid client {:?} on line {} of client list",
+                    entry,
+                    index + 1
+                ))
+            })?;
+            clients.insert(client);
+        }
+        Ok(clients)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_lists_and_excludes_over_only() {
+        let only = ClientFilter::read_list("client\n1\n\n2 # incident 42\n3\n".as_bytes()).unwrap();
+        let filter = ClientFilter::default()
+            .only(only)
+            .exclude(HashSet::from([3]));
+
+        assert!(filter.allows(1) && filter.allows(2));
+        assert!(!filter.allows(3) && !filter.allows(4));
+        assert!(ClientFilter::default().allows(4));
+        assert!(ClientFilter::read_list("1\nclient\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use std::str::FromStr;
+
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::error::Error;
+use crate::policy::{
+    AccountPolicy, DedupKey, DedupStrategy, DisputeOverdraftPolicy, FeePolicy,
+    LockedAccountDisputePolicy, OrderingPolicy, ReversalPolicy, VoidPolicy, ZeroAmountPolicy,
+};
+
+pub const DEFAULT_PRECISION: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    // Round half to even, what `Decimal::round_dp` does and the historical behaviour.
+    #[default]
+    Bankers,
+    Truncate,
+    HalfUp,
+}
+
+impl Rounding {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Rounding::Bankers => RoundingStrategy::MidpointNearestEven,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+        }
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bankers" => Ok(Rounding::Bankers),
+            "truncate" => Ok(Rounding::Truncate),
+            "half-up" => Ok(Rounding::HalfUp),
+            _ => Err(Error::InvalidArgument(format!("unknown rounding {}", s))),
+        }
+    }
+}
+
+// Money precision applied when amounts enter the engine and again when balances are written
+// out, so both ends agree on the number of decimal places.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub precision: u32,
+    pub rounding: Rounding,
+    // Seconds after which a deposit can no longer be disputed, measured against the
+    // dispute row's timestamp
+    pub dispute_window: Option<u64>,
+    pub dedup: DedupStrategy,
+    pub dedup_key: DedupKey,
+    pub dispute_overdraft: DisputeOverdraftPolicy,
+    pub zero_amount: ZeroAmountPolicy,
+    pub locked_dispute: LockedAccountDisputePolicy,
+    pub locked_account: AccountPolicy,
+    pub void: VoidPolicy,
+    pub chargeback_reversal: ReversalPolicy,
+    // How far below zero a withdrawal may take available; zero keeps the original hard floor
+    pub overdraft_limit: Decimal,
+    pub withdrawal_fee: FeePolicy,
+    // House account credited with the fees; no fees are charged without one
+    pub fee_account: Option<u16>,
+    // Fraction of available credited as interest at the end of a run
+    pub interest_rate: Option<Decimal>,
+    // Rows timestamped after this (unix seconds) are ignored, giving balances as of then
+    pub as_of: Option<u64>,
+    // Whether each client's timestamps must not go backwards
+    pub ordering: OrderingPolicy,
+    // Bounds on the size of non-zero amounts, as fat-finger protection; signed amounts are
+    // held to them by magnitude
+    pub min_amount: Option<Decimal>,
+    pub max_amount: Option<Decimal>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            precision: DEFAULT_PRECISION,
+            rounding: Rounding::default(),
+            dispute_window: None,
+            dedup: DedupStrategy::default(),
+            dedup_key: DedupKey::default(),
+            dispute_overdraft: DisputeOverdraftPolicy::default(),
+            zero_amount: ZeroAmountPolicy::default(),
+            locked_dispute: LockedAccountDisputePolicy::default(),
+            locked_account: AccountPolicy::default(),
+            void: VoidPolicy::default(),
+            chargeback_reversal: ReversalPolicy::default(),
+            overdraft_limit: Decimal::ZERO,
+            withdrawal_fee: FeePolicy::default(),
+            fee_account: None,
+            interest_rate: None,
+            as_of: None,
+            ordering: OrderingPolicy::default(),
+            min_amount: None,
+            max_amount: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn round(&self, amount: Decimal) -> Decimal {
+        amount.round_dp_with_strategy(self.precision, self.rounding.strategy())
+    }
+
+    // Whether a row is past the `as_of` cut-off. Rows without a timestamp never are.
+    pub fn after_cutoff(&self, timestamp: Option<u64>) -> bool {
+        self.as_of
+            .zip(timestamp)
+            .is_some_and(|(as_of, ts)| ts > as_of)
+    }
+
+    pub fn format(&self, amount: Decimal) -> String {
+        format!("{:.*}", self.precision as usize, self.round(amount))
+    }
+}
+
+// Rates like `1.5%`, or as a plain fraction, `0.015`
+pub fn parse_rate(s: &str) -> Result<Decimal, Error> {
+    let (number, scale) = match s.strip_suffix('%') {
+        Some(number) => (number, Decimal::ONE_HUNDRED),
+        None => (s, Decimal::ONE),
+    };
+    Decimal::from_str(number)
+        .ok()
+        .filter(|rate| !rate.is_sign_negative())
+        .map(|rate| rate / scale)
+        .ok_or_else(|| Error::InvalidArgument(format!("invalid rate {}", s)))
+}
+
+// Durations like `90d`, `12h`, `30m`, `45s` or plain seconds
+pub fn parse_duration(s: &str) -> Result<u64, Error> {
+    let invalid = || Error::InvalidArgument(format!("invalid duration {}", s));
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => s.split_at(idx),
+        None => (s, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let value: u64 = digits.parse().map_err(|_| invalid())?;
+    value.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(precision: u32, rounding: Rounding) -> Config {
+        Config {
+            precision,
+            rounding,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90d").unwrap(), 90 * 86_400);
+        assert_eq!(parse_duration("12h").unwrap(), 43_200);
+        assert_eq!(parse_duration("45").unwrap(), 45);
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("3w").is_err());
+    }
+
+    #[test]
+    fn rates() {
+        assert_eq!(parse_rate("1.5%").unwrap(), Decimal::new(15, 3));
+        assert_eq!(parse_rate("0.015").unwrap(), Decimal::new(15, 3));
+        assert!(parse_rate("-1%").is_err());
+        assert!(parse_rate("%").is_err());
+    }
+
+    #[test]
+    fn rounding_strategies() {
+        let amount = Decimal::new(12345, 4); // 1.2345
+
+        assert_eq!(
+            config(3, Rounding::Bankers).round(amount),
+            Decimal::new(1234, 3)
+        );
+        assert_eq!(
+            config(3, Rounding::HalfUp).round(amount),
+            Decimal::new(1235, 3)
+        );
+        assert_eq!(
+            config(2, Rounding::Truncate).round(amount),
+            Decimal::new(123, 2)
+        );
+    }
+
+    #[test]
+    fn as_of_cutoff() {
+        let config = Config {
+            as_of: Some(100),
+            ..Default::default()
+        };
+
+        assert!(!config.after_cutoff(Some(100)));
+        assert!(config.after_cutoff(Some(101)));
+        assert!(!config.after_cutoff(None));
+        assert!(!Config::default().after_cutoff(Some(u64::MAX)));
+    }
+
+    #[test]
+    fn format_pads_to_precision() {
+        assert_eq!(
+            config(2, Rounding::Bankers).format(Decimal::new(5, 0)),
+            "5.00"
+        );
+        assert_eq!(config(0, Rounding::HalfUp).format(Decimal::new(25, 1)), "3");
+    }
+}
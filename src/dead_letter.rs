@@ -0,0 +1,38 @@
+use std::io::Write;
+
+use crate::canonical::TransactionWriter;
+use crate::error::Error;
+use crate::transactions::TransactionRow;
+
+// Rows that never made it through a worker, because the worker panicked before or while
+// applying them, as transaction CSV in the canonical layout. Once the bug is fixed the file
+// is fed back in as input; the rows were accepted by the reader, so dedup must not have seen
+// them, i.e. re-feed them into a run that doesn't load this run's `--seen-store`.
+pub struct DeadLetterQueue<W: Write> {
+    inner: TransactionWriter<W>,
+    count: u64,
+}
+
+impl<W: Write> DeadLetterQueue<W> {
+    pub fn new(inner: W) -> Result<Self, Error> {
+        Ok(Self {
+            inner: TransactionWriter::new(inner)?,
+            count: 0,
+        })
+    }
+
+    pub fn write(&mut self, row: &TransactionRow) -> Result<(), Error> {
+        self.inner.write(row)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    // Rows written so far
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
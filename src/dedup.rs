@@ -0,0 +1,205 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+use bloomfilter::Bloom;
+
+use crate::error::Error;
+use crate::policy::{DedupKey, DedupStrategy};
+
+// Roughly ~24 bits per element at the below fp rate, tweakable with `Deduplicator::bloom`,
+// 10 million expected deposit and withdraw txs uses ~30MB RAM, would produce ~100 false positives
+pub const DEFAULT_EXPECTED_TRANSACTIONS: usize = 10_000_000;
+pub const DEFAULT_BLOOM_FP_RATE: f64 = 0.00001;
+
+// Seen store layout: magic, strategy byte, key byte, then the bloom filter's own byte form or
+// a count followed by the exact keys, all little endian
+const SEEN_MAGIC: &[u8; 8] = b"TPSEEN\0\x01";
+
+// Dedup keys (see `TransactionRow::dedup_key`) of deposits and withdrawals already handed to
+// the workers.
+pub enum Deduplicator {
+    Bloom(Bloom<u64>),
+    Exact(HashSet<u64>),
+    None,
+}
+
+impl Deduplicator {
+    pub fn new(strategy: DedupStrategy) -> Self {
+        match strategy {
+            DedupStrategy::Bloom => {
+                Deduplicator::bloom(DEFAULT_EXPECTED_TRANSACTIONS, DEFAULT_BLOOM_FP_RATE).unwrap()
+            }
+            DedupStrategy::Exact => Deduplicator::Exact(HashSet::new()),
+            DedupStrategy::None => Deduplicator::None,
+        }
+    }
+
+    // A bloom filter sized for `expected` keys at a false positive rate of `fp_rate` (0 to 1,
+    // exclusive). Past `expected` keys the rate climbs, dropping more legitimate rows.
+    pub fn bloom(expected: usize, fp_rate: f64) -> Result<Self, Error> {
+        let invalid = |reason: &str| {
+            Error::InvalidArgument(format!(
+                "cannot size a bloom filter for {} keys at {}: {}",
+                expected, fp_rate, reason
+            ))
+        };
+        if expected == 0 || !(fp_rate > 0.0 && fp_rate < 1.0) {
+            return Err(invalid("out of range"));
+        }
+        Bloom::new_for_fp_rate(expected, fp_rate)
+            .map(Deduplicator::Bloom)
+            .map_err(invalid)
+    }
+
+    // Records `key` and returns whether it had (possibly, for the bloom filter) been seen before
+    pub fn check_and_insert(&mut self, key: u64) -> bool {
+        match self {
+            Deduplicator::Bloom(bloom) => {
+                if bloom.check(&key) {
+                    true
+                } else {
+                    bloom.set(&key);
+                    false
+                }
+            }
+            Deduplicator::Exact(seen) => !seen.insert(key),
+            Deduplicator::None => false,
+        }
+    }
+
+    // Highest tx id among the keys, only known when they are kept exactly. Both `DedupKey`s
+    // keep the tx id in the low 32 bits.
+    pub fn last_tx(&self) -> Option<u32> {
+        match self {
+            Deduplicator::Exact(seen) => seen.iter().map(|key| *key as u32).max(),
+            Deduplicator::Bloom(_) | Deduplicator::None => None,
+        }
+    }
+
+    fn strategy(&self) -> DedupStrategy {
+        match self {
+            Deduplicator::Bloom(_) => DedupStrategy::Bloom,
+            Deduplicator::Exact(_) => DedupStrategy::Exact,
+            Deduplicator::None => DedupStrategy::None,
+        }
+    }
+
+    // Persists the seen keys for `load` in a later run. `key` is recorded so keys computed
+    // differently are never mixed.
+    pub fn save(&self, key: DedupKey, mut out: impl Write) -> Result<(), Error> {
+        out.write_all(SEEN_MAGIC)?;
+        out.write_all(&[strategy_byte(self.strategy()), key_byte(key)])?;
+        match self {
+            Deduplicator::Bloom(bloom) => out.write_all(&bloom.to_bytes())?,
+            Deduplicator::Exact(seen) => {
+                out.write_all(&(seen.len() as u64).to_le_bytes())?;
+                for key in seen {
+                    out.write_all(&key.to_le_bytes())?;
+                }
+            }
+            Deduplicator::None => {}
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    // Restores what `save` wrote. The store must match the run's strategy and key, otherwise
+    // rows would be checked against keys that mean something else.
+    pub fn load(
+        strategy: DedupStrategy,
+        key: DedupKey,
+        mut input: impl Read,
+    ) -> Result<Self, Error> {
+        let mut header = [0u8; 10];
+        input
+            .read_exact(&mut header)
+            .map_err(|_| Error::SeenStore("truncated header".to_string()))?;
+        if &header[..8] != SEEN_MAGIC {
+            return Err(Error::SeenStore("bad header".to_string()));
+        }
+        if header[8] != strategy_byte(strategy) || header[9] != key_byte(key) {
+            return Err(Error::SeenStore(format!(
+                "written with a different --dedup or --dedupe-key than {:?}/{:?}",
+                strategy, key
+            )));
+        }
+
+        let mut body = Vec::new();
+        input.read_to_end(&mut body)?;
+        match strategy {
+            DedupStrategy::Bloom => Bloom::from_bytes(body)
+                .map(Deduplicator::Bloom)
+                .map_err(|e| Error::SeenStore(e.to_string())),
+            DedupStrategy::Exact => {
+                let (count, keys) = body
+                    .split_first_chunk::<8>()
+                    .ok_or_else(|| Error::SeenStore("truncated key count".to_string()))?;
+                let (keys, rest) = keys.as_chunks::<8>();
+                if !rest.is_empty() || keys.len() as u64 != u64::from_le_bytes(*count) {
+                    return Err(Error::SeenStore("truncated keys".to_string()));
+                }
+                Ok(Deduplicator::Exact(
+                    keys.iter().map(|k| u64::from_le_bytes(*k)).collect(),
+                ))
+            }
+            DedupStrategy::None => Ok(Deduplicator::None),
+        }
+    }
+}
+
+fn strategy_byte(strategy: DedupStrategy) -> u8 {
+    match strategy {
+        DedupStrategy::Bloom => 0,
+        DedupStrategy::Exact => 1,
+        DedupStrategy::None => 2,
+    }
+}
+
+fn key_byte(key: DedupKey) -> u8 {
+    match key {
+        DedupKey::Tx => 0,
+        DedupKey::ClientTx => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seen_keys_survive_save_and_load() {
+        for strategy in [DedupStrategy::Bloom, DedupStrategy::Exact] {
+            let mut dedup = Deduplicator::new(strategy);
+            dedup.check_and_insert(7);
+            dedup.check_and_insert(1 << 40);
+            let mut buffer = Vec::new();
+            dedup.save(DedupKey::ClientTx, &mut buffer).unwrap();
+
+            let mut loaded =
+                Deduplicator::load(strategy, DedupKey::ClientTx, buffer.as_slice()).unwrap();
+            assert!(loaded.check_and_insert(7));
+            assert!(loaded.check_and_insert(1 << 40));
+            assert!(!loaded.check_and_insert(8));
+
+            let mismatch = Deduplicator::load(strategy, DedupKey::Tx, buffer.as_slice());
+            assert!(matches!(mismatch, Err(Error::SeenStore(_))));
+        }
+    }
+
+    #[test]
+    fn bloom_filters_are_sized_on_request() {
+        let Ok(Deduplicator::Bloom(small)) = Deduplicator::bloom(1_000, 0.01) else {
+            panic!("expected a bloom filter");
+        };
+        let Ok(Deduplicator::Bloom(default)) =
+            Deduplicator::bloom(DEFAULT_EXPECTED_TRANSACTIONS, DEFAULT_BLOOM_FP_RATE)
+        else {
+            panic!("expected a bloom filter");
+        };
+        assert!(small.number_of_bits() < default.number_of_bits());
+
+        for (expected, fp_rate) in [(0, 0.01), (1_000, 0.0), (1_000, 1.0)] {
+            assert!(Deduplicator::bloom(expected, fp_rate).is_err());
+        }
+    }
+}
@@ -1,58 +1,467 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::RwLock;
 
 use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 
-use crate::transactions::DepositTx;
+use crate::account::AccountKey;
+use crate::amount::{Funds, decimal, fits, funds};
+use crate::error::Error;
+use crate::transactions::{DepositTx, Transaction, WithdrawalTx};
 
 // Memory scales with deposit count (~20 bytes each). At scale (billions of txs),
 // this is impractical. Production alternatives: external storage (DB/Redis), time-based
 // dispute windows (e.g., 90 days), or transaction-count limits with cold storage archival.
+//
+// Withdrawals are kept too, as the originals that `withdrawal_correction` rows are checked
+// against. The deposit accessors never return them.
 pub trait DepositStore {
     fn insert(&mut self, tx: &DepositTx);
     #[allow(dead_code)]
-    fn get(&self, tx_id: u32) -> Option<&StoredDeposit>;
-    fn get_mut(&mut self, tx_id: u32) -> Option<&mut StoredDeposit>;
+    fn get(&self, key: DepositKey) -> Option<&StoredDeposit>;
+    fn get_mut(&mut self, key: DepositKey) -> Option<&mut StoredDeposit>;
     #[allow(dead_code)]
-    fn remove(&mut self, tx_id: u32) -> Option<StoredDeposit>;
+    fn remove(&mut self, key: DepositKey) -> Option<StoredDeposit>;
+    fn insert_withdrawal(&mut self, tx: &WithdrawalTx);
+    fn get_withdrawal_mut(&mut self, key: DepositKey) -> Option<&mut StoredDeposit>;
+    // Drops deposits timestamped before `cutoff`, returning the keys evicted. Disputed deposits
+    // are kept regardless of age since their funds are still held, voided ones because they
+    // are the audit record.
+    fn evict_before(&mut self, cutoff: u64) -> Vec<DepositKey>;
+    // Drops the entries inserted longest ago until at most `max` remain, returning the keys
+    // evicted. Disputed deposits are kept, their funds are still held. Stores that don't track
+    // insertion order evict nothing.
+    fn evict_oldest(&mut self, _max: usize) -> Vec<DepositKey> {
+        Vec::new()
+    }
+}
+
+// What a deposit or withdrawal is stored under: its id together with its client, as clients
+// may reuse each other's ids. Sorts by id first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DepositKey {
+    pub tx: u32,
+    pub client: u16,
 }
 
-impl DepositStore for HashMap<u32, StoredDeposit> {
+impl DepositKey {
+    pub fn new(client: u16, tx: u32) -> Self {
+        Self { tx, client }
+    }
+}
+
+impl DepositStore for HashMap<DepositKey, StoredDeposit> {
     fn insert(&mut self, tx: &DepositTx) {
         let stored_deposit = StoredDeposit::from(tx);
-        self.insert(tx.id(), stored_deposit);
+        self.insert(DepositKey::new(tx.client(), tx.id()), stored_deposit);
+    }
+
+    fn get(&self, key: DepositKey) -> Option<&StoredDeposit> {
+        self.get(&key).filter(|d| !d.is_withdrawal())
+    }
+
+    fn get_mut(&mut self, key: DepositKey) -> Option<&mut StoredDeposit> {
+        self.get_mut(&key).filter(|d| !d.is_withdrawal())
+    }
+
+    fn remove(&mut self, key: DepositKey) -> Option<StoredDeposit> {
+        match self.get(&key) {
+            Some(d) if !d.is_withdrawal() => self.remove(&key),
+            _ => None,
+        }
+    }
+
+    fn insert_withdrawal(&mut self, tx: &WithdrawalTx) {
+        self.insert(
+            DepositKey::new(tx.client(), tx.id()),
+            StoredDeposit::from(tx),
+        );
+    }
+
+    fn get_withdrawal_mut(&mut self, key: DepositKey) -> Option<&mut StoredDeposit> {
+        self.get_mut(&key).filter(|d| d.is_withdrawal())
     }
 
-    fn get(&self, tx_id: u32) -> Option<&StoredDeposit> {
-        self.get(&tx_id)
+    fn evict_before(&mut self, cutoff: u64) -> Vec<DepositKey> {
+        self.extract_if(|_, d| !d.holds_funds() && !d.is_voided() && d.is_older_than(cutoff))
+            .map(|(key, _)| key)
+            .collect()
     }
+}
+
+// The `HashMap` store plus the order entries were inserted in, so it can be capped with
+// `evict_oldest`. Ids removed by other means are skipped when they come up.
+#[derive(Debug, Default)]
+pub struct OrderedDeposits {
+    deposits: HashMap<DepositKey, StoredDeposit>,
+    order: VecDeque<DepositKey>,
+}
 
-    fn get_mut(&mut self, tx_id: u32) -> Option<&mut StoredDeposit> {
-        self.get_mut(&tx_id)
+impl OrderedDeposits {
+    // Moves out the entries of `client`, for a handoff to another worker
+    pub fn extract_client(&mut self, client: u16) -> Vec<(DepositKey, StoredDeposit)> {
+        self.deposits
+            .extract_if(|key, _| key.client == client)
+            .collect()
     }
 
-    fn remove(&mut self, tx_id: u32) -> Option<StoredDeposit> {
-        self.remove(&tx_id)
+    // Adds entries from elsewhere as the newest
+    pub fn extend(&mut self, entries: impl IntoIterator<Item = (DepositKey, StoredDeposit)>) {
+        for (key, deposit) in entries {
+            self.track(key);
+            self.deposits.insert(key, deposit);
+        }
+    }
+
+    // Whether a deposit or withdrawal is stored under `key`
+    pub fn contains(&self, key: DepositKey) -> bool {
+        self.deposits.contains_key(&key)
+    }
+
+    pub fn into_map(self) -> HashMap<DepositKey, StoredDeposit> {
+        self.deposits
+    }
+
+    // Account and id of the pending deposits whose hold is over at `now`
+    pub fn due(&self, now: u64) -> Vec<(AccountKey, u32)> {
+        self.deposits
+            .iter()
+            .filter(|(_, deposit)| deposit.is_due(now))
+            .map(|(key, deposit)| (deposit.account(), key.tx))
+            .collect()
+    }
+
+    fn track(&mut self, key: DepositKey) {
+        self.order.push_back(key);
+        // Stale keys pile up when entries leave other ways, drop them once they dominate
+        if self.order.len() > 2 * self.deposits.len() + 1024 {
+            let deposits = &self.deposits;
+            self.order.retain(|key| deposits.contains_key(key));
+        }
     }
 }
 
+impl DepositStore for OrderedDeposits {
+    fn insert(&mut self, tx: &DepositTx) {
+        self.track(DepositKey::new(tx.client(), tx.id()));
+        DepositStore::insert(&mut self.deposits, tx);
+    }
+
+    fn get(&self, key: DepositKey) -> Option<&StoredDeposit> {
+        DepositStore::get(&self.deposits, key)
+    }
+
+    fn get_mut(&mut self, key: DepositKey) -> Option<&mut StoredDeposit> {
+        DepositStore::get_mut(&mut self.deposits, key)
+    }
+
+    fn remove(&mut self, key: DepositKey) -> Option<StoredDeposit> {
+        DepositStore::remove(&mut self.deposits, key)
+    }
+
+    fn insert_withdrawal(&mut self, tx: &WithdrawalTx) {
+        self.track(DepositKey::new(tx.client(), tx.id()));
+        self.deposits.insert_withdrawal(tx);
+    }
+
+    fn get_withdrawal_mut(&mut self, key: DepositKey) -> Option<&mut StoredDeposit> {
+        self.deposits.get_withdrawal_mut(key)
+    }
+
+    fn evict_before(&mut self, cutoff: u64) -> Vec<DepositKey> {
+        self.deposits.evict_before(cutoff)
+    }
+
+    fn evict_oldest(&mut self, max: usize) -> Vec<DepositKey> {
+        let mut evicted = Vec::new();
+        let mut kept = Vec::new();
+        while self.deposits.len() > max
+            && let Some(key) = self.order.pop_front()
+        {
+            match self.deposits.get(&key) {
+                Some(deposit) if deposit.holds_funds() => kept.push(key),
+                Some(_) => {
+                    self.deposits.remove(&key);
+                    evicted.push(key);
+                }
+                None => {}
+            }
+        }
+        for key in kept.into_iter().rev() {
+            self.order.push_front(key);
+        }
+        evicted
+    }
+}
+
+// What is left of deposits dropped from a store once resolved or charged back: their account
+// and status, about 12 bytes an id instead of a full `StoredDeposit`. A later row naming one
+// is refused with the error it would have met, an `invalid_state` for another dispute of it,
+// rather than `unknown_tx`. What the status still allowed, voiding or correcting a resolved
+// deposit and reversing a chargeback, is refused as `deposit_evicted`.
+#[derive(Debug, Default)]
+pub struct TerminalDeposits {
+    ids: HashMap<DepositKey, Tombstone>,
+}
+
+pub type Tombstone = (AccountKey, DepositStatus);
+
+impl TerminalDeposits {
+    // Moves deposit `key` out of `store` if it is resolved or charged back
+    pub fn evict(&mut self, store: &mut impl DepositStore, key: DepositKey) {
+        let terminal = store.get(key).is_some_and(|deposit| {
+            matches!(
+                deposit.status,
+                DepositStatus::Resolved | DepositStatus::Chargedback
+            )
+        });
+        if terminal && let Some(deposit) = store.remove(key) {
+            self.ids.insert(key, (deposit.account(), deposit.status));
+        }
+    }
+
+    // `error` of `transaction`, as it would have been with the deposit still stored
+    pub fn explain(&self, error: Error, transaction: &Transaction) -> Error {
+        let Error::StoredDepositNotFound(tx_id) = error else {
+            return error;
+        };
+        let key = DepositKey::new(transaction.client(), tx_id);
+        let Some(&(account, mut status)) = self.ids.get(&key) else {
+            return error;
+        };
+        if let Err(e) = check_account(tx_id, account, transaction.account()) {
+            return e;
+        }
+        let transition = match transaction {
+            Transaction::Dispute(_) => status.dispute(),
+            Transaction::Resolve(_) => status.resolve(),
+            Transaction::Chargeback(_) => status.chargeback(),
+            Transaction::Void(_) => status.void(),
+            Transaction::ChargebackReversal(_) => status.reverse_chargeback(),
+            Transaction::Correction(_) => status.check_correction(),
+            Transaction::Release(_) => status.release(),
+            _ => return error,
+        };
+        match transition {
+            Ok(()) => Error::DepositEvicted(tx_id),
+            Err(e) => e.into(),
+        }
+    }
+
+    // Moves out the ids of `client`, for a handoff to another worker
+    pub fn extract_client(&mut self, client: u16) -> Vec<(DepositKey, Tombstone)> {
+        self.ids.extract_if(|key, _| key.client == client).collect()
+    }
+
+    pub fn extend(&mut self, ids: impl IntoIterator<Item = (DepositKey, Tombstone)>) {
+        self.ids.extend(ids);
+    }
+}
+
+// Which client each deposit and withdrawal id belongs to, across all workers. Deposits are
+// stored under their client, on the worker of that client, so a dispute naming another
+// client's deposit finds nothing; this tells it the deposit exists and whose it is. Filled by the reader as it routes
+// rows, so an id is known before any later row can refer to it, whichever worker is faster.
+// Each row recorded is counted, and forgotten again by its worker when it is not stored or
+// once it is evicted, so the ids known are the ids stored. That costs 16 to 32 bytes per
+// stored id, table growth included, on top of the deposit stores. An id used by several
+// clients, as `DedupKey::ClientTx` allows, has no single owner.
+#[derive(Debug, Default)]
+pub struct DepositOwners {
+    owners: RwLock<HashMap<u32, Owner>>,
+}
+
 #[derive(Debug)]
+struct Owner {
+    client: u16,
+    shared: bool,
+    // Rows recorded and not forgotten yet
+    refs: u32,
+}
+
+impl DepositOwners {
+    pub fn record(&self, tx_id: u32, client: u16) {
+        let mut owners = self.owners.write().unwrap();
+        let owner = owners.entry(tx_id).or_insert(Owner {
+            client,
+            shared: false,
+            refs: 0,
+        });
+        owner.shared |= owner.client != client;
+        owner.refs += 1;
+    }
+
+    // Drops one row recorded for `tx_id`, and the id with its last one
+    pub fn forget(&self, tx_id: u32) {
+        let mut owners = self.owners.write().unwrap();
+        if let Some(owner) = owners.get_mut(&tx_id) {
+            owner.refs = owner.refs.saturating_sub(1);
+            if owner.refs == 0 {
+                owners.remove(&tx_id);
+            }
+        }
+    }
+
+    // `None` for an unknown id and for one several clients used
+    pub fn owner(&self, tx_id: u32) -> Option<u16> {
+        let owners = self.owners.read().unwrap();
+        owners
+            .get(&tx_id)
+            .filter(|owner| !owner.shared)
+            .map(|owner| owner.client)
+    }
+
+    // `error` for a row of `client`, as a client mismatch when the transaction it could not
+    // find is another client's
+    pub fn explain(&self, error: Error, client: u16) -> Error {
+        let (Error::StoredDepositNotFound(tx_id) | Error::StoredWithdrawalNotFound(tx_id)) = error
+        else {
+            return error;
+        };
+        match self.owner(tx_id) {
+            Some(owner) if owner != client => Error::ClientMismatch {
+                tx_id,
+                expected: owner,
+                found: client,
+            },
+            _ => error,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct StoredDeposit {
     client: u16,
-    amount: Decimal,
+    #[cfg_attr(feature = "serde", serde(default))]
+    sub: u16,
+    amount: Funds,
+    // Portion held by the current (or last) dispute, released or charged back in full
+    disputed: Funds,
     status: DepositStatus,
+    timestamp: Option<u64>,
+    // End of the hold of a pending deposit, unix seconds
+    #[cfg_attr(feature = "serde", serde(default))]
+    pending_until: Option<u64>,
+    // A withdrawal kept for corrections; never disputed
+    withdrawal: bool,
 }
 
 impl StoredDeposit {
+    #[allow(dead_code)]
     pub fn client(&self) -> u16 {
         self.client
     }
 
+    #[allow(dead_code)]
+    pub fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    pub fn account(&self) -> AccountKey {
+        AccountKey::new(self.client, self.sub)
+    }
+
     pub fn amount(&self) -> Decimal {
-        self.amount
+        decimal(self.amount)
+    }
+
+    #[allow(dead_code)]
+    pub fn status(&self) -> DepositStatus {
+        self.status
+    }
+
+    #[allow(dead_code)]
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    pub fn disputed_amount(&self) -> Decimal {
+        decimal(self.disputed)
+    }
+
+    #[allow(dead_code)]
+    pub fn pending_until(&self) -> Option<u64> {
+        self.pending_until
+    }
+
+    // What the deposit keeps in the client's held funds: the disputed part during a dispute,
+    // all of it while pending
+    pub fn held(&self) -> Decimal {
+        match self.status {
+            DepositStatus::Disputed => self.disputed_amount(),
+            DepositStatus::Pending => self.amount(),
+            _ => Decimal::ZERO,
+        }
+    }
+
+    // Undisputed part of the deposit, still available to the client during a partial dispute
+    #[allow(dead_code)]
+    pub fn remaining(&self) -> Decimal {
+        decimal(self.amount - self.disputed)
+    }
+
+    // Deposits without a timestamp never age out
+    pub fn is_older_than(&self, cutoff: u64) -> bool {
+        self.timestamp.is_some_and(|ts| ts < cutoff)
+    }
+
+    #[allow(dead_code)]
+    pub fn is_disputed(&self) -> bool {
+        self.status == DepositStatus::Disputed
+    }
+
+    // Disputed or pending, either way its funds are held and it must not be evicted
+    pub fn holds_funds(&self) -> bool {
+        matches!(
+            self.status,
+            DepositStatus::Disputed | DepositStatus::Pending
+        )
+    }
+
+    // Pending with a hold that is over at `now`
+    pub fn is_due(&self, now: u64) -> bool {
+        self.status == DepositStatus::Pending && self.pending_until.is_some_and(|end| end <= now)
+    }
+
+    pub fn is_voided(&self) -> bool {
+        self.status == DepositStatus::Voided
+    }
+
+    pub fn is_withdrawal(&self) -> bool {
+        self.withdrawal
+    }
+
+    // Corrections move the original amount by `delta` but never below zero, and only while
+    // no dispute is holding or has taken the funds
+    pub fn check_correction(&self, tx_id: u32, delta: Decimal) -> Result<(), Error> {
+        self.status.check_correction()?;
+        if self.amount() + delta < Decimal::ZERO {
+            return Err(Error::CorrectionExceedsOriginal {
+                tx_id,
+                original: self.amount(),
+                correction: delta,
+            });
+        }
+        if !fits(self.amount() + delta) {
+            return Err(Error::AmountOutOfBounds {
+                tx_id,
+                amount: delta,
+            });
+        }
+        Ok(())
+    }
+
+    // Call `check_correction` first
+    pub fn apply_correction(&mut self, delta: Decimal) {
+        self.amount += funds(delta);
     }
 
-    pub fn set_disputed(&mut self) -> Result<(), DepositStateError> {
-        self.status.dispute()
+    pub fn set_disputed(&mut self, amount: Decimal) -> Result<(), DepositStateError> {
+        self.status.dispute()?;
+        self.disputed = funds(amount);
+        Ok(())
     }
 
     pub fn set_resolved(&mut self) -> Result<(), DepositStateError> {
@@ -63,20 +472,123 @@ impl StoredDeposit {
         self.status.chargeback()
     }
 
-    pub fn ensure_client_matches(
-        &self,
-        tx_id: u32,
-        tx_client: u16,
-    ) -> Result<(), crate::error::Error> {
-        if tx_client != self.client() {
-            Err(crate::error::Error::ClientMismatch {
-                tx_id,
-                expected: self.client(),
-                found: tx_client,
-            })
-        } else {
-            Ok(())
+    pub fn set_voided(&mut self) -> Result<(), DepositStateError> {
+        self.status.void()
+    }
+
+    pub fn set_chargeback_reversed(&mut self) -> Result<(), DepositStateError> {
+        self.status.reverse_chargeback()
+    }
+
+    // Settles a pending deposit. With `now` the hold must be over by then, without it the
+    // release is taken as already checked (e.g. replayed from the WAL).
+    pub fn set_released(&mut self, tx_id: u32, now: Option<u64>) -> Result<(), Error> {
+        if let (DepositStatus::Pending, Some(now), Some(until)) =
+            (self.status, now, self.pending_until)
+            && now < until
+        {
+            return Err(Error::StillPending { tx_id, until });
         }
+        self.status.release()?;
+        Ok(())
+    }
+
+    // A follow-up row must name the client and the sub-account the deposit went to
+    pub fn ensure_account_matches(&self, tx_id: u32, account: AccountKey) -> Result<(), Error> {
+        check_account(tx_id, self.account(), account)
+    }
+}
+
+fn check_account(tx_id: u32, expected: AccountKey, found: AccountKey) -> Result<(), Error> {
+    if found.client != expected.client {
+        Err(Error::ClientMismatch {
+            tx_id,
+            expected: expected.client,
+            found: found.client,
+        })
+    } else if found.sub != expected.sub {
+        Err(Error::SubAccountMismatch {
+            tx_id,
+            expected: expected.sub,
+            found: found.sub,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+// A kept deposit or withdrawal as a row of `--save-deposits` and `--load-deposits`:
+//
+//   tx,type,client,sub,amount,disputed,status,timestamp,pending_until
+//   7,deposit,1,0,10,4,disputed,1700000000,
+//
+// Amounts as plain decimals without trailing zeros, the status as `DepositStatus::as_str`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepositRecord {
+    pub tx: u32,
+    // `deposit` or `withdrawal`
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub client: u16,
+    #[serde(default)]
+    pub sub: u16,
+    pub amount: Decimal,
+    pub disputed: Decimal,
+    pub status: String,
+    pub timestamp: Option<u64>,
+    pub pending_until: Option<u64>,
+}
+
+impl StoredDeposit {
+    pub fn to_record(&self, tx: u32) -> DepositRecord {
+        DepositRecord {
+            tx,
+            kind: match self.withdrawal {
+                true => "withdrawal",
+                false => "deposit",
+            }
+            .to_string(),
+            client: self.client,
+            sub: self.sub,
+            amount: self.amount().normalize(),
+            disputed: self.disputed_amount().normalize(),
+            status: self.status.as_str().to_string(),
+            timestamp: self.timestamp,
+            pending_until: self.pending_until,
+        }
+    }
+}
+
+impl TryFrom<DepositRecord> for StoredDeposit {
+    type Error = Error;
+
+    fn try_from(record: DepositRecord) -> Result<Self, Self::Error> {
+        let withdrawal = match record.kind.as_str() {
+            "deposit" => false,
+            "withdrawal" => true,
+            other => {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown type {} for tx {}",
+                    other, record.tx
+                )));
+            }
+        };
+        if !fits(record.amount) {
+            return Err(Error::AmountOutOfBounds {
+                tx_id: record.tx,
+                amount: record.amount,
+            });
+        }
+        Ok(StoredDeposit {
+            client: record.client,
+            sub: record.sub,
+            amount: funds(record.amount),
+            disputed: funds(record.disputed),
+            status: record.status.parse()?,
+            timestamp: record.timestamp,
+            pending_until: record.pending_until,
+            withdrawal,
+        })
     }
 }
 
@@ -84,18 +596,65 @@ impl From<&DepositTx> for StoredDeposit {
     fn from(tx: &DepositTx) -> Self {
         StoredDeposit {
             client: tx.client(),
-            amount: tx.amount(),
+            sub: tx.sub(),
+            amount: funds(tx.amount()),
+            disputed: Funds::ZERO,
+            status: match tx.pending_until() {
+                Some(_) => DepositStatus::Pending,
+                None => DepositStatus::Clear,
+            },
+            timestamp: tx.timestamp(),
+            pending_until: tx.pending_until(),
+            withdrawal: false,
+        }
+    }
+}
+
+impl From<&WithdrawalTx> for StoredDeposit {
+    fn from(tx: &WithdrawalTx) -> Self {
+        StoredDeposit {
+            client: tx.client(),
+            sub: tx.sub(),
+            amount: funds(tx.amount()),
+            disputed: Funds::ZERO,
             status: DepositStatus::Clear,
+            timestamp: tx.timestamp(),
+            pending_until: None,
+            withdrawal: true,
         }
     }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum DepositStatus {
+    // Funds not settled yet, held until a release
+    Pending,
     Clear,
     Disputed,
     Resolved,
     Chargedback,
+    // Administratively removed, terminal
+    Voided,
+}
+
+impl FromStr for DepositStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(DepositStatus::Pending),
+            "clear" => Ok(DepositStatus::Clear),
+            "disputed" => Ok(DepositStatus::Disputed),
+            "resolved" => Ok(DepositStatus::Resolved),
+            "chargedback" => Ok(DepositStatus::Chargedback),
+            "voided" => Ok(DepositStatus::Voided),
+            other => Err(Error::InvalidArgument(format!(
+                "unknown deposit status {}",
+                other
+            ))),
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -107,6 +666,10 @@ pub enum DepositStateError {
     CannotDisputeResolved,
     #[error("Cannot dispute a chargedback deposit")]
     CannotDisputeChargedback,
+    #[error("Cannot dispute a voided deposit")]
+    CannotDisputeVoided,
+    #[error("Cannot dispute a pending deposit")]
+    CannotDisputePending,
 
     // Resolve errors
     #[error("Cannot resolve an undisputed deposit")]
@@ -115,6 +678,8 @@ pub enum DepositStateError {
     AlreadyResolved,
     #[error("Cannot resolve a chargedback deposit")]
     CannotResolveChargedback,
+    #[error("Cannot resolve a voided deposit")]
+    CannotResolveVoided,
 
     // Chargeback errors
     #[error("Cannot chargeback an undisputed deposit")]
@@ -123,9 +688,50 @@ pub enum DepositStateError {
     CannotChargebackResolved,
     #[error("Deposit has already been chargedback")]
     AlreadyChargedback,
+    #[error("Cannot chargeback a voided deposit")]
+    CannotChargebackVoided,
+
+    // Void errors
+    #[error("Cannot void a deposit under dispute")]
+    CannotVoidDisputed,
+    #[error("Cannot void a chargedback deposit")]
+    CannotVoidChargedback,
+    #[error("Deposit has already been voided")]
+    AlreadyVoided,
+    #[error("Cannot void a pending deposit")]
+    CannotVoidPending,
+
+    // Chargeback reversal errors
+    #[error("Cannot reverse a chargeback on a deposit that is not chargedback")]
+    CannotReverseNotChargedback,
+
+    // Correction errors
+    #[error("Cannot correct a deposit under dispute")]
+    CannotCorrectDisputed,
+    #[error("Cannot correct a chargedback deposit")]
+    CannotCorrectChargedback,
+    #[error("Cannot correct a voided deposit")]
+    CannotCorrectVoided,
+    #[error("Cannot correct a pending deposit")]
+    CannotCorrectPending,
+
+    // Release errors
+    #[error("Deposit is not pending")]
+    NotPending,
 }
 
 impl DepositStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DepositStatus::Pending => "pending",
+            DepositStatus::Clear => "clear",
+            DepositStatus::Disputed => "disputed",
+            DepositStatus::Resolved => "resolved",
+            DepositStatus::Chargedback => "chargedback",
+            DepositStatus::Voided => "voided",
+        }
+    }
+
     fn dispute(&mut self) -> Result<(), DepositStateError> {
         match self {
             DepositStatus::Clear => {
@@ -135,6 +741,8 @@ impl DepositStatus {
             DepositStatus::Disputed => Err(DepositStateError::AlreadyDisputed),
             DepositStatus::Resolved => Err(DepositStateError::CannotDisputeResolved),
             DepositStatus::Chargedback => Err(DepositStateError::CannotDisputeChargedback),
+            DepositStatus::Voided => Err(DepositStateError::CannotDisputeVoided),
+            DepositStatus::Pending => Err(DepositStateError::CannotDisputePending),
         }
     }
 
@@ -144,9 +752,12 @@ impl DepositStatus {
                 *self = DepositStatus::Resolved;
                 Ok(())
             }
-            DepositStatus::Clear => Err(DepositStateError::CannotResolveUndisputed),
+            DepositStatus::Clear | DepositStatus::Pending => {
+                Err(DepositStateError::CannotResolveUndisputed)
+            }
             DepositStatus::Resolved => Err(DepositStateError::AlreadyResolved),
             DepositStatus::Chargedback => Err(DepositStateError::CannotResolveChargedback),
+            DepositStatus::Voided => Err(DepositStateError::CannotResolveVoided),
         }
     }
 
@@ -156,9 +767,60 @@ impl DepositStatus {
                 *self = DepositStatus::Chargedback;
                 Ok(())
             }
-            DepositStatus::Clear => Err(DepositStateError::CannotChargebackUndisputed),
+            DepositStatus::Clear | DepositStatus::Pending => {
+                Err(DepositStateError::CannotChargebackUndisputed)
+            }
             DepositStatus::Resolved => Err(DepositStateError::CannotChargebackResolved),
             DepositStatus::Chargedback => Err(DepositStateError::AlreadyChargedback),
+            DepositStatus::Voided => Err(DepositStateError::CannotChargebackVoided),
+        }
+    }
+
+    fn void(&mut self) -> Result<(), DepositStateError> {
+        match self {
+            DepositStatus::Clear | DepositStatus::Resolved => {
+                *self = DepositStatus::Voided;
+                Ok(())
+            }
+            DepositStatus::Disputed => Err(DepositStateError::CannotVoidDisputed),
+            DepositStatus::Chargedback => Err(DepositStateError::CannotVoidChargedback),
+            DepositStatus::Voided => Err(DepositStateError::AlreadyVoided),
+            DepositStatus::Pending => Err(DepositStateError::CannotVoidPending),
+        }
+    }
+
+    fn reverse_chargeback(&mut self) -> Result<(), DepositStateError> {
+        match self {
+            DepositStatus::Chargedback => {
+                *self = DepositStatus::Resolved;
+                Ok(())
+            }
+            DepositStatus::Pending
+            | DepositStatus::Clear
+            | DepositStatus::Disputed
+            | DepositStatus::Resolved
+            | DepositStatus::Voided => Err(DepositStateError::CannotReverseNotChargedback),
+        }
+    }
+
+    // Not a transition, the status stays as it is
+    fn check_correction(self) -> Result<(), DepositStateError> {
+        match self {
+            DepositStatus::Clear | DepositStatus::Resolved => Ok(()),
+            DepositStatus::Disputed => Err(DepositStateError::CannotCorrectDisputed),
+            DepositStatus::Chargedback => Err(DepositStateError::CannotCorrectChargedback),
+            DepositStatus::Voided => Err(DepositStateError::CannotCorrectVoided),
+            DepositStatus::Pending => Err(DepositStateError::CannotCorrectPending),
+        }
+    }
+
+    fn release(&mut self) -> Result<(), DepositStateError> {
+        match self {
+            DepositStatus::Pending => {
+                *self = DepositStatus::Clear;
+                Ok(())
+            }
+            _ => Err(DepositStateError::NotPending),
         }
     }
 }
@@ -169,15 +831,40 @@ mod tests {
 
     use super::*;
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn stored_deposit_round_trips() {
+        let mut deposits = HashMap::new();
+        let mut deposit = StoredDeposit::from(&DepositTx::new(3, 11, Decimal::new(25, 1)));
+        deposit.set_disputed(Decimal::ONE).unwrap();
+        deposits.insert(11, deposit);
+
+        let json = serde_json::to_string(&deposits).unwrap();
+        let restored: HashMap<u32, StoredDeposit> = serde_json::from_str(&json).unwrap();
+
+        let deposit = &restored[&11];
+        assert_eq!(deposit.client(), 3);
+        assert_eq!(deposit.amount(), Decimal::new(25, 1));
+        assert_eq!(deposit.disputed_amount(), Decimal::ONE);
+        assert_eq!(deposit.status(), DepositStatus::Disputed);
+        assert!(!deposit.is_withdrawal());
+    }
+
     #[test]
     fn client_mismatch_rejected() {
         let deposit = StoredDeposit {
             client: 1,
-            amount: Decimal::new(100, 0),
+            sub: 0,
+            amount: funds(Decimal::new(100, 0)),
+            disputed: Funds::ZERO,
             status: DepositStatus::Clear,
+            timestamp: None,
+            pending_until: None,
+            withdrawal: false,
         };
 
-        let result = deposit.ensure_client_matches(42, 2); // tx 42, wrong client 2
+        let result = deposit.ensure_account_matches(42, AccountKey::from(2)); // tx 42, wrong client 2
+        let other_sub = deposit.ensure_account_matches(42, AccountKey::new(1, 3));
 
         assert!(matches!(
             result,
@@ -187,5 +874,160 @@ mod tests {
                 found: 2
             })
         ));
+        assert!(matches!(
+            other_sub,
+            Err(crate::error::Error::SubAccountMismatch {
+                tx_id: 42,
+                expected: 0,
+                found: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn evicts_oldest_undisputed_first() {
+        let key = |tx| DepositKey::new(1, tx);
+        let mut store = OrderedDeposits::default();
+        for id in 1..=4 {
+            store.insert(&DepositTx::new(1, id, Decimal::new(10, 0)));
+        }
+        store
+            .get_mut(key(1))
+            .unwrap()
+            .set_disputed(Decimal::ZERO)
+            .unwrap();
+        DepositStore::remove(&mut store, key(2));
+
+        assert_eq!(store.evict_oldest(2), vec![key(3)]);
+        // The disputed deposit outlives newer ones, and stays first in line
+        assert!(DepositStore::get(&store, key(1)).is_some());
+        assert!(DepositStore::get(&store, key(3)).is_none());
+        assert_eq!(store.evict_oldest(1), vec![key(4)]);
+        assert!(DepositStore::get(&store, key(4)).is_none());
+    }
+
+    #[test]
+    fn reused_ids_are_stored_apart() {
+        let mut store: HashMap<DepositKey, StoredDeposit> = HashMap::new();
+        DepositStore::insert(&mut store, &DepositTx::new(1, 5, Decimal::ONE));
+        DepositStore::insert(&mut store, &DepositTx::new(2, 5, Decimal::TWO));
+
+        let amount = |client| {
+            DepositStore::get(&store, DepositKey::new(client, 5))
+                .unwrap()
+                .amount()
+        };
+        assert_eq!(amount(1), Decimal::ONE);
+        assert_eq!(amount(2), Decimal::TWO);
+        assert!(DepositStore::get(&store, DepositKey::new(3, 5)).is_none());
+    }
+
+    #[test]
+    fn withdrawals_are_not_deposits() {
+        let key = DepositKey::new(1, 7);
+        let mut store: HashMap<DepositKey, StoredDeposit> = HashMap::new();
+        store.insert_withdrawal(&WithdrawalTx::new(1, 7, Decimal::new(30, 0)));
+
+        assert!(DepositStore::get_mut(&mut store, key).is_none());
+        assert!(DepositStore::remove(&mut store, key).is_none());
+
+        let withdrawal = store.get_withdrawal_mut(key).unwrap();
+        assert!(withdrawal.check_correction(7, Decimal::new(-30, 0)).is_ok());
+        assert!(matches!(
+            withdrawal.check_correction(7, Decimal::new(-31, 0)),
+            Err(Error::CorrectionExceedsOriginal { tx_id: 7, .. })
+        ));
+    }
+
+    #[test]
+    fn voided_deposit_is_terminal() {
+        let mut status = DepositStatus::Resolved;
+
+        status.void().unwrap();
+
+        assert!(matches!(
+            status.dispute(),
+            Err(DepositStateError::CannotDisputeVoided)
+        ));
+        assert!(matches!(
+            status.void(),
+            Err(DepositStateError::AlreadyVoided)
+        ));
+        assert!(matches!(
+            DepositStatus::Disputed.void(),
+            Err(DepositStateError::CannotVoidDisputed)
+        ));
+    }
+
+    #[test]
+    fn pending_deposit_is_released_once_its_hold_is_over() {
+        let tx = DepositTx::new(1, 9, Decimal::TEN).with_pending_until(Some(100));
+        let mut deposit = StoredDeposit::from(&tx);
+
+        assert_eq!(deposit.held(), Decimal::TEN);
+        assert!(!deposit.is_due(99) && deposit.is_due(100));
+        assert!(matches!(
+            deposit.set_disputed(Decimal::TEN),
+            Err(DepositStateError::CannotDisputePending)
+        ));
+        assert!(matches!(
+            deposit.set_released(9, Some(99)),
+            Err(Error::StillPending {
+                tx_id: 9,
+                until: 100
+            })
+        ));
+
+        deposit.set_released(9, Some(100)).unwrap();
+        assert_eq!(deposit.status(), DepositStatus::Clear);
+        assert_eq!(deposit.held(), Decimal::ZERO);
+        assert!(matches!(
+            deposit.set_released(9, None),
+            Err(Error::DepositState(DepositStateError::NotPending))
+        ));
+    }
+
+    #[test]
+    fn reversed_chargeback_is_resolved_once() {
+        let mut status = DepositStatus::Chargedback;
+
+        status.reverse_chargeback().unwrap();
+
+        assert_eq!(status, DepositStatus::Resolved);
+        assert!(matches!(
+            status.reverse_chargeback(),
+            Err(DepositStateError::CannotReverseNotChargedback)
+        ));
+    }
+
+    #[test]
+    fn eviction_keeps_disputed_and_untimestamped_deposits() {
+        let key = |tx| DepositKey::new(1, tx);
+        let mut store: HashMap<DepositKey, StoredDeposit> = HashMap::new();
+        let amount = Decimal::new(10, 0);
+        DepositStore::insert(
+            &mut store,
+            &DepositTx::new(1, 1, amount).with_timestamp(Some(5)),
+        );
+        DepositStore::insert(
+            &mut store,
+            &DepositTx::new(1, 2, amount).with_timestamp(Some(5)),
+        );
+        DepositStore::insert(&mut store, &DepositTx::new(1, 3, amount));
+        DepositStore::insert(
+            &mut store,
+            &DepositTx::new(1, 4, amount).with_timestamp(Some(50)),
+        );
+        store
+            .get_mut(&key(2))
+            .unwrap()
+            .set_disputed(amount)
+            .unwrap();
+
+        let evicted = store.evict_before(10);
+
+        assert_eq!(evicted, vec![key(1)]);
+        assert!(!store.contains_key(&key(1)));
+        assert!((2..=4).all(|tx| store.contains_key(&key(tx))));
     }
 }
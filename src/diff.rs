@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Write};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::account::{Account, AccountKey, AccountMap};
+use crate::error::Error;
+
+// An account row as written by a run, whatever its precision or compat layout. Extra columns
+// such as `overdraft` are ignored. Balances are read as text so they keep their scale.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+struct SnapshotRow {
+    client: u16,
+    #[serde(default)]
+    sub: u16,
+    #[serde(with = "rust_decimal::serde::str")]
+    available: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    held: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    total: Decimal,
+    locked: bool,
+}
+
+// How an account changed between the two snapshots, beyond its balances
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    // Only in the new snapshot
+    New,
+    // Only in the old snapshot
+    Removed,
+    Locked,
+    Unlocked,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Change::New => "new",
+            Change::Removed => "removed",
+            Change::Locked => "locked",
+            Change::Unlocked => "unlocked",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AccountDelta {
+    // Written as `1`, or `1/2` for a sub-account
+    #[serde(rename = "client", serialize_with = "serialize_key")]
+    pub account: AccountKey,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub change: Option<Change>,
+}
+
+impl From<&Account> for SnapshotRow {
+    fn from(account: &Account) -> Self {
+        Self {
+            client: account.client(),
+            sub: account.sub(),
+            available: account.available(),
+            held: account.held(),
+            total: account.total(),
+            locked: account.locked(),
+        }
+    }
+}
+
+impl fmt::Display for AccountDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "client {}: available {}, held {}, total {}",
+            self.account, self.available, self.held, self.total
+        )?;
+        match self.change {
+            Some(change) => write!(f, " ({})", change),
+            None => Ok(()),
+        }
+    }
+}
+
+fn serialize_key<S: serde::Serializer>(key: &AccountKey, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(key)
+}
+
+fn read_snapshot(reader: impl Read) -> Result<BTreeMap<AccountKey, SnapshotRow>, Error> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut accounts = BTreeMap::new();
+    for row in rdr.deserialize() {
+        let row: SnapshotRow = row?;
+        accounts.insert(AccountKey::new(row.client, row.sub), row);
+    }
+    Ok(accounts)
+}
+
+// Per-account differences from `old` to `new` account outputs, in client and sub-account
+// order. Accounts whose balances and lock are unchanged are left out. An account missing from
+// one side counts as a zero, unlocked account there.
+pub fn diff(old: impl Read, new: impl Read) -> Result<Vec<AccountDelta>, Error> {
+    Ok(deltas(&read_snapshot(old)?, &read_snapshot(new)?))
+}
+
+// Like `diff`, between two in-memory results
+pub fn compare(old: &AccountMap, new: &AccountMap) -> Vec<AccountDelta> {
+    let snapshot = |accounts: &AccountMap| {
+        accounts
+            .iter()
+            .map(|account| (account.key(), SnapshotRow::from(account)))
+            .collect()
+    };
+    deltas(&snapshot(old), &snapshot(new))
+}
+
+fn deltas(
+    old: &BTreeMap<AccountKey, SnapshotRow>,
+    new: &BTreeMap<AccountKey, SnapshotRow>,
+) -> Vec<AccountDelta> {
+    let mut keys: Vec<AccountKey> = old.keys().chain(new.keys()).copied().collect();
+    keys.sort_unstable();
+    keys.dedup();
+
+    let mut deltas = Vec::new();
+    for account in keys {
+        let (before, after) = (old.get(&account), new.get(&account));
+        let change = match (before, after) {
+            (None, _) => Some(Change::New),
+            (_, None) => Some(Change::Removed),
+            (Some(b), Some(a)) if a.locked && !b.locked => Some(Change::Locked),
+            (Some(b), Some(a)) if b.locked && !a.locked => Some(Change::Unlocked),
+            _ => None,
+        };
+        let (before, after) = (
+            before.copied().unwrap_or_default(),
+            after.copied().unwrap_or_default(),
+        );
+        // Padded to the finer of the two rows, so a column reads alike
+        let scale = [before, after]
+            .iter()
+            .flat_map(|row| [row.available, row.held, row.total])
+            .map(|value| value.scale())
+            .max()
+            .unwrap_or(0);
+        let padded = |mut value: Decimal| {
+            value.rescale(scale);
+            value
+        };
+        let delta = AccountDelta {
+            account,
+            available: padded(after.available - before.available),
+            held: padded(after.held - before.held),
+            total: padded(after.total - before.total),
+            change,
+        };
+        if change.is_some()
+            || !(delta.available.is_zero() && delta.held.is_zero() && delta.total.is_zero())
+        {
+            deltas.push(delta);
+        }
+    }
+    deltas
+}
+
+// Writes the deltas as CSV: client,available,held,total,change
+pub fn write(sink: impl Write, deltas: &[AccountDelta]) -> Result<(), Error> {
+    let mut wtr = csv::Writer::from_writer(sink);
+    if deltas.is_empty() {
+        wtr.write_record(["client", "available", "held", "total", "change"])?;
+    }
+    for delta in deltas {
+        wtr.serialize(delta)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_changed_new_removed_and_locked() {
+        let old = "client,available,held,total,locked
+1,10.0000,0.0000,10.0000,false
+2,5.0000,0.0000,5.0000,false
+3,7.0000,0.0000,7.0000,false
+4,1.0000,0.0000,1.0000,false";
+        let new = "client,available,held,total,locked,overdraft
+1,10.0000,0.0000,10.0000,false,false
+2,0.0000,0.0000,0.0000,true,false
+4,-1.5,2,0.5,false,true
+5,3,0,3,false,false";
+
+        let deltas = diff(old.as_bytes(), new.as_bytes()).unwrap();
+        let mut out = Vec::new();
+        write(&mut out, &deltas).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,change
+2,-5.0000,0.0000,-5.0000,locked
+3,-7.0000,0.0000,-7.0000,removed
+4,-2.5000,2.0000,-0.5000,
+5,3,0,3,new
+"
+        );
+    }
+}
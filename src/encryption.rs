@@ -0,0 +1,152 @@
+use std::cell::RefCell;
+use std::io::{self, BufRead, Read, Write};
+use std::rc::Rc;
+
+use age::stream::StreamWriter;
+
+use crate::account::AccountOutput;
+use crate::error::Error;
+use crate::output::OutputSink;
+
+type Stream = Rc<RefCell<Option<StreamWriter<Box<dyn Write>>>>>;
+
+// An output encrypted with age to an X25519 recipient (`age1...`, as `age-keygen` prints it)
+// while it is written, so account dumps left on shared storage are unreadable without the
+// matching identity. The plaintext never touches the destination. `decrypt` reverses it, and
+// so does the `age` CLI.
+pub struct EncryptedSink {
+    inner: Box<dyn OutputSink>,
+    stream: Stream,
+}
+
+impl EncryptedSink {
+    // `format` builds the sink for the plaintext (CSV, JSON, ...) over the writer it is given
+    pub fn new(
+        out: impl Write + 'static,
+        recipient: &str,
+        format: impl FnOnce(Plaintext) -> Result<Box<dyn OutputSink>, Error>,
+    ) -> Result<Self, Error> {
+        let recipient = parse_recipient(recipient)?;
+        let encryptor =
+            age::Encryptor::with_recipients(std::iter::once(&recipient as &dyn age::Recipient))
+                .map_err(|e| Error::Encryption(e.to_string()))?;
+        let out: Box<dyn Write> = Box::new(out);
+        let stream = Rc::new(RefCell::new(Some(encryptor.wrap_output(out)?)));
+        let inner = format(Plaintext(stream.clone()))?;
+        Ok(Self { inner, stream })
+    }
+}
+
+impl OutputSink for EncryptedSink {
+    fn write_account(&mut self, account: &AccountOutput) -> Result<(), Error> {
+        self.inner.write_account(account)
+    }
+
+    // Without the final chunk age writes here, the output would not decrypt
+    fn finish(&mut self) -> Result<(), Error> {
+        self.inner.finish()?;
+        if let Some(stream) = self.stream.borrow_mut().take() {
+            stream.finish()?.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// An X25519 recipient as `age-keygen` prints it
+pub fn parse_recipient(recipient: &str) -> Result<age::x25519::Recipient, Error> {
+    recipient
+        .parse()
+        .map_err(|e| Error::InvalidArgument(format!("invalid age recipient {}: {}", recipient, e)))
+}
+
+// What the sink inside an `EncryptedSink` writes to
+pub struct Plaintext(Stream);
+
+impl Write for Plaintext {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0.borrow_mut().as_mut() {
+            Some(stream) => stream.write(buf),
+            None => Err(io::Error::other("encrypted output already finished")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.0.borrow_mut().as_mut() {
+            Some(stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// Writes the plaintext of an age file to `out`, with any identity of `identities`, an identity
+// file as `age-keygen` writes it
+pub fn decrypt(
+    identities: impl BufRead,
+    input: impl Read,
+    mut out: impl Write,
+) -> Result<(), Error> {
+    let identities = age::IdentityFile::from_buffer(identities)?
+        .into_identities()
+        .map_err(|e| Error::Encryption(e.to_string()))?;
+    let mut plaintext = age::Decryptor::new(input)
+        .and_then(|decryptor| {
+            decryptor.decrypt(identities.iter().map(|identity| identity.as_ref()))
+        })
+        .map_err(|e| Error::Encryption(e.to_string()))?;
+    io::copy(&mut plaintext, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use age::secrecy::ExposeSecret;
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::account::Account;
+    use crate::output::CsvSink;
+
+    #[test]
+    fn encrypted_output_decrypts_with_the_identity() {
+        let identity = age::x25519::Identity::generate();
+        let recipient = identity.to_public().to_string();
+        let path = std::env::temp_dir().join(format!("toy-processor-{}.age", std::process::id()));
+
+        let mut account = Account::new(1);
+        account.deposit(Decimal::TEN).unwrap();
+        let mut sink = EncryptedSink::new(File::create(&path).unwrap(), &recipient, |out| {
+            Ok(Box::new(CsvSink::new(out)))
+        })
+        .unwrap();
+        sink.write_account(&AccountOutput::from(account)).unwrap();
+        sink.finish().unwrap();
+
+        let encrypted = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(!encrypted.windows(7).any(|w| w == b"10.0000"));
+        let mut plaintext = Vec::new();
+        let key = identity.to_string();
+        decrypt(
+            key.expose_secret().as_bytes(),
+            &encrypted[..],
+            &mut plaintext,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(plaintext).unwrap(),
+            "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n"
+        );
+
+        let other = age::x25519::Identity::generate().to_string();
+        let mut ignored = Vec::new();
+        let wrong = decrypt(
+            other.expose_secret().as_bytes(),
+            &encrypted[..],
+            &mut ignored,
+        );
+        assert!(matches!(wrong, Err(Error::Encryption(_))));
+    }
+}
@@ -8,12 +8,42 @@ pub enum Error {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
-    #[error("Usage: cargo run -- <transactions.csv>")]
+    #[error("Usage: cargo run -- [options] <transactions.csv>")]
     MissingArgument,
 
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    #[error("Canary run failed: {0}")]
+    CanaryFailed(String),
+
+    #[error("Non-deterministic result: {0}")]
+    Nondeterministic(String),
+
+    #[error("Invariants violated: {0}")]
+    InvariantsViolated(String),
+
+    #[error("Validation failed: {0}")]
+    ValidationFailed(String),
+
+    #[error("Benchmark regressed: {0}")]
+    BenchRegressed(String),
+
+    #[error("Corrupt WAL: {0}")]
+    CorruptWal(String),
+
+    #[error("Unusable seen store: {0}")]
+    SeenStore(String),
+
     #[error("Account {0} is locked")]
     AccountLocked(u16),
 
+    #[error("Account {0} is frozen")]
+    AccountFrozen(u16),
+
+    #[error("Account {0} is closed")]
+    AccountClosed(u16),
+
     #[error("Account {0} not found")]
     AccountNotFound(u16),
 
@@ -31,12 +61,140 @@ pub enum Error {
         found: u16,
     },
 
+    #[error("Sub-account mismatch for transaction {tx_id}: expected {expected}, found {found}")]
+    SubAccountMismatch {
+        tx_id: u32,
+        expected: u16,
+        found: u16,
+    },
+
     #[error("Stored deposit {0} not found")]
     StoredDepositNotFound(u32),
 
     #[error("Deposit state error: {0}")]
     DepositState(#[from] DepositStateError),
 
+    #[error("Dispute on deposit {tx_id} exceeds it: deposit {deposit}, requested {requested}")]
+    DisputeExceedsDeposit {
+        tx_id: u32,
+        deposit: rust_decimal::Decimal,
+        requested: rust_decimal::Decimal,
+    },
+
+    #[error("Client {0} was produced by more than one shard")]
+    ShardCollision(u16),
+
+    #[error("Stored withdrawal {0} not found")]
+    StoredWithdrawalNotFound(u32),
+
+    #[error("Correction of {correction} on transaction {tx_id} exceeds its amount {original}")]
+    CorrectionExceedsOriginal {
+        tx_id: u32,
+        original: rust_decimal::Decimal,
+        correction: rust_decimal::Decimal,
+    },
+
+    #[error("Amount {amount} of transaction {tx_id} is outside the configured limits")]
+    AmountOutOfBounds {
+        tx_id: u32,
+        amount: rust_decimal::Decimal,
+    },
+
+    #[error("Balance of account {0} would overflow")]
+    BalanceOverflow(u16),
+
+    #[error("Transaction {tx_id} reuses the id of the row on line {first_line} with other data")]
+    ConflictingDuplicate { tx_id: u32, first_line: u64 },
+
+    #[error("Dispute window expired for deposit {0}")]
+    DisputeWindowExpired(u32),
+
+    #[error("Deposit {0} was evicted once settled")]
+    DepositEvicted(u32),
+
+    #[error("Deposit {tx_id} is pending until {until}")]
+    StillPending { tx_id: u32, until: u64 },
+
+    #[error(
+        "Transaction {tx_id} of client {client} is out of order: timestamp {timestamp} after {latest}"
+    )]
+    OutOfOrder {
+        tx_id: u32,
+        client: u16,
+        timestamp: u64,
+        latest: u64,
+    },
+
+    #[cfg(feature = "scripting")]
+    #[error("Script error: {0}")]
+    Script(String),
+
+    #[cfg(feature = "wasm-plugins")]
+    #[error("Plugin error: {0}")]
+    Plugin(String),
+
+    #[cfg(feature = "s3")]
+    #[error("S3 error: {0}")]
+    S3(String),
+
+    #[cfg(feature = "encryption")]
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+
+    #[cfg(feature = "redis")]
+    #[error("Redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+
+    #[cfg(feature = "arrow")]
+    #[error("Arrow error: {0}")]
+    Arrow(#[from] arrow_schema::ArrowError),
+
+    #[cfg(feature = "grpc")]
+    #[error("gRPC error: {0}")]
+    Grpc(#[from] tonic::transport::Error),
+
     #[error("Invalid transaction row: {0}")]
     InvalidTransactionRow(u32),
+
+    #[error("Line {line}: {source}")]
+    RowFailed { line: u64, source: Box<Error> },
+}
+
+impl Error {
+    // Short stable name for why a row was rejected, used to group rejects in reports
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Error::Csv(_) => "parse_error",
+            Error::InvalidTransactionRow(_) => "invalid_row",
+            Error::AccountLocked(_) => "account_locked",
+            Error::AccountFrozen(_) => "account_frozen",
+            Error::AccountClosed(_) => "account_closed",
+            Error::InsufficientFunds { .. } => "insufficient_funds",
+            Error::ClientMismatch { .. } => "client_mismatch",
+            Error::SubAccountMismatch { .. } => "sub_account_mismatch",
+            Error::AccountNotFound(_)
+            | Error::StoredDepositNotFound(_)
+            | Error::StoredWithdrawalNotFound(_) => "unknown_tx",
+            Error::DepositState(_) => "invalid_state",
+            Error::DisputeExceedsDeposit { .. } => "dispute_exceeds_deposit",
+            Error::CorrectionExceedsOriginal { .. } => "correction_exceeds_original",
+            Error::AmountOutOfBounds { .. } => "amount_out_of_bounds",
+            Error::BalanceOverflow(_) => "balance_overflow",
+            Error::ConflictingDuplicate { .. } => "conflicting_duplicate",
+            Error::DisputeWindowExpired(_) => "dispute_window_expired",
+            Error::DepositEvicted(_) => "deposit_evicted",
+            Error::StillPending { .. } => "still_pending",
+            Error::OutOfOrder { .. } => "out_of_order",
+            #[cfg(feature = "scripting")]
+            Error::Script(_) => "rule_error",
+            #[cfg(feature = "wasm-plugins")]
+            Error::Plugin(_) => "rule_error",
+            Error::RowFailed { source, .. } => source.reason(),
+            _ => "other",
+        }
+    }
 }
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use log::{Level, error, log_enabled};
+
+// Rejected rows logged per kind before the rest are only counted
+pub const DEFAULT_ERROR_LOG_LIMIT: u64 = 100;
+
+// Keeps a pathological input from flooding the log: each kind of row error (`Error::reason`)
+// is logged in full for its first `limit` occurrences. After that a summary line is logged
+// whenever the count reaches a power of ten, so a million bad rows cost a handful of lines.
+// Shared by the reader and the workers; the lock is only taken on the error path, and not at
+// all when error logging is off.
+#[derive(Debug)]
+pub struct ErrorLog {
+    limit: u64,
+    counts: Mutex<HashMap<&'static str, u64>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Verdict {
+    Log,
+    // The first suppressed occurrence
+    Suppress,
+    Summary(u64),
+    Skip,
+}
+
+impl Default for ErrorLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_ERROR_LOG_LIMIT)
+    }
+}
+
+impl ErrorLog {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            counts: Mutex::default(),
+        }
+    }
+
+    // Logs `message` at error level, unless `kind` is over the limit
+    pub fn error(&self, kind: &'static str, message: fmt::Arguments<'_>) {
+        if !log_enabled!(Level::Error) {
+            return;
+        }
+        match self.admit(kind) {
+            Verdict::Log => error!("{}", message),
+            Verdict::Suppress => error!(
+                "{} (further {} errors are counted, not logged)",
+                message, kind
+            ),
+            Verdict::Summary(count) => error!("{} {} errors so far", count, kind),
+            Verdict::Skip => {}
+        }
+    }
+
+    fn admit(&self, kind: &'static str) -> Verdict {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(kind).or_default();
+        *count += 1;
+        match *count {
+            n if n <= self.limit => Verdict::Log,
+            n if n == self.limit + 1 => Verdict::Suppress,
+            n if is_power_of_ten(n) => Verdict::Summary(n),
+            _ => Verdict::Skip,
+        }
+    }
+
+    // One line per kind that went over the limit, with its final count
+    pub fn finish(&self) {
+        let counts = self.counts.lock().unwrap();
+        let mut over: Vec<_> = counts
+            .iter()
+            .filter(|(_, count)| **count > self.limit)
+            .collect();
+        over.sort();
+        for (kind, count) in over {
+            error!(
+                "{} {} errors in total, {} not logged",
+                count,
+                kind,
+                count - self.limit
+            );
+        }
+    }
+}
+
+fn is_power_of_ten(mut n: u64) -> bool {
+    while n >= 10 && n.is_multiple_of(10) {
+        n /= 10;
+    }
+    n == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logs_the_first_then_powers_of_ten() {
+        let log = ErrorLog::new(2);
+        let verdicts: Vec<_> = (0..1_000).map(|_| log.admit("parse_error")).collect();
+
+        assert_eq!(
+            verdicts[..3],
+            [Verdict::Log, Verdict::Log, Verdict::Suppress]
+        );
+        let summaries: Vec<_> = verdicts
+            .iter()
+            .filter_map(|v| match v {
+                Verdict::Summary(n) => Some(*n),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(summaries, [10, 100, 1_000]);
+        // Kinds are limited separately
+        assert_eq!(log.admit("insufficient_funds"), Verdict::Log);
+    }
+}
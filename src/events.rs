@@ -0,0 +1,22 @@
+use crate::error::Error;
+use crate::transactions::{Transaction, TransactionRow};
+
+// Callbacks for embedders, registered with `ProcessorBuilder::event_handler`, to emit their own
+// metrics, notifications or side effects as the run goes. Every method does nothing by default.
+//
+// Called on the thread that applies the client's rows, so a client's events come in the order
+// its rows were applied, while the events of clients on different workers interleave. A
+// handler holds up that worker for as long as it runs; anything slow belongs on a thread of
+// its own. Rows the reader refuses before routing (out of order, conflicting tx ids) are
+// reported from the reader's thread, and rows that never parsed, rule rejections and
+// duplicates not at all.
+pub trait EventHandler: Send + Sync {
+    // A transaction was applied to its account
+    fn on_applied(&self, _transaction: &Transaction) {}
+
+    // A row was refused with `error`, as counted in the metrics' rejections
+    fn on_rejected(&self, _row: &TransactionRow, _error: &Error) {}
+
+    // A chargeback locked `client`'s account, or one of its sub-accounts
+    fn on_account_locked(&self, _client: u16) {}
+}
@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use rust_decimal::Decimal;
+
+// Deposits remembered as dispute targets. Older ones age out, which keeps memory flat for
+// huge outputs and mirrors real traffic where disputes follow their deposit fairly soon.
+const RECENT_DEPOSITS: usize = 10_000;
+const CHARGEBACK_SHARE: f64 = 0.2;
+const WITHDRAWAL_SHARE: f64 = 0.25;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GenerateConfig {
+    pub rows: u64,
+    pub clients: u16,
+    // Share of rows that open a dispute; about as many again resolve or charge one back
+    pub dispute_rate: f64,
+    pub malformed_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for GenerateConfig {
+    fn default() -> Self {
+        Self {
+            rows: 1_000,
+            clients: 100,
+            dispute_rate: 0.01,
+            malformed_rate: 0.001,
+            seed: 42,
+        }
+    }
+}
+
+// xorshift64*, plenty for test data and keeps output stable across platforms and releases
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift
+        Self(seed.max(1))
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    pub(crate) fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+// Writes `config.rows` data rows of plausible traffic: deposits and withdrawals from
+// `config.clients` clients, disputes against recent deposits of the same client that are
+// later resolved or charged back, and a sprinkling of malformed rows.
+pub fn generate(config: &GenerateConfig, out: impl Write) -> io::Result<()> {
+    let mut out = io::BufWriter::new(out);
+    let mut rng = Rng::new(config.seed);
+    let mut recent: VecDeque<(u16, u32)> = VecDeque::with_capacity(RECENT_DEPOSITS);
+    let mut disputed: Vec<(u16, u32)> = Vec::new();
+    let mut next_tx: u32 = 1;
+    let clients = u64::from(config.clients.max(1));
+
+    writeln!(out, "type,client,tx,amount")?;
+    for _ in 0..config.rows {
+        if rng.chance(config.malformed_rate) {
+            write_malformed(&mut rng, &mut out)?;
+            continue;
+        }
+
+        if !disputed.is_empty() && rng.chance(config.dispute_rate) {
+            let (client, tx) = disputed.swap_remove(rng.below(disputed.len() as u64) as usize);
+            let kind = if rng.chance(CHARGEBACK_SHARE) {
+                "chargeback"
+            } else {
+                "resolve"
+            };
+            writeln!(out, "{},{},{},", kind, client, tx)?;
+            continue;
+        }
+
+        if !recent.is_empty() && rng.chance(config.dispute_rate) {
+            let idx = rng.below(recent.len() as u64) as usize;
+            let (client, tx) = recent.remove(idx).unwrap();
+            writeln!(out, "dispute,{},{},", client, tx)?;
+            disputed.push((client, tx));
+            continue;
+        }
+
+        let client = rng.below(clients) as u16 + 1;
+        let tx = next_tx;
+        next_tx = next_tx.wrapping_add(1);
+        let amount = amount(&mut rng);
+        if rng.chance(WITHDRAWAL_SHARE) {
+            writeln!(out, "withdrawal,{},{},{}", client, tx, amount)?;
+        } else {
+            writeln!(out, "deposit,{},{},{}", client, tx, amount)?;
+            if recent.len() == RECENT_DEPOSITS {
+                recent.pop_front();
+            }
+            recent.push_back((client, tx));
+        }
+    }
+    out.flush()
+}
+
+// Mostly small amounts with an occasional large one, up to 4 decimal places
+fn amount(rng: &mut Rng) -> Decimal {
+    let units = if rng.chance(0.05) {
+        rng.below(100_000_000_000)
+    } else {
+        rng.below(10_000_000)
+    };
+    Decimal::new(units as i64 + 1, 4).normalize()
+}
+
+fn write_malformed(rng: &mut Rng, out: &mut impl Write) -> io::Result<()> {
+    match rng.below(5) {
+        0 => writeln!(out, "deposit,not-a-client,1,10.0"),
+        1 => writeln!(out, "refund,1,{},5.0", rng.below(1_000)),
+        2 => writeln!(out, "deposit,1,{},-5.0", rng.below(1_000)),
+        3 => writeln!(out, "withdrawal,1,{},", rng.below(1_000)),
+        _ => writeln!(out, "deposit,1"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(config: &GenerateConfig) -> String {
+        let mut buf = Vec::new();
+        generate(config, &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn same_seed_same_output() {
+        let config = GenerateConfig::default();
+
+        assert_eq!(output(&config), output(&config));
+        assert_ne!(
+            output(&config),
+            output(&GenerateConfig { seed: 7, ..config })
+        );
+    }
+
+    #[test]
+    fn emits_requested_rows_and_disputes_reference_deposits() {
+        let config = GenerateConfig {
+            rows: 5_000,
+            clients: 10,
+            dispute_rate: 0.05,
+            malformed_rate: 0.0,
+            seed: 1,
+        };
+        let text = output(&config);
+        let mut lines = text.lines();
+
+        assert_eq!(lines.next(), Some("type,client,tx,amount"));
+        let rows: Vec<Vec<&str>> = lines.map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 5_000);
+
+        let deposits: std::collections::HashMap<&str, &str> = rows
+            .iter()
+            .filter(|r| r[0] == "deposit")
+            .map(|r| (r[2], r[1]))
+            .collect();
+        let disputes: Vec<_> = rows.iter().filter(|r| r[0] == "dispute").collect();
+        assert!(!disputes.is_empty());
+        assert!(disputes.iter().all(|r| deposits.get(r[2]) == Some(&r[1])));
+    }
+}
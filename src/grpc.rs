@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use log::{debug, info};
+use rust_decimal::Decimal;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::account::{Account, AccountFilter, AccountKey, AccountMap};
+use crate::config::Config;
+use crate::dedup::Deduplicator;
+use crate::deposit_store::{DepositKey, StoredDeposit};
+use crate::error::Error;
+use crate::transactions::{Transaction, TransactionRow};
+
+// Page size for `ListAccounts` when the request leaves it open, and the most it may ask for
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1_000;
+
+pub mod proto {
+    tonic::include_proto!("toy_processor.v1");
+}
+
+use proto::ledger_server::{Ledger, LedgerServer};
+use proto::transaction::Kind;
+
+struct LedgerState {
+    accounts: AccountMap,
+    deposits: HashMap<DepositKey, StoredDeposit>,
+    dedup: Deduplicator,
+}
+
+// One engine shared by every connection. Transactions are applied under a lock in arrival
+// order, which keeps each client's transactions ordered without sharding; interactive traffic
+// is nowhere near the volume the CSV pipeline's workers exist for.
+struct Engine {
+    config: Config,
+    state: Mutex<LedgerState>,
+}
+
+impl Engine {
+    // Same path as a CSV row: dedup, validation, then processing
+    fn apply(&self, transaction: proto::Transaction) -> proto::TransactionResult {
+        let row = match to_row(transaction) {
+            Ok(row) => row,
+            Err(e) => return rejected(0, e),
+        };
+        let tx = row.tx();
+
+        let mut state = self.state.lock().unwrap();
+        let LedgerState {
+            accounts,
+            deposits,
+            dedup,
+        } = &mut *state;
+        if row.should_dedupe() && dedup.check_and_insert(row.dedup_key(self.config.dedup_key)) {
+            return rejected(tx, "possible duplicate".to_string());
+        }
+        match Transaction::from_row(row, &self.config).and_then(|t| t.process(accounts, deposits)) {
+            Ok(()) => proto::TransactionResult {
+                tx,
+                applied: true,
+                error: String::new(),
+            },
+            Err(e) => {
+                debug!("Submitted transaction {} failed: {}", tx, e);
+                rejected(tx, e.to_string())
+            }
+        }
+    }
+}
+
+pub struct LedgerService {
+    engine: Arc<Engine>,
+}
+
+impl LedgerService {
+    pub fn new(config: Config) -> Self {
+        Self {
+            engine: Arc::new(Engine {
+                config,
+                state: Mutex::new(LedgerState {
+                    accounts: AccountMap::new(),
+                    deposits: HashMap::new(),
+                    dedup: Deduplicator::new(config.dedup),
+                }),
+            }),
+        }
+    }
+}
+
+type ResultStream = Pin<Box<dyn Stream<Item = Result<proto::TransactionResult, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Ledger for LedgerService {
+    type SubmitTransactionsStream = ResultStream;
+
+    async fn submit_transactions(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<Self::SubmitTransactionsStream>, Status> {
+        let engine = self.engine.clone();
+        let results = request
+            .into_inner()
+            .map(move |transaction| transaction.map(|t| engine.apply(t)));
+        Ok(Response::new(Box::pin(results)))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let client = request.into_inner().client;
+        let id = u16::try_from(client)
+            .map_err(|_| Status::invalid_argument(format!("client {} out of range", client)))?;
+
+        let state = self.engine.state.lock().unwrap();
+        let account = state
+            .accounts
+            .get(id)
+            .ok_or_else(|| Status::not_found(format!("account {} not found", client)))?;
+        Ok(Response::new(to_proto(account, &self.engine.config)))
+    }
+
+    async fn list_accounts(
+        &self,
+        request: Request<proto::ListAccountsRequest>,
+    ) -> Result<Response<proto::ListAccountsResponse>, Status> {
+        let request = request.into_inner();
+        // The protocol has no sub-accounts, the engine behind it only ever fills main accounts
+        let cursor = request
+            .cursor
+            .map(|cursor| {
+                u16::try_from(cursor).map(AccountKey::from).map_err(|_| {
+                    Status::invalid_argument(format!("cursor {} out of range", cursor))
+                })
+            })
+            .transpose()?;
+        let limit = match request.limit as usize {
+            0 => DEFAULT_PAGE_SIZE,
+            limit => limit.min(MAX_PAGE_SIZE),
+        };
+        let filter = AccountFilter {
+            locked: request.locked,
+            held_only: request.held_only,
+        };
+
+        let state = self.engine.state.lock().unwrap();
+        let page = state.accounts.list_accounts(cursor, limit, &filter);
+        let config = &self.engine.config;
+        Ok(Response::new(proto::ListAccountsResponse {
+            accounts: page.accounts.iter().map(|a| to_proto(a, config)).collect(),
+            next_cursor: page.next.map(|key| u32::from(key.client)),
+        }))
+    }
+}
+
+fn to_proto(account: &Account, config: &Config) -> proto::Account {
+    proto::Account {
+        client: u32::from(account.client()),
+        available: config.format(account.available()),
+        held: config.format(account.held()),
+        total: config.format(account.total()),
+        locked: account.locked(),
+    }
+}
+
+pub async fn serve(addr: SocketAddr, config: Config) -> Result<(), Error> {
+    info!("gRPC ledger listening on {}", addr);
+    tonic::transport::Server::builder()
+        .add_service(LedgerServer::new(LedgerService::new(config)))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
+
+fn to_row(transaction: proto::Transaction) -> Result<TransactionRow, String> {
+    let (kind, client, tx, amount) = match transaction.kind {
+        Some(Kind::Deposit(d)) => ("deposit", d.client, d.tx, Some(d.amount)),
+        Some(Kind::Withdrawal(w)) => ("withdrawal", w.client, w.tx, Some(w.amount)),
+        Some(Kind::Dispute(d)) => ("dispute", d.client, d.tx, d.amount),
+        Some(Kind::Resolve(r)) => ("resolve", r.client, r.tx, None),
+        Some(Kind::Chargeback(c)) => ("chargeback", c.client, c.tx, None),
+        None => return Err("transaction without a kind".to_string()),
+    };
+    let client = u16::try_from(client).map_err(|_| format!("client {} out of range", client))?;
+    let amount = amount
+        .map(|a| Decimal::from_str(&a).map_err(|_| format!("invalid amount {}", a)))
+        .transpose()?;
+    Ok(TransactionRow::new(kind, client, tx, amount))
+}
+
+fn rejected(tx: u32, error: String) -> proto::TransactionResult {
+    proto::TransactionResult {
+        tx,
+        applied: false,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    use super::proto::ledger_client::LedgerClient;
+    use super::*;
+
+    fn deposit(client: u32, tx: u32, amount: &str) -> proto::Transaction {
+        proto::Transaction {
+            kind: Some(Kind::Deposit(proto::Deposit {
+                client,
+                tx,
+                amount: amount.to_string(),
+            })),
+        }
+    }
+
+    #[test]
+    fn submit_and_query_over_the_wire() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(
+                tonic::transport::Server::builder()
+                    .add_service(LedgerServer::new(LedgerService::new(Config::default())))
+                    .serve_with_incoming(TcpListenerStream::new(listener)),
+            );
+
+            let mut client = LedgerClient::connect(format!("http://{}", addr))
+                .await
+                .unwrap();
+            let withdrawal = proto::Transaction {
+                kind: Some(Kind::Withdrawal(proto::Withdrawal {
+                    client: 1,
+                    tx: 2,
+                    amount: "20".to_string(),
+                })),
+            };
+            let submitted = vec![deposit(1, 1, "10.5"), withdrawal, deposit(1, 1, "10.5")];
+            let results: Vec<_> = client
+                .submit_transactions(tokio_stream::iter(submitted))
+                .await
+                .unwrap()
+                .into_inner()
+                .map(|r| r.unwrap())
+                .collect()
+                .await;
+
+            let applied: Vec<_> = results.iter().map(|r| (r.tx, r.applied)).collect();
+            assert_eq!(applied, [(1, true), (2, false), (1, false)]);
+            assert!(results[1].error.contains("Insufficient funds"));
+
+            let account = client
+                .get_account(proto::GetAccountRequest { client: 1 })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(account.available, "10.5000");
+            assert!(!account.locked);
+
+            let missing = client
+                .get_account(proto::GetAccountRequest { client: 2 })
+                .await;
+            assert_eq!(missing.unwrap_err().code(), tonic::Code::NotFound);
+
+            client
+                .submit_transactions(tokio_stream::iter(vec![deposit(3, 3, "1")]))
+                .await
+                .unwrap()
+                .into_inner()
+                .collect::<Vec<_>>()
+                .await;
+            let first = client
+                .list_accounts(proto::ListAccountsRequest {
+                    limit: 1,
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            let second = client
+                .list_accounts(proto::ListAccountsRequest {
+                    cursor: first.next_cursor,
+                    limit: 1,
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(first.accounts[0].client, 1);
+            assert_eq!(second.accounts[0].client, 3);
+            assert_eq!(second.next_cursor, None);
+        });
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::account::{Account, AccountMap, AccountOutput};
+use crate::deposit_store::{DepositKey, DepositRecord, StoredDeposit};
+use crate::error::Error;
+
+// Carrying state from one run to the next, for processing a day's file on top of the day
+// before instead of replaying the whole history:
+//
+//   toy-processor day1.csv --save-deposits day1.deposits.csv > day1.accounts.csv
+//   toy-processor day2.csv --load-accounts day1.accounts.csv \
+//       --load-deposits day1.deposits.csv --save-deposits day2.deposits.csv > day2.accounts.csv
+//
+// The accounts are read back from the CSV output, so it must be written at full precision
+// and without `--clients`; the deposits are what day 2's disputes refer to. Transaction ids
+// already seen are not carried over, see `--seen-store` for that.
+
+// Accounts from the CSV output of an earlier run; an account listed twice is an error
+pub fn load_accounts(reader: impl Read) -> Result<AccountMap, Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut accounts = AccountMap::new();
+    for (line, row) in (2..).zip(reader.deserialize::<AccountOutput>()) {
+        let row = row?;
+        let account = Account::try_from(row).map_err(|detail| invalid(line, &detail))?;
+        let key = account.key();
+        if accounts.get(key).is_some() {
+            return Err(invalid(
+                line,
+                &format!("account {}/{} listed twice", key.client, key.sub),
+            ));
+        }
+        accounts.insert(account);
+    }
+    Ok(accounts)
+}
+
+// Deposits as written by `write_deposits`
+pub fn load_deposits(reader: impl Read) -> Result<HashMap<DepositKey, StoredDeposit>, Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut deposits = HashMap::new();
+    for (line, record) in (2..).zip(reader.deserialize::<DepositRecord>()) {
+        let record = record?;
+        let key = DepositKey::new(record.client, record.tx);
+        let deposit = StoredDeposit::try_from(record).map_err(|e| invalid(line, &e.to_string()))?;
+        if deposits.insert(key, deposit).is_some() {
+            return Err(invalid(
+                line,
+                &format!("tx {} of client {} listed twice", key.tx, key.client),
+            ));
+        }
+    }
+    Ok(deposits)
+}
+
+// The deposits and withdrawals a run kept, in tx order
+pub fn write_deposits(
+    writer: impl Write,
+    deposits: &HashMap<DepositKey, StoredDeposit>,
+) -> Result<(), Error> {
+    let mut sorted: Vec<_> = deposits.iter().collect();
+    sorted.sort_by_key(|(key, _)| **key);
+    let mut writer = csv::Writer::from_writer(writer);
+    for (key, deposit) in sorted {
+        writer.serialize(deposit.to_record(key.tx))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// `line` counts the header as the first
+fn invalid(line: u64, detail: &str) -> Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, detail),
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::transactions::{DepositTx, DisputeTx};
+
+    #[test]
+    fn carries_accounts_and_deposits_over() {
+        let mut accounts = AccountMap::new();
+        let mut deposits = HashMap::new();
+        DepositTx::new(1, 1, Decimal::new(1000, 2))
+            .process(&mut accounts, &mut deposits)
+            .unwrap();
+        DepositTx::new(2, 2, Decimal::new(550, 2))
+            .process(&mut accounts, &mut deposits)
+            .unwrap();
+        DisputeTx::new(1, 1)
+            .process(&mut accounts, &mut deposits)
+            .unwrap();
+
+        let mut output = csv::Writer::from_writer(Vec::new());
+        for account in accounts.clone().into_iter_sorted() {
+            output
+                .serialize(AccountOutput::new(account, &Config::default()))
+                .unwrap();
+        }
+        let output = output.into_inner().unwrap();
+        assert!(load_accounts(output.as_slice()).unwrap() == accounts);
+
+        let mut saved = Vec::new();
+        write_deposits(&mut saved, &deposits).unwrap();
+        let loaded = load_deposits(saved.as_slice()).unwrap();
+        assert_eq!(loaded.len(), 2);
+        let key = DepositKey::new(1, 1);
+        assert_eq!(loaded[&key].to_record(1), deposits[&key].to_record(1));
+        assert_eq!(loaded[&key].held(), Decimal::TEN);
+
+        let unbalanced = "client,available,held,total,locked\n1,5,1,7,false\n";
+        assert!(load_accounts(unbalanced.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,425 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::progress::Progress;
+use crate::transactions::TransactionRow;
+
+// UTF-8 input (with or without a BOM, which the csv reader strips) needs no transcoding.
+// Everything else goes through encoding_rs, available with the `encoding` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputEncoding {
+    #[default]
+    Utf8,
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+    // Sniff a UTF-8/UTF-16 BOM and transcode accordingly, otherwise pass through as UTF-8
+    Auto,
+}
+
+impl FromStr for InputEncoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf-8" | "utf8" => Ok(InputEncoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(InputEncoding::Latin1),
+            "utf-16le" => Ok(InputEncoding::Utf16Le),
+            "utf-16be" => Ok(InputEncoding::Utf16Be),
+            "auto" => Ok(InputEncoding::Auto),
+            _ => Err(Error::InvalidArgument(format!("unknown encoding {}", s))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn detect(path: &str) -> Self {
+        if path.ends_with(".gz") {
+            Compression::Gzip
+        } else if path.ends_with(".zst") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(Error::InvalidArgument(format!("unknown compression {}", s))),
+        }
+    }
+}
+
+// How the input files are laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    // Object container files, requires the `avro` feature
+    Avro,
+}
+
+impl FromStr for InputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "avro" => Ok(InputFormat::Avro),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown input format {}",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InputOptions {
+    pub format: InputFormat,
+    pub encoding: InputEncoding,
+    // None means detect from the file extension
+    pub compression: Option<Compression>,
+    // Keep reading local files as they grow instead of stopping at their end
+    pub follow: bool,
+}
+
+impl InputOptions {
+    pub fn compression_for(&self, path: &str) -> Compression {
+        self.compression
+            .unwrap_or_else(|| Compression::detect(path))
+    }
+
+    // True if the bytes of `path` reach the csv reader unmodified
+    pub fn is_raw(&self, path: &str) -> bool {
+        self.encoding == InputEncoding::Utf8 && self.compression_for(path) == Compression::None
+    }
+}
+
+// The files a run reads, in order: each path as given, or the files a wildcard (`*`, `?`) in
+// its file name matches, sorted by name; then every file in `dir`, sorted by name. Like a
+// shell, hidden files are skipped unless the pattern starts with a dot. Daily files named by
+// date so come out in date order. Object store URLs are taken as they are.
+pub fn resolve_inputs(paths: &[String], dir: Option<&str>) -> Result<Vec<String>, Error> {
+    let mut files = Vec::new();
+    for path in paths {
+        if is_remote(path) || !is_pattern(path) {
+            files.push(path.clone());
+            continue;
+        }
+        let (parent, pattern) = match path.rsplit_once('/') {
+            Some((parent, pattern)) => (Some(parent), pattern),
+            None => (None, path.as_str()),
+        };
+        if parent.is_some_and(is_pattern) {
+            return Err(Error::InvalidArgument(format!(
+                "only the file name may contain wildcards: {}",
+                path
+            )));
+        }
+        let matched = list_files(parent, |name| {
+            wildcard(pattern, name) && (pattern.starts_with('.') || !name.starts_with('.'))
+        })?;
+        if matched.is_empty() {
+            return Err(Error::InvalidArgument(format!("no input matches {}", path)));
+        }
+        files.extend(matched);
+    }
+    if let Some(dir) = dir {
+        let listed = list_files(Some(dir), |name| !name.starts_with('.'))?;
+        if listed.is_empty() {
+            return Err(Error::InvalidArgument(format!("no input files in {}", dir)));
+        }
+        files.extend(listed);
+    }
+    Ok(files)
+}
+
+fn is_pattern(path: &str) -> bool {
+    path.contains(['*', '?'])
+}
+
+// Files directly in `dir` (the working directory if None) whose names `keep` accepts
+fn list_files(dir: Option<&str>, keep: impl Fn(&str) -> bool) -> Result<Vec<String>, Error> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir.unwrap_or("."))? {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        // Through symlinks, so a linked file counts and a linked directory does not
+        if keep(&name) && entry.path().is_file() {
+            files.push(match dir {
+                Some(dir) => Path::new(dir).join(name).to_string_lossy().into_owned(),
+                None => name,
+            });
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Shell-style match of a whole name, `*` for any run of characters and `?` for one
+fn wildcard(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // Pattern position after the last `*`, and where in the name it started matching
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            // Let the last `*` swallow one more character and retry from there
+            _ => match star {
+                Some((after, from)) => {
+                    star = Some((after, from + 1));
+                    (p, n) = (after, from + 1);
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// An object store URL (`s3://bucket/key`) rather than a local path
+pub fn is_remote(path: &str) -> bool {
+    path.starts_with("s3://")
+}
+
+pub fn open(path: &str, options: &InputOptions) -> Result<Box<dyn Read + Send>, Error> {
+    open_counted(path, options, None)
+}
+
+// Like `open`, counting the file bytes read towards `progress`
+pub fn open_counted(
+    path: &str,
+    options: &InputOptions,
+    progress: Option<&Arc<Progress>>,
+) -> Result<Box<dyn Read + Send>, Error> {
+    let file: Box<dyn Read + Send> = match is_remote(path) {
+        #[cfg(feature = "s3")]
+        true => Box::new(crate::s3::S3Reader::open(path)?),
+        #[cfg(not(feature = "s3"))]
+        true => {
+            return Err(Error::InvalidArgument(format!(
+                "{} needs the `s3` feature",
+                path
+            )));
+        }
+        false if options.follow => Box::new(Follow(File::open(path)?)),
+        false => Box::new(File::open(path)?),
+    };
+    let file: Box<dyn Read + Send> = match progress {
+        Some(progress) => Box::new(progress.counting(file)),
+        None => Box::new(file),
+    };
+    let reader = decompress(file, options.compression_for(path))?;
+    decode(reader, options.encoding)
+}
+
+// How often a followed file is checked for appended bytes
+const FOLLOW_POLL: Duration = Duration::from_millis(200);
+
+// A file that never ends, like `tail -f`: at its end, a read waits for the writer to append
+// more. A row cut off by the end is simply completed by the next append. Truncating or
+// rotating the file is not noticed.
+struct Follow(File);
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.0.read(buf)? {
+                0 if !buf.is_empty() => thread::sleep(FOLLOW_POLL),
+                read => return Ok(read),
+            }
+        }
+    }
+}
+
+// Columns a row can't do without; the rest are optional, and columns the engine doesn't read
+// (`memo`, an export's own ids) are ignored
+pub const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+
+// Rows are deserialized by column name, so columns may come in any order. A required column
+// missing from the header fails the input up front, rather than every row on its own.
+pub fn check_header(headers: &csv::ByteRecord) -> Result<(), csv::Error> {
+    match REQUIRED_COLUMNS
+        .into_iter()
+        .find(|name| !headers.iter().any(|field| field == name.as_bytes()))
+    {
+        Some(name) => Err(csv::Error::from(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("missing column {}", name),
+        ))),
+        None => Ok(()),
+    }
+}
+
+// Deserializes rows like `csv::Reader::into_deserialize`, additionally tagging each row with
+// the line it started on so failures can point back into the file.
+pub fn numbered_rows<R: Read>(
+    mut rdr: csv::Reader<R>,
+) -> Result<impl Iterator<Item = Result<TransactionRow, csv::Error>>, csv::Error> {
+    check_header(rdr.byte_headers()?)?;
+    let headers = rdr.headers()?.clone();
+    Ok(rdr.into_records().map(move |record| {
+        let record = record?;
+        let mut row: TransactionRow = record.deserialize(Some(&headers))?;
+        if let Some(position) = record.position() {
+            row.set_line(position.line());
+        }
+        Ok(row)
+    }))
+}
+
+fn decompress(
+    reader: Box<dyn Read + Send>,
+    compression: Compression,
+) -> Result<Box<dyn Read + Send>, Error> {
+    match compression {
+        Compression::None => Ok(reader),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(
+            std::io::BufReader::new(reader),
+        ))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(Box::new(zstd::stream::read::Decoder::with_buffer(
+            std::io::BufReader::new(reader),
+        )?)),
+        #[allow(unreachable_patterns)]
+        other => Err(Error::InvalidArgument(format!(
+            "{:?} input is not supported by this build, enable the gzip/zstd features",
+            other
+        ))),
+    }
+}
+
+#[cfg(feature = "encoding")]
+fn decode(
+    reader: Box<dyn Read + Send>,
+    encoding: InputEncoding,
+) -> Result<Box<dyn Read + Send>, Error> {
+    use encoding_rs::{UTF_16BE, UTF_16LE, WINDOWS_1252};
+    use encoding_rs_io::DecodeReaderBytesBuilder;
+
+    // WHATWG maps ISO-8859-1 to windows-1252, a superset for the printable range
+    let forced = match encoding {
+        InputEncoding::Utf8 => return Ok(reader),
+        InputEncoding::Auto => None,
+        InputEncoding::Latin1 => Some(WINDOWS_1252),
+        InputEncoding::Utf16Le => Some(UTF_16LE),
+        InputEncoding::Utf16Be => Some(UTF_16BE),
+    };
+    Ok(Box::new(
+        DecodeReaderBytesBuilder::new()
+            .encoding(forced)
+            .build(reader),
+    ))
+}
+
+#[cfg(not(feature = "encoding"))]
+fn decode(
+    reader: Box<dyn Read + Send>,
+    encoding: InputEncoding,
+) -> Result<Box<dyn Read + Send>, Error> {
+    match encoding {
+        InputEncoding::Utf8 => Ok(reader),
+        _ => Err(Error::InvalidArgument(
+            "transcoding requires the `encoding` feature".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_whole_names() {
+        assert!(wildcard("*.csv", "day1.csv"));
+        assert!(wildcard("day?.csv", "day1.csv"));
+        assert!(wildcard("d*1*.csv", "day1.csv.csv"));
+        assert!(wildcard("*", ""));
+        assert!(!wildcard("*.csv", "day1.csv.gz"));
+        assert!(!wildcard("day?.csv", "day10.csv"));
+    }
+
+    #[test]
+    fn columns_are_found_by_name() {
+        let reader = |data: &'static str| {
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(data.as_bytes())
+        };
+        let rows: Vec<_> = numbered_rows(reader(
+            "memo, tx, amount, client, type\nrefund, 7, 2.5, 3, deposit\n",
+        ))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+        let mut expected = TransactionRow::new(
+            crate::transactions::TxType::Deposit,
+            3,
+            7,
+            Some("2.5".parse().unwrap()),
+        );
+        expected.set_line(2);
+        assert_eq!(rows, [expected]);
+
+        let missing = numbered_rows(reader("type, tx, amount\ndeposit, 7, 2.5\n"));
+        assert_eq!(missing.err().unwrap().to_string(), "missing column client");
+    }
+
+    #[test]
+    fn resolves_globs_in_name_order() {
+        let dir = std::env::temp_dir().join(format!("toy-processor-inputs-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested.csv")).unwrap();
+        for name in ["day2.csv", "day1.csv", "notes.txt", ".hidden.csv"] {
+            File::create(dir.join(name)).unwrap();
+        }
+        let dir_str = dir.to_str().unwrap();
+
+        let globbed = resolve_inputs(&[format!("{}/*.csv", dir_str)], None).unwrap();
+        let listed = resolve_inputs(&[], Some(dir_str)).unwrap();
+        let missing = resolve_inputs(&[format!("{}/*.json", dir_str)], None);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let names = |files: Vec<String>| -> Vec<String> {
+            files
+                .iter()
+                .map(|f| f.rsplit('/').next().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(names(globbed), ["day1.csv", "day2.csv"]);
+        assert_eq!(names(listed), ["day1.csv", "day2.csv", "notes.txt"]);
+        assert!(missing.is_err());
+    }
+}
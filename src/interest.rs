@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+
+use crate::account::{AccountKey, AccountMap};
+use crate::config::Config;
+use crate::deposit_store::{DepositKey, StoredDeposit};
+use crate::error::Error;
+use crate::transactions::DepositTx;
+
+// Credits `rate` of every account's available balance as interest, at the end of a run. Each
+// accrual is an ordinary deposit, stored like any other, so it shows in the deposits output
+// and the WAL and can be disputed. Fresh tx ids are handed out in account order, past both
+// `last_tx`, the highest id the run read or carried over, and the highest one in `deposits`.
+// Locked and closed accounts and accounts without a positive balance accrue nothing, and
+// neither do amounts that round to zero.
+pub fn accrue(
+    accounts: &mut AccountMap,
+    deposits: &mut HashMap<DepositKey, StoredDeposit>,
+    rate: Decimal,
+    last_tx: Option<u32>,
+    config: &Config,
+) -> Result<Vec<DepositTx>, Error> {
+    let mut eligible: Vec<(AccountKey, Decimal)> = accounts
+        .iter()
+        .filter(|a| a.status().accepts_deposits() && a.available() > Decimal::ZERO)
+        .map(|a| (a.key(), a.available()))
+        .collect();
+    eligible.sort_unstable_by_key(|(key, _)| *key);
+
+    // Evicted deposits and rows that stored nothing are missing from `deposits`
+    let mut next = deposits
+        .keys()
+        .map(|key| key.tx)
+        .max()
+        .max(last_tx)
+        .map_or(Some(1), |id| id.checked_add(1));
+    let mut accruals = Vec::new();
+    for (key, available) in eligible {
+        let interest = config.round(available * rate);
+        if interest.is_zero() {
+            continue;
+        }
+        let id = next.ok_or_else(|| {
+            Error::InvalidArgument("no tx ids left for interest deposits".to_string())
+        })?;
+        let deposit = DepositTx::new(key.client, id, interest).with_sub(key.sub);
+        deposit.process(accounts, deposits)?;
+        accruals.push(deposit);
+        next = id.checked_add(1);
+    }
+    Ok(accruals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deposit_store::DepositStore;
+    use crate::transactions::DisputeTx;
+
+    #[test]
+    fn accrues_disputable_deposits() {
+        let mut accounts = AccountMap::new();
+        let mut deposits: HashMap<DepositKey, StoredDeposit> = HashMap::new();
+        for (client, tx, amount) in [(2, 7, 200), (1, 3, 100), (3, 9, 1)] {
+            DepositTx::new(client, tx, Decimal::new(amount, 0))
+                .process(&mut accounts, &mut deposits)
+                .unwrap();
+        }
+        accounts.get_or_create(4);
+
+        let rate = Decimal::new(15, 3); // 1.5%
+        let config = Config {
+            precision: 1,
+            ..Config::default()
+        };
+        let accruals = accrue(&mut accounts, &mut deposits, rate, None, &config).unwrap();
+
+        // Client 3's 0.015 rounds to nothing, client 4 has no balance
+        let ids: Vec<_> = accruals.iter().map(|d| (d.client(), d.id())).collect();
+        assert_eq!(ids, [(1, 10), (2, 11)]);
+        assert_eq!(accounts.get(1).unwrap().available(), Decimal::new(1015, 1));
+        assert_eq!(
+            DepositStore::get(&deposits, DepositKey::new(2, 11))
+                .unwrap()
+                .amount(),
+            Decimal::new(3, 0)
+        );
+
+        DisputeTx::new(2, 11)
+            .process(&mut accounts, &mut deposits)
+            .unwrap();
+        assert_eq!(accounts.get(2).unwrap().held(), Decimal::new(3, 0));
+
+        // Past an id the run read but didn't keep
+        let accruals = accrue(&mut accounts, &mut deposits, rate, Some(40), &config).unwrap();
+        assert_eq!(accruals[0].id(), 41);
+    }
+}
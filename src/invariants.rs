@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::account::{AccountKey, AccountMap};
+
+// One account breaking an invariant the engine should always keep
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub account: AccountKey,
+    pub invariant: &'static str,
+    pub detail: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "client {}: {} ({})",
+            self.account, self.invariant, self.detail
+        )
+    }
+}
+
+// Checks the merged state of a run, a safety net for logic regressions rather than anything
+// an input can cause:
+//
+// - `held_matches_disputes`: an account holds exactly what its disputed and pending deposits
+//   hold
+// - `held_not_negative`: no account holds less than zero
+// - `total_not_negative`: no account is below zero overall, unless `negative_totals` says
+//   something in the run was allowed to take it there (clawback, overdraft, ...)
+//
+// `disputed` is what each account's deposits hold, see `ProcessOutput::disputed`.
+// Violations come sorted by account.
+pub fn check(
+    accounts: &AccountMap,
+    mut disputed: HashMap<AccountKey, Decimal>,
+    negative_totals: bool,
+) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut violation = |account, invariant, detail| {
+        violations.push(Violation {
+            account,
+            invariant,
+            detail,
+        })
+    };
+    for account in accounts.iter() {
+        let key = account.key();
+        let held = account.held();
+        let in_disputes = disputed.remove(&key).unwrap_or_default();
+        if held != in_disputes {
+            let detail = format!("held {}, disputed deposits {}", held, in_disputes);
+            violation(key, "held_matches_disputes", detail);
+        }
+        if held < Decimal::ZERO {
+            violation(key, "held_not_negative", format!("held {}", held));
+        }
+        if !negative_totals && account.total() < Decimal::ZERO {
+            let detail = format!("total {}", account.total());
+            violation(key, "total_not_negative", detail);
+        }
+    }
+    // Disputes of accounts the run does not have
+    for (key, in_disputes) in disputed {
+        let detail = format!("no account, disputed deposits {}", in_disputes);
+        violation(key, "held_matches_disputes", detail);
+    }
+    violations.sort_by_key(|v| v.account);
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_must_match_disputed_deposits() {
+        let mut accounts = AccountMap::new();
+        for client in [1, 2] {
+            accounts
+                .get_or_create(client)
+                .deposit(Decimal::TEN)
+                .unwrap();
+        }
+        // Client 1 holds its dispute, client 2 lost it and overdrew, client 3 has no account
+        accounts.get_or_create(1).dispute(Decimal::TEN).unwrap();
+        accounts.get_or_create(2).void(Decimal::new(15, 0)).unwrap();
+        let disputed = HashMap::from([
+            (AccountKey::from(1), Decimal::TEN),
+            (AccountKey::from(2), Decimal::TEN),
+            (AccountKey::from(3), Decimal::ONE),
+        ]);
+
+        assert_eq!(check(&accounts, disputed.clone(), true).len(), 2);
+        let found: Vec<_> = check(&accounts, disputed, false)
+            .into_iter()
+            .map(|v| (v.account.client, v.invariant))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (2, "held_matches_disputes"),
+                (2, "total_not_negative"),
+                (3, "held_matches_disputes"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use std::io::Write;
+use std::ops::Sub;
+
+use rust_decimal::Decimal;
+
+use crate::account::{AccountKey, AccountMap};
+use crate::config::Config;
+use crate::error::Error;
+use crate::transactions::{DepositTx, Transaction};
+
+// Money moving in and out of the system: deposits, withdrawals, chargebacks, corrections
+pub const SETTLEMENT: &str = "settlement";
+// Deposits voided as booked in error, which never were money in
+pub const SUSPENSE: &str = "suspense";
+// Interest accrued to clients at the end of a run
+pub const INTEREST: &str = "interest";
+// Operator adjustments, kept apart from settlement so they reconcile on their own
+pub const ADJUSTMENTS: &str = "adjustments";
+
+pub const HEADER: [&str; 6] = ["entry", "tx", "type", "account", "debit", "credit"];
+
+// An account's available and held funds, or how much a transaction changed them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Balances {
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+impl Balances {
+    // Zero for an account that does not exist yet
+    pub fn of(accounts: &AccountMap, account: AccountKey) -> Self {
+        accounts
+            .get(account)
+            .map_or_else(Self::default, |account| Self {
+                available: account.available(),
+                held: account.held(),
+            })
+    }
+}
+
+impl Sub for Balances {
+    type Output = Balances;
+
+    fn sub(self, before: Balances) -> Balances {
+        Balances {
+            available: self.available - before.available,
+            held: self.held - before.held,
+        }
+    }
+}
+
+// Double-entry journal of applied transactions, as CSV with one line per posting:
+//
+//   entry,tx,type,account,debit,credit
+//   1,1,deposit,settlement,10.0000,
+//   1,1,deposit,client:1:available,,10.0000
+//
+// Client funds are liabilities, `client:<id>:available` and `client:<id>:held`, credited when
+// they grow; a sub-account's id reads `<client>/<sub>`. The other side of each entry is
+// `settlement`, `suspense` for voids, `adjustments` for operator adjustments, or `interest`,
+// and a withdrawal fee is credited to the house account's `available`. Every entry balances.
+// Transactions that move no funds, such as a freeze, have no entry.
+pub struct LedgerWriter<W: Write> {
+    inner: csv::Writer<W>,
+    config: Config,
+    entries: u64,
+}
+
+impl<W: Write> LedgerWriter<W> {
+    // Amounts are written at the precision of `config`
+    pub fn new(inner: W, config: &Config) -> Result<Self, Error> {
+        let mut inner = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::Any(b'\n'))
+            .from_writer(inner);
+        inner.write_record(HEADER)?;
+        Ok(Self {
+            inner,
+            config: *config,
+            entries: 0,
+        })
+    }
+
+    // The entry for `tx`, which changed its account's balances by `change`
+    pub fn record(&mut self, kind: &str, tx: &Transaction, change: Balances) -> Result<(), Error> {
+        let contra = match tx {
+            Transaction::Void(_) => SUSPENSE,
+            Transaction::Adjustment(_) => ADJUSTMENTS,
+            _ => SETTLEMENT,
+        };
+        let fee = tx.fee().map(|fee| (fee.account, fee.amount));
+        self.entry(tx.id(), kind, tx.account(), change, fee, contra)
+    }
+
+    pub fn record_interest(&mut self, deposit: &DepositTx) -> Result<(), Error> {
+        let change = Balances {
+            available: deposit.amount(),
+            held: Decimal::ZERO,
+        };
+        self.entry(
+            deposit.id(),
+            INTEREST,
+            deposit.account(),
+            change,
+            None,
+            INTEREST,
+        )
+    }
+
+    fn entry(
+        &mut self,
+        tx: u32,
+        kind: &str,
+        account: AccountKey,
+        change: Balances,
+        fee: Option<(u16, Decimal)>,
+        contra: &str,
+    ) -> Result<(), Error> {
+        let fee_amount = fee.map_or(Decimal::ZERO, |(_, amount)| amount);
+        // Credits on the client side are the debit on the other
+        let contra_debit = change.available + change.held + fee_amount;
+        let mut postings = vec![
+            (contra.to_string(), -contra_debit),
+            (format!("client:{}:available", account), change.available),
+            (format!("client:{}:held", account), change.held),
+        ];
+        if let Some((house, amount)) = fee {
+            postings.push((format!("client:{}:available", house), amount));
+        }
+        postings.retain(|(_, credit)| !credit.is_zero());
+        if postings.is_empty() {
+            return Ok(());
+        }
+
+        self.entries += 1;
+        let (entry, tx) = (self.entries.to_string(), tx.to_string());
+        // Debits first, as journals are usually laid out
+        postings.sort_by_key(|(_, credit)| credit.is_sign_positive());
+        for (account, credit) in postings {
+            let amount = self.config.format(credit.abs());
+            let (debit, credit) = match credit.is_sign_positive() {
+                true => ("", amount.as_str()),
+                false => (amount.as_str(), ""),
+            };
+            self.inner
+                .write_record([entry.as_str(), &tx, kind, &account, debit, credit])?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{AdminAction, AdminTx, DisputeTx, Fee, WithdrawalTx};
+
+    #[test]
+    fn entries_balance_with_fees_and_skip_no_ops() {
+        let mut out = Vec::new();
+        let mut ledger = LedgerWriter::new(&mut out, &Config::default()).unwrap();
+        let fee = Fee {
+            account: 9,
+            amount: Decimal::ONE,
+        };
+        let withdrawal = WithdrawalTx::new(1, 2, Decimal::TEN).with_fee(Some(fee));
+        let change = Balances {
+            available: Decimal::new(-11, 0),
+            held: Decimal::ZERO,
+        };
+        ledger
+            .record("withdrawal", &Transaction::Withdrawal(withdrawal), change)
+            .unwrap();
+        let dispute = DisputeTx::new(1, 1);
+        let change = Balances {
+            available: Decimal::new(-5, 0),
+            held: Decimal::new(5, 0),
+        };
+        ledger
+            .record("dispute", &Transaction::Dispute(dispute), change)
+            .unwrap();
+        let freeze = AdminTx::new(1, 3, AdminAction::Freeze);
+        ledger
+            .record("freeze", &Transaction::Admin(freeze), Balances::default())
+            .unwrap();
+        ledger.flush().unwrap();
+        drop(ledger);
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "entry,tx,type,account,debit,credit
+1,2,withdrawal,client:1:available,11.0000,
+1,2,withdrawal,settlement,,10.0000
+1,2,withdrawal,client:9:available,,1.0000
+2,1,dispute,client:1:available,5.0000,
+2,1,dispute,client:1:held,,5.0000
+"
+        );
+    }
+}
@@ -1,6 +1,73 @@
 pub mod account;
+pub mod account_store;
+pub mod adjustments;
+pub mod alerts;
+pub mod amount;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "avro")]
+pub mod avro;
+pub mod bench;
+pub mod canary;
+pub mod canonical;
+pub mod client_filter;
+pub mod config;
+pub mod conflicts;
+pub mod dead_letter;
+pub mod dedup;
 pub mod deposit_store;
+pub mod diff;
+#[cfg(feature = "encryption")]
+pub mod encryption;
 pub mod error;
+pub mod error_log;
+pub mod events;
+pub mod generate;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod incremental;
+pub mod input;
+pub mod interest;
+pub mod invariants;
+pub mod ledger;
+pub mod locked;
+pub mod metrics;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod output;
+pub mod overdraft;
+pub mod parallel;
+#[cfg(feature = "wasm-plugins")]
+pub mod plugin;
+pub mod policy;
+pub mod processor;
+pub mod progress;
+pub mod quarantine;
+pub mod rule;
+pub mod run_config;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "simd")]
+pub mod simd;
+#[cfg(unix)]
+pub mod socket;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod state_hash;
+pub mod stats;
+pub mod summary;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+pub mod throttle;
 pub mod transactions;
+pub mod transform;
+pub mod tune;
+pub mod validate;
+pub mod wal;
+#[cfg(feature = "wasm")]
+pub mod web;
 
+pub use processor::{ProcessOutput, Processor, ProcessorBuilder};
 pub use transactions::TransactionRow;
@@ -0,0 +1,101 @@
+use std::io::Write;
+
+use rust_decimal::Decimal;
+
+use crate::account::{AccountKey, AccountMap};
+use crate::config::Config;
+use crate::error::Error;
+
+pub const HEADER: [&str; 7] = [
+    "client",
+    "tx",
+    "amount",
+    "available",
+    "held",
+    "total",
+    "locked",
+];
+
+// A chargeback that locked an account, see `ProcessOutput::locks`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountLock {
+    pub account: AccountKey,
+    // The chargeback's tx id, i.e. the deposit's
+    pub tx: u32,
+    // Funds the chargeback took out of held
+    pub amount: Decimal,
+}
+
+// Every account a chargeback locked during the run, for the fraud team, as CSV:
+//
+//   client,tx,amount,available,held,total,locked
+//   3,17,250.0000,-40.0000,0.0000,-40.0000,true
+//
+// One line per lock, in account order, with the account's balances at the end of the run.
+// `locked` is false once a chargeback reversal unlocked it again.
+pub fn write_report(
+    out: impl Write,
+    locks: &[AccountLock],
+    accounts: &AccountMap,
+    config: &Config,
+) -> Result<(), Error> {
+    let mut out = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(out);
+    out.write_record(HEADER)?;
+    for lock in locks {
+        let Some(account) = accounts.get(lock.account) else {
+            continue;
+        };
+        out.write_record([
+            lock.account.to_string().as_str(),
+            &lock.tx.to_string(),
+            &config.format(lock.amount),
+            &config.format(account.available()),
+            &config.format(account.held()),
+            &config.format(account.total()),
+            &account.locked().to_string(),
+        ])?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::ProcessorBuilder;
+
+    #[test]
+    fn reports_accounts_chargebacks_locked() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,100\ndeposit,2,2,50\ndeposit,2,3,20\n\
+                     dispute,2,2,\nchargeback,2,2,\ndispute,2,3,\nchargeback,2,3,\n\
+                     dispute,1,1,\nresolve,1,1,\n";
+        let rows: Vec<_> = csv::ReaderBuilder::new()
+            .from_reader(input.as_bytes())
+            .into_deserialize()
+            .collect();
+        let output = ProcessorBuilder::new()
+            .workers(2)
+            .build()
+            .run("test", rows)
+            .unwrap();
+
+        let mut out = Vec::new();
+        write_report(
+            &mut out,
+            &output.locks,
+            &output.accounts,
+            &Config::default(),
+        )
+        .unwrap();
+        // The second chargeback settles too, but the account was locked already
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,tx,amount,available,held,total,locked
+2,2,50.0000,0.0000,0.0000,0.0000,true
+"
+        );
+    }
+}
@@ -1,137 +1,1002 @@
 use std::collections::HashMap;
+use std::env;
 use std::fs::File;
-use std::sync::mpsc::{self, Receiver};
-use std::{env, thread};
+use std::io::{BufWriter, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
 
-use bloomfilter::Bloom;
-use log::{debug, error, info, warn};
+use log::{error, info};
 
-use crate::account::{AccountMap, AccountOutput};
-use crate::deposit_store::StoredDeposit;
-use crate::transactions::{Transaction, TransactionRow};
+use crate::account::{AccountMap, AccountOutput, AccountStatus};
+use crate::account_store::AccountStore;
+use crate::adjustments::AdjustmentReport;
+use crate::alerts::AlertWriter;
+use crate::bench::{BenchOptions, BenchResults};
+use crate::cli::{Args, Command};
+use crate::client_filter::ClientFilter;
+use crate::config::Config;
+use crate::conflicts::ConflictReport;
+use crate::dead_letter::DeadLetterQueue;
+use crate::dedup::Deduplicator;
+use crate::deposit_store::{DepositKey, StoredDeposit};
+use crate::input::{InputFormat, InputOptions};
+use crate::ledger::LedgerWriter;
+use crate::output::{
+    AccountOrder, CsvSink, JsonSink, OutputCompat, OutputFormat, OutputSink, OutputTarget,
+};
+use crate::overdraft::OverdraftLimits;
+use crate::parallel::ParallelRows;
+use crate::policy::{DedupStrategy, DisputeOverdraftPolicy, VoidPolicy};
+use crate::processor::{ProcessOutput, ProcessorBuilder};
+use crate::progress::Progress;
+use crate::quarantine::RecoveringReader;
+use crate::summary::Summary;
+use crate::transactions::TransactionRow;
+use crate::transform::{MappingTransformer, RowTransformer};
+use crate::wal::{WalReader, WalWriter};
 
 mod account;
+mod account_store;
+mod adjustments;
+mod alerts;
+mod amount;
+#[cfg(feature = "arrow")]
+mod arrow;
+#[cfg(feature = "avro")]
+mod avro;
+mod bench;
+mod canary;
+mod canonical;
+mod cli;
+mod client_filter;
+mod config;
+mod conflicts;
+mod dead_letter;
+mod dedup;
 mod deposit_store;
+mod diff;
+#[cfg(feature = "encryption")]
+mod encryption;
 mod error;
+mod error_log;
+mod events;
+mod generate;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod incremental;
+mod input;
+mod interest;
+mod invariants;
+mod ledger;
+mod locked;
+mod metrics;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod output;
+mod overdraft;
+mod parallel;
+#[cfg(feature = "wasm-plugins")]
+mod plugin;
+mod policy;
+mod processor;
+mod progress;
+mod quarantine;
+mod rule;
+mod run_config;
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "scripting")]
+mod script;
+#[cfg(feature = "simd")]
+mod simd;
+#[cfg(unix)]
+mod socket;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod state_hash;
+mod stats;
+mod summary;
+mod throttle;
 mod transactions;
+mod transform;
+mod tune;
+mod validate;
+mod wal;
 
-const WORKER_COUNT: usize = 4;
-// Roughly ~24 bits per element at the below fp rate, tweakable depending on real world requirements,
-// 10 million expected deposit and withdraw txs uses ~30MB RAM, would produce ~100 false positives
-const EXPECTED_N_TRANSACTIONS: usize = 10_000_000;
-const BLOOM_FP_RATE: f64 = 0.00001;
-
-fn worker_loop(rx: Receiver<TransactionRow>) -> AccountMap {
+fn replay(path: &str) -> Result<AccountMap, error::Error> {
     let mut accounts = AccountMap::new();
-    let mut deposits: HashMap<u32, StoredDeposit> = HashMap::new();
-
-    // Blocks until message or channel closed (sender dropped)
-    while let Ok(row) = rx.recv() {
-        let transaction: Transaction = match row.try_into() {
-            Ok(tx) => tx,
-            Err(e) => {
-                error!("Failed to convert transaction: {}", e);
-                continue;
-            }
-        };
+    let mut deposits: HashMap<DepositKey, StoredDeposit> = HashMap::new();
+
+    for transaction in WalReader::new(std::io::BufReader::new(File::open(path)?))? {
+        let transaction = transaction?;
+        if let Err(e) = transaction.process(&mut accounts, &mut deposits) {
+            error!("Replayed transaction failed: {}", e);
+        } else if let Some(fee) = transaction.fee() {
+            accounts.get_or_create(fee.account).collect_fees(fee.amount);
+        }
+    }
+
+    Ok(accounts)
+}
+
+#[cfg(feature = "grpc")]
+fn grpc_serve(addr: std::net::SocketAddr, config: Config) -> Result<(), error::Error> {
+    tokio::runtime::Runtime::new()?.block_on(grpc::serve(addr, config))
+}
+
+#[cfg(not(feature = "grpc"))]
+fn grpc_serve(_addr: std::net::SocketAddr, _config: Config) -> Result<(), error::Error> {
+    Err(error::Error::InvalidArgument(
+        "grpc-serve requires the `grpc` feature".to_string(),
+    ))
+}
 
-        debug!("Processing: {:?}", transaction);
+#[cfg(feature = "encryption")]
+fn decrypt(identity: &str, input: &str) -> Result<(), error::Error> {
+    encryption::decrypt(
+        std::io::BufReader::new(File::open(identity)?),
+        File::open(input)?,
+        std::io::stdout().lock(),
+    )
+}
+
+#[cfg(not(feature = "encryption"))]
+fn decrypt(_identity: &str, _input: &str) -> Result<(), error::Error> {
+    Err(error::Error::InvalidArgument(
+        "decrypt requires the `encryption` feature".to_string(),
+    ))
+}
+
+fn bench(options: BenchOptions) -> Result<(), error::Error> {
+    // Generated traffic has plenty of failing rows; logging them would time the terminal
+    log::set_max_level(log::LevelFilter::Off);
+    let results = bench::run(&options)?;
+    if let Some(path) = &options.save {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &results)
+            .map_err(std::io::Error::from)?;
+    }
+    let baseline: BenchResults = match &options.baseline {
+        Some(path) => serde_json::from_reader(std::io::BufReader::new(File::open(path)?))
+            .map_err(std::io::Error::from)?,
+        None => BenchResults::new(),
+    };
+
+    let comparisons = bench::compare(&results, &baseline, options.fail_threshold);
+    for comparison in &comparisons {
+        println!("{}", comparison);
+    }
+    let regressed: Vec<_> = comparisons
+        .iter()
+        .filter(|c| c.regressed)
+        .map(|c| c.name.as_str())
+        .collect();
+    if !regressed.is_empty() {
+        return Err(error::Error::BenchRegressed(format!(
+            "{} slower than baseline by more than {}%",
+            regressed.join(", "),
+            options.fail_threshold
+        )));
+    }
+    Ok(())
+}
 
-        let result = match &transaction {
-            Transaction::Deposit(t) => t.process(&mut accounts, &mut deposits),
-            Transaction::Withdrawal(t) => t.process(&mut accounts),
-            Transaction::Dispute(t) => t.process(&mut accounts, &mut deposits),
-            Transaction::Resolve(t) => t.process(&mut accounts, &mut deposits),
-            Transaction::Chargeback(t) => t.process(&mut accounts, &mut deposits),
+fn write_accounts(
+    sink: &mut dyn OutputSink,
+    accounts: AccountMap,
+    config: &Config,
+    compat: OutputCompat,
+    order: AccountOrder,
+    flag_overdraft: bool,
+) -> Result<(), error::Error> {
+    // Runs that never freeze or close an account keep the original columns
+    let flag_status = compat == OutputCompat::Latest
+        && accounts
+            .iter()
+            .any(|a| matches!(a.status(), AccountStatus::Frozen | AccountStatus::Closed));
+    // Likewise runs without sub-accounts, which v1 never had
+    let flag_sub = compat == OutputCompat::Latest && accounts.has_sub_accounts();
+    for account in accounts.into_iter_sorted_by(|a, b| order.compare(a, b)) {
+        let (status, in_overdraft, sub) = (account.status(), account.in_overdraft(), account.sub());
+        let mut output = match compat {
+            OutputCompat::Latest => AccountOutput::new(account, config),
+            // The default config formats exactly like v1 did
+            OutputCompat::V1 => AccountOutput::from(account),
         };
+        if flag_sub {
+            output = output.with_sub(sub);
+        }
+        if flag_status {
+            output = output.with_status(status);
+        }
+        if compat == OutputCompat::Latest && flag_overdraft {
+            output = output.with_overdraft(in_overdraft);
+        }
+        sink.write_account(&output)?;
+    }
+    sink.finish()
+}
 
-        if let Err(e) = result {
-            error!("Transaction failed: {}", e);
+// The sink for stdout and file targets, in the requested format
+fn format_sink(
+    sink: impl Write + 'static,
+    config: &Config,
+    format: OutputFormat,
+) -> Result<Box<dyn OutputSink>, error::Error> {
+    match format {
+        OutputFormat::Csv => Ok(Box::new(CsvSink::new(sink))),
+        OutputFormat::Json => Ok(Box::new(JsonSink::new(sink))),
+        #[cfg(feature = "arrow")]
+        OutputFormat::Arrow => Ok(Box::new(arrow::ArrowSink::new(sink, config))),
+        #[cfg(not(feature = "arrow"))]
+        OutputFormat::Arrow => {
+            let _ = config;
+            Err(error::Error::InvalidArgument(
+                "arrow output requires the `arrow` feature".to_string(),
+            ))
         }
     }
+}
 
-    accounts
+// `format_sink`, encrypted to the `--encrypt-output` recipient if there is one
+fn output_sink(
+    sink: impl Write + 'static,
+    config: &Config,
+    format: OutputFormat,
+    recipient: Option<&str>,
+) -> Result<Box<dyn OutputSink>, error::Error> {
+    #[cfg(feature = "encryption")]
+    if let Some(recipient) = recipient {
+        let encrypted = encryption::EncryptedSink::new(sink, recipient, |plaintext| {
+            format_sink(plaintext, config, format)
+        })?;
+        return Ok(Box::new(encrypted));
+    }
+    #[cfg(not(feature = "encryption"))]
+    if recipient.is_some() {
+        return Err(error::Error::InvalidArgument(
+            "--encrypt-output requires the `encryption` feature".to_string(),
+        ));
+    }
+    format_sink(sink, config, format)
 }
 
-fn main() -> Result<(), error::Error> {
-    env_logger::init();
+// Only runs that allow overdrafts get the column
+fn overdraft_column(args: &Args) -> bool {
+    !args.config.overdraft_limit.is_zero() || args.overdraft_limits.is_some()
+}
 
-    let path = env::args().nth(1).ok_or(error::Error::MissingArgument)?;
-    info!("Processing transactions from: {}", path);
+// Writes `path` through `write`, into `<path>.partial` first and renamed over `path` once
+// `write` succeeded, so a crash mid-write never leaves a truncated file that looks complete.
+// `write` must flush what it buffers.
+fn write_atomic(
+    path: &str,
+    write: impl FnOnce(BufWriter<File>) -> Result<(), error::Error>,
+) -> Result<(), error::Error> {
+    let partial = format!("{}.partial", path);
+    let written = File::create(&partial)
+        .map_err(error::Error::from)
+        .and_then(|file| write(BufWriter::new(file)));
+    match written {
+        Ok(()) => Ok(std::fs::rename(&partial, path)?),
+        Err(e) => {
+            std::fs::remove_file(&partial).ok();
+            Err(e)
+        }
+    }
+}
 
-    let file = File::open(&path)?;
-    let mut rdr = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_reader(file);
+// Rewrites the `--output` file of a followed run with each snapshot, the way the final output
+// would be written. The file is written aside and renamed, so a reader never sees half of it.
+fn snapshot_writer(args: &Args, path: &str) -> impl FnMut(AccountMap) + Send + 'static {
+    let path = path.to_string();
+    let (config, format) = (args.config, args.output_format);
+    let (compat, order) = (args.output_compat, args.order);
+    let recipient = args.encrypt_output.clone();
+    let clients = args.clients.clone();
+    let overdraft = overdraft_column(args);
+    move |mut accounts| {
+        if !clients.is_empty() {
+            accounts.retain_clients(&clients);
+        }
+        let written = write_atomic(&path, |file| {
+            let mut sink = output_sink(file, &config, format, recipient.as_deref())?;
+            write_accounts(sink.as_mut(), accounts, &config, compat, order, overdraft)
+        });
+        if let Err(e) = written {
+            error!("Failed to write snapshot to {}: {}", path, e);
+        }
+    }
+}
 
-    let mut bloom = Bloom::new_for_fp_rate(EXPECTED_N_TRANSACTIONS, BLOOM_FP_RATE).unwrap();
+// Input that never ends: a followed file or a socket
+fn streaming(args: &Args) -> bool {
+    args.input_options.follow || args.listen_unix.is_some()
+}
 
-    let (senders, receivers): (Vec<_>, Vec<_>) = (0..WORKER_COUNT)
-        .map(|_| mpsc::channel::<TransactionRow>())
-        .unzip();
+// `--follow` reads one local file as it grows, and `--listen-unix` rows as other processes send
+// them. Both keep rewriting a file output, so they have no use for anything that waits for the
+// end of the input.
+fn check_streaming(args: &Args, inputs: &[String]) -> Result<(), error::Error> {
+    let flag = match args.listen_unix {
+        Some(_) => "--listen-unix",
+        None => "--follow",
+    };
+    let invalid = |reason: &str| {
+        Err(error::Error::InvalidArgument(format!(
+            "{} {}",
+            flag, reason
+        )))
+    };
+    match &args.listen_unix {
+        Some(_) if args.input_options.follow => {
+            return invalid("cannot be combined with --follow");
+        }
+        // Nothing to sample before the rows arrive
+        Some(_) if args.canary.is_some() || args.auto_tune => {
+            return invalid("cannot be combined with --canary or --auto-tune");
+        }
+        Some(_) => {}
+        None if inputs.len() != 1 || input::is_remote(&inputs[0]) => {
+            return invalid("takes a single local input file");
+        }
+        None => {}
+    }
+    if !matches!(args.output, OutputTarget::File(_)) {
+        return invalid("requires --output file:<path>, rewritten with every snapshot");
+    }
+    if args.validate_only
+        || args.verify_deterministic
+        || args.rebalance
+        || args.mmap
+        || args.simd
+        || args.quarantine.is_some()
+        || args.parse_threads.is_some()
+    {
+        return invalid(
+            "cannot be combined with --validate-only, --verify-deterministic, --rebalance, --mmap, --simd, --quarantine or --parse-threads",
+        );
+    }
+    Ok(())
+}
 
-    let handles: Vec<_> = receivers
-        .into_iter()
-        .map(|rx| thread::spawn(move || worker_loop(rx)))
-        .collect();
+type Rows = Box<dyn Iterator<Item = Result<TransactionRow, csv::Error>>>;
+
+fn open_reader(
+    path: &str,
+    options: &InputOptions,
+) -> Result<csv::Reader<Box<dyn Read + Send>>, error::Error> {
+    Ok(csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input::open(path, options)?))
+}
 
-    for result in rdr.deserialize() {
-        let row: TransactionRow = match result {
-            Ok(r) => r,
-            Err(e) => {
-                error!("Failed to parse CSV row: {}", e);
-                continue;
+// Every input's rows back to back, for the canary and auto-tune samples. A followed input is
+// sampled as it is now.
+fn sample_rows(
+    paths: &[String],
+    options: &InputOptions,
+) -> Result<impl Iterator<Item = Result<TransactionRow, csv::Error>>, error::Error> {
+    let options = &InputOptions {
+        follow: false,
+        ..*options
+    };
+    let readers = paths
+        .iter()
+        .map(|path| -> Result<Rows, error::Error> {
+            match options.format {
+                InputFormat::Avro => avro_rows(input::open(path, options)?),
+                InputFormat::Csv => Ok(Box::new(open_reader(path, options)?.into_deserialize())),
             }
-        };
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(readers.into_iter().flatten())
+}
 
-        if row.should_dedupe() {
-            if !bloom.check(&row.tx()) {
-                bloom.set(&row.tx());
-            } else {
-                warn!(
-                    "Possible duplicate tx={} client={} type={} amount={:?} - dropped",
-                    row.tx(),
-                    row.client(),
-                    row.tx_type(),
-                    row.amount()
-                );
-                continue;
+// The rows of one input for the real run, through the reader the options ask for
+fn open_rows(
+    path: &str,
+    args: &Args,
+    progress: Option<&Arc<Progress>>,
+    quarantine: Option<&File>,
+) -> Result<Rows, error::Error> {
+    // Recovery and the memory map both need the file on local disk
+    if args.parse_threads.is_some() && (quarantine.is_some() || args.mmap || args.simd) {
+        return Err(error::Error::InvalidArgument(
+            "--parse-threads applies to the buffered reader, not --quarantine, --mmap or --simd"
+                .to_string(),
+        ));
+    }
+    if args.input_options.format == InputFormat::Avro {
+        if quarantine.is_some() || args.mmap || args.simd || args.parse_threads.is_some() {
+            return Err(error::Error::InvalidArgument(
+                "--quarantine, --mmap, --simd and --parse-threads read CSV, not --format avro"
+                    .to_string(),
+            ));
+        }
+        return avro_rows(input::open_counted(path, &args.input_options, progress)?);
+    }
+    if input::is_remote(path) && (quarantine.is_some() || args.mmap || args.simd) {
+        return Err(error::Error::InvalidArgument(format!(
+            "--quarantine, --mmap and --simd need a local file, not {}",
+            path
+        )));
+    }
+    Ok(match quarantine {
+        // Recovery seeks within the raw file, so it cannot sit behind a decoder
+        Some(_) if !args.input_options.is_raw(path) => {
+            return Err(error::Error::InvalidArgument(
+                "--quarantine requires uncompressed UTF-8 input".to_string(),
+            ));
+        }
+        // Every input shares the one quarantine file
+        Some(sink) => Box::new(RecoveringReader::from_path(path, sink.try_clone()?)?),
+        // The map is parsed in place, so like recovery it needs the bytes as they are on disk
+        None if (args.mmap || args.simd) && !args.input_options.is_raw(path) => {
+            return Err(error::Error::InvalidArgument(
+                "--mmap and --simd require uncompressed UTF-8 input".to_string(),
+            ));
+        }
+        #[cfg(feature = "simd")]
+        None if args.simd => {
+            let rows = simd::SimdRows::open(path)?;
+            match progress {
+                Some(progress) => Box::new(rows.with_progress(progress.clone())),
+                None => Box::new(rows),
             }
         }
-
-        let worker_idx = row.client() as usize % WORKER_COUNT;
-        {
-            let sender = &senders[worker_idx];
-            if let Err(e) = sender.send(row) {
-                error!("Failed to send transaction to worker {}: {}", worker_idx, e);
+        #[cfg(not(feature = "simd"))]
+        None if args.simd => {
+            return Err(error::Error::InvalidArgument(
+                "--simd requires the `simd` feature".to_string(),
+            ));
+        }
+        #[cfg(feature = "mmap")]
+        None if args.mmap => {
+            let rows = mmap::MmapRows::open(path)?;
+            match progress {
+                Some(progress) => Box::new(rows.with_progress(progress.clone())),
+                None => Box::new(rows),
             }
         }
+        #[cfg(not(feature = "mmap"))]
+        None if args.mmap => {
+            return Err(error::Error::InvalidArgument(
+                "--mmap requires the `mmap` feature".to_string(),
+            ));
+        }
+        None => {
+            let reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(input::open_counted(path, &args.input_options, progress)?);
+            match args.parse_threads {
+                Some(threads) => Box::new(ParallelRows::new(reader, threads)?),
+                None => Box::new(input::numbered_rows(reader)?),
+            }
+        }
+    })
+}
+
+#[cfg(feature = "avro")]
+fn avro_rows(reader: Box<dyn Read + Send>) -> Result<Rows, error::Error> {
+    Ok(Box::new(avro::AvroRows::new(std::io::BufReader::new(
+        reader,
+    ))?))
+}
+
+#[cfg(not(feature = "avro"))]
+fn avro_rows(_reader: Box<dyn Read + Send>) -> Result<Rows, error::Error> {
+    Err(error::Error::InvalidArgument(
+        "--format avro requires the `avro` feature".to_string(),
+    ))
+}
+
+#[cfg(unix)]
+fn listen_unix(path: &str) -> Result<Rows, error::Error> {
+    Ok(Box::new(socket::SocketRows::bind(path)?))
+}
+
+#[cfg(not(unix))]
+fn listen_unix(_path: &str) -> Result<Rows, error::Error> {
+    Err(error::Error::InvalidArgument(
+        "--listen-unix requires a Unix platform".to_string(),
+    ))
+}
+
+// Everything about the engine that decides the outcome of a run, without its side effects
+// (WAL, progress) or the tuning
+fn processor_builder(args: &Args) -> Result<ProcessorBuilder, error::Error> {
+    let config = args.config;
+    let mut builder = ProcessorBuilder::new()
+        .config(config)
+        .strict(args.strict)
+        .rebalance(args.rebalance)
+        .evict_terminal(args.evict_terminal);
+    if let Some(workers) = args.workers {
+        builder = builder.workers(workers);
     }
+    if let Some(capacity) = args.channel_capacity {
+        builder = builder.channel_capacity(capacity);
+    }
+    if let Some(size) = args.batch_size {
+        builder = builder.batch_size(size);
+    }
+    if let Some(rate) = args.max_tps {
+        builder = builder.max_tps(rate);
+    }
+    if let Some(max) = args.max_deposits_per_worker {
+        builder = builder.max_deposits_per_worker(max);
+    }
+    if let Some(limit) = args.error_log_limit {
+        builder = builder.error_log_limit(limit);
+    }
+    // A missing store is the first run
+    if let Some(path) = &args.seen_store
+        && std::path::Path::new(path).exists()
+    {
+        let reader = std::io::BufReader::new(File::open(path)?);
+        builder = builder.seen(Deduplicator::load(config.dedup, config.dedup_key, reader)?);
+    } else if let Some(path) = &args.bloom_load {
+        let reader = std::io::BufReader::new(File::open(path)?);
+        builder = builder.seen(Deduplicator::load(config.dedup, config.dedup_key, reader)?);
+    } else if config.dedup == DedupStrategy::Bloom {
+        builder = builder.seen(Deduplicator::bloom(
+            args.expected_transactions,
+            args.bloom_fp_rate,
+        )?);
+    }
+    if let Some(path) = &args.overdraft_limits {
+        builder = builder.overdraft_limits(OverdraftLimits::from_reader(File::open(path)?)?);
+    }
+    // Read again for `--verify-deterministic`, so both runs start from the same state
+    if let Some(path) = &args.load_accounts {
+        let reader = std::io::BufReader::new(File::open(path)?);
+        builder = builder.accounts(incremental::load_accounts(reader)?);
+    }
+    if let Some(path) = &args.load_deposits {
+        let reader = std::io::BufReader::new(File::open(path)?);
+        builder = builder.deposits(incremental::load_deposits(reader)?);
+    }
+    if args.only_clients.is_some() || args.exclude_clients.is_some() {
+        let mut filter = ClientFilter::default();
+        if let Some(path) = &args.only_clients {
+            filter = filter.only(ClientFilter::read_list(File::open(path)?)?);
+        }
+        if let Some(path) = &args.exclude_clients {
+            filter = filter.exclude(ClientFilter::read_list(File::open(path)?)?);
+        }
+        builder = builder.client_filter(filter);
+    }
+    if let Some(path) = &args.mapping {
+        builder = builder.transformer(MappingTransformer::from_reader(File::open(path)?)?);
+    }
+    #[cfg(feature = "scripting")]
+    if let Some(path) = &args.script {
+        builder = builder.rule(script::ScriptRule::from_file(path)?);
+    }
+    #[cfg(not(feature = "scripting"))]
+    if args.script.is_some() {
+        return Err(error::Error::InvalidArgument(
+            "--script requires the `scripting` feature".to_string(),
+        ));
+    }
+    #[cfg(feature = "wasm-plugins")]
+    if let Some(path) = &args.plugin {
+        builder = builder.rule(plugin::WasmRule::from_file(path)?);
+    }
+    #[cfg(not(feature = "wasm-plugins"))]
+    if args.plugin.is_some() {
+        return Err(error::Error::InvalidArgument(
+            "--plugin requires the `wasm-plugins` feature".to_string(),
+        ));
+    }
+    Ok(builder)
+}
 
-    // Explicit drop to avoid another closure and a dedicated thread
-    drop(senders);
+// The store named by `--account-store`, if any
+#[cfg(feature = "redis")]
+fn open_account_store(args: &Args) -> Result<Option<Box<dyn AccountStore>>, error::Error> {
+    let Some(url) = &args.account_store else {
+        return Ok(None);
+    };
+    Ok(Some(Box::new(account_store::RedisAccountStore::open(url)?)))
+}
 
-    let accounts: AccountMap = handles
-        .into_iter()
-        .filter_map(|h| match h.join() {
-            Ok(acc) => Some(acc),
-            Err(_) => {
-                error!("Worker thread panicked");
-                None
-            }
+#[cfg(not(feature = "redis"))]
+fn open_account_store(args: &Args) -> Result<Option<Box<dyn AccountStore>>, error::Error> {
+    match args.account_store {
+        Some(_) => Err(error::Error::InvalidArgument(
+            "--account-store requires the `redis` feature".to_string(),
+        )),
+        None => Ok(None),
+    }
+}
+
+// Differing accounts listed in a failed determinism check
+const MAX_REPORTED_DELTAS: usize = 10;
+
+// Processes the inputs again on a single worker, where no client ever meets another shard,
+// and fails the run if any account ends up different from `accounts`
+fn verify_deterministic(
+    args: &Args,
+    inputs: &[String],
+    start: Option<&AccountMap>,
+    accounts: &AccountMap,
+) -> Result<(), error::Error> {
+    let sources = inputs
+        .iter()
+        .map(|path| Ok((path.clone(), open_rows(path, args, None, None)?)))
+        .collect::<Result<Vec<_>, error::Error>>()?;
+    let mut builder = processor_builder(args)?.workers(1);
+    if let Some(start) = start {
+        builder = builder.accounts(start.clone());
+    }
+    let reference = builder.build().run_sources(sources)?;
+    if reference.accounts == *accounts {
+        info!("Deterministic: a single worker produced the same accounts");
+        return Ok(());
+    }
+
+    // Sharded minus serial, per differing client
+    let deltas = diff::compare(&reference.accounts, accounts);
+    let mut report = format!("{} accounts differ from a single-worker run", deltas.len());
+    for delta in deltas.iter().take(MAX_REPORTED_DELTAS) {
+        report.push_str(&format!("\n  {}", delta));
+    }
+    if deltas.len() > MAX_REPORTED_DELTAS {
+        report.push_str(&format!(
+            "\n  ... {} more",
+            deltas.len() - MAX_REPORTED_DELTAS
+        ));
+    }
+    Err(error::Error::Nondeterministic(report))
+}
+
+// Violations listed in a failed invariant check
+const MAX_REPORTED_VIOLATIONS: usize = 10;
+
+// Fails the run if the merged state breaks an invariant, see `invariants::check`
+fn assert_invariants(
+    args: &Args,
+    config: &Config,
+    output: &ProcessOutput,
+) -> Result<(), error::Error> {
+    // Anything that may legitimately take an account below zero
+    let processed = |kind| output.metrics.counters(kind).processed > 0;
+    let negative_totals = config.dispute_overdraft == DisputeOverdraftPolicy::Clawback
+        || !config.overdraft_limit.is_zero()
+        || args.overdraft_limits.is_some()
+        || (config.void == VoidPolicy::Reverse && processed("void"))
+        || processed("adjustment");
+    let violations = invariants::check(&output.accounts, output.disputed.clone(), negative_totals);
+    if violations.is_empty() {
+        info!("Invariants hold for {} accounts", output.accounts.len());
+        return Ok(());
+    }
+
+    let mut report = format!("{} violations", violations.len());
+    for violation in violations.iter().take(MAX_REPORTED_VIOLATIONS) {
+        report.push_str(&format!("\n  {}", violation));
+    }
+    if violations.len() > MAX_REPORTED_VIOLATIONS {
+        report.push_str(&format!(
+            "\n  ... {} more",
+            violations.len() - MAX_REPORTED_VIOLATIONS
+        ));
+    }
+    Err(error::Error::InvariantsViolated(report))
+}
+
+fn main() -> Result<(), error::Error> {
+    env_logger::init();
+
+    let args: Args = match Command::parse(env::args().skip(1))? {
+        Command::Process(args) => *args,
+        Command::Replay { wal, config } => {
+            info!("Replaying WAL: {}", wal);
+            return write_accounts(
+                &mut CsvSink::new(std::io::stdout()),
+                replay(&wal)?,
+                &config,
+                OutputCompat::default(),
+                AccountOrder::default(),
+                !config.overdraft_limit.is_zero(),
+            );
+        }
+        Command::Generate(config) => {
+            return Ok(generate::generate(&config, std::io::stdout().lock())?);
+        }
+        Command::GrpcServe { addr, config } => return grpc_serve(addr, config),
+        Command::Bench(options) => return bench(options),
+        Command::Diff { old, new } => {
+            let deltas = diff::diff(File::open(old)?, File::open(new)?)?;
+            return diff::write(std::io::stdout().lock(), &deltas);
+        }
+        Command::Decrypt { identity, input } => return decrypt(&identity, &input),
+    };
+    let inputs = input::resolve_inputs(&args.inputs, args.input_dir.as_deref())?;
+    let config = args.config;
+    if streaming(&args) {
+        check_streaming(&args, &inputs)?;
+    }
+
+    if args.validate_only {
+        let sources = inputs
+            .iter()
+            .map(|path| Ok((path.clone(), open_rows(path, &args, None, None)?)))
+            .collect::<Result<Vec<_>, error::Error>>()?;
+        let mapping = match &args.mapping {
+            Some(path) => Some(MappingTransformer::from_reader(File::open(path)?)?),
+            None => None,
+        };
+        let transformer = mapping.as_ref().map(|m| m as &dyn RowTransformer);
+        let report = validate::validate(sources, transformer, &config);
+        report.write(std::io::stdout().lock())?;
+        if !report.passed() {
+            return Err(error::Error::ValidationFailed(report.to_string()));
+        }
+        info!("Validation passed: {}", report);
+        return Ok(());
+    }
+
+    if let Some(canary) = args.canary {
+        let report = canary::run(sample_rows(&inputs, &args.input_options)?, &canary, &config);
+        if !report.passed(&canary) {
+            return Err(error::Error::CanaryFailed(report.to_string()));
+        }
+        info!("Canary passed: {}", report);
+    }
+
+    match &args.listen_unix {
+        Some(path) => info!("Processing transactions sent to {}", path),
+        None => info!("Processing transactions from: {}", inputs.join(", ")),
+    }
+    // Skipping corrupt regions is exactly the silent data loss strict mode exists to stop
+    if args.quarantine.is_some() && args.strict {
+        return Err(error::Error::InvalidArgument(
+            "--strict cannot be combined with --quarantine".to_string(),
+        ));
+    }
+    let quarantine = args.quarantine.as_ref().map(File::create).transpose()?;
+    let progress = args.progress.then(|| Arc::new(Progress::default()));
+    // Opened up front, so a missing or unreadable input fails the run before any row is applied
+    let mut sources = inputs
+        .iter()
+        .map(|path| {
+            let rows = open_rows(path, &args, progress.as_ref(), quarantine.as_ref())?;
+            Ok((path.clone(), rows))
         })
-        .fold(AccountMap::new(), |mut merged, shard| {
-            merged.merge(shard);
-            merged
-        });
+        .collect::<Result<Vec<_>, error::Error>>()?;
+    if let Some(path) = &args.listen_unix {
+        sources.push((format!("unix:{}", path), listen_unix(path)?));
+    }
 
-    info!("Processing complete. {} accounts.", accounts.len());
+    // Evictions depend on how clients are spread over the workers, and recovery would write
+    // the skipped regions twice
+    if args.verify_deterministic
+        && (args.max_deposits_per_worker.is_some() || args.quarantine.is_some())
+    {
+        return Err(error::Error::InvalidArgument(
+            "--verify-deterministic cannot be combined with --max-deposits-per-worker or --quarantine"
+                .to_string(),
+        ));
+    }
 
-    let mut wtr = csv::Writer::from_writer(std::io::stdout());
-    for account in accounts.into_iter_sorted() {
-        wtr.serialize(AccountOutput::from(account))?;
+    let mut builder = processor_builder(&args)?;
+    // Loaded once, so the determinism check starts from the same accounts as the run
+    let mut account_store = open_account_store(&args)?;
+    let stored = match &mut account_store {
+        Some(store) => Some(store.load()?),
+        None => None,
+    };
+    if let Some(stored) = &stored {
+        info!("Loaded {} accounts from the account store", stored.len());
+        builder = builder.accounts(stored.clone());
+    }
+    if args.auto_tune {
+        let sample: Vec<TransactionRow> = sample_rows(&inputs, &args.input_options)?
+            .take(tune::DEFAULT_SAMPLE_ROWS)
+            .filter_map(Result::ok)
+            .collect();
+        let tuned = tune::tune(
+            &sample,
+            &config,
+            &tune::worker_candidates(),
+            args.channel_capacity,
+        );
+        info!("Auto-tune chose {}", tuned);
+        builder = builder
+            .workers(tuned.workers)
+            .channel_capacity(tuned.channel_capacity);
+    }
+    if let Some(path) = &args.wal {
+        let sink: Box<dyn Write + Send> = Box::new(BufWriter::new(File::create(path)?));
+        builder = builder.wal(WalWriter::new(sink)?);
+    }
+    if let Some(path) = &args.ledger {
+        let sink: Box<dyn Write + Send> = Box::new(BufWriter::new(File::create(path)?));
+        builder = builder.ledger(LedgerWriter::new(sink, &config)?);
+    }
+    if let Some(path) = &args.adjustments {
+        let sink: Box<dyn Write + Send> = Box::new(BufWriter::new(File::create(path)?));
+        builder = builder.adjustments(AdjustmentReport::new(sink, &config)?);
+    }
+    if let Some(path) = &args.conflicts {
+        let sink: Box<dyn Write + Send> = Box::new(BufWriter::new(File::create(path)?));
+        builder = builder.conflicts(ConflictReport::new(sink, &config)?);
+    }
+    if let Some(path) = &args.dead_letters {
+        let sink: Box<dyn Write + Send> = Box::new(BufWriter::new(File::create(path)?));
+        builder = builder.dead_letters(DeadLetterQueue::new(sink)?);
+    }
+    if !args.alerts.is_empty() {
+        let writer = AlertWriter::new(args.alert_target.clone(), &config);
+        builder = builder.alerts(args.alerts, writer);
+    }
+    // The input never ends, so the output is rewritten as it goes rather than once
+    if streaming(&args)
+        && let OutputTarget::File(path) = &args.output
+    {
+        let interval = Duration::from_secs(args.snapshot_interval);
+        builder = builder.snapshots(interval, snapshot_writer(&args, path));
     }
-    wtr.flush()?;
 
-    Ok(())
+    let to_sqlite = matches!(args.output, OutputTarget::Sqlite(_));
+    if args.output_deposits && !to_sqlite {
+        return Err(error::Error::InvalidArgument(
+            "--output-deposits requires --output sqlite:<path>".to_string(),
+        ));
+    }
+    if to_sqlite && args.output_format != OutputFormat::Csv {
+        return Err(error::Error::InvalidArgument(
+            "--output-format does not apply to sqlite output".to_string(),
+        ));
+    }
+    if to_sqlite && args.encrypt_output.is_some() {
+        return Err(error::Error::InvalidArgument(
+            "--encrypt-output does not apply to sqlite output".to_string(),
+        ));
+    }
+    // A bad key fails the run before it starts rather than once the output is due
+    #[cfg(feature = "encryption")]
+    if let Some(recipient) = &args.encrypt_output {
+        encryption::parse_recipient(recipient)?;
+    }
+    #[cfg(not(feature = "encryption"))]
+    if args.encrypt_output.is_some() {
+        return Err(error::Error::InvalidArgument(
+            "--encrypt-output requires the `encryption` feature".to_string(),
+        ));
+    }
+
+    // Recovery reads the file itself, and a socket has no size, so there is no byte count to
+    // estimate from
+    let mut reporter = None;
+    if let Some(progress) = progress {
+        let size = inputs
+            .iter()
+            .map(|path| std::fs::metadata(path).ok().map(|m| m.len()))
+            .sum::<Option<u64>>();
+        let total = if args.quarantine.is_some() || args.listen_unix.is_some() {
+            None
+        } else {
+            size
+        };
+        reporter = Some(progress.report(total));
+        builder = builder.progress(progress);
+    }
+    let output = builder.build().run_sources(sources);
+    // Stops the reporter, with a last line, before anything else is printed
+    drop(reporter);
+    let mut output = output?;
+
+    info!("Processing complete. {} accounts.", output.accounts.len());
+
+    if args.verify_deterministic {
+        verify_deterministic(&args, &inputs, stored.as_ref(), &output.accounts)?;
+    }
+    if args.assert_invariants {
+        assert_invariants(&args, &config, &output)?;
+    }
+    for stats in &output.stats {
+        info!("Source stats: {}", stats);
+    }
+
+    // Written only after a successful run, and swapped in whole so a crash mid-write keeps
+    // the previous store
+    if let Some(path) = &args.seen_store {
+        write_atomic(path, |file| output.seen.save(config.dedup_key, file))?;
+    }
+    if let Some(path) = &args.bloom_save {
+        write_atomic(path, |file| output.seen.save(config.dedup_key, file))?;
+    }
+    if let Some(path) = &args.save_deposits {
+        write_atomic(path, |file| {
+            incremental::write_deposits(file, &output.deposits)
+        })?;
+    }
+    // Only what this run changed, so accounts other instances saved meanwhile are kept
+    if let (Some(store), Some(stored)) = (&mut account_store, &stored) {
+        let changed = account_store::changed(stored, &output.accounts);
+        info!(
+            "Saving {} changed accounts to the account store",
+            changed.len()
+        );
+        store.save(&changed)?;
+    }
+
+    if let Some(path) = &args.metrics_json {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &output.metrics)
+            .map_err(std::io::Error::from)?;
+    }
+
+    if let Some(path) = &args.locked_report {
+        let out = BufWriter::new(File::create(path)?);
+        locked::write_report(out, &output.locks, &output.accounts, &config)?;
+    }
+
+    // Over every account, before `--client` narrows the output
+    if let Some(target) = &args.summary {
+        let summary = Summary::new(&output.metrics, &output.accounts, &config);
+        match target.as_str() {
+            "-" => eprint!("{}", summary),
+            path => write!(BufWriter::new(File::create(path)?), "{}", summary)?,
+        }
+    }
+
+    // Over every account too, so it doesn't depend on the output options
+    if args.state_hash {
+        eprintln!("state-hash: {}", state_hash::state_hash(&output.accounts));
+    }
+
+    if !args.clients.is_empty() {
+        output.accounts.retain_clients(&args.clients);
+    }
+
+    let overdraft = overdraft_column(&args);
+    let mut sink: Box<dyn OutputSink> = match &args.output {
+        OutputTarget::Stdout => output_sink(
+            std::io::stdout().lock(),
+            &config,
+            args.output_format,
+            args.encrypt_output.as_deref(),
+        )?,
+        // Swapped in whole, so a run that dies while writing leaves the previous file, if any
+        OutputTarget::File(path) => {
+            return write_atomic(path, |file| {
+                let recipient = args.encrypt_output.as_deref();
+                let mut sink = output_sink(file, &config, args.output_format, recipient)?;
+                let (compat, order) = (args.output_compat, args.order);
+                write_accounts(
+                    sink.as_mut(),
+                    output.accounts,
+                    &config,
+                    compat,
+                    order,
+                    overdraft,
+                )
+            });
+        }
+        #[cfg(feature = "sqlite")]
+        OutputTarget::Sqlite(path) => {
+            let sink = sqlite::SqliteSink::open(path, &config)?;
+            match args.output_deposits {
+                true => Box::new(sink.with_deposits(output.deposits)),
+                false => Box::new(sink),
+            }
+        }
+        #[cfg(not(feature = "sqlite"))]
+        OutputTarget::Sqlite(_) => {
+            return Err(error::Error::InvalidArgument(
+                "sqlite output requires the `sqlite` feature".to_string(),
+            ));
+        }
+    };
+    write_accounts(
+        sink.as_mut(),
+        output.accounts,
+        &config,
+        args.output_compat,
+        args.order,
+        overdraft,
+    )
 }
@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::stats::SourceStats;
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct TxCounters {
+    pub processed: u64,
+    pub rejected: u64,
+}
+
+impl TxCounters {
+    fn add(&mut self, other: &TxCounters) {
+        self.processed += other.processed;
+        self.rejected += other.rejected;
+    }
+}
+
+// Depth of a worker's channel, bumped by the reader on send and dropped by the worker on
+// receive. The high-water mark is what matters for sizing, so that is what gets reported.
+#[derive(Debug, Default)]
+pub struct QueueGauge {
+    depth: AtomicUsize,
+    max: AtomicUsize,
+}
+
+impl QueueGauge {
+    pub fn push(&self, rows: usize) {
+        let depth = self.depth.fetch_add(rows, Ordering::Relaxed) + rows;
+        self.max.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    pub fn pop(&self) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn max(&self) -> usize {
+        self.max.load(Ordering::Relaxed)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+}
+
+// Collected inside a single worker, no synchronisation needed until the final merge.
+#[derive(Debug, Default)]
+pub struct WorkerMetrics {
+    transactions: BTreeMap<&'static str, TxCounters>,
+    // Total value of the processed deposits and withdrawals
+    values: BTreeMap<&'static str, Decimal>,
+    // Rejected rows by `Error::reason`, or "rule" for rows a rule turned down
+    rejections: BTreeMap<&'static str, u64>,
+    // Deposits dropped to keep the store under `--max-deposits-per-worker`
+    evicted_deposits: u64,
+    // Disputes accepted on locked accounts under `LockedAccountDisputePolicy::AllowButFlag`
+    flagged_disputes: u64,
+    // Thresholds crossed, see `ProcessorBuilder::alerts`
+    alerts: u64,
+}
+
+impl WorkerMetrics {
+    pub fn record_processed(&mut self, kind: &'static str) {
+        self.transactions.entry(kind).or_default().processed += 1;
+    }
+
+    pub fn record_rejected(&mut self, kind: &'static str) {
+        self.transactions.entry(kind).or_default().rejected += 1;
+    }
+
+    pub fn record_value(&mut self, kind: &'static str, amount: Decimal) {
+        *self.values.entry(kind).or_default() += amount;
+    }
+
+    pub fn record_reason(&mut self, reason: &'static str) {
+        *self.rejections.entry(reason).or_default() += 1;
+    }
+
+    pub fn record_evicted(&mut self, count: usize) {
+        self.evicted_deposits += count as u64;
+    }
+
+    pub fn evicted_deposits(&self) -> u64 {
+        self.evicted_deposits
+    }
+
+    pub fn record_flagged_dispute(&mut self) {
+        self.flagged_disputes += 1;
+    }
+
+    pub fn record_alert(&mut self) {
+        self.alerts += 1;
+    }
+
+    fn totals(&self) -> TxCounters {
+        let mut totals = TxCounters::default();
+        for counters in self.transactions.values() {
+            totals.add(counters);
+        }
+        totals
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct WorkerSummary {
+    processed: u64,
+    rejected: u64,
+    max_queue_depth: usize,
+    // Size of the worker's deposit store at the end, and what a cap evicted from it
+    deposits: usize,
+    evicted_deposits: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Metrics {
+    rows_read: u64,
+    parse_errors: u64,
+    dedup_hits: u64,
+    // Rows ignored for being timestamped after `--as-of`
+    after_as_of: u64,
+    // Rows timestamped before an earlier row of their client, see `OrderingPolicy`
+    out_of_order: u64,
+    // Deposits and withdrawals refused for reusing a tx id with other data, see
+    // `ConflictReport`
+    conflicts: u64,
+    // Rows of clients left out by `ProcessorBuilder::client_filter`
+    filtered_clients: u64,
+    // Clients moved to another worker by rebalancing
+    rebalanced_clients: u64,
+    // Disputes accepted on locked accounts, see `LockedAccountDisputePolicy::AllowButFlag`
+    flagged_disputes: u64,
+    // Rows a panicked worker left unapplied, see `ProcessorBuilder::dead_letters`
+    dead_letters: u64,
+    // Thresholds crossed, see `ProcessorBuilder::alerts`
+    alerts: u64,
+    // Time the reader waited to stay under `ProcessorBuilder::max_tps`
+    throttled_secs: f64,
+    transactions: BTreeMap<&'static str, TxCounters>,
+    values: BTreeMap<&'static str, Decimal>,
+    rejections: BTreeMap<&'static str, u64>,
+    workers: Vec<WorkerSummary>,
+    // One per input, in the order they were read
+    sources: Vec<SourceStats>,
+    elapsed_secs: f64,
+    rows_per_sec: f64,
+}
+
+impl Metrics {
+    pub fn record_row(&mut self) {
+        self.rows_read += 1;
+    }
+
+    pub fn record_parse_error(&mut self) {
+        self.rows_read += 1;
+        self.parse_errors += 1;
+    }
+
+    pub fn record_dedup_hit(&mut self) {
+        self.dedup_hits += 1;
+    }
+
+    pub fn record_after_as_of(&mut self) {
+        self.after_as_of += 1;
+    }
+
+    // Counted whether the row was rejected or only warned about
+    pub fn record_out_of_order(&mut self, rejected: bool) {
+        self.out_of_order += 1;
+        if rejected {
+            *self.rejections.entry("out_of_order").or_default() += 1;
+        }
+    }
+
+    pub fn record_conflict(&mut self) {
+        self.conflicts += 1;
+        *self.rejections.entry("conflicting_duplicate").or_default() += 1;
+    }
+
+    pub fn record_filtered_client(&mut self) {
+        self.filtered_clients += 1;
+    }
+
+    pub fn record_rebalance(&mut self) {
+        self.rebalanced_clients += 1;
+    }
+
+    #[allow(dead_code)]
+    pub fn rebalanced_clients(&self) -> u64 {
+        self.rebalanced_clients
+    }
+
+    #[allow(dead_code)]
+    pub fn flagged_disputes(&self) -> u64 {
+        self.flagged_disputes
+    }
+
+    #[allow(dead_code)]
+    pub fn alerts(&self) -> u64 {
+        self.alerts
+    }
+
+    pub fn record_throttled(&mut self, wait: Duration) {
+        self.throttled_secs += wait.as_secs_f64();
+    }
+
+    #[allow(dead_code)]
+    pub fn throttled_secs(&self) -> f64 {
+        self.throttled_secs
+    }
+
+    pub fn record_dead_letters(&mut self, count: u64) {
+        self.dead_letters = count;
+    }
+
+    pub fn dead_letters(&self) -> u64 {
+        self.dead_letters
+    }
+
+    pub fn add_worker(&mut self, worker: WorkerMetrics, gauge: &QueueGauge, deposits: usize) {
+        self.flagged_disputes += worker.flagged_disputes;
+        self.alerts += worker.alerts;
+        for (kind, counters) in &worker.transactions {
+            self.transactions.entry(kind).or_default().add(counters);
+        }
+        for (kind, value) in &worker.values {
+            *self.values.entry(kind).or_default() += value;
+        }
+        for (reason, count) in &worker.rejections {
+            *self.rejections.entry(reason).or_default() += count;
+        }
+        let totals = worker.totals();
+        self.workers.push(WorkerSummary {
+            processed: totals.processed,
+            rejected: totals.rejected,
+            max_queue_depth: gauge.max(),
+            deposits,
+            evicted_deposits: worker.evicted_deposits,
+        });
+    }
+
+    pub fn record_source(&mut self, stats: &SourceStats) {
+        self.sources.push(stats.clone());
+    }
+
+    pub fn sources(&self) -> &[SourceStats] {
+        &self.sources
+    }
+
+    pub fn counters(&self, kind: &str) -> TxCounters {
+        self.transactions.get(kind).copied().unwrap_or_default()
+    }
+
+    pub fn value(&self, kind: &str) -> Decimal {
+        self.values.get(kind).copied().unwrap_or_default()
+    }
+
+    // Rejected rows by reason, including the reader's parse errors and duplicates
+    pub fn rejections(&self) -> BTreeMap<&'static str, u64> {
+        let mut rejections = self.rejections.clone();
+        for (reason, count) in [
+            ("parse_error", self.parse_errors),
+            ("duplicate", self.dedup_hits),
+        ] {
+            if count > 0 {
+                *rejections.entry(reason).or_default() += count;
+            }
+        }
+        rejections
+    }
+
+    pub fn rows_per_sec(&self) -> f64 {
+        self.rows_per_sec
+    }
+
+    pub fn finish(&mut self, elapsed: Duration) {
+        self.elapsed_secs = elapsed.as_secs_f64();
+        if self.elapsed_secs > 0.0 {
+            self.rows_per_sec = self.rows_read as f64 / self.elapsed_secs;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_worker_counters() {
+        let gauge = QueueGauge::default();
+        gauge.push(2);
+        gauge.pop();
+
+        let mut worker = WorkerMetrics::default();
+        worker.record_processed("deposit");
+        worker.record_processed("deposit");
+        worker.record_rejected("withdrawal");
+
+        let mut metrics = Metrics::default();
+        metrics.add_worker(worker, &gauge, 0);
+
+        assert_eq!(metrics.transactions["deposit"].processed, 2);
+        assert_eq!(metrics.transactions["withdrawal"].rejected, 1);
+        assert_eq!(metrics.workers[0].max_queue_depth, 2);
+        assert_eq!(metrics.workers[0].processed, 2);
+    }
+}
@@ -0,0 +1,297 @@
+use std::fs::File;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use csv_core::ReadRecordResult;
+use memmap2::Mmap;
+use rust_decimal::Decimal;
+
+use crate::error::Error;
+use crate::progress::Progress;
+use crate::transactions::{TransactionRow, TxType};
+
+pub(crate) const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+// Column positions of the fields the engine reads, taken from the header
+#[derive(Debug, Default)]
+pub(crate) struct Columns {
+    count: usize,
+    tx_type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    timestamp: Option<usize>,
+    reason: Option<usize>,
+    pending_until: Option<usize>,
+    sub: Option<usize>,
+}
+
+// Fast path for large uncompressed UTF-8 files: parses the bytes of a memory-mapped file in
+// place with `csv_core`, reusing one field buffer for every record. Known transaction types
+// are recognised from the raw field (`TxType`), so a row costs no allocation unless its type
+// is one the engine does not know. Trims fields like the buffered reader's `Trim::All` and
+// yields the same rows, tagged with the line they start on.
+pub struct MmapRows<D = Mmap> {
+    data: D,
+    pos: usize,
+    reader: csv_core::Reader,
+    columns: Columns,
+    fields: Vec<u8>,
+    ends: Vec<usize>,
+    // Newlines before `pos`, so a record's first line is known before it is read
+    lines: u64,
+    progress: Option<Arc<Progress>>,
+    // Bytes already counted towards `progress`
+    reported: usize,
+}
+
+impl MmapRows<Mmap> {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        // Safety: the map is read-only, but another process truncating the file while it is
+        // mapped would fault the run. Input files are not written to while being processed.
+        let data = unsafe { Mmap::map(&file)? };
+        Self::new(data)
+    }
+}
+
+impl<D: AsRef<[u8]>> MmapRows<D> {
+    pub fn new(data: D) -> Result<Self, Error> {
+        let mut rows = Self {
+            data,
+            pos: 0,
+            reader: csv_core::Reader::new(),
+            columns: Columns::default(),
+            fields: vec![0; 256],
+            ends: vec![0; 8],
+            lines: 0,
+            progress: None,
+            reported: 0,
+        };
+        if rows.data.as_ref().starts_with(UTF8_BOM) {
+            rows.pos = UTF8_BOM.len();
+        }
+        let Some((_, count)) = rows.read_record() else {
+            return Err(Error::InvalidArgument("missing header row".to_string()));
+        };
+        rows.columns = Columns::from_header(count, |i| rows.field(i))?;
+        Ok(rows)
+    }
+
+    // Counts the bytes parsed towards the input bytes read
+    pub fn with_progress(mut self, progress: Arc<Progress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    // Reads the next record into `fields`/`ends`, returning its first line and field count
+    fn read_record(&mut self) -> Option<(u64, usize)> {
+        let data = self.data.as_ref();
+        // Blank lines are not records, skip them here so the start line is exact
+        while let Some(&byte) = data.get(self.pos)
+            && (byte == b'\n' || byte == b'\r')
+        {
+            self.lines += u64::from(byte == b'\n');
+            self.pos += 1;
+        }
+        let line = self.lines + 1;
+        let (mut out, mut count) = (0, 0);
+        loop {
+            let (result, read, written, ended) = self.reader.read_record(
+                &data[self.pos..],
+                &mut self.fields[out..],
+                &mut self.ends[count..],
+            );
+            self.pos += read;
+            self.lines += data[self.pos - read..self.pos]
+                .iter()
+                .filter(|&&byte| byte == b'\n')
+                .count() as u64;
+            out += written;
+            count += ended;
+            match result {
+                // An empty slice tells the reader the input has ended
+                ReadRecordResult::InputEmpty => {}
+                ReadRecordResult::OutputFull => self.fields.resize(self.fields.len() * 2, 0),
+                ReadRecordResult::OutputEndsFull => self.ends.resize(self.ends.len() * 2, 0),
+                ReadRecordResult::Record => return Some((line, count)),
+                ReadRecordResult::End => return None,
+            }
+        }
+    }
+
+    fn field(&self, index: usize) -> &[u8] {
+        let start = match index {
+            0 => 0,
+            i => self.ends[i - 1],
+        };
+        self.fields[start..self.ends[index]].trim_ascii()
+    }
+}
+
+impl Columns {
+    // Finds the columns in the header's `count` fields, trimmed
+    pub(crate) fn from_header<'a>(
+        count: usize,
+        field: impl Fn(usize) -> &'a [u8],
+    ) -> Result<Self, Error> {
+        let find = |name: &str| (0..count).position(|i| field(i) == name.as_bytes());
+        let require = |name: &str| {
+            find(name).ok_or_else(|| Error::InvalidArgument(format!("missing column {}", name)))
+        };
+        Ok(Columns {
+            count,
+            tx_type: require("type")?,
+            client: require("client")?,
+            tx: require("tx")?,
+            amount: find("amount"),
+            timestamp: find("timestamp"),
+            reason: find("reason"),
+            pending_until: find("pending_until"),
+            sub: find("sub"),
+        })
+    }
+
+    // The row of a record's `count` fields, trimmed, that started on `line`
+    pub(crate) fn parse<'a>(
+        &self,
+        line: u64,
+        count: usize,
+        field: impl Fn(usize) -> &'a [u8],
+    ) -> Result<TransactionRow, io::Error> {
+        if count != self.count {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "line {}: expected {} fields, found {}",
+                    line, self.count, count
+                ),
+            ));
+        }
+        // An optional column, absent when empty
+        let optional = |index: Option<usize>| index.map(&field).filter(|f| !f.is_empty());
+
+        let tx_type = match TxType::from_bytes(field(self.tx_type)) {
+            Some(tx_type) => tx_type,
+            None => TxType::Other(text(field(self.tx_type), line, "type")?.to_string()),
+        };
+        let amount = match optional(self.amount) {
+            Some(bytes) => {
+                let amount = text(bytes, line, "amount")?;
+                // The same forms the serde path accepts
+                let parsed =
+                    Decimal::from_str(amount).or_else(|_| Decimal::from_scientific(amount));
+                Some(parsed.map_err(|_| invalid(line, "amount"))?)
+            }
+            None => None,
+        };
+        let timestamp = optional(self.timestamp)
+            .map(|bytes| number(bytes, line, "timestamp"))
+            .transpose()?;
+        let reason = optional(self.reason)
+            .map(|bytes| text(bytes, line, "reason").map(str::to_string))
+            .transpose()?;
+        let pending_until = optional(self.pending_until)
+            .map(|bytes| number(bytes, line, "pending_until"))
+            .transpose()?;
+        let sub = optional(self.sub)
+            .map(|bytes| number(bytes, line, "sub"))
+            .transpose()?;
+
+        let mut row = TransactionRow::new(
+            tx_type,
+            number(field(self.client), line, "client")?,
+            number(field(self.tx), line, "tx")?,
+            amount,
+        );
+        row.set_timestamp(timestamp);
+        row.set_reason(reason);
+        row.set_pending_until(pending_until);
+        row.set_sub(sub);
+        row.set_line(line);
+        Ok(row)
+    }
+}
+
+fn text<'a>(bytes: &'a [u8], line: u64, name: &str) -> Result<&'a str, io::Error> {
+    std::str::from_utf8(bytes).map_err(|_| invalid(line, name))
+}
+
+fn number<T: FromStr>(bytes: &[u8], line: u64, name: &str) -> Result<T, io::Error> {
+    text(bytes, line, name)?
+        .parse()
+        .map_err(|_| invalid(line, name))
+}
+
+fn invalid(line: u64, field: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: invalid {}", line, field),
+    )
+}
+
+impl<D: AsRef<[u8]>> Iterator for MmapRows<D> {
+    type Item = Result<TransactionRow, csv::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (line, count) = self.read_record()?;
+        if let Some(progress) = &self.progress {
+            progress.record_bytes((self.pos - self.reported) as u64);
+            self.reported = self.pos;
+        }
+        let row = self.columns.parse(line, count, |i| self.field(i));
+        Some(row.map_err(csv::Error::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::numbered_rows;
+
+    #[test]
+    fn matches_buffered_reader() {
+        let data = "\u{feff}type, client, tx, amount, timestamp\n\
+                    deposit, 1, 1, 1.5, 10\n\
+                    withdrawal,2,2,2e1,\n\
+                    dispute,1,1,,\n\
+                    \"refund\",3,4,0.25,\n\
+                    deposit,4,5,not-a-number,\n\
+                    resolve,1,1";
+        let buffered: Vec<_> = numbered_rows(
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(data.as_bytes()),
+        )
+        .unwrap()
+        .collect();
+        let fast: Vec<_> = MmapRows::new(data.as_bytes()).unwrap().collect();
+
+        assert_eq!(fast.len(), buffered.len());
+        for (fast, buffered) in fast.iter().zip(&buffered) {
+            match (fast, buffered) {
+                (Ok(fast), Ok(buffered)) => assert_eq!(fast, buffered),
+                (Err(_), Err(_)) => {}
+                _ => panic!("readers disagree: {:?} vs {:?}", fast, buffered),
+            }
+        }
+        assert!(
+            matches!(&fast[3], Ok(row) if row.tx_type().as_str() == "refund" && row.line() == Some(5))
+        );
+        // A bad amount and a short row are errors with either reader
+        assert!(fast[4].is_err() && fast[5].is_err());
+    }
+
+    #[test]
+    fn requires_the_engine_columns() {
+        assert!(MmapRows::new("type,client,amount\n".as_bytes()).is_err());
+        assert!(MmapRows::new("".as_bytes()).is_err());
+        assert_eq!(
+            MmapRows::new("type,tx,client\n".as_bytes())
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use std::cmp::Ordering;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::account::{Account, AccountOutput};
+use crate::error::Error;
+
+// Receives the final account states one at a time, in output order, then `finish` once.
+// The CLI writes through one of these whatever the target; library users can implement it to
+// send accounts anywhere else.
+pub trait OutputSink {
+    fn write_account(&mut self, account: &AccountOutput) -> Result<(), Error>;
+
+    // Flushes or commits whatever the sink buffered; nothing is guaranteed to be written before
+    fn finish(&mut self) -> Result<(), Error>;
+}
+
+// CSV with a header row, the default output
+pub struct CsvSink<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(sink: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(sink),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for CsvSink<W> {
+    fn write_account(&mut self, account: &AccountOutput) -> Result<(), Error> {
+        Ok(self.writer.serialize(account)?)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+// A JSON array of account objects, streamed so it is never held in memory whole. Amounts are
+// strings like in the CSV, so no precision is lost to floats on the reading side.
+pub struct JsonSink<W: Write> {
+    sink: W,
+    written: u64,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(sink: W) -> Self {
+        Self { sink, written: 0 }
+    }
+}
+
+impl<W: Write> OutputSink for JsonSink<W> {
+    fn write_account(&mut self, account: &AccountOutput) -> Result<(), Error> {
+        let separator: &[u8] = if self.written == 0 {
+            b"[\n  "
+        } else {
+            b",\n  "
+        };
+        self.sink.write_all(separator)?;
+        serde_json::to_writer(&mut self.sink, account).map_err(std::io::Error::from)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        let end: &[u8] = if self.written == 0 { b"[]\n" } else { b"\n]\n" };
+        self.sink.write_all(end)?;
+        Ok(self.sink.flush()?)
+    }
+}
+
+// Where the final account states go. CSV on stdout unless `--output` says otherwise; a plain
+// path is a file.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum OutputTarget {
+    #[default]
+    Stdout,
+    File(String),
+    Sqlite(String),
+}
+
+// Encoding of the account table for stdout and file targets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+    // Arrow IPC stream, requires the `arrow` feature
+    Arrow,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "arrow" => Ok(OutputFormat::Arrow),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown output format {}",
+                s
+            ))),
+        }
+    }
+}
+
+// Layout of the CSV output. `V1` is frozen: columns client,available,held,total,locked with
+// amounts at 4 decimal places whatever `--precision` says, so existing downstream parsers keep
+// working while the default layout evolves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputCompat {
+    #[default]
+    Latest,
+    V1,
+}
+
+impl FromStr for OutputCompat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "latest" => Ok(OutputCompat::Latest),
+            "v1" => Ok(OutputCompat::V1),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown output compatibility {}",
+                s
+            ))),
+        }
+    }
+}
+
+// Column the output is ordered by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    #[default]
+    Client,
+    Available,
+    Held,
+    Total,
+}
+
+impl FromStr for SortKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(SortKey::Client),
+            "available" => Ok(SortKey::Available),
+            "held" => Ok(SortKey::Held),
+            "total" => Ok(SortKey::Total),
+            _ => Err(Error::InvalidArgument(format!("unknown sort key {}", s))),
+        }
+    }
+}
+
+// Order of the emitted accounts, `--sort-by` and `--desc`. Equal keys fall back to ascending
+// client id, then sub-account, so the output stays deterministic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AccountOrder {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl AccountOrder {
+    pub fn compare(&self, a: &Account, b: &Account) -> Ordering {
+        let ordering = match self.key {
+            SortKey::Client => a.key().cmp(&b.key()),
+            SortKey::Available => a.available().cmp(&b.available()),
+            SortKey::Held => a.held().cmp(&b.held()),
+            SortKey::Total => a.total().cmp(&b.total()),
+        };
+        let ordering = if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+        ordering.then_with(|| a.key().cmp(&b.key()))
+    }
+}
+
+impl FromStr for OutputTarget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "-" || s == "stdout" => Ok(OutputTarget::Stdout),
+            Some(("file", path)) if !path.is_empty() => Ok(OutputTarget::File(path.to_string())),
+            Some(("sqlite", path)) if !path.is_empty() => {
+                Ok(OutputTarget::Sqlite(path.to_string()))
+            }
+            None if !s.is_empty() => Ok(OutputTarget::File(s.to_string())),
+            _ => Err(Error::InvalidArgument(format!("unknown output {}", s))),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::error::Error;
+
+#[derive(Debug, Deserialize)]
+struct LimitRow {
+    client: u16,
+    limit: Decimal,
+}
+
+// Per-client overdraft limits from a `client,limit` CSV, e.g.
+//
+//   client,limit
+//   1,250.00
+//   7,0
+//
+// A listed client gets its own limit in place of `Config::overdraft_limit`, so `0` takes a
+// client out of a run-wide limit.
+#[derive(Debug, Default, Clone)]
+pub struct OverdraftLimits {
+    clients: HashMap<u16, Decimal>,
+}
+
+impl OverdraftLimits {
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, Error> {
+        let mut limits = Self::default();
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        for result in rdr.deserialize() {
+            let row: LimitRow = result?;
+            if row.limit.is_sign_negative() {
+                return Err(Error::InvalidArgument(format!(
+                    "negative overdraft limit for client {}",
+                    row.client
+                )));
+            }
+            limits.clients.insert(row.client, row.limit);
+        }
+        Ok(limits)
+    }
+
+    pub fn get(&self, client: u16) -> Option<Decimal> {
+        self.clients.get(&client).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_limits_and_rejects_negative() {
+        let limits =
+            OverdraftLimits::from_reader("client,limit\n1, 250.5\n7,0\n".as_bytes()).unwrap();
+
+        assert_eq!(limits.get(1), Some(Decimal::new(2505, 1)));
+        assert_eq!(limits.get(7), Some(Decimal::ZERO));
+        assert_eq!(limits.get(2), None);
+        assert!(OverdraftLimits::from_reader("client,limit\n1,-5\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use csv::ByteRecord;
+
+use crate::transactions::TransactionRow;
+
+// Records handed to a parser thread at a time
+const CHUNK_ROWS: usize = 512;
+
+type Chunk = (u64, Vec<Result<ByteRecord, csv::Error>>);
+type Parsed = (u64, Vec<Result<TransactionRow, csv::Error>>);
+
+// `numbered_rows` with the deserializing spread over a pool of threads, for inputs where the
+// single reader can't keep up with the workers. A splitter thread only cuts the input into
+// records, in chunks, and `threads` parsers turn whole chunks into rows. Chunks are numbered,
+// so the rows come out in input order whichever parser finishes first, and everything after
+// (dedup, routing, a client's order) is as with one thread. At most a few chunks per thread
+// are in flight. The threads start with the first row, so inputs opened up front and read one
+// after the other don't all parse at once.
+pub struct ParallelRows {
+    start: Option<Box<dyn FnOnce() -> Receiver<Parsed>>>,
+    parsed: Option<Receiver<Parsed>>,
+    // Chunks parsed ahead of the one due next
+    ahead: HashMap<u64, Vec<Result<TransactionRow, csv::Error>>>,
+    next: u64,
+    current: std::vec::IntoIter<Result<TransactionRow, csv::Error>>,
+}
+
+impl ParallelRows {
+    pub fn new<R: Read + Send + 'static>(
+        rdr: csv::Reader<R>,
+        threads: usize,
+    ) -> Result<Self, csv::Error> {
+        Self::with_chunks(rdr, threads, CHUNK_ROWS)
+    }
+
+    fn with_chunks<R: Read + Send + 'static>(
+        mut rdr: csv::Reader<R>,
+        threads: usize,
+        chunk_rows: usize,
+    ) -> Result<Self, csv::Error> {
+        let headers = Arc::new(rdr.byte_headers()?.clone());
+        crate::input::check_header(&headers)?;
+        let start = move || spawn(rdr, headers, threads.max(1), chunk_rows);
+        Ok(Self {
+            start: Some(Box::new(start)),
+            parsed: None,
+            ahead: HashMap::new(),
+            next: 0,
+            current: Vec::new().into_iter(),
+        })
+    }
+}
+
+fn spawn<R: Read + Send + 'static>(
+    rdr: csv::Reader<R>,
+    headers: Arc<ByteRecord>,
+    threads: usize,
+    chunk_rows: usize,
+) -> Receiver<Parsed> {
+    let (chunks, work) = mpsc::sync_channel::<Chunk>(threads * 2);
+    let (done, parsed) = mpsc::sync_channel::<Parsed>(threads * 2);
+
+    // Once the rows are dropped, a failed send stops each thread in turn
+    thread::spawn(move || {
+        let mut records = rdr.into_byte_records();
+        for seq in 0.. {
+            let chunk: Vec<_> = records.by_ref().take(chunk_rows).collect();
+            if chunk.is_empty() || chunks.send((seq, chunk)).is_err() {
+                break;
+            }
+        }
+    });
+    let work = Arc::new(Mutex::new(work));
+    for _ in 0..threads {
+        let (work, done, headers) = (work.clone(), done.clone(), headers.clone());
+        thread::spawn(move || {
+            loop {
+                // The lock is held only to take a chunk, not while parsing it
+                let next = work.lock().unwrap().recv();
+                let Ok((seq, records)) = next else {
+                    break;
+                };
+                let rows = records
+                    .into_iter()
+                    .map(|record| parse(record?, &headers))
+                    .collect();
+                if done.send((seq, rows)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    parsed
+}
+
+// As in `numbered_rows`, tagged with the line the record started on
+fn parse(record: ByteRecord, headers: &ByteRecord) -> Result<TransactionRow, csv::Error> {
+    let mut row: TransactionRow = record.deserialize(Some(headers))?;
+    if let Some(position) = record.position() {
+        row.set_line(position.line());
+    }
+    Ok(row)
+}
+
+impl Iterator for ParallelRows {
+    type Item = Result<TransactionRow, csv::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.current.next() {
+                return Some(row);
+            }
+            let parsed = self
+                .parsed
+                .get_or_insert_with(|| (self.start.take().unwrap())());
+            let rows = match self.ahead.remove(&self.next) {
+                Some(rows) => rows,
+                None => match parsed.recv() {
+                    Ok((seq, rows)) if seq == self.next => rows,
+                    Ok((seq, rows)) => {
+                        self.ahead.insert(seq, rows);
+                        continue;
+                    }
+                    // Every parser is gone, so every chunk was parsed
+                    Err(_) => return None,
+                },
+            };
+            self.next += 1;
+            self.current = rows.into_iter();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::numbered_rows;
+
+    fn reader(data: &str) -> csv::Reader<std::io::Cursor<Vec<u8>>> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(std::io::Cursor::new(data.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn rows_come_out_in_input_order() {
+        let mut data = "type, client, tx, amount\n".to_string();
+        for tx in 1..=1000 {
+            match tx % 97 {
+                0 => data.push_str("deposit,1,x,1\n"),
+                _ => data.push_str(&format!("deposit, {}, {}, {}.5\n", tx % 7, tx, tx)),
+            }
+        }
+        let expected: Vec<_> = numbered_rows(reader(&data)).unwrap().collect();
+        let parallel: Vec<_> = ParallelRows::with_chunks(reader(&data), 4, 7)
+            .unwrap()
+            .collect();
+
+        assert_eq!(parallel.len(), expected.len());
+        for (parallel, expected) in parallel.iter().zip(&expected) {
+            match (parallel, expected) {
+                (Ok(parallel), Ok(expected)) => assert_eq!(parallel, expected),
+                (Err(_), Err(_)) => {}
+                _ => panic!("readers disagree: {:?} vs {:?}", parallel, expected),
+            }
+        }
+        assert_eq!(parallel.iter().filter(|row| row.is_err()).count(), 10);
+    }
+}
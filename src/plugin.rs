@@ -0,0 +1,218 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use wasmtime::{Config, Engine, Instance, Module, Store, TypedFunc};
+
+use crate::account::Account;
+use crate::error::Error;
+use crate::rule::{RowRule, RuleDecision};
+use crate::transactions::TransactionRow;
+
+// Host API, version 1. A plugin is a WASM module without imports exporting:
+//
+//   tp_abi_version() -> i32                              must return 1
+//   tp_evaluate(kind: i32, client: i32, tx: i32, amount: i64,
+//               available: i64, held: i64, locked: i32) -> i64
+//
+// kind is 1 deposit, 2 withdrawal, 3 dispute, 4 resolve, 5 chargeback, 6 void,
+// 7 deposit_correction, 8 withdrawal_correction, 0 anything else.
+// Amounts are fixed point with 4 decimal places (12.5 is 125000); a missing amount, or the
+// balances of a client without an account yet, are i64::MIN. The result is REJECT (-1),
+// ACCEPT (-2) or, when >= 0, the amount to accept the row with.
+pub const ABI_VERSION: i32 = 1;
+pub const NONE: i64 = i64::MIN;
+pub const REJECT: i64 = -1;
+pub const ACCEPT: i64 = -2;
+
+const SCALE: u32 = 4;
+// Upper bound on the work a single call may do, so a looping plugin fails the row instead of
+// stalling its worker
+const FUEL_PER_ROW: u64 = 1_000_000;
+
+type EvaluateFn = TypedFunc<(i32, i32, i32, i64, i64, i64, i32), i64>;
+
+struct PluginInstance {
+    store: Store<()>,
+    evaluate: EvaluateFn,
+}
+
+// Sandboxed alternative to scripting: the module gets no host imports at all (no filesystem,
+// clock or network), only the arguments above. A wasmtime store is single threaded, so the
+// workers take turns on one instance; plugins are meant to be small pure functions.
+pub struct WasmRule {
+    instance: Mutex<PluginInstance>,
+}
+
+impl WasmRule {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::compile(std::fs::read(path)?)
+    }
+
+    // Accepts binary modules, and the text format for convenience
+    pub fn compile(bytes: impl AsRef<[u8]>) -> Result<Self, Error> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(plugin_error)?;
+        let module = Module::new(&engine, bytes).map_err(plugin_error)?;
+        if module.imports().len() > 0 {
+            return Err(Error::Plugin(
+                "plugins may not import host functions".to_string(),
+            ));
+        }
+
+        let mut store = Store::new(&engine, ());
+        store.set_fuel(FUEL_PER_ROW).map_err(plugin_error)?;
+        let instance = Instance::new(&mut store, &module, &[]).map_err(plugin_error)?;
+
+        let version = instance
+            .get_typed_func::<(), i32>(&mut store, "tp_abi_version")
+            .and_then(|f| f.call(&mut store, ()))
+            .map_err(plugin_error)?;
+        if version != ABI_VERSION {
+            return Err(Error::Plugin(format!(
+                "unsupported plugin ABI version {}, expected {}",
+                version, ABI_VERSION
+            )));
+        }
+        let evaluate = instance
+            .get_typed_func(&mut store, "tp_evaluate")
+            .map_err(plugin_error)?;
+
+        Ok(Self {
+            instance: Mutex::new(PluginInstance { store, evaluate }),
+        })
+    }
+}
+
+impl RowRule for WasmRule {
+    fn evaluate(
+        &self,
+        mut row: TransactionRow,
+        account: Option<&Account>,
+    ) -> Result<RuleDecision, Error> {
+        let amount = row.amount().map_or(Ok(NONE), to_fixed)?;
+        let (available, held, locked) = match account {
+            Some(a) => (to_fixed(a.available())?, to_fixed(a.held())?, a.locked()),
+            None => (NONE, NONE, false),
+        };
+        let args = (
+            kind_code(row.kind()),
+            row.client() as i32,
+            row.tx() as i32,
+            amount,
+            available,
+            held,
+            locked as i32,
+        );
+
+        let mut instance = self.instance.lock().unwrap();
+        let PluginInstance { store, evaluate } = &mut *instance;
+        store.set_fuel(FUEL_PER_ROW).map_err(plugin_error)?;
+        let result = evaluate.call(&mut *store, args).map_err(plugin_error)?;
+
+        match result {
+            REJECT => Ok(RuleDecision::Reject),
+            ACCEPT => Ok(RuleDecision::Accept(row)),
+            amount if amount >= 0 => {
+                row.set_amount(Some(Decimal::new(amount, SCALE)));
+                Ok(RuleDecision::Accept(row))
+            }
+            other => Err(Error::Plugin(format!(
+                "tp_evaluate returned invalid result {}",
+                other
+            ))),
+        }
+    }
+}
+
+fn kind_code(kind: &str) -> i32 {
+    match kind {
+        "deposit" => 1,
+        "withdrawal" => 2,
+        "dispute" => 3,
+        "resolve" => 4,
+        "chargeback" => 5,
+        "void" => 6,
+        "deposit_correction" => 7,
+        "withdrawal_correction" => 8,
+        "chargeback_reversal" => 9,
+        _ => 0,
+    }
+}
+
+fn to_fixed(amount: Decimal) -> Result<i64, Error> {
+    (amount * Decimal::from(10i64.pow(SCALE)))
+        .round()
+        .to_i64()
+        .filter(|v| *v != NONE)
+        .ok_or_else(|| Error::Plugin(format!("amount {} out of range", amount)))
+}
+
+fn plugin_error(e: wasmtime::Error) -> Error {
+    Error::Plugin(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Rejects withdrawals above the available balance, halves every deposit
+    const PLUGIN: &str = r#"
+        (module
+          (func (export "tp_abi_version") (result i32) i32.const 1)
+          (func (export "tp_evaluate")
+                (param $kind i32) (param $client i32) (param $tx i32) (param $amount i64)
+                (param $available i64) (param $held i64) (param $locked i32) (result i64)
+            (if (i32.eq (local.get $kind) (i32.const 1))
+              (then (return (i64.div_u (local.get $amount) (i64.const 2)))))
+            (if (i32.and (i32.eq (local.get $kind) (i32.const 2))
+                         (i64.gt_s (local.get $amount) (local.get $available)))
+              (then (return (i64.const -1))))
+            i64.const -2))
+    "#;
+
+    #[test]
+    fn plugin_decides_per_row() {
+        let rule = WasmRule::compile(PLUGIN).unwrap();
+        let mut account = Account::new(1);
+        account.deposit(Decimal::new(10, 0)).unwrap();
+
+        let deposit = TransactionRow::new("deposit", 1, 1, Some(Decimal::new(5, 0)));
+        let RuleDecision::Accept(row) = rule.evaluate(deposit, None).unwrap() else {
+            panic!("expected accept");
+        };
+        assert_eq!(row.amount(), Some(Decimal::new(25, 1)));
+
+        let withdrawal = TransactionRow::new("withdrawal", 1, 2, Some(Decimal::new(20, 0)));
+        assert!(matches!(
+            rule.evaluate(withdrawal, Some(&account)).unwrap(),
+            RuleDecision::Reject
+        ));
+    }
+
+    #[test]
+    fn rejects_plugins_with_imports_or_wrong_abi() {
+        let imports = r#"(module (import "env" "now" (func)))"#;
+        let version = r#"(module (func (export "tp_abi_version") (result i32) i32.const 2))"#;
+
+        assert!(matches!(WasmRule::compile(imports), Err(Error::Plugin(_))));
+        assert!(matches!(WasmRule::compile(version), Err(Error::Plugin(_))));
+    }
+
+    #[test]
+    fn runaway_plugin_fails_the_row() {
+        let looping = r#"
+            (module
+              (func (export "tp_abi_version") (result i32) i32.const 1)
+              (func (export "tp_evaluate") (param i32 i32 i32 i64 i64 i64 i32) (result i64)
+                (loop $l (br $l))
+                i64.const -2))
+        "#;
+        let rule = WasmRule::compile(looping).unwrap();
+
+        let row = TransactionRow::new("deposit", 1, 1, Some(Decimal::ONE));
+        assert!(matches!(rule.evaluate(row, None), Err(Error::Plugin(_))));
+    }
+}
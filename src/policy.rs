@@ -0,0 +1,306 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+
+use crate::error::Error;
+
+// How the reader recognises replayed deposits and withdrawals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DedupStrategy {
+    // Constant memory, rare false drops. The historical behaviour.
+    #[default]
+    Bloom,
+    // Never drops a legitimate row, memory grows with the number of tx ids
+    Exact,
+    // Trust the input
+    None,
+}
+
+impl FromStr for DedupStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bloom" => Ok(DedupStrategy::Bloom),
+            "exact" => Ok(DedupStrategy::Exact),
+            "none" => Ok(DedupStrategy::None),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown dedup strategy {}",
+                s
+            ))),
+        }
+    }
+}
+
+// What identifies a replayed row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DedupKey {
+    // Tx id alone. Drops rows of different clients that happen to reuse an id.
+    Tx,
+    // Client and tx id together, what deposits are stored and disputed by
+    #[default]
+    ClientTx,
+}
+
+impl FromStr for DedupKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tx" => Ok(DedupKey::Tx),
+            "client-tx" => Ok(DedupKey::ClientTx),
+            _ => Err(Error::InvalidArgument(format!("unknown dedup key {}", s))),
+        }
+    }
+}
+
+// What a dispute does when the client has already spent part of the deposit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisputeOverdraftPolicy {
+    // Hold the full amount and let available go negative (debt owed by the client)
+    #[default]
+    Clawback,
+    // Refuse disputes that would take available below zero
+    Reject,
+}
+
+impl FromStr for DisputeOverdraftPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clawback" => Ok(DisputeOverdraftPolicy::Clawback),
+            "reject" => Ok(DisputeOverdraftPolicy::Reject),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown dispute overdraft policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+// Zero value deposits and withdrawals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ZeroAmountPolicy {
+    #[default]
+    Accept,
+    Reject,
+}
+
+impl FromStr for ZeroAmountPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accept" => Ok(ZeroAmountPolicy::Accept),
+            "reject" => Ok(ZeroAmountPolicy::Reject),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown zero amount policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+// What voiding a deposit does to the client's balance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VoidPolicy {
+    // Take the deposited amount back out of available, which may go negative like a clawback
+    #[default]
+    Reverse,
+    // Only mark the deposit, e.g. when the funds were already corrected out of band
+    Retain,
+}
+
+impl FromStr for VoidPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reverse" => Ok(VoidPolicy::Reverse),
+            "retain" => Ok(VoidPolicy::Retain),
+            _ => Err(Error::InvalidArgument(format!("unknown void policy {}", s))),
+        }
+    }
+}
+
+// Whether reversing a chargeback also lifts the lock the chargeback put on the account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReversalPolicy {
+    // Only the funds come back; other chargebacks may be why the account is locked
+    #[default]
+    KeepLocked,
+    Unlock,
+}
+
+impl FromStr for ReversalPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep-locked" => Ok(ReversalPolicy::KeepLocked),
+            "unlock" => Ok(ReversalPolicy::Unlock),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown chargeback reversal policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+// What happens to a row timestamped before an earlier row of the same client. Rows without a
+// timestamp are never out of order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OrderingPolicy {
+    // Apply rows in input order whatever their timestamps
+    #[default]
+    Any,
+    // Apply it, but log a warning and count it
+    Warn,
+    // Drop it like an invalid row
+    Reject,
+}
+
+impl FromStr for OrderingPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(OrderingPolicy::Any),
+            "warn" => Ok(OrderingPolicy::Warn),
+            "reject" => Ok(OrderingPolicy::Reject),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown ordering policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+// What resolves and chargebacks may still do once an account has been locked. New disputes
+// are governed by `LockedAccountDisputePolicy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccountPolicy {
+    // Open disputes settle normally: resolves release held funds, chargebacks remove them
+    #[default]
+    Settle,
+    // Held funds can be released, but no further chargebacks
+    ResolveOnly,
+    // Nothing moves on a locked account, held funds stay held until it is reviewed
+    Frozen,
+}
+
+impl AccountPolicy {
+    pub fn allows_resolve(self) -> bool {
+        self != AccountPolicy::Frozen
+    }
+
+    pub fn allows_chargeback(self) -> bool {
+        self == AccountPolicy::Settle
+    }
+}
+
+impl FromStr for AccountPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "settle" => Ok(AccountPolicy::Settle),
+            "resolve-only" => Ok(AccountPolicy::ResolveOnly),
+            "frozen" => Ok(AccountPolicy::Frozen),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown locked account policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+// Whether new disputes are accepted once an account has been locked by a chargeback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockedAccountDisputePolicy {
+    #[default]
+    Allow,
+    Reject,
+    // Accepted, but logged as a warning and counted as `flagged_disputes` in the metrics, so
+    // risk can review them without refusing the client's claim
+    AllowButFlag,
+}
+
+impl FromStr for LockedAccountDisputePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(LockedAccountDisputePolicy::Allow),
+            "reject" => Ok(LockedAccountDisputePolicy::Reject),
+            "allow-but-flag" => Ok(LockedAccountDisputePolicy::AllowButFlag),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown locked account dispute policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+// Fee charged on each withdrawal, on top of the amount withdrawn: `0.5%` of the amount or a
+// fixed `0.25`. Collected fees go to the house account (`Config::fee_account`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FeePolicy {
+    #[default]
+    None,
+    Fixed(Decimal),
+    // As a fraction, `0.5%` is 0.005
+    Rate(Decimal),
+}
+
+impl FeePolicy {
+    // The fee for withdrawing `amount`, before rounding
+    pub fn fee(&self, amount: Decimal) -> Decimal {
+        match self {
+            FeePolicy::None => Decimal::ZERO,
+            FeePolicy::Fixed(fee) => *fee,
+            FeePolicy::Rate(rate) => amount * rate,
+        }
+    }
+}
+
+impl FromStr for FeePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidArgument(format!("invalid fee {}", s));
+        let (number, percent) = match s.strip_suffix('%') {
+            Some(number) => (number, true),
+            None if s == "none" => return Ok(FeePolicy::None),
+            None => (s, false),
+        };
+        let value = Decimal::from_str(number).map_err(|_| invalid())?;
+        if value.is_sign_negative() {
+            return Err(invalid());
+        }
+        Ok(match percent {
+            true => FeePolicy::Rate(value / Decimal::ONE_HUNDRED),
+            false => FeePolicy::Fixed(value),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fees() {
+        let rate: FeePolicy = "0.5%".parse().unwrap();
+        assert_eq!(rate, FeePolicy::Rate(Decimal::new(5, 3)));
+        assert_eq!(rate.fee(Decimal::new(50, 0)), Decimal::new(25, 2));
+        assert_eq!(
+            "0.25".parse::<FeePolicy>().unwrap(),
+            FeePolicy::Fixed(Decimal::new(25, 2))
+        );
+        assert_eq!("none".parse::<FeePolicy>().unwrap(), FeePolicy::None);
+        assert!("-1".parse::<FeePolicy>().is_err());
+        assert!("%".parse::<FeePolicy>().is_err());
+    }
+}
@@ -0,0 +1,2136 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{debug, error, warn};
+use rust_decimal::Decimal;
+
+use crate::account::{Account, AccountKey, AccountMap};
+use crate::adjustments::AdjustmentReport;
+use crate::alerts::{AlertThresholds, AlertWriter};
+use crate::client_filter::ClientFilter;
+use crate::config::Config;
+use crate::conflicts::ConflictReport;
+use crate::dead_letter::DeadLetterQueue;
+use crate::dedup::Deduplicator;
+use crate::deposit_store::{
+    DepositKey, DepositOwners, DepositStore, OrderedDeposits, StoredDeposit, TerminalDeposits,
+    Tombstone,
+};
+use crate::error::Error;
+use crate::error_log::{DEFAULT_ERROR_LOG_LIMIT, ErrorLog};
+use crate::events::EventHandler;
+use crate::interest;
+use crate::ledger::{Balances, LedgerWriter};
+use crate::locked::AccountLock;
+use crate::metrics::{Metrics, QueueGauge, WorkerMetrics};
+use crate::overdraft::OverdraftLimits;
+use crate::policy::{
+    AccountPolicy, DedupKey, DedupStrategy, DisputeOverdraftPolicy, FeePolicy,
+    LockedAccountDisputePolicy, OrderingPolicy, ZeroAmountPolicy,
+};
+use crate::progress::Progress;
+use crate::rule::{RowRule, RuleDecision};
+use crate::stats::SourceStats;
+use crate::throttle::TokenBucket;
+use crate::transactions::{ProcessableTx, ReleaseTx, Transaction, TransactionRow, TxRegistry};
+use crate::transform::RowTransformer;
+use crate::wal::WalWriter;
+
+pub const DEFAULT_WORKERS: usize = 4;
+// Rows buffered per worker before the reader blocks. Bounds memory when workers fall behind
+// a fast reader; at ~100 bytes a row this is about 1MB per worker.
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 10_000;
+// Rows the reader gathers per worker before sending them over in one message. One send per
+// row costs more than the row itself once the input is fast.
+pub const DEFAULT_BATCH_SIZE: usize = 1024;
+// With a dispute window, expired deposits are swept from the store every this many rows
+const EVICTION_INTERVAL: usize = 10_000;
+// With rebalancing, queue depths are compared every this many routed rows
+const REBALANCE_INTERVAL: usize = 1_000;
+// How often a worker waiting for a handed-off client checks whether the run was aborted
+const HANDOFF_POLL: Duration = Duration::from_millis(50);
+
+pub type WalSink = WalWriter<Box<dyn Write + Send>>;
+
+// Shared across workers; appends are serialised by the mutex. Per-client order in the log
+// still matches application order because a client is only ever handled by one worker at a
+// time; rebalancing hands a client over only after its old worker has applied all its rows.
+type SharedWal = Arc<Mutex<WalSink>>;
+
+pub type LedgerSink = LedgerWriter<Box<dyn Write + Send>>;
+
+// Shared like the WAL, entries of a client are in application order
+type SharedLedger = Arc<Mutex<LedgerSink>>;
+
+pub type AdjustmentSink = AdjustmentReport<Box<dyn Write + Send>>;
+
+type SharedAdjustments = Arc<Mutex<AdjustmentSink>>;
+
+pub type ConflictSink = ConflictReport<Box<dyn Write + Send>>;
+
+pub type DeadLetterSink = DeadLetterQueue<Box<dyn Write + Send>>;
+
+// Written by the reader when a worker's queue is gone, and by a worker that panicked
+type SharedDeadLetters = Arc<Mutex<DeadLetterSink>>;
+
+// Shared like the WAL, so a client's alerts are in application order
+type SharedAlerts = Arc<Mutex<AlertWriter>>;
+
+// Receives the accounts of each periodic snapshot, see `ProcessorBuilder::snapshots`
+pub type SnapshotSink = Box<dyn FnMut(AccountMap) + Send>;
+
+// Everything a worker needs besides its queue, cloned once per spawned worker
+#[derive(Clone)]
+struct WorkerContext {
+    config: Config,
+    wal: Option<SharedWal>,
+    ledger: Option<SharedLedger>,
+    adjustments: Option<SharedAdjustments>,
+    dead_letters: Option<SharedDeadLetters>,
+    alerts: Option<(AlertThresholds, SharedAlerts)>,
+    registry: Arc<TxRegistry>,
+    owners: Arc<DepositOwners>,
+    rules: Vec<Arc<dyn RowRule>>,
+    handlers: Vec<Arc<dyn EventHandler>>,
+    record_order: bool,
+    strict: bool,
+    overdraft_limits: Arc<OverdraftLimits>,
+    progress: Option<Arc<Progress>>,
+    max_deposits: Option<usize>,
+    evict_terminal: bool,
+    errors: Arc<ErrorLog>,
+    // Set by the first worker to fail in strict mode, tells the reader to stop
+    abort: Arc<AtomicBool>,
+}
+
+// What travels on a worker's queue
+enum WorkerMessage {
+    // Rows in input order, at most a batch of them
+    Rows(Vec<TransactionRow>),
+    // Send `client`'s state to worker `to`. Queued behind the last row routed here for it.
+    Release { client: u16, to: usize },
+    // Hold `client`'s rows until its state arrives from the previous worker
+    Expect(u16),
+    // Send a copy of the worker's accounts and unpaid fees as of the rows before this one
+    Snapshot(Sender<(AccountMap, HashMap<u16, Decimal>)>),
+}
+
+// A client's state on its way between workers
+struct Handoff {
+    client: u16,
+    // The client's main account and sub-accounts, whichever exist
+    accounts: Vec<Account>,
+    deposits: Vec<(DepositKey, StoredDeposit)>,
+    terminal: Vec<(DepositKey, Tombstone)>,
+    order: Vec<u32>,
+    // Seq of the client's last applied row, for the ordering check
+    last_seq: Option<u64>,
+}
+
+struct WorkerOutput {
+    accounts: AccountMap,
+    deposits: HashMap<DepositKey, StoredDeposit>,
+    metrics: WorkerMetrics,
+    order: AppliedOrder,
+    fees: HashMap<u16, Decimal>,
+    locks: Vec<AccountLock>,
+    failure: Option<(u64, Error)>,
+}
+
+// Tx ids in the order they were applied, per client
+pub type AppliedOrder = HashMap<u16, Vec<u32>>;
+
+// Assembles a `Processor`. Every behaviour that used to be a hard-coded decision is a policy
+// on the underlying `Config`; the defaults reproduce the original engine exactly.
+pub struct ProcessorBuilder {
+    config: Config,
+    workers: usize,
+    channel_capacity: usize,
+    batch_size: usize,
+    max_tps: Option<u64>,
+    transformer: Option<Box<dyn RowTransformer>>,
+    wal: Option<WalSink>,
+    ledger: Option<LedgerSink>,
+    adjustments: Option<AdjustmentSink>,
+    conflicts: Option<ConflictSink>,
+    dead_letters: Option<DeadLetterSink>,
+    alerts: Option<(AlertThresholds, AlertWriter)>,
+    registry: TxRegistry,
+    rules: Vec<Arc<dyn RowRule>>,
+    handlers: Vec<Arc<dyn EventHandler>>,
+    record_order: bool,
+    strict: bool,
+    rebalance: bool,
+    seen: Option<Deduplicator>,
+    overdraft_limits: OverdraftLimits,
+    progress: Option<Arc<Progress>>,
+    max_deposits: Option<usize>,
+    evict_terminal: bool,
+    error_log_limit: u64,
+    client_filter: ClientFilter,
+    snapshots: Option<(Duration, SnapshotSink)>,
+    accounts: AccountMap,
+    deposits: HashMap<DepositKey, StoredDeposit>,
+}
+
+impl Default for ProcessorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProcessorBuilder {
+    pub fn new() -> Self {
+        Self {
+            config: Config::default(),
+            workers: DEFAULT_WORKERS,
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            batch_size: DEFAULT_BATCH_SIZE,
+            max_tps: None,
+            transformer: None,
+            wal: None,
+            ledger: None,
+            adjustments: None,
+            conflicts: None,
+            dead_letters: None,
+            alerts: None,
+            registry: TxRegistry::default(),
+            rules: Vec::new(),
+            handlers: Vec::new(),
+            record_order: false,
+            strict: false,
+            rebalance: false,
+            seen: None,
+            overdraft_limits: OverdraftLimits::default(),
+            progress: None,
+            max_deposits: None,
+            evict_terminal: false,
+            error_log_limit: DEFAULT_ERROR_LOG_LIMIT,
+            client_filter: ClientFilter::default(),
+            snapshots: None,
+            accounts: AccountMap::new(),
+            deposits: HashMap::new(),
+        }
+    }
+
+    // Replaces the whole config, policies included. Call before the individual setters.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    // Zero makes every send a rendezvous with the worker
+    pub fn channel_capacity(mut self, capacity: usize) -> Self {
+        self.channel_capacity = capacity;
+        self
+    }
+
+    // Rows sent to a worker at a time. The queue holds `channel_capacity` rows' worth of
+    // batches. Runs with snapshots send rows one by one, since a followed input can wait
+    // indefinitely with a batch half full.
+    pub fn batch_size(mut self, size: usize) -> Self {
+        self.batch_size = size.max(1);
+        self
+    }
+
+    // Sends at most `rate` rows a second to the workers, see `TokenBucket`. Rows dropped
+    // before routing, as duplicates, filtered or rejected, don't count.
+    pub fn max_tps(mut self, rate: u64) -> Self {
+        self.max_tps = Some(rate);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn dedup(mut self, strategy: DedupStrategy) -> Self {
+        self.config.dedup = strategy;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn dedup_key(mut self, key: DedupKey) -> Self {
+        self.config.dedup_key = key;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn dispute_overdraft(mut self, policy: DisputeOverdraftPolicy) -> Self {
+        self.config.dispute_overdraft = policy;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn zero_amount(mut self, policy: ZeroAmountPolicy) -> Self {
+        self.config.zero_amount = policy;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn locked_account(mut self, policy: AccountPolicy) -> Self {
+        self.config.locked_account = policy;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn locked_dispute(mut self, policy: LockedAccountDisputePolicy) -> Self {
+        self.config.locked_dispute = policy;
+        self
+    }
+
+    // Refuses rows whose non-zero amount is smaller than `min` or larger than `max`, with
+    // `Error::AmountOutOfBounds`
+    #[allow(dead_code)]
+    pub fn amount_limits(mut self, min: Option<Decimal>, max: Option<Decimal>) -> Self {
+        self.config.min_amount = min;
+        self.config.max_amount = max;
+        self
+    }
+
+    // Charges `policy` on every withdrawal and credits the fees to `account`
+    #[allow(dead_code)]
+    pub fn withdrawal_fee(mut self, policy: FeePolicy, account: u16) -> Self {
+        self.config.withdrawal_fee = policy;
+        self.config.fee_account = Some(account);
+        self
+    }
+
+    pub fn transformer(mut self, transformer: impl RowTransformer + 'static) -> Self {
+        self.transformer = Some(Box::new(transformer));
+        self
+    }
+
+    pub fn wal(mut self, wal: WalSink) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    // Journals every applied transaction, and the interest accrued at the end, as balanced
+    // double-entry postings. Flushed at the end of the run.
+    pub fn ledger(mut self, ledger: LedgerSink) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    // Reports every adjustment row that reached an account, applied or refused, with its
+    // reason code. Flushed at the end of the run.
+    pub fn adjustments(mut self, report: AdjustmentSink) -> Self {
+        self.adjustments = Some(report);
+        self
+    }
+
+    // Refuses deposits and withdrawals that reuse an earlier one's tx id with other data, and
+    // writes them to `report`. Checked by the reader, before dedup. Flushed at the end of the
+    // run.
+    pub fn conflicts(mut self, report: ConflictSink) -> Self {
+        self.conflicts = Some(report);
+        self
+    }
+
+    // Writes the rows a panicking worker leaves unapplied to `queue` instead of losing them:
+    // the row it panicked on, the rest of its queue, and rows the reader had for it after.
+    // The worker's accounts are still lost. Flushed at the end of the run.
+    pub fn dead_letters(mut self, queue: DeadLetterSink) -> Self {
+        self.dead_letters = Some(queue);
+        self
+    }
+
+    // Sends an alert to `writer` whenever a transaction takes an account across one of the
+    // `thresholds`, as it is applied. Failures to send are logged and the run goes on.
+    pub fn alerts(mut self, thresholds: AlertThresholds, writer: AlertWriter) -> Self {
+        self.alerts = (!thresholds.is_empty()).then_some((thresholds, writer));
+        self
+    }
+
+    // Accepts rows of type `name`, parsed by `parse` into a transaction of its own, see
+    // `TxRegistry::register`
+    #[allow(dead_code)]
+    pub fn register_tx<T, F>(mut self, name: &str, parse: F) -> Self
+    where
+        T: ProcessableTx + 'static,
+        F: Fn(TransactionRow, &Config) -> Result<T, Error> + Send + Sync + 'static,
+    {
+        self.registry.register(name, parse);
+        self
+    }
+
+    // Rules run in the order they were added; the first rejection wins
+    #[allow(dead_code)]
+    pub fn rule(mut self, rule: impl RowRule + 'static) -> Self {
+        self.rules.push(Arc::new(rule));
+        self
+    }
+
+    // Calls `handler` as rows are applied and refused and accounts locked, see `EventHandler`.
+    // Handlers are called in the order they were added. With any handler, each row is cloned
+    // before it is applied, to report it if it is refused.
+    #[allow(dead_code)]
+    pub fn event_handler(mut self, handler: impl EventHandler + 'static) -> Self {
+        self.handlers.push(Arc::new(handler));
+        self
+    }
+
+    // Records the tx ids each client had applied, in order, and returns them in
+    // `ProcessOutput::applied_order`. Costs 4 bytes per applied row; meant for tests and audits.
+    #[allow(dead_code)]
+    pub fn record_order(mut self, record: bool) -> Self {
+        self.record_order = record;
+        self
+    }
+
+    // Abort the run on the first malformed row, rule error or failed transaction instead of
+    // logging and skipping it. Rows a rule rejects or dedup drops are not failures.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    // Moves busy clients off a worker whose queue backs up while another worker sits idle.
+    // A client moves at most once per run, and only after its old worker has drained its rows.
+    pub fn rebalance(mut self, rebalance: bool) -> Self {
+        self.rebalance = rebalance;
+        self
+    }
+
+    // Starts dedup from keys seen by earlier runs (`Deduplicator::load`) instead of empty. The
+    // keys of this run come back in `ProcessOutput::seen`.
+    pub fn seen(mut self, seen: Deduplicator) -> Self {
+        self.seen = Some(seen);
+        self
+    }
+
+    // Per-client overdraft limits, taking precedence over `Config::overdraft_limit`
+    pub fn overdraft_limits(mut self, limits: OverdraftLimits) -> Self {
+        self.overdraft_limits = limits;
+        self
+    }
+
+    // Live counters for a progress display, updated as rows are read and rejected
+    pub fn progress(mut self, progress: Arc<Progress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    // Caps each worker's deposit store at `max` entries, evicting the oldest beyond it. An
+    // evicted deposit can no longer be disputed, so this trades correctness for memory.
+    pub fn max_deposits_per_worker(mut self, max: usize) -> Self {
+        self.max_deposits = Some(max);
+        self
+    }
+
+    // Drops deposits from the store once resolved or charged back, keeping only their id,
+    // account and status for accurate rejects; see `TerminalDeposits`. Resolved deposits can
+    // then no longer be voided or corrected, nor chargebacks reversed. They are missing from
+    // `ProcessOutput::deposits` too.
+    pub fn evict_terminal(mut self, evict: bool) -> Self {
+        self.evict_terminal = evict;
+        self
+    }
+
+    // Logs only the first `limit` rejected rows of each kind, then counts; see `ErrorLog`
+    pub fn error_log_limit(mut self, limit: u64) -> Self {
+        self.error_log_limit = limit;
+        self
+    }
+
+    // Drops the rows of clients the filter does not allow as they are read, after the
+    // transformer but before dedup, so they neither reach a worker nor mark a tx id as seen
+    pub fn client_filter(mut self, filter: ClientFilter) -> Self {
+        self.client_filter = filter;
+        self
+    }
+
+    // Every `interval` while the run is going, collects the accounts from the workers and
+    // hands them to `sink`, withdrawal fees paid but interest not yet accrued. Each worker
+    // answers between two of its rows, so the accounts are each consistent but not all as of
+    // the same input row, and with rebalancing a client on its way between workers is missing.
+    // For runs over input that does not end, such as a followed file.
+    pub fn snapshots(
+        mut self,
+        interval: Duration,
+        sink: impl FnMut(AccountMap) + Send + 'static,
+    ) -> Self {
+        self.snapshots = Some((interval, Box::new(sink)));
+        self
+    }
+
+    // Starts from `accounts` instead of none, e.g. as loaded from an `AccountStore`. Only the
+    // balances carry over: deposits of earlier runs are unknown unless passed to `deposits`,
+    // so disputes of them fail as unknown transactions. The accounts come back in
+    // `ProcessOutput::accounts`, whether or not this run touched them.
+    pub fn accounts(mut self, accounts: AccountMap) -> Self {
+        self.accounts = accounts;
+        self
+    }
+
+    // Starts from the deposits of an earlier run, as `ProcessOutput::deposits` returned them,
+    // so this run's rows can dispute, resolve and charge them back. Pass the accounts of the
+    // same run to `accounts`: the funds a dispute holds are in those balances.
+    pub fn deposits(mut self, deposits: HashMap<DepositKey, StoredDeposit>) -> Self {
+        self.deposits = deposits;
+        self
+    }
+
+    pub fn build(self) -> Processor {
+        Processor {
+            accounts: self.accounts,
+            deposits: self.deposits,
+            client_filter: self.client_filter,
+            snapshots: self.snapshots,
+            seen: self.seen,
+            workers: self.workers,
+            channel_capacity: self.channel_capacity,
+            batch_size: self.batch_size,
+            max_tps: self.max_tps,
+            rebalance: self.rebalance,
+            transformer: self.transformer,
+            conflicts: self.conflicts,
+            context: WorkerContext {
+                config: self.config,
+                wal: self.wal.map(|wal| Arc::new(Mutex::new(wal))),
+                ledger: self.ledger.map(|ledger| Arc::new(Mutex::new(ledger))),
+                adjustments: self.adjustments.map(|report| Arc::new(Mutex::new(report))),
+                dead_letters: self.dead_letters.map(|queue| Arc::new(Mutex::new(queue))),
+                alerts: self
+                    .alerts
+                    .map(|(thresholds, writer)| (thresholds, Arc::new(Mutex::new(writer)))),
+                registry: Arc::new(self.registry),
+                owners: Arc::default(),
+                rules: self.rules,
+                handlers: self.handlers,
+                record_order: self.record_order,
+                strict: self.strict,
+                overdraft_limits: Arc::new(self.overdraft_limits),
+                progress: self.progress,
+                max_deposits: self.max_deposits,
+                evict_terminal: self.evict_terminal,
+                errors: Arc::new(ErrorLog::new(self.error_log_limit)),
+                abort: Arc::default(),
+            },
+        }
+    }
+}
+
+pub struct ProcessOutput {
+    pub accounts: AccountMap,
+    // Deposit stores of all workers, withdrawals included (`StoredDeposit::is_withdrawal`)
+    #[allow(dead_code)]
+    pub deposits: HashMap<DepositKey, StoredDeposit>,
+    // What each account's disputed and pending deposits hold
+    pub disputed: HashMap<AccountKey, Decimal>,
+    pub metrics: Metrics,
+    // One per input source, in the order they were read
+    pub stats: Vec<SourceStats>,
+    // Keys of every deposit and withdrawal seen so far, including those of `ProcessorBuilder::seen`
+    pub seen: Deduplicator,
+    // Only with `ProcessorBuilder::record_order`
+    #[allow(dead_code)]
+    pub applied_order: Option<AppliedOrder>,
+    // Accounts locked by a chargeback during the run, by account and tx id
+    pub locks: Vec<AccountLock>,
+}
+
+pub struct Processor {
+    workers: usize,
+    channel_capacity: usize,
+    batch_size: usize,
+    max_tps: Option<u64>,
+    rebalance: bool,
+    seen: Option<Deduplicator>,
+    transformer: Option<Box<dyn RowTransformer>>,
+    conflicts: Option<ConflictSink>,
+    client_filter: ClientFilter,
+    snapshots: Option<(Duration, SnapshotSink)>,
+    accounts: AccountMap,
+    deposits: HashMap<DepositKey, StoredDeposit>,
+    context: WorkerContext,
+}
+
+impl Processor {
+    // Streams `rows` through the workers and returns the merged state once the input is
+    // exhausted. Parse errors are counted and skipped, they never abort the run unless
+    // strict, in which case the failure with the lowest line is returned as `RowFailed`.
+    // Rows without a line are numbered by position, assuming a header and one row per line.
+    pub fn run<I>(self, source: &str, rows: I) -> Result<ProcessOutput, Error>
+    where
+        I: IntoIterator<Item = Result<TransactionRow, csv::Error>>,
+    {
+        self.run_sources([(source.to_string(), rows)])
+    }
+
+    // Like `run`, over several sources read one after the other into the same workers, as if
+    // they were one input. Lines, and so the line of a strict failure, are per source.
+    pub fn run_sources<S, I>(self, sources: S) -> Result<ProcessOutput, Error>
+    where
+        S: IntoIterator<Item = (String, I)>,
+        I: IntoIterator<Item = Result<TransactionRow, csv::Error>>,
+    {
+        let mut dedup = self
+            .seen
+            .unwrap_or_else(|| Deduplicator::new(self.context.config.dedup));
+        let mut conflicts = self.conflicts;
+        let mut all_stats = Vec::new();
+        let mut metrics = Metrics::default();
+        let started = Instant::now();
+        let strict = self.context.strict;
+        let mut limiter = self.max_tps.map(TokenBucket::new);
+        let mut failure = None;
+
+        let batch_size = match self.snapshots {
+            Some(_) => 1,
+            None => self.batch_size,
+        };
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..self.workers)
+            .map(|_| {
+                let capacity = self.channel_capacity.div_ceil(batch_size);
+                mpsc::sync_channel::<WorkerMessage>(capacity)
+            })
+            .unzip();
+        // Unbounded, so a worker releasing a client never waits on the one adopting it
+        let (handoff_senders, handoff_receivers): (Vec<_>, Vec<_>) = (0..self.workers)
+            .map(|_| mpsc::channel::<Handoff>())
+            .unzip();
+
+        let gauges: Vec<Arc<QueueGauge>> = (0..self.workers).map(|_| Arc::default()).collect();
+        // Starting accounts and deposits go to the worker their client's rows will be routed to
+        let mut seeds: Vec<Shard> = (0..self.workers).map(|_| Shard::default()).collect();
+        for account in self.accounts.into_iter_sorted() {
+            seeds[account.client() as usize % self.workers]
+                .accounts
+                .insert(account);
+        }
+        // Interest goes past every id read, in this run or one carried over
+        let mut last_tx = dedup
+            .last_tx()
+            .max(self.deposits.keys().map(|key| key.tx).max());
+        let mut deposits: Vec<_> = self.deposits.into_iter().collect();
+        deposits.sort_by_key(|(key, _)| *key);
+        for (key, deposit) in deposits {
+            self.context.owners.record(key.tx, key.client);
+            seeds[key.client as usize % self.workers]
+                .deposits
+                .extend([(key, deposit)]);
+        }
+
+        let handles: Vec<_> = receivers
+            .into_iter()
+            .zip(handoff_receivers)
+            .zip(&gauges)
+            .zip(seeds)
+            .enumerate()
+            .map(|(index, (((rx, handoffs), gauge), shard))| {
+                let peers = handoff_senders.clone();
+                let gauge = gauge.clone();
+                let context = self.context.clone();
+                thread::spawn(move || {
+                    worker_loop(index, rx, handoffs, peers, gauge, shard, context)
+                })
+            })
+            .collect();
+        drop(handoff_senders);
+        let snapshots = self
+            .snapshots
+            .map(|(interval, sink)| Snapshots::spawn(interval, sink, senders.clone()));
+
+        // Clients rebalancing moved off `client % workers`, and rows per client since the
+        // last depth check
+        let mut routes: HashMap<u16, usize> = HashMap::new();
+        let mut recent: HashMap<u16, usize> = HashMap::new();
+        let mut since_check = 0usize;
+        // Latest timestamp per client, for `OrderingPolicy`
+        let mut latest: HashMap<u16, u64> = HashMap::new();
+        // Rows read for each worker but not sent yet
+        let mut batches: Vec<Vec<TransactionRow>> = (0..self.workers)
+            .map(|_| Vec::with_capacity(batch_size))
+            .collect();
+
+        // Rows read so far, over all sources
+        let mut seq = 0u64;
+
+        'sources: for (source, rows) in sources {
+            let mut stats = SourceStats::new(source.as_str());
+            let mut record = 0u64;
+            for result in rows {
+                if self.context.abort.load(Ordering::Relaxed) {
+                    break 'sources;
+                }
+                record += 1;
+                if let Some(progress) = &self.context.progress {
+                    progress.record_row();
+                }
+                let mut row: TransactionRow = match result {
+                    Ok(r) => r,
+                    Err(e) => {
+                        self.context.errors.error(
+                            "parse_error",
+                            format_args!("Failed to parse CSV row: {}", e),
+                        );
+                        stats.record_reject();
+                        metrics.record_parse_error();
+                        if let Some(progress) = &self.context.progress {
+                            progress.record_rejected();
+                        }
+                        if strict {
+                            let line = e.position().map_or(record + 1, |p| p.line());
+                            failure = Some((line, Error::Csv(e)));
+                            break 'sources;
+                        }
+                        continue;
+                    }
+                };
+                if row.line().is_none() {
+                    row.set_line(record + 1);
+                }
+                row.set_seq(seq);
+                seq += 1;
+                metrics.record_row();
+                last_tx = last_tx.max(Some(row.tx()));
+
+                let row = match &self.transformer {
+                    Some(transformer) => match transformer.transform(row) {
+                        Some(row) => row,
+                        None => {
+                            debug!("Row dropped by transformer");
+                            continue;
+                        }
+                    },
+                    None => row,
+                };
+
+                if !self.client_filter.allows(row.client()) {
+                    metrics.record_filtered_client();
+                    continue;
+                }
+
+                // Ignored before dedup, so a later row can't shadow one that still counts
+                if self.context.config.after_cutoff(row.timestamp()) {
+                    debug!("Row tx={} is after the as-of cut-off - ignored", row.tx());
+                    metrics.record_after_as_of();
+                    continue;
+                }
+
+                // Also before dedup, so a rejected row doesn't mark its tx id as seen
+                let ordering = self.context.config.ordering;
+                if ordering != OrderingPolicy::Any
+                    && let Some(timestamp) = row.timestamp()
+                {
+                    let latest = latest.entry(row.client()).or_insert(timestamp);
+                    if timestamp < *latest {
+                        let e = Error::OutOfOrder {
+                            tx_id: row.tx(),
+                            client: row.client(),
+                            timestamp,
+                            latest: *latest,
+                        };
+                        let reject = ordering == OrderingPolicy::Reject;
+                        metrics.record_out_of_order(reject);
+                        if reject {
+                            self.context.errors.error(e.reason(), format_args!("{}", e));
+                            self.context.rejected(&row, &e);
+                            stats.record_reject();
+                            if let Some(progress) = &self.context.progress {
+                                progress.record_rejected();
+                            }
+                            if strict {
+                                failure = Some((row.line().unwrap_or(record + 1), e));
+                                break 'sources;
+                            }
+                            continue;
+                        }
+                        warn!("{}", e);
+                    }
+                    *latest = timestamp.max(*latest);
+                }
+
+                // Before dedup too, which would drop the row as a mere duplicate with
+                // `DedupKey::Tx`
+                let conflict = conflicts.as_mut().and_then(|report| {
+                    report
+                        .check(&source, &row)
+                        .unwrap_or_else(|e| {
+                            error!("Failed to write to the conflicts report: {}", e);
+                            None
+                        })
+                        .map(|first_line| Error::ConflictingDuplicate {
+                            tx_id: row.tx(),
+                            first_line,
+                        })
+                });
+                if let Some(e) = conflict {
+                    self.context.errors.error(e.reason(), format_args!("{}", e));
+                    self.context.rejected(&row, &e);
+                    stats.record_reject();
+                    metrics.record_conflict();
+                    if let Some(progress) = &self.context.progress {
+                        progress.record_rejected();
+                    }
+                    if strict {
+                        failure = Some((row.line().unwrap_or(record + 1), e));
+                        break 'sources;
+                    }
+                    continue;
+                }
+
+                if row.should_dedupe()
+                    && dedup.check_and_insert(row.dedup_key(self.context.config.dedup_key))
+                {
+                    warn!(
+                        "Possible duplicate tx={} client={} type={} amount={:?} - dropped",
+                        row.tx(),
+                        row.client(),
+                        row.tx_type(),
+                        row.amount()
+                    );
+                    stats.record_duplicate();
+                    metrics.record_dedup_hit();
+                    continue;
+                }
+
+                stats.record_accepted(&row);
+                if row.should_dedupe() {
+                    self.context.owners.record(row.tx(), row.client());
+                }
+
+                let client = row.client();
+                if self.rebalance {
+                    *recent.entry(client).or_default() += 1;
+                    since_check += 1;
+                    if since_check >= REBALANCE_INTERVAL {
+                        since_check = 0;
+                        let depths: Vec<usize> = gauges.iter().map(|g| g.depth()).collect();
+                        if let Some((moved, from, to)) =
+                            pick_move(&depths, &recent, &routes, self.channel_capacity)
+                        {
+                            debug!("Moving client {} from worker {} to {}", moved, from, to);
+                            // The client's rows read so far must reach its old worker before
+                            // the release does
+                            for worker in [from, to] {
+                                let batch = std::mem::take(&mut batches[worker]);
+                                if let Err(e) = send_batch(&senders[worker], &gauges[worker], batch)
+                                {
+                                    dead_letter(self.context.dead_letters.as_ref(), e.0);
+                                }
+                            }
+                            // Expect goes first so the new worker holds rows that could otherwise
+                            // overtake the state still on its way
+                            let _ = senders[to].send(WorkerMessage::Expect(moved));
+                            let _ =
+                                senders[from].send(WorkerMessage::Release { client: moved, to });
+                            routes.insert(moved, to);
+                            metrics.record_rebalance();
+                        }
+                        recent.clear();
+                    }
+                }
+                if let Some(limiter) = &mut limiter {
+                    let wait = limiter.take();
+                    if !wait.is_zero() {
+                        // Rows already read go out before the wait rather than after it
+                        for (worker, batch) in batches.iter_mut().enumerate() {
+                            let batch = std::mem::take(batch);
+                            if let Err(e) = send_batch(&senders[worker], &gauges[worker], batch) {
+                                dead_letter(self.context.dead_letters.as_ref(), e.0);
+                            }
+                        }
+                        metrics.record_throttled(wait);
+                        thread::sleep(wait);
+                    }
+                }
+                let worker_idx = routes
+                    .get(&client)
+                    .copied()
+                    .unwrap_or(client as usize % self.workers);
+                batches[worker_idx].push(row);
+                if batches[worker_idx].len() >= batch_size {
+                    let batch =
+                        std::mem::replace(&mut batches[worker_idx], Vec::with_capacity(batch_size));
+                    if let Err(e) = send_batch(&senders[worker_idx], &gauges[worker_idx], batch) {
+                        // The worker stopped on a strict failure
+                        if self.context.abort.load(Ordering::Relaxed) {
+                            break 'sources;
+                        }
+                        error!(
+                            "Failed to send transactions to worker {}: {}",
+                            worker_idx, e
+                        );
+                        dead_letter(self.context.dead_letters.as_ref(), e.0);
+                    }
+                }
+            }
+            metrics.record_source(&stats);
+            all_stats.push(stats);
+        }
+        for (index, batch) in batches.into_iter().enumerate() {
+            if let Err(e) = send_batch(&senders[index], &gauges[index], batch)
+                && !self.context.abort.load(Ordering::Relaxed)
+            {
+                error!("Failed to send transactions to worker {}: {}", index, e);
+                dead_letter(self.context.dead_letters.as_ref(), e.0);
+            }
+        }
+
+        // The snapshot thread holds senders too, the workers only finish once it is gone
+        if let Some(snapshots) = snapshots {
+            snapshots.stop();
+        }
+        // Explicit drop to avoid another closure and a dedicated thread
+        drop(senders);
+
+        let mut accounts = AccountMap::new();
+        let mut deposits = HashMap::new();
+        let mut applied_order = AppliedOrder::new();
+        let mut fees: HashMap<u16, Decimal> = HashMap::new();
+        let mut disputed: HashMap<AccountKey, Decimal> = HashMap::new();
+        let mut locks = Vec::new();
+        for (handle, gauge) in handles.into_iter().zip(&gauges) {
+            match handle.join() {
+                Ok(shard) => {
+                    metrics.add_worker(shard.metrics, gauge, shard.deposits.len());
+                    accounts.merge(shard.accounts)?;
+                    for deposit in shard.deposits.values().filter(|d| d.holds_funds()) {
+                        *disputed.entry(deposit.account()).or_default() += deposit.held();
+                    }
+                    deposits.extend(shard.deposits);
+                    // A client ends up on exactly one worker, so shards have disjoint keys
+                    applied_order.extend(shard.order);
+                    locks.extend(shard.locks);
+                    for (account, fee) in shard.fees {
+                        *fees.entry(account).or_default() += fee;
+                    }
+                    // Each worker sees its rows in input order, so the lowest line across
+                    // workers is the first failing row of the input
+                    if let Some((line, e)) = shard.failure
+                        && failure.as_ref().is_none_or(|(first, _)| line < *first)
+                    {
+                        failure = Some((line, e));
+                    }
+                }
+                Err(_) => error!("Worker thread panicked"),
+            }
+        }
+        locks.sort_unstable_by_key(|lock: &AccountLock| (lock.account, lock.tx));
+        // The house account can live on any worker, so it is only paid once they are merged
+        for (account, fee) in fees {
+            accounts.get_or_create(account).collect_fees(fee);
+        }
+        if let Some(rate) = self.context.config.interest_rate {
+            let accruals = interest::accrue(
+                &mut accounts,
+                &mut deposits,
+                rate,
+                last_tx,
+                &self.context.config,
+            )?;
+            debug!("Accrued interest on {} accounts", accruals.len());
+            if let Some(ledger) = &self.context.ledger {
+                let mut ledger = ledger.lock().unwrap();
+                for deposit in &accruals {
+                    ledger.record_interest(deposit)?;
+                }
+            }
+            if let Some(wal) = &self.context.wal {
+                let mut wal = wal.lock().unwrap();
+                for deposit in accruals {
+                    wal.append(&Transaction::Deposit(deposit))?;
+                }
+            }
+        }
+        metrics.finish(started.elapsed());
+        self.context.errors.finish();
+
+        if let Some(wal) = &self.context.wal {
+            wal.lock().unwrap().flush()?;
+        }
+        if let Some(ledger) = &self.context.ledger {
+            ledger.lock().unwrap().flush()?;
+        }
+        if let Some(adjustments) = &self.context.adjustments {
+            adjustments.lock().unwrap().flush()?;
+        }
+        if let Some(conflicts) = &mut conflicts {
+            conflicts.flush()?;
+        }
+        if let Some(dead_letters) = &self.context.dead_letters {
+            let mut dead_letters = dead_letters.lock().unwrap();
+            dead_letters.flush()?;
+            metrics.record_dead_letters(dead_letters.count());
+        }
+
+        if let Some((line, e)) = failure {
+            return Err(Error::RowFailed {
+                line,
+                source: Box::new(e),
+            });
+        }
+
+        Ok(ProcessOutput {
+            accounts,
+            deposits,
+            disputed,
+            metrics,
+            stats: all_stats,
+            seen: dedup,
+            applied_order: self.context.record_order.then_some(applied_order),
+            locks,
+        })
+    }
+}
+
+// Hands the rows the reader gathered for a worker over in one message, if there are any
+fn send_batch(
+    worker: &SyncSender<WorkerMessage>,
+    gauge: &QueueGauge,
+    batch: Vec<TransactionRow>,
+) -> Result<(), mpsc::SendError<WorkerMessage>> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    gauge.push(batch.len());
+    worker.send(WorkerMessage::Rows(batch))
+}
+
+// Writes the rows of a message a worker will never apply to the dead-letter queue, if any
+fn dead_letter(dead_letters: Option<&SharedDeadLetters>, message: WorkerMessage) {
+    if let WorkerMessage::Rows(rows) = message {
+        dead_letter_rows(dead_letters, rows);
+    }
+}
+
+fn dead_letter_rows(
+    dead_letters: Option<&SharedDeadLetters>,
+    rows: impl IntoIterator<Item = TransactionRow>,
+) {
+    let Some(dead_letters) = dead_letters else {
+        return;
+    };
+    let mut dead_letters = dead_letters.lock().unwrap();
+    for row in rows {
+        if let Err(e) = dead_letters.write(&row) {
+            error!("Failed to write to the dead-letter queue: {}", e);
+            return;
+        }
+    }
+}
+
+// The thread behind `ProcessorBuilder::snapshots`
+struct Snapshots {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: JoinHandle<()>,
+}
+
+impl Snapshots {
+    fn spawn(
+        interval: Duration,
+        mut sink: SnapshotSink,
+        workers: Vec<SyncSender<WorkerMessage>>,
+    ) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = stop.clone();
+        let handle = thread::spawn(move || {
+            let (stopped, wake) = &*signal;
+            loop {
+                let guard = stopped.lock().unwrap();
+                let guard = wake
+                    .wait_timeout_while(guard, interval, |stopped| !*stopped)
+                    .unwrap()
+                    .0;
+                if *guard {
+                    return;
+                }
+                drop(guard);
+                match collect_snapshot(&workers) {
+                    Some(accounts) => sink(accounts),
+                    // A worker stopped on a strict failure
+                    None => return,
+                }
+            }
+        });
+        Self { stop, handle }
+    }
+
+    fn stop(self) {
+        let (stopped, wake) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        wake.notify_one();
+        let _ = self.handle.join();
+    }
+}
+
+// The merged accounts of all workers, with their fees credited like at the end of a run
+fn collect_snapshot(workers: &[SyncSender<WorkerMessage>]) -> Option<AccountMap> {
+    let (reply, replies) = mpsc::channel();
+    for worker in workers {
+        worker.send(WorkerMessage::Snapshot(reply.clone())).ok()?;
+    }
+    drop(reply);
+    let mut accounts = AccountMap::new();
+    let mut fees: HashMap<u16, Decimal> = HashMap::new();
+    for _ in workers {
+        let (shard, shard_fees) = replies.recv().ok()?;
+        accounts.merge(shard).ok()?;
+        for (account, fee) in shard_fees {
+            *fees.entry(account).or_default() += fee;
+        }
+    }
+    for (account, fee) in fees {
+        accounts.get_or_create(account).collect_fees(fee);
+    }
+    Some(accounts)
+}
+
+// Picks a client to move off the deepest queue when another worker has run dry: the
+// busiest recent client there, unless it carries most of that worker's load alone, in which
+// case moving it would only move the hot spot and the runner-up goes instead.
+fn pick_move(
+    depths: &[usize],
+    recent: &HashMap<u16, usize>,
+    routes: &HashMap<u16, usize>,
+    capacity: usize,
+) -> Option<(u16, usize, usize)> {
+    let (hot, &hot_depth) = depths.iter().enumerate().max_by_key(|(_, d)| **d)?;
+    let (cold, &cold_depth) = depths.iter().enumerate().min_by_key(|(_, d)| **d)?;
+    if hot == cold || cold_depth > 0 || hot_depth < (capacity / 2).max(1) {
+        return None;
+    }
+
+    let mut candidates: Vec<(u16, usize)> = recent
+        .iter()
+        .filter(|(client, _)| {
+            !routes.contains_key(client) && **client as usize % depths.len() == hot
+        })
+        .map(|(client, rows)| (*client, *rows))
+        .collect();
+    // Ties broken by client id so a run moves the same clients given the same depths
+    candidates.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let total: usize = candidates.iter().map(|(_, rows)| rows).sum();
+    // A worker's only client has nowhere better to go
+    let pick = match candidates[..] {
+        [(_, rows), (runner_up, _), ..] if rows * 2 > total => runner_up,
+        [(busiest, _), _, ..] => busiest,
+        _ => return None,
+    };
+    Some((pick, hot, cold))
+}
+
+// One worker's slice of the engine state
+#[derive(Default)]
+struct Shard {
+    accounts: AccountMap,
+    deposits: OrderedDeposits,
+    // Deposits evicted once resolved or charged back, with `ProcessorBuilder::evict_terminal`
+    terminal: TerminalDeposits,
+    metrics: WorkerMetrics,
+    order: AppliedOrder,
+    // Withdrawal fees charged by this worker, by house account, credited after the merge
+    fees: HashMap<u16, Decimal>,
+    // Latest timestamp seen by this worker, the reference point for deposit eviction
+    clock: u64,
+    since_eviction: usize,
+    // Seq of the last row applied per client, only kept by debug builds
+    last_seq: HashMap<u16, u64>,
+    // Chargebacks that locked an account
+    locks: Vec<AccountLock>,
+}
+
+impl Shard {
+    // Applies one row, logging and counting a failure. Returns it, with the row's line, only
+    // when it must end the run.
+    fn step(&mut self, row: TransactionRow, context: &WorkerContext) -> Option<(u64, Error)> {
+        if cfg!(debug_assertions)
+            && let Some(seq) = row.seq()
+        {
+            let last = self.last_seq.insert(row.client(), seq);
+            debug_assert!(
+                last.is_none_or(|last| last < seq),
+                "row {} of client {} applied after row {}",
+                seq,
+                row.client(),
+                last.unwrap_or_default()
+            );
+        }
+        let line = row.line().unwrap_or_default();
+        let kind = row.kind();
+        let copy = (!context.handlers.is_empty()).then(|| row.clone());
+        // The reader recorded the owner of this id, which only holds while this row is stored
+        let recorded = row.should_dedupe().then(|| {
+            let key = DepositKey::new(row.client(), row.tx());
+            (key, self.deposits.contains(key))
+        });
+        let result = self.apply(row, context);
+        if let Some((key, held)) = recorded
+            && (held || !self.deposits.contains(key))
+        {
+            context.owners.forget(key.tx);
+        }
+        match result {
+            Ok(()) => None,
+            Err(e) => {
+                self.reject(kind, e.reason(), context);
+                if let Some(row) = copy {
+                    context.rejected(&row, &e);
+                }
+                context.strict.then_some((line, e))
+            }
+        }
+    }
+
+    fn apply(&mut self, row: TransactionRow, context: &WorkerContext) -> Result<(), Error> {
+        let WorkerContext { config, .. } = context;
+        let kind = row.kind();
+        if let Some(ts) = row.timestamp() {
+            self.clock = self.clock.max(ts);
+        }
+        if let Some(window) = config.dispute_window {
+            self.since_eviction += 1;
+            if self.since_eviction >= EVICTION_INTERVAL {
+                self.since_eviction = 0;
+                let evicted = self
+                    .deposits
+                    .evict_before(self.clock.saturating_sub(window));
+                debug!(
+                    "Evicted {} deposits outside the dispute window",
+                    evicted.len()
+                );
+                forget(context, &evicted);
+            }
+        }
+
+        let row = match apply_rules(&context.rules, row, &self.accounts) {
+            Ok(Some(row)) => row,
+            Ok(None) => {
+                debug!("Row rejected by rule");
+                self.reject(kind, "rule", context);
+                return Ok(());
+            }
+            Err(e) => {
+                let message = format_args!("Rule failed: {}", e);
+                context.errors.error(e.reason(), message);
+                return Err(e);
+            }
+        };
+
+        let mut transaction = context.registry.parse(row, config).inspect_err(|e| {
+            let message = format_args!("Failed to convert transaction: {}", e);
+            context.errors.error(e.reason(), message);
+        })?;
+        if let Some(limit) = context.overdraft_limits.get(transaction.client()) {
+            transaction = transaction.with_overdraft_limit(limit);
+        }
+        self.execute(transaction, context)
+    }
+
+    // Applies a parsed transaction and records it: metrics, fees, WAL, ledger and reports
+    fn execute(&mut self, transaction: Transaction, context: &WorkerContext) -> Result<(), Error> {
+        // Registered types are all "unknown" as rows
+        let kind = transaction.kind();
+
+        debug!("Processing: {:?}", transaction);
+
+        let before = (context.ledger.is_some() || context.alerts.is_some())
+            .then(|| Balances::of(&self.accounts, transaction.account()));
+        let locked = |accounts: &AccountMap| {
+            accounts
+                .get(transaction.account())
+                .is_some_and(|account| account.locked())
+        };
+        let flagged = matches!(transaction, Transaction::Dispute(_))
+            && context.config.locked_dispute == LockedAccountDisputePolicy::AllowButFlag
+            && locked(&self.accounts);
+        // A chargeback on an unlocked account, which may lock it
+        let chargeback = (matches!(transaction, Transaction::Chargeback(_))
+            && !locked(&self.accounts))
+        .then(|| Balances::of(&self.accounts, transaction.account()));
+        let mut result = transaction.process(&mut self.accounts, &mut self.deposits);
+        if context.evict_terminal {
+            result = result.map_err(|e| self.terminal.explain(e, &transaction));
+        }
+        result = result.map_err(|e| context.owners.explain(e, transaction.client()));
+        if let (Some(report), Transaction::Adjustment(adjustment)) =
+            (&context.adjustments, &transaction)
+        {
+            let after = Balances::of(&self.accounts, adjustment.account());
+            let recorded =
+                report
+                    .lock()
+                    .unwrap()
+                    .record(adjustment, result.as_ref().copied(), after);
+            if let Err(e) = recorded {
+                error!("Failed to write to the adjustments report: {}", e);
+            }
+        }
+        if let Err(e) = result {
+            let message = format_args!("Transaction failed: {}", e);
+            context.errors.error(e.reason(), message);
+            return Err(e);
+        }
+        self.metrics.record_processed(kind);
+        if context.evict_terminal
+            && let Transaction::Resolve(_) | Transaction::Chargeback(_) = transaction
+        {
+            let key = DepositKey::new(transaction.client(), transaction.id());
+            self.terminal.evict(&mut self.deposits, key);
+        }
+        if flagged {
+            warn!(
+                "Dispute {} accepted on locked account {} - flagged for review",
+                transaction.id(),
+                transaction.account()
+            );
+            self.metrics.record_flagged_dispute();
+        }
+        if let Some(before) = chargeback
+            && locked(&self.accounts)
+        {
+            let after = Balances::of(&self.accounts, transaction.account());
+            self.locks.push(AccountLock {
+                account: transaction.account(),
+                tx: transaction.id(),
+                amount: before.held - after.held,
+            });
+            for handler in &context.handlers {
+                handler.on_account_locked(transaction.client());
+            }
+        }
+        if let Transaction::Deposit(_) | Transaction::Withdrawal(_) = transaction
+            && let Some(amount) = transaction.amount()
+        {
+            self.metrics.record_value(kind, amount);
+            if let Some(max) = context.max_deposits {
+                self.cap_deposits(max, context);
+            }
+        }
+        if let Some(fee) = transaction.fee() {
+            *self.fees.entry(fee.account).or_default() += fee.amount;
+            self.metrics.record_value("fee", fee.amount);
+        }
+        if context.record_order {
+            self.order
+                .entry(transaction.client())
+                .or_default()
+                .push(transaction.id());
+        }
+
+        if let Some(wal) = &context.wal
+            && let Err(e) = wal.lock().unwrap().append(&transaction)
+        {
+            error!("Failed to append to WAL: {}", e);
+        }
+        if let (Some(ledger), Some(before)) = (&context.ledger, before) {
+            let change = Balances::of(&self.accounts, transaction.account()) - before;
+            if let Err(e) = ledger.lock().unwrap().record(kind, &transaction, change) {
+                error!("Failed to write to the ledger: {}", e);
+            }
+        }
+        for handler in &context.handlers {
+            handler.on_applied(&transaction);
+        }
+        if let (Some((thresholds, writer)), Some(before)) = (&context.alerts, before) {
+            let after = Balances::of(&self.accounts, transaction.account());
+            for alert in thresholds.crossed(transaction.account(), transaction.id(), before, after)
+            {
+                self.metrics.record_alert();
+                if let Err(e) = writer.lock().unwrap().send(&alert) {
+                    error!("Failed to send an alert: {}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Releases the pending deposits whose hold is over by the as-of cut-off, as if a release
+    // row had come for each at that time
+    fn settle(&mut self, as_of: u64, context: &WorkerContext) {
+        let mut due = self.deposits.due(as_of);
+        // Ids are unique per shard, so this orders the WAL and ledger entries
+        due.sort_unstable_by_key(|&(_, id)| id);
+        for (account, id) in due {
+            let release = ReleaseTx::new(account.client, id)
+                .with_sub(account.sub)
+                .with_timestamp(Some(as_of));
+            if let Err(e) = self.execute(Transaction::Release(release), context) {
+                error!("Failed to settle deposit {}: {}", id, e);
+            }
+        }
+    }
+
+    fn reject(&mut self, kind: &'static str, reason: &'static str, context: &WorkerContext) {
+        self.metrics.record_rejected(kind);
+        self.metrics.record_reason(reason);
+        if let Some(progress) = &context.progress {
+            progress.record_rejected();
+        }
+    }
+
+    fn cap_deposits(&mut self, max: usize, context: &WorkerContext) {
+        let evicted = self.deposits.evict_oldest(max);
+        if evicted.is_empty() {
+            return;
+        }
+        forget(context, &evicted);
+        // Once per worker, the metrics carry the count
+        if self.metrics.evicted_deposits() == 0 {
+            warn!(
+                "Deposit store over {} entries, evicting the oldest; they can no longer be disputed",
+                max
+            );
+        }
+        self.metrics.record_evicted(evicted.len());
+    }
+
+    fn release(&mut self, client: u16) -> Handoff {
+        Handoff {
+            client,
+            accounts: self.accounts.remove_client(client),
+            deposits: self.deposits.extract_client(client),
+            terminal: self.terminal.extract_client(client),
+            order: self.order.remove(&client).unwrap_or_default(),
+            last_seq: self.last_seq.remove(&client),
+        }
+    }
+
+    fn adopt(&mut self, handoff: Handoff) {
+        for account in handoff.accounts {
+            self.accounts.insert(account);
+        }
+        self.deposits.extend(handoff.deposits);
+        self.terminal.extend(handoff.terminal);
+        if !handoff.order.is_empty() {
+            self.order.insert(handoff.client, handoff.order);
+        }
+        if let Some(seq) = handoff.last_seq {
+            self.last_seq.insert(handoff.client, seq);
+        }
+    }
+}
+
+// Rows held back for clients whose state has not arrived yet, and clients whose state
+// arrived before the worker got to their `Expect`
+#[derive(Default)]
+struct Pending {
+    held: HashMap<u16, Vec<TransactionRow>>,
+    early: HashSet<u16>,
+}
+
+impl Pending {
+    // Adopts the handed-off client and applies the rows held for it
+    fn adopt(
+        &mut self,
+        shard: &mut Shard,
+        handoff: Handoff,
+        context: &WorkerContext,
+    ) -> Option<(u64, Error)> {
+        let client = handoff.client;
+        shard.adopt(handoff);
+        let Some(rows) = self.held.remove(&client) else {
+            self.early.insert(client);
+            return None;
+        };
+        rows.into_iter().find_map(|row| shard.step(row, context))
+    }
+}
+
+// The clients a worker should be sent: its home clients under `client % workers`, adjusted by
+// rebalancing moves. Backs a debug-build check that the router and the shards agree, since a
+// client reaching two workers would silently lose its per-client ordering.
+struct Ownership {
+    index: usize,
+    workers: usize,
+    moved_in: HashSet<u16>,
+    moved_out: HashSet<u16>,
+}
+
+impl Ownership {
+    fn owns(&self, client: u16) -> bool {
+        if self.moved_out.contains(&client) {
+            return false;
+        }
+        self.moved_in.contains(&client) || client as usize % self.workers == self.index
+    }
+}
+
+fn worker_loop(
+    index: usize,
+    rx: Receiver<WorkerMessage>,
+    handoffs: Receiver<Handoff>,
+    peers: Vec<Sender<Handoff>>,
+    gauge: Arc<QueueGauge>,
+    mut shard: Shard,
+    context: WorkerContext,
+) -> WorkerOutput {
+    let mut pending = Pending::default();
+    let mut failure = None;
+    let mut ownership = Ownership {
+        index,
+        workers: peers.len(),
+        moved_in: HashSet::new(),
+        moved_out: HashSet::new(),
+    };
+
+    // Blocks until message or channel closed (sender dropped)
+    while failure.is_none()
+        && let Ok(message) = rx.recv()
+    {
+        while failure.is_none()
+            && let Ok(handoff) = handoffs.try_recv()
+        {
+            failure = pending.adopt(&mut shard, handoff, &context);
+        }
+        if failure.is_some() {
+            break;
+        }
+        match message {
+            WorkerMessage::Rows(rows) => {
+                let mut rows = rows.into_iter();
+                // Copy of the row being applied, only kept for the dead-letter queue
+                let mut current = None;
+                let applied = panic::catch_unwind(AssertUnwindSafe(|| {
+                    for row in rows.by_ref() {
+                        gauge.pop();
+                        if context.dead_letters.is_some() {
+                            current = Some(row.clone());
+                        }
+                        debug_assert!(
+                            ownership.owns(row.client()),
+                            "worker {} got a row for client {}, which belongs to another shard",
+                            index,
+                            row.client()
+                        );
+                        match pending.held.get_mut(&row.client()) {
+                            Some(held) => held.push(row),
+                            None => failure = shard.step(row, &context),
+                        }
+                        if failure.is_some() {
+                            break;
+                        }
+                    }
+                }));
+                if let Err(payload) = applied {
+                    let held = pending.held.into_values().flatten();
+                    let unapplied = current.into_iter().chain(rows).chain(held);
+                    dead_letter_rows(context.dead_letters.as_ref(), unapplied);
+                    // Keeps taking rows until the reader is done, so none are lost in the queue
+                    if context.dead_letters.is_some() {
+                        while let Ok(message) = rx.recv() {
+                            if let WorkerMessage::Rows(rows) = &message {
+                                rows.iter().for_each(|_| gauge.pop());
+                            }
+                            dead_letter(context.dead_letters.as_ref(), message);
+                        }
+                    }
+                    panic::resume_unwind(payload);
+                }
+            }
+            WorkerMessage::Release { client, to } => {
+                ownership.moved_out.insert(client);
+                if peers[to].send(shard.release(client)).is_err() {
+                    error!("Failed to hand client {} to worker {}", client, to);
+                }
+            }
+            WorkerMessage::Expect(client) => {
+                ownership.moved_in.insert(client);
+                if !pending.early.remove(&client) {
+                    pending.held.insert(client, Vec::new());
+                }
+            }
+            WorkerMessage::Snapshot(reply) => {
+                let _ = reply.send((shard.accounts.clone(), shard.fees.clone()));
+            }
+        }
+    }
+
+    // The input is done, but clients on their way here still have rows held back. Their old
+    // workers release them before finishing, unless a strict failure stopped them first.
+    while failure.is_none() && !pending.held.is_empty() {
+        match handoffs.recv_timeout(HANDOFF_POLL) {
+            Ok(handoff) => failure = pending.adopt(&mut shard, handoff, &context),
+            Err(RecvTimeoutError::Timeout) if !context.abort.load(Ordering::Relaxed) => {}
+            Err(_) => break,
+        }
+    }
+
+    if failure.is_some() {
+        context.abort.store(true, Ordering::Relaxed);
+    } else if let Some(as_of) = context.config.as_of {
+        shard.settle(as_of, &context);
+    }
+
+    WorkerOutput {
+        accounts: shard.accounts,
+        deposits: shard.deposits.into_map(),
+        metrics: shard.metrics,
+        order: shard.order,
+        fees: shard.fees,
+        locks: shard.locks,
+        failure,
+    }
+}
+
+impl WorkerContext {
+    fn rejected(&self, row: &TransactionRow, error: &Error) {
+        for handler in &self.handlers {
+            handler.on_rejected(row, error);
+        }
+    }
+}
+
+// Drops the owners of deposits a worker evicted
+fn forget(context: &WorkerContext, evicted: &[DepositKey]) {
+    for key in evicted {
+        context.owners.forget(key.tx);
+    }
+}
+
+fn apply_rules(
+    rules: &[Arc<dyn RowRule>],
+    mut row: TransactionRow,
+    accounts: &AccountMap,
+) -> Result<Option<TransactionRow>, Error> {
+    for rule in rules {
+        let account = row.account();
+        row = match rule.evaluate(row, accounts.get(account))? {
+            RuleDecision::Accept(row) => row,
+            RuleDecision::Reject => return Ok(None),
+        };
+    }
+    Ok(Some(row))
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::alerts::AlertTarget;
+
+    fn run(builder: ProcessorBuilder, input: &str) -> AccountMap {
+        let rows: Vec<_> = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .into_deserialize()
+            .collect();
+        builder.build().run("test", rows).unwrap().accounts
+    }
+
+    fn available(accounts: &AccountMap, client: u16) -> Decimal {
+        accounts.get(client).unwrap().available()
+    }
+
+    #[test]
+    fn dedup_strategies() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,1,10\n";
+
+        let exact = run(ProcessorBuilder::new().dedup(DedupStrategy::Exact), input);
+        let none = run(ProcessorBuilder::new().dedup(DedupStrategy::None), input);
+
+        assert_eq!(available(&exact, 1), Decimal::new(10, 0));
+        assert_eq!(available(&none, 1), Decimal::new(20, 0));
+    }
+
+    #[test]
+    fn dispute_overdraft_reject_keeps_balance_positive() {
+        let input = "type,client,tx,amount\ndeposit,1,1,100\nwithdrawal,1,2,80\ndispute,1,1,\n";
+
+        let clawback = run(ProcessorBuilder::new(), input);
+        let reject = run(
+            ProcessorBuilder::new().dispute_overdraft(DisputeOverdraftPolicy::Reject),
+            input,
+        );
+
+        assert_eq!(available(&clawback, 1), Decimal::new(-80, 0));
+        assert_eq!(available(&reject, 1), Decimal::new(20, 0));
+    }
+
+    #[test]
+    fn zero_amount_and_locked_dispute_policies() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,100\ndeposit,1,2,50\ndispute,1,1,\nchargeback,1,1,\n\
+                     dispute,1,2,\ndeposit,2,3,0\n";
+
+        let accounts = run(
+            ProcessorBuilder::new()
+                .workers(1)
+                .zero_amount(ZeroAmountPolicy::Reject)
+                .locked_dispute(LockedAccountDisputePolicy::Reject),
+            input,
+        );
+
+        assert_eq!(accounts.get(1).unwrap().held(), Decimal::ZERO);
+        assert!(accounts.get(2).is_none());
+    }
+
+    #[test]
+    fn amount_limits_refuse_rows_outside_them() {
+        let input = "type,client,tx,amount,reason\n\
+                     deposit,1,1,100,\ndeposit,1,2,1000001,\ndeposit,1,3,0.00001,\n\
+                     withdrawal,1,4,2000000,\nadjustment,1,5,-5000000,FAT_FINGER\n\
+                     deposit,1,6,0,\n";
+
+        let accounts = run(
+            ProcessorBuilder::new()
+                .workers(1)
+                .amount_limits(Some(Decimal::new(1, 4)), Some(Decimal::new(1_000_000, 0))),
+            input,
+        );
+
+        assert_eq!(accounts.get(1).unwrap().available(), Decimal::ONE_HUNDRED);
+        let error = Transaction::from_row(
+            TransactionRow::new("deposit", 1, 2, Some(Decimal::new(1_000_001, 0))),
+            &Config {
+                max_amount: Some(Decimal::new(1_000_000, 0)),
+                ..Config::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(error.reason(), "amount_out_of_bounds");
+    }
+
+    #[test]
+    fn conflicting_ids_are_refused_not_applied() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5\ndeposit,3,1,500\ndeposit,1,1,5\nwithdrawal,1,2,1\n";
+        let rows: Vec<_> = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .into_deserialize()
+            .collect();
+        let report = ConflictReport::new(
+            Box::new(std::io::sink()) as Box<dyn Write + Send>,
+            &Config::default(),
+        )
+        .unwrap();
+
+        let output = ProcessorBuilder::new()
+            .workers(2)
+            .conflicts(report)
+            .build()
+            .run("test", rows)
+            .unwrap();
+
+        assert_eq!(available(&output.accounts, 1), Decimal::new(4, 0));
+        assert!(output.accounts.get(3).is_none());
+        let rejections = output.metrics.rejections();
+        assert_eq!(rejections.get("conflicting_duplicate"), Some(&1));
+        assert_eq!(rejections.get("duplicate"), Some(&1));
+    }
+
+    #[test]
+    fn evicted_terminal_deposits_keep_their_rejects() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10\ndispute,1,1,\nresolve,1,1,\ndispute,1,1,\n\
+                     deposit,1,2,5\ndispute,1,2,\nchargeback,1,2,\nchargeback_reversal,1,2,\n\
+                     void,1,1,\ndispute,2,1,\n";
+        let rows: Vec<_> = csv::ReaderBuilder::new()
+            .from_reader(input.as_bytes())
+            .into_deserialize()
+            .collect();
+
+        let output = ProcessorBuilder::new()
+            .workers(1)
+            .evict_terminal(true)
+            .build()
+            .run("test", rows)
+            .unwrap();
+
+        assert_eq!(available(&output.accounts, 1), Decimal::new(10, 0));
+        assert!(output.deposits.is_empty());
+        let rejections = output.metrics.rejections();
+        assert_eq!(rejections.get("invalid_state"), Some(&1));
+        assert_eq!(rejections.get("deposit_evicted"), Some(&2));
+        assert_eq!(rejections.get("client_mismatch"), Some(&1));
+    }
+
+    // Stands in for an engine bug
+    struct PanicOn(u32);
+
+    impl RowRule for PanicOn {
+        fn evaluate(
+            &self,
+            row: TransactionRow,
+            _account: Option<&Account>,
+        ) -> Result<RuleDecision, Error> {
+            assert_ne!(row.tx(), self.0, "bug");
+            Ok(RuleDecision::Accept(row))
+        }
+    }
+
+    #[test]
+    fn rows_a_panicked_worker_left_are_dead_lettered() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5\ndeposit,2,2,5\ndeposit,1,3,5\ndeposit,1,4,5\n\
+                     withdrawal,2,5,1\ndeposit,1,6,5\n";
+        let rows: Vec<_> = csv::ReaderBuilder::new()
+            .from_reader(input.as_bytes())
+            .into_deserialize()
+            .collect();
+        let path = std::env::temp_dir().join(format!("toy-processor-{}.dlq", std::process::id()));
+        let sink: Box<dyn Write + Send> = Box::new(std::fs::File::create(&path).unwrap());
+
+        let output = ProcessorBuilder::new()
+            .workers(2)
+            .batch_size(1)
+            .rule(PanicOn(3))
+            .dead_letters(DeadLetterQueue::new(sink).unwrap())
+            .build()
+            .run("test", rows)
+            .unwrap();
+        let dead_letters = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        // Client 1's worker is gone, client 2's carries on
+        assert!(output.accounts.get(1).is_none());
+        assert_eq!(available(&output.accounts, 2), Decimal::new(4, 0));
+        assert_eq!(output.metrics.dead_letters(), 3);
+        assert_eq!(
+            dead_letters,
+            "type,client,tx,amount,timestamp,reason,pending_until,sub
+deposit,1,3,5,,,,
+deposit,1,4,5,,,,
+deposit,1,6,5,,,,
+"
+        );
+    }
+
+    #[test]
+    fn disputes_on_locked_accounts_can_be_flagged() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,100\ndeposit,1,2,50\ndispute,1,1,\nchargeback,1,1,\n\
+                     dispute,1,2,\n";
+        let rows: Vec<_> = csv::ReaderBuilder::new()
+            .from_reader(input.as_bytes())
+            .into_deserialize()
+            .collect();
+
+        let output = ProcessorBuilder::new()
+            .workers(1)
+            .locked_dispute(LockedAccountDisputePolicy::AllowButFlag)
+            .build()
+            .run("test", rows)
+            .unwrap();
+
+        assert_eq!(output.accounts.get(1).unwrap().held(), Decimal::new(50, 0));
+        assert_eq!(output.metrics.flagged_disputes(), 1);
+    }
+
+    #[test]
+    fn event_handlers_see_applied_rejected_and_locked() {
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+
+        impl EventHandler for Arc<Recorder> {
+            fn on_applied(&self, transaction: &Transaction) {
+                let event = format!("applied {}", transaction.id());
+                self.0.lock().unwrap().push(event);
+            }
+
+            fn on_rejected(&self, row: &TransactionRow, error: &Error) {
+                let event = format!("rejected {} {}", row.tx(), error.reason());
+                self.0.lock().unwrap().push(event);
+            }
+
+            fn on_account_locked(&self, client: u16) {
+                self.0.lock().unwrap().push(format!("locked {}", client));
+            }
+        }
+
+        let recorder = Arc::new(Recorder::default());
+        run(
+            ProcessorBuilder::new()
+                .workers(1)
+                .event_handler(recorder.clone()),
+            "type,client,tx,amount\n\
+             deposit,1,1,10\nwithdrawal,1,2,50\ndispute,1,1,\nchargeback,1,1,\n\
+             deposit,1,3,5\n",
+        );
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "applied 1",
+                "rejected 2 insufficient_funds",
+                "applied 1",
+                "locked 1",
+                "applied 1",
+                "rejected 3 account_locked",
+            ]
+        );
+    }
+
+    #[test]
+    fn alerts_when_an_account_crosses_a_threshold() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,100\nwithdrawal,1,2,80\ndispute,1,1,\n\
+                     deposit,2,3,500\ndeposit,2,4,300\ndispute,2,3,\nresolve,2,3,\n\
+                     dispute,2,4,\n";
+        let rows: Vec<_> = csv::ReaderBuilder::new()
+            .from_reader(input.as_bytes())
+            .into_deserialize()
+            .collect();
+        let thresholds = AlertThresholds {
+            available_below: Some(Decimal::ZERO),
+            held_above: Some(Decimal::ONE_HUNDRED),
+        };
+
+        let output = ProcessorBuilder::new()
+            .workers(2)
+            .alerts(
+                thresholds,
+                AlertWriter::new(AlertTarget::Stderr, &Config::default()),
+            )
+            .build()
+            .run("test", rows)
+            .unwrap();
+
+        // Client 1's dispute takes available to -80; client 2's held crosses 100 twice
+        assert_eq!(output.metrics.alerts(), 3);
+    }
+
+    #[test]
+    fn max_tps_throttles_the_reader() {
+        let rows = (1..=30)
+            .map(|tx| Ok(TransactionRow::new("deposit", 1, tx, Some(Decimal::ONE))))
+            .collect::<Vec<_>>();
+
+        let start = Instant::now();
+        let output = ProcessorBuilder::new()
+            .max_tps(100)
+            .build()
+            .run("test", rows)
+            .unwrap();
+
+        // A burst of 10, then 20 rows at 10ms each
+        assert!(start.elapsed() >= Duration::from_millis(190));
+        assert!(output.metrics.throttled_secs() > 0.15);
+        assert_eq!(output.accounts.get(1).unwrap().total(), Decimal::from(30));
+    }
+
+    #[test]
+    fn interest_ids_go_past_every_id_read() {
+        let rows = vec![
+            Ok(TransactionRow::new("deposit", 1, 1, Some(Decimal::TEN))),
+            // Rejected, so it never reaches the deposit store
+            Ok(TransactionRow::new("withdrawal", 2, 9, Some(Decimal::ONE))),
+        ];
+        let output = ProcessorBuilder::new()
+            .config(Config {
+                interest_rate: Some(Decimal::new(1, 1)),
+                ..Config::default()
+            })
+            .build()
+            .run("test", rows)
+            .unwrap();
+
+        assert_eq!(
+            output.deposits[&DepositKey::new(1, 10)].amount(),
+            Decimal::ONE
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "belongs to another shard")]
+    fn misrouted_row_trips_ownership_check() {
+        let context = ProcessorBuilder::new().build().context;
+        let (tx, rx) = mpsc::sync_channel(1);
+        let (_handoff_tx, handoffs) = mpsc::channel();
+        // Client 1 belongs to worker 1 of 4
+        tx.send(WorkerMessage::Rows(vec![TransactionRow::new(
+            "deposit",
+            1,
+            1,
+            Some(Decimal::ONE),
+        )]))
+        .unwrap();
+        drop(tx);
+
+        let peers = (0..4).map(|_| mpsc::channel().0).collect();
+        worker_loop(
+            0,
+            rx,
+            handoffs,
+            peers,
+            Arc::default(),
+            Shard::default(),
+            context,
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "row 3 of client 1 applied after row 5")]
+    fn reordered_rows_trip_sequence_check() {
+        let context = ProcessorBuilder::new().workers(1).build().context;
+        let (tx, rx) = mpsc::sync_channel(1);
+        let (_handoff_tx, handoffs) = mpsc::channel();
+        let rows = [5, 3].map(|seq| {
+            let mut row = TransactionRow::new("deposit", 1, seq as u32, Some(Decimal::ONE));
+            row.set_seq(seq);
+            row
+        });
+        tx.send(WorkerMessage::Rows(rows.to_vec())).unwrap();
+        drop(tx);
+
+        let peers = vec![mpsc::channel().0];
+        worker_loop(
+            0,
+            rx,
+            handoffs,
+            peers,
+            Arc::default(),
+            Shard::default(),
+            context,
+        );
+    }
+
+    // Client 2 disputes client 1's deposit, stored on the other worker when there are two
+    #[test]
+    fn disputes_of_another_clients_deposit_mismatch_on_any_shard() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10\ndispute,2,1,\ndispute,2,9,\n";
+        for workers in [1, 2] {
+            let rows = csv::Reader::from_reader(input.as_bytes()).into_deserialize();
+            let output = ProcessorBuilder::new()
+                .workers(workers)
+                .build()
+                .run("test", rows)
+                .unwrap();
+
+            let rejections = output.metrics.rejections();
+            assert_eq!(rejections["client_mismatch"], 1, "{} workers", workers);
+            assert_eq!(rejections["unknown_tx"], 1, "{} workers", workers);
+        }
+
+        // Clients 1 and 3 both use tx 1, so it is no one's in particular
+        let shared = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,3,1,5\ndispute,2,1,\n";
+        let rows = csv::Reader::from_reader(shared.as_bytes()).into_deserialize();
+        let output = ProcessorBuilder::new()
+            .workers(2)
+            .dedup_key(DedupKey::ClientTx)
+            .build()
+            .run("test", rows)
+            .unwrap();
+        assert_eq!(output.metrics.rejections()["unknown_tx"], 1);
+    }
+
+    // Owners go with the deposits, so they are bounded by whatever bounds the stores
+    #[test]
+    fn owners_are_forgotten_with_their_deposits() {
+        let context = ProcessorBuilder::new()
+            .max_deposits_per_worker(1)
+            .build()
+            .context;
+        let owners = context.owners.clone();
+        let mut shard = Shard::default();
+        let rows = [
+            ("deposit", 1, Decimal::TEN),
+            ("deposit", 1, Decimal::TEN),
+            ("deposit", 2, Decimal::TEN),
+            ("withdrawal", 3, Decimal::ONE_HUNDRED),
+        ];
+        for (kind, tx_id, amount) in rows {
+            owners.record(tx_id, 1);
+            shard.step(TransactionRow::new(kind, 1, tx_id, Some(amount)), &context);
+        }
+
+        // The duplicate of tx 1 leaves it to the first row, which the cap then evicts
+        assert_eq!(owners.owner(1), None);
+        assert_eq!(owners.owner(2), Some(1));
+        // The withdrawal was refused, so never stored
+        assert_eq!(owners.owner(3), None);
+    }
+
+    #[test]
+    fn registered_types_reach_the_workers() {
+        let builder = ProcessorBuilder::new().register_tx("credit", |row, _| {
+            let amount = row.amount().unwrap_or_default();
+            Ok(crate::transactions::DepositTx::new(
+                row.client(),
+                row.tx(),
+                amount,
+            ))
+        });
+        let accounts = run(
+            builder,
+            "type,client,tx,amount
+deposit,1,1,1.0
+credit,1,2,2.5
+refund,1,3,1.0",
+        );
+
+        assert_eq!(available(&accounts, 1), Decimal::new(35, 1));
+    }
+
+    // Batching changes how rows travel, not what is applied or which row fails first
+    #[test]
+    fn batch_size_does_not_change_the_outcome() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,2,5\n\
+                     withdrawal,1,3,4\nwithdrawal,2,4,9\ndeposit,3,5,1\n";
+        for size in [1, 2, DEFAULT_BATCH_SIZE] {
+            let builder = || ProcessorBuilder::new().workers(2).batch_size(size);
+            let rows = csv::Reader::from_reader(input.as_bytes()).into_deserialize();
+            let strict = builder().strict(true).build().run("test", rows);
+            let accounts = run(builder(), input);
+
+            assert!(
+                matches!(strict, Err(Error::RowFailed { line: 5, .. })),
+                "batch size {}",
+                size
+            );
+            assert_eq!(available(&accounts, 1), Decimal::new(6, 0));
+            assert_eq!(available(&accounts, 2), Decimal::new(5, 0));
+            assert_eq!(available(&accounts, 3), Decimal::ONE);
+        }
+    }
+
+    // Each starting account must reach the worker its client's rows go to
+    #[test]
+    fn runs_continue_from_starting_accounts() {
+        let mut start = AccountMap::new();
+        for client in 1..=4 {
+            start.get_or_create(client).deposit(Decimal::TEN).unwrap();
+        }
+        let input = "type,client,tx,amount\nwithdrawal,1,1,4\nwithdrawal,3,2,11\ndeposit,5,3,1\n";
+
+        let accounts = run(ProcessorBuilder::new().workers(2).accounts(start), input);
+
+        assert_eq!(accounts.len(), 5);
+        assert_eq!(available(&accounts, 1), Decimal::new(6, 0));
+        assert_eq!(available(&accounts, 2), Decimal::TEN);
+        assert_eq!(available(&accounts, 3), Decimal::TEN);
+        assert_eq!(available(&accounts, 5), Decimal::ONE);
+    }
+
+    #[test]
+    fn snapshots_while_the_input_waits() {
+        let (rows, input) = mpsc::channel();
+        let (taken, snapshots) = mpsc::channel();
+        let processor = ProcessorBuilder::new()
+            .snapshots(Duration::from_millis(10), move |accounts| {
+                let _ = taken.send(accounts);
+            })
+            .build();
+        let run = thread::spawn(move || processor.run("test", input));
+
+        for (client, tx) in [(1, 1), (2, 2), (1, 3)] {
+            let row = TransactionRow::new("deposit", client, tx, Some(Decimal::ONE));
+            rows.send(Ok(row)).unwrap();
+        }
+        // The input has not ended, yet every row sent shows up
+        let accounts = snapshots
+            .iter()
+            .find(|accounts| accounts.len() == 2 && available(accounts, 1) == Decimal::TWO)
+            .unwrap();
+        assert_eq!(available(&accounts, 2), Decimal::ONE);
+
+        drop(rows);
+        assert!(run.join().unwrap().unwrap().accounts == accounts);
+    }
+}
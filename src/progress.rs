@@ -0,0 +1,191 @@
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+// Live counters for `--progress`, bumped by the reader and the workers while a run is going
+// and sampled once a second by a `Reporter`. Unlike `Metrics`, which is merged at the end,
+// these are shared, so they cost an atomic add per row and are only kept when asked for.
+#[derive(Debug, Default)]
+pub struct Progress {
+    rows: AtomicU64,
+    rejected: AtomicU64,
+    // Bytes of the input file consumed so far, before decompression or decoding, so they
+    // compare with the file size
+    bytes: AtomicU64,
+}
+
+impl Progress {
+    pub fn record_row(&self) {
+        self.rows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_bytes(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn sample(&self) -> Sample {
+        Sample {
+            rows: self.rows.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    // Counts what is read through `inner` towards the input bytes
+    pub fn counting<R: Read>(self: &Arc<Self>, inner: R) -> CountingReader<R> {
+        CountingReader {
+            inner,
+            progress: self.clone(),
+        }
+    }
+
+    // Prints a progress line to stderr every second until the returned reporter is dropped.
+    // `total_bytes` is the input file size, without it there is no ETA.
+    pub fn report(self: &Arc<Self>, total_bytes: Option<u64>) -> Reporter {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let progress = self.clone();
+        let signal = stop.clone();
+        let handle = thread::spawn(move || {
+            let started = Instant::now();
+            let (mut last, mut last_at) = (Sample::default(), started);
+            let (stopped, wake) = &*signal;
+            let mut stopped = stopped.lock().unwrap();
+            while !*stopped {
+                stopped = wake.wait_timeout(stopped, REPORT_INTERVAL).unwrap().0;
+                let (now, now_at) = (progress.sample(), Instant::now());
+                let line = now.line(&last, now_at - last_at, now_at - started, total_bytes);
+                eprintln!("{}", line);
+                (last, last_at) = (now, now_at);
+            }
+        });
+        Reporter {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Sample {
+    rows: u64,
+    rejected: u64,
+    bytes: u64,
+}
+
+impl Sample {
+    // Throughput is over the last interval, so a stall shows up at once; the ETA uses the
+    // average byte rate since the start, which is steadier
+    fn line(
+        &self,
+        last: &Sample,
+        interval: Duration,
+        elapsed: Duration,
+        total_bytes: Option<u64>,
+    ) -> String {
+        let rate = (self.rows - last.rows) as f64 / interval.as_secs_f64().max(f64::EPSILON);
+        let mut line = format!(
+            "progress: rows={} rejected={} rate={:.0}/s",
+            self.rows, self.rejected, rate
+        );
+        if let Some(total) = total_bytes.filter(|total| *total > 0) {
+            let done = self.bytes.min(total);
+            line.push_str(&format!(" read={:.1}%", done as f64 * 100.0 / total as f64));
+            let rate = done as f64 / elapsed.as_secs_f64();
+            if done > 0 && rate > 0.0 {
+                let eta = ((total - done) as f64 / rate).round() as u64;
+                line.push_str(&format!(" eta={}", format_duration(eta)));
+            }
+        }
+        line
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        s if s >= 3600 => format!("{}h{:02}m", s / 3600, s % 3600 / 60),
+        s if s >= 60 => format!("{}m{:02}s", s / 60, s % 60),
+        s => format!("{}s", s),
+    }
+}
+
+// Stops the reporting thread when dropped, after one last line with the final counts
+pub struct Reporter {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        let (stopped, wake) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        wake.notify_one();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+pub struct CountingReader<R> {
+    inner: R,
+    progress: Arc<Progress>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.record_bytes(read as u64);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_reports_rate_and_eta() {
+        let last = Sample {
+            rows: 1_000,
+            rejected: 0,
+            bytes: 0,
+        };
+        let now = Sample {
+            rows: 3_500,
+            rejected: 4,
+            bytes: 250,
+        };
+
+        let second = Duration::from_secs(1);
+        let line = now.line(&last, second, Duration::from_secs(10), Some(1_000));
+
+        assert_eq!(
+            line,
+            "progress: rows=3500 rejected=4 rate=2500/s read=25.0% eta=30s"
+        );
+        assert_eq!(
+            now.line(&last, second / 2, second, None),
+            "progress: rows=3500 rejected=4 rate=5000/s"
+        );
+        assert_eq!(format_duration(3_725), "1h02m");
+    }
+
+    #[test]
+    fn counting_reader_counts_bytes() {
+        let progress = Arc::new(Progress::default());
+        let mut read = Vec::new();
+        progress
+            .counting("type,client\n".as_bytes())
+            .read_to_end(&mut read)
+            .unwrap();
+
+        assert_eq!(progress.sample().bytes, 12);
+    }
+}
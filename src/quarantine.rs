@@ -0,0 +1,135 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use log::warn;
+
+use crate::transactions::TransactionRow;
+
+// A legitimate row never spans more than one line, so a record that does means an unbalanced
+// quote has pulled the following rows into a single field. Instead of losing everything up to
+// the next stray quote, the line that opened the quote is copied to the quarantine sink and
+// parsing restarts on the line after it.
+pub struct RecoveringReader<R: Read + Seek, W: Write> {
+    rdr: csv::Reader<R>,
+    headers: csv::StringRecord,
+    record: csv::StringRecord,
+    quarantine: W,
+    ranges: Vec<Range<u64>>,
+}
+
+impl<W: Write> RecoveringReader<File, W> {
+    pub fn from_path(path: &str, quarantine: W) -> Result<Self, csv::Error> {
+        let rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(File::open(path)?);
+        Self::new(rdr, quarantine)
+    }
+}
+
+impl<R: Read + Seek, W: Write> RecoveringReader<R, W> {
+    pub fn new(mut rdr: csv::Reader<R>, quarantine: W) -> Result<Self, csv::Error> {
+        let headers = rdr.headers()?.clone();
+        Ok(Self {
+            rdr,
+            headers,
+            record: csv::StringRecord::new(),
+            quarantine,
+            ranges: Vec::new(),
+        })
+    }
+
+    // Byte ranges of the input that were skipped and written to the quarantine sink.
+    #[allow(dead_code)]
+    pub fn quarantined(&self) -> &[Range<u64>] {
+        &self.ranges
+    }
+
+    fn skip_line(&mut self, start: &csv::Position) -> Result<(), csv::Error> {
+        let raw = self.rdr.get_mut();
+        raw.seek(SeekFrom::Start(start.byte()))?;
+        let mut line = Vec::new();
+        BufReader::new(raw).read_until(b'\n', &mut line)?;
+
+        let range = start.byte()..start.byte() + line.len() as u64;
+        let mut resume = csv::Position::new();
+        resume
+            .set_byte(range.end)
+            .set_line(start.line() + 1)
+            .set_record(start.record());
+
+        // Blank lines are skipped by the csv reader but still counted in the record's span
+        if !line.trim_ascii().is_empty() {
+            warn!(
+                "Quarantined malformed region at line {} (bytes {}..{})",
+                start.line(),
+                range.start,
+                range.end
+            );
+            self.quarantine.write_all(&line)?;
+            self.ranges.push(range);
+            resume.set_record(start.record() + 1);
+        }
+        self.rdr.seek(resume)
+    }
+}
+
+impl<R: Read + Seek, W: Write> Iterator for RecoveringReader<R, W> {
+    type Item = Result<TransactionRow, csv::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let result = self.rdr.read_record(&mut self.record);
+            let record_start = match &result {
+                Ok(true) => self.record.position().cloned(),
+                Ok(false) => None,
+                Err(e) => e.position().cloned(),
+            };
+            if let Some(start) = record_start
+                && self.rdr.position().line() > start.line() + 1
+            {
+                if let Err(e) = self.skip_line(&start) {
+                    return Some(Err(e));
+                }
+                continue;
+            }
+
+            return match result {
+                Ok(false) => None,
+                Ok(true) => Some(self.record.deserialize(Some(&self.headers))),
+                Err(e) => Some(Err(e)),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn unbalanced_quote_only_loses_one_line() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,100\n\
+                     \n\
+                     deposit,1,\"2,10\n\
+                     deposit,1,3,10\n\
+                     deposit,1,4,10\n";
+        let rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(Cursor::new(input));
+        let mut reader = RecoveringReader::new(rdr, Vec::new()).unwrap();
+
+        let txs: Vec<u32> = reader
+            .by_ref()
+            .filter_map(Result::ok)
+            .map(|r| r.tx())
+            .collect();
+
+        assert_eq!(txs, vec![1, 3, 4]);
+        assert_eq!(reader.quarantined().to_vec(), vec![(39..55)]);
+        assert_eq!(reader.quarantine, b"deposit,1,\"2,10\n");
+    }
+}
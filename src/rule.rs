@@ -0,0 +1,22 @@
+use crate::account::Account;
+use crate::error::Error;
+use crate::transactions::TransactionRow;
+
+// Only constructed by the optional rule backends
+#[allow(dead_code)]
+pub enum RuleDecision {
+    Accept(TransactionRow),
+    Reject,
+}
+
+// Custom per-row logic, evaluated inside the worker that owns the client after dedup and
+// before validation, so it sees the current balances of the account the row applies to.
+// Backed by rhai scripts or WASM plugins in the binary; library users can implement it
+// directly.
+pub trait RowRule: Send + Sync {
+    fn evaluate(
+        &self,
+        row: TransactionRow,
+        account: Option<&Account>,
+    ) -> Result<RuleDecision, Error>;
+}
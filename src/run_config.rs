@@ -0,0 +1,171 @@
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::error::Error;
+
+// The settings of a run kept in a file, `--config run.toml`, instead of on the command line:
+//
+//   workers = 8
+//   dedup = "bloom"
+//   precision = 2
+//   locked-dispute = "reject"
+//   output-format = "json"
+//   strict = true
+//
+// Keys are the long flags without the dashes, and every one is optional. The file is turned
+// back into those flags, placed before the command line's, so values are checked exactly as
+// flags are and a flag given on the command line wins. A switch set to false leaves it off,
+// as it is by default. Unknown keys are an error, so a typo doesn't go unnoticed.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RunConfig {
+    // Processing
+    workers: Option<usize>,
+    channel_capacity: Option<usize>,
+    batch_size: Option<usize>,
+    max_tps: Option<u64>,
+    parse_threads: Option<usize>,
+    max_deposits_per_worker: Option<usize>,
+    strict: Option<bool>,
+    rebalance: Option<bool>,
+    evict_terminal: Option<bool>,
+    state_hash: Option<bool>,
+    // Dedup
+    dedup: Option<String>,
+    dedupe_key: Option<String>,
+    expected_transactions: Option<usize>,
+    bloom_fp_rate: Option<f64>,
+    // Amounts and policies; decimals and durations as strings, like on the command line
+    precision: Option<u32>,
+    rounding: Option<String>,
+    min_amount: Option<String>,
+    max_amount: Option<String>,
+    dispute_window: Option<String>,
+    require_monotonic: Option<String>,
+    dispute_overdraft: Option<String>,
+    zero_amount: Option<String>,
+    locked_dispute: Option<String>,
+    locked_account: Option<String>,
+    void: Option<String>,
+    chargeback_reversal: Option<String>,
+    withdrawal_fee: Option<String>,
+    fee_account: Option<u16>,
+    overdraft_limit: Option<String>,
+    accrue_interest: Option<String>,
+    // Alerts
+    alert_below: Option<String>,
+    alert_held_above: Option<String>,
+    alert_webhook: Option<String>,
+    // Input and output
+    format: Option<String>,
+    encoding: Option<String>,
+    compression: Option<String>,
+    output: Option<String>,
+    output_format: Option<String>,
+    output_compat: Option<String>,
+}
+
+impl RunConfig {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|e| Error::InvalidArgument(format!("{}: {}", path, e)))
+    }
+
+    // The file as command line flags
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut value = |flag: &str, value: Option<String>| {
+            if let Some(value) = value {
+                args.extend([flag.to_string(), value]);
+            }
+        };
+        let string = |value: &Option<String>| value.clone();
+        let number = |value: Option<usize>| value.map(|v| v.to_string());
+
+        value("--workers", number(self.workers));
+        value("--channel-capacity", number(self.channel_capacity));
+        value("--batch-size", number(self.batch_size));
+        value("--max-tps", self.max_tps.map(|rate| rate.to_string()));
+        value("--parse-threads", number(self.parse_threads));
+        value(
+            "--max-deposits-per-worker",
+            number(self.max_deposits_per_worker),
+        );
+        value("--dedup", string(&self.dedup));
+        value("--dedupe-key", string(&self.dedupe_key));
+        value(
+            "--expected-transactions",
+            number(self.expected_transactions),
+        );
+        value("--bloom-fp-rate", self.bloom_fp_rate.map(|r| r.to_string()));
+        value("--precision", self.precision.map(|p| p.to_string()));
+        value("--rounding", string(&self.rounding));
+        value("--min-amount", string(&self.min_amount));
+        value("--max-amount", string(&self.max_amount));
+        value("--dispute-window", string(&self.dispute_window));
+        value("--require-monotonic", string(&self.require_monotonic));
+        value("--dispute-overdraft", string(&self.dispute_overdraft));
+        value("--zero-amount", string(&self.zero_amount));
+        value("--locked-dispute", string(&self.locked_dispute));
+        value("--locked-account", string(&self.locked_account));
+        value("--void", string(&self.void));
+        value("--chargeback-reversal", string(&self.chargeback_reversal));
+        value("--withdrawal-fee", string(&self.withdrawal_fee));
+        value("--fee-account", self.fee_account.map(|a| a.to_string()));
+        value("--overdraft-limit", string(&self.overdraft_limit));
+        value("--accrue-interest", string(&self.accrue_interest));
+        value("--alert-below", string(&self.alert_below));
+        value("--alert-held-above", string(&self.alert_held_above));
+        value("--alert-webhook", string(&self.alert_webhook));
+        value("--format", string(&self.format));
+        value("--encoding", string(&self.encoding));
+        value("--compression", string(&self.compression));
+        value("--output", string(&self.output));
+        value("--output-format", string(&self.output_format));
+        value("--output-compat", string(&self.output_compat));
+
+        for (flag, on) in [
+            ("--strict", self.strict),
+            ("--rebalance", self.rebalance),
+            ("--evict-terminal", self.evict_terminal),
+            ("--state-hash", self.state_hash),
+        ] {
+            if on == Some(true) {
+                args.push(flag.to_string());
+            }
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turns_keys_into_flags() {
+        let config: RunConfig = toml::from_str(
+            "workers = 8\n\
+             precision = 2\n\
+             overdraft-limit = \"12.5\"\n\
+             strict = true\n\
+             rebalance = false\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.to_args(),
+            [
+                "--workers",
+                "8",
+                "--precision",
+                "2",
+                "--overdraft-limit",
+                "12.5",
+                "--strict"
+            ]
+        );
+        assert!(toml::from_str::<RunConfig>("worker = 8").is_err());
+    }
+}
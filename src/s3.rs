@@ -0,0 +1,75 @@
+use std::io::{self, Read};
+
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::error::DisplayErrorContext;
+use tokio::io::AsyncRead;
+use tokio::runtime::Runtime;
+use tokio_util::io::SyncIoBridge;
+
+use crate::error::Error;
+
+// An object streamed from S3 as it is read, for inputs given as `s3://bucket/key`. The SDK is
+// async, so the reader owns a small runtime that the blocking reads drive. Credentials, region
+// and endpoint come from the usual AWS chain (environment, profile, instance or Lambda role),
+// so `AWS_ENDPOINT_URL` points it at an S3-compatible store.
+pub struct S3Reader {
+    body: SyncIoBridge<Box<dyn AsyncRead + Send + Unpin>>,
+    // Dropped after the body that runs on it
+    _runtime: Runtime,
+}
+
+impl S3Reader {
+    pub fn open(url: &str) -> Result<Self, Error> {
+        let (bucket, key) = parse_url(url)?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        let object = runtime
+            .block_on(async {
+                let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+                aws_sdk_s3::Client::new(&config)
+                    .get_object()
+                    .bucket(bucket)
+                    .key(key)
+                    .send()
+                    .await
+            })
+            .map_err(|e| Error::S3(format!("{}: {}", url, DisplayErrorContext(&e))))?;
+        let body: Box<dyn AsyncRead + Send + Unpin> = Box::new(object.body.into_async_read());
+        Ok(Self {
+            body: SyncIoBridge::new_with_handle(body, runtime.handle().clone()),
+            _runtime: runtime,
+        })
+    }
+}
+
+impl Read for S3Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+// Bucket and key of `s3://bucket/key`
+fn parse_url(url: &str) -> Result<(&str, &str), Error> {
+    url.strip_prefix("s3://")
+        .and_then(|rest| rest.split_once('/'))
+        .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+        .ok_or_else(|| Error::InvalidArgument(format!("expected s3://bucket/key, got {}", url)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bucket_and_key() {
+        assert_eq!(
+            parse_url("s3://ledger/2024/01/01.csv.gz").unwrap(),
+            ("ledger", "2024/01/01.csv.gz")
+        );
+        assert!(parse_url("s3://ledger").is_err());
+        assert!(parse_url("s3:///key.csv").is_err());
+        assert!(parse_url("s3://ledger/").is_err());
+    }
+}
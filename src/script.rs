@@ -0,0 +1,154 @@
+use std::path::Path;
+
+use rhai::{AST, Dynamic, Engine, Map, Scope};
+use rust_decimal::Decimal;
+
+use crate::account::Account;
+use crate::error::Error;
+use crate::rule::{RowRule, RuleDecision};
+use crate::transactions::TransactionRow;
+
+// A user supplied rhai script evaluated once per row, inside the worker that owns the client.
+// The script sees two maps:
+//
+//   row     - type, client, tx, amount (decimal or ())
+//   account - available, held, total, locked, or () for a client not seen yet
+//
+// and returns `true`/`()` to accept, `false` to reject, or a map with a new `amount` to accept
+// the row with that amount. Amounts are decimals (rhai is built with `decimal` + `no_float`)
+// so rules never go through floating point.
+pub struct ScriptRule {
+    engine: Engine,
+    ast: AST,
+}
+
+impl ScriptRule {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let source = std::fs::read_to_string(path)?;
+        Self::compile(&source)
+    }
+
+    pub fn compile(source: &str) -> Result<Self, Error> {
+        let engine = Engine::new();
+        let ast = engine
+            .compile(source)
+            .map_err(|e| Error::Script(e.to_string()))?;
+        Ok(Self { engine, ast })
+    }
+}
+
+impl RowRule for ScriptRule {
+    fn evaluate(
+        &self,
+        mut row: TransactionRow,
+        account: Option<&Account>,
+    ) -> Result<RuleDecision, Error> {
+        let mut scope = Scope::new();
+        scope.push("row", row_map(&row));
+        scope.push(
+            "account",
+            account.map_or(Dynamic::UNIT, |a| account_map(a).into()),
+        );
+
+        let result: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| Error::Script(e.to_string()))?;
+
+        if result.is_unit() {
+            return Ok(RuleDecision::Accept(row));
+        }
+        if let Some(accept) = result.clone().try_cast::<bool>() {
+            return Ok(if accept {
+                RuleDecision::Accept(row)
+            } else {
+                RuleDecision::Reject
+            });
+        }
+        if let Some(changes) = result.try_cast::<Map>() {
+            if let Some(amount) = changes.get("amount") {
+                let amount = amount.clone().try_cast::<Decimal>().ok_or_else(|| {
+                    Error::Script("script returned a non-decimal amount".to_string())
+                })?;
+                row.set_amount(Some(amount));
+            }
+            return Ok(RuleDecision::Accept(row));
+        }
+        Err(Error::Script(
+            "script must return a bool, () or a map".to_string(),
+        ))
+    }
+}
+
+fn row_map(row: &TransactionRow) -> Map {
+    let mut map = Map::new();
+    map.insert("type".into(), row.tx_type().as_str().into());
+    map.insert("client".into(), (row.client() as i64).into());
+    map.insert("tx".into(), (row.tx() as i64).into());
+    map.insert(
+        "amount".into(),
+        row.amount().map_or(Dynamic::UNIT, Dynamic::from_decimal),
+    );
+    map
+}
+
+fn account_map(account: &Account) -> Map {
+    let mut map = Map::new();
+    map.insert(
+        "available".into(),
+        Dynamic::from_decimal(account.available()),
+    );
+    map.insert("held".into(), Dynamic::from_decimal(account.held()));
+    map.insert("total".into(), Dynamic::from_decimal(account.total()));
+    map.insert("locked".into(), account.locked().into());
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deposit(amount: i64) -> TransactionRow {
+        TransactionRow::new("deposit", 1, 1, Some(Decimal::new(amount, 0)))
+    }
+
+    #[test]
+    fn rejects_by_rule() {
+        let rule = ScriptRule::compile("row.amount <= 1000.0").unwrap();
+
+        assert!(matches!(
+            rule.evaluate(deposit(5000), None).unwrap(),
+            RuleDecision::Reject
+        ));
+        assert!(matches!(
+            rule.evaluate(deposit(10), None).unwrap(),
+            RuleDecision::Accept(_)
+        ));
+    }
+
+    #[test]
+    fn modifies_amount() {
+        let rule = ScriptRule::compile("#{ amount: row.amount - 0.5 }").unwrap();
+
+        let RuleDecision::Accept(row) = rule.evaluate(deposit(10), None).unwrap() else {
+            panic!("expected accept");
+        };
+        assert_eq!(row.amount(), Some(Decimal::new(95, 1)));
+    }
+
+    #[test]
+    fn sees_account_balances() {
+        let mut account = Account::new(1);
+        account.deposit(Decimal::new(20, 0)).unwrap();
+        let rule = ScriptRule::compile("account == () || account.available >= row.amount").unwrap();
+
+        assert!(matches!(
+            rule.evaluate(deposit(50), Some(&account)).unwrap(),
+            RuleDecision::Reject
+        ));
+        assert!(matches!(
+            rule.evaluate(deposit(50), None).unwrap(),
+            RuleDecision::Accept(_)
+        ));
+    }
+}
@@ -0,0 +1,223 @@
+use std::fs::File;
+use std::ops::Range;
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+use crate::error::Error;
+use crate::mmap::{Columns, UTF8_BOM};
+use crate::progress::Progress;
+use crate::transactions::TransactionRow;
+
+// Faster take on `MmapRows` for plain CSV: records are split at newlines with `memchr`'s SIMD
+// search and into fields at each comma, and the fields are read straight from the mapped
+// bytes, without a copy or a state machine per byte. A record with a quote in it, a quoted
+// field or one spanning lines, goes through the `csv` crate instead. Either way the rows are
+// the buffered reader's, tagged with the line they start on.
+pub struct SimdRows<D = Mmap> {
+    data: D,
+    pos: usize,
+    columns: Columns,
+    // Bounds of the current record's fields within `data`
+    fields: Vec<(usize, usize)>,
+    // Newlines before `pos`
+    lines: u64,
+    progress: Option<Arc<Progress>>,
+    // Bytes already counted towards `progress`
+    reported: usize,
+}
+
+impl SimdRows<Mmap> {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        // Safety: as for `MmapRows::open`, input files are not written to while processed
+        let data = unsafe { Mmap::map(&file)? };
+        Self::new(data)
+    }
+}
+
+impl<D: AsRef<[u8]>> SimdRows<D> {
+    pub fn new(data: D) -> Result<Self, Error> {
+        let mut rows = Self {
+            data,
+            pos: 0,
+            columns: Columns::default(),
+            fields: Vec::with_capacity(8),
+            lines: 0,
+            progress: None,
+            reported: 0,
+        };
+        if rows.data.as_ref().starts_with(UTF8_BOM) {
+            rows.pos = UTF8_BOM.len();
+        }
+        let Some((_, record, _)) = rows.next_record() else {
+            return Err(Error::InvalidArgument("missing header row".to_string()));
+        };
+        let header = csv_record(&rows.data.as_ref()[record])?;
+        rows.columns = Columns::from_header(header.len(), |i| header[i].as_bytes())?;
+        Ok(rows)
+    }
+
+    // Counts the bytes parsed towards the input bytes read
+    pub fn with_progress(mut self, progress: Arc<Progress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    // The next record's first line, its bytes without the line ending, and whether it has
+    // quotes in it
+    fn next_record(&mut self) -> Option<(u64, Range<usize>, bool)> {
+        let data = self.data.as_ref();
+        // Blank lines are not records
+        while let Some(&byte) = data.get(self.pos)
+            && (byte == b'\n' || byte == b'\r')
+        {
+            self.lines += u64::from(byte == b'\n');
+            self.pos += 1;
+        }
+        if self.pos >= data.len() {
+            return None;
+        }
+        let (line, start) = (self.lines + 1, self.pos);
+        let mut end = line_end(data, start);
+        let quoted = memchr::memchr(b'"', &data[start..end]).is_some();
+        // A newline inside quotes is part of the field, so the record goes on until the
+        // quotes balance
+        while quoted
+            && end < data.len()
+            && memchr::memchr_iter(b'"', &data[start..end]).count() % 2 == 1
+        {
+            self.lines += 1;
+            end = line_end(data, end + 1);
+        }
+        if end < data.len() {
+            self.lines += 1;
+        }
+        self.pos = (end + 1).min(data.len());
+        Some((line, start..end, quoted))
+    }
+}
+
+// Index of the newline ending the line at `start`, or the end of the data
+fn line_end(data: &[u8], start: usize) -> usize {
+    memchr::memchr(b'\n', &data[start..]).map_or(data.len(), |i| start + i)
+}
+
+// One record parsed by the `csv` crate, fields trimmed like the buffered reader's
+fn csv_record(bytes: &[u8]) -> Result<csv::StringRecord, csv::Error> {
+    let mut record = csv::StringRecord::new();
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_reader(bytes)
+        .read_record(&mut record)?;
+    Ok(record)
+}
+
+impl<D: AsRef<[u8]>> Iterator for SimdRows<D> {
+    type Item = Result<TransactionRow, csv::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (line, record, quoted) = self.next_record()?;
+        if let Some(progress) = &self.progress {
+            progress.record_bytes((self.pos - self.reported) as u64);
+            self.reported = self.pos;
+        }
+        let data = self.data.as_ref();
+        let row = if quoted {
+            csv_record(&data[record]).and_then(|fields| {
+                let row = self
+                    .columns
+                    .parse(line, fields.len(), |i| fields[i].as_bytes());
+                row.map_err(csv::Error::from)
+            })
+        } else {
+            self.fields.clear();
+            let mut field = record.start;
+            for comma in memchr::memchr_iter(b',', &data[record.clone()]) {
+                self.fields.push((field, record.start + comma));
+                field = record.start + comma + 1;
+            }
+            self.fields.push((field, record.end));
+            let fields = &self.fields;
+            let row = self.columns.parse(line, fields.len(), |i| {
+                let (start, end) = fields[i];
+                data[start..end].trim_ascii()
+            });
+            row.map_err(csv::Error::from)
+        };
+        Some(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::numbered_rows;
+    use crate::mmap::MmapRows;
+
+    #[test]
+    fn matches_buffered_reader() {
+        let data = "\u{feff}type, client, tx, amount, reason\n\
+                    deposit, 1, 1, 1.5,\n\
+                    adjustment,2,2,-2e1,\"FX, March\"\n\
+                    adjustment,2,3,1,\"two\nlines\"\n\
+                    \"refund\",3,4,0.25,\n\
+                    deposit,4,5,not-a-number,\n\
+                    dispute,1,1\n\
+                    resolve,1,1,,";
+        let buffered: Vec<_> = numbered_rows(
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(data.as_bytes()),
+        )
+        .unwrap()
+        .collect();
+        let mapped: Vec<_> = MmapRows::new(data.as_bytes()).unwrap().collect();
+        let fast: Vec<_> = SimdRows::new(data.as_bytes()).unwrap().collect();
+
+        assert_eq!(fast.len(), buffered.len());
+        for ((fast, buffered), mapped) in fast.iter().zip(&buffered).zip(&mapped) {
+            match (fast, buffered, mapped) {
+                (Ok(fast), Ok(buffered), Ok(mapped)) => {
+                    assert_eq!(fast, buffered);
+                    assert_eq!(fast, mapped);
+                }
+                (Err(_), Err(_), Err(_)) => {}
+                _ => panic!("readers disagree: {:?} vs {:?}", fast, buffered),
+            }
+        }
+        assert!(matches!(&fast[1], Ok(row) if row.reason() == Some("FX, March")));
+        assert!(matches!(&fast[3], Ok(row) if row.line() == Some(6)));
+        // A bad amount and a short row are errors with either reader
+        assert!(fast[4].is_err() && fast[5].is_err());
+        assert!(matches!(&fast[6], Ok(row) if row.line() == Some(9)));
+    }
+
+    // Blank lines count towards the line numbers, unlike with the buffered reader
+    #[test]
+    fn skips_blank_lines_and_carriage_returns() {
+        let data = "type,client,tx,amount\r\ndeposit,1,1,2\r\n\r\nwithdrawal,1,2,1\r\n";
+        let mapped: Vec<_> = MmapRows::new(data.as_bytes())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let fast: Vec<_> = SimdRows::new(data.as_bytes())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(fast, mapped);
+        assert_eq!(fast[1].line(), Some(4));
+    }
+
+    #[test]
+    fn requires_the_engine_columns() {
+        assert!(SimdRows::new("type,client,amount\n".as_bytes()).is_err());
+        assert!(SimdRows::new("".as_bytes()).is_err());
+        assert_eq!(
+            SimdRows::new("type,tx,client".as_bytes()).unwrap().count(),
+            0
+        );
+    }
+}
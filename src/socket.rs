@@ -0,0 +1,104 @@
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+
+use log::warn;
+
+use crate::error::Error;
+use crate::input::numbered_rows;
+use crate::transactions::TransactionRow;
+
+// Rows read ahead of the engine, across all connections
+const BUFFERED_ROWS: usize = 1024;
+
+type Row = Result<TransactionRow, csv::Error>;
+
+// Rows streamed in by other processes on the host over a Unix domain socket, for
+// `--listen-unix /tmp/txs.sock`. Every connection sends CSV as a file would, header first, and
+// any number may be open at once: each is read on its own thread, so a connection's rows keep
+// their order, while rows of different connections interleave as they arrive. Lines are
+// counted per connection. The rows never end; the listener runs until the process is stopped.
+pub struct SocketRows {
+    rows: Receiver<Row>,
+}
+
+impl SocketRows {
+    pub fn bind(path: &str) -> Result<Self, Error> {
+        // A socket left behind by an earlier run that was stopped; anything else is not ours
+        if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let (sender, rows) = mpsc::sync_channel(BUFFERED_ROWS);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let sender = sender.clone();
+                        thread::spawn(move || read_connection(stream, sender));
+                    }
+                    Err(e) => warn!("Failed to accept a connection: {}", e),
+                }
+            }
+        });
+        Ok(Self { rows })
+    }
+}
+
+// A connection without the required columns is closed without a row; bad rows are passed
+// on like a file's
+fn read_connection(stream: UnixStream, sender: SyncSender<Row>) {
+    let reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(stream);
+    let rows = match numbered_rows(reader) {
+        Ok(rows) => rows,
+        Err(e) => {
+            warn!("Closed a connection with an invalid header: {}", e);
+            return;
+        }
+    };
+    for row in rows {
+        // The run is over
+        if sender.send(row).is_err() {
+            return;
+        }
+    }
+}
+
+impl Iterator for SocketRows {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn reads_every_connection() {
+        let path = std::env::temp_dir().join(format!("toy-processor-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut rows = SocketRows::bind(path).unwrap();
+
+        let mut first = UnixStream::connect(path).unwrap();
+        let mut second = UnixStream::connect(path).unwrap();
+        second.write_all(b"client,type\n1,deposit\n").unwrap();
+        drop(second);
+        first
+            .write_all(b"type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,4\n")
+            .unwrap();
+        drop(first);
+
+        let rows: Vec<_> = rows.by_ref().take(2).map(Result::unwrap).collect();
+        fs::remove_file(path).ok();
+        assert_eq!((rows[0].tx(), rows[1].tx()), (1, 2));
+    }
+}
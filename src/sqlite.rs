@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use rusqlite::{Connection, params};
+
+use crate::account::AccountOutput;
+use crate::config::Config;
+use crate::deposit_store::{DepositKey, StoredDeposit};
+use crate::error::Error;
+use crate::output::OutputSink;
+
+// Amounts are stored as TEXT formatted at the run's precision: SQLite has no decimal type and
+// REAL would reintroduce exactly the rounding errors the engine avoids. Cast in queries
+// (`CAST(available AS NUMERIC)`) when arithmetic is needed.
+const SCHEMA: &str = "
+    DROP TABLE IF EXISTS accounts;
+    DROP TABLE IF EXISTS deposits;
+    CREATE TABLE accounts (
+        client    INTEGER NOT NULL,
+        sub       INTEGER NOT NULL DEFAULT 0,
+        available TEXT NOT NULL,
+        held      TEXT NOT NULL,
+        total     TEXT NOT NULL,
+        locked    INTEGER NOT NULL,
+        PRIMARY KEY (client, sub)
+    );
+    CREATE TABLE deposits (
+        tx        INTEGER NOT NULL,
+        client    INTEGER NOT NULL,
+        sub       INTEGER NOT NULL DEFAULT 0,
+        amount    TEXT NOT NULL,
+        disputed  TEXT NOT NULL,
+        status    TEXT NOT NULL,
+        timestamp INTEGER,
+        PRIMARY KEY (client, tx)
+    );
+";
+
+// Replaces the tables of an existing database, so reruns against the same path are clean.
+// Everything is written in one transaction committed by `finish`, so a failed run leaves
+// the previous tables in place. The deposits table is always created, and left empty unless
+// deposits are passed.
+pub struct SqliteSink {
+    conn: Connection,
+    deposits: Option<HashMap<DepositKey, StoredDeposit>>,
+    config: Config,
+}
+
+impl SqliteSink {
+    pub fn open(path: impl AsRef<Path>, config: &Config) -> Result<Self, Error> {
+        Self::new(Connection::open(path)?, config)
+    }
+
+    fn new(conn: Connection, config: &Config) -> Result<Self, Error> {
+        conn.execute_batch("BEGIN")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn,
+            deposits: None,
+            config: *config,
+        })
+    }
+
+    // Also fills the deposits table, withdrawals left out
+    pub fn with_deposits(mut self, deposits: HashMap<DepositKey, StoredDeposit>) -> Self {
+        self.deposits = Some(deposits);
+        self
+    }
+}
+
+impl OutputSink for SqliteSink {
+    fn write_account(&mut self, account: &AccountOutput) -> Result<(), Error> {
+        let mut insert = self.conn.prepare_cached(
+            "INSERT INTO accounts (client, sub, available, held, total, locked) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        insert.execute(params![
+            account.client(),
+            account.sub().unwrap_or_default(),
+            account.available(),
+            account.held(),
+            account.total(),
+            account.locked(),
+        ])?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        {
+            let mut insert = self.conn.prepare(
+                "INSERT INTO deposits (tx, client, sub, amount, disputed, status, timestamp) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            let config = &self.config;
+            let deposits = self.deposits.take().into_iter().flatten();
+            for (key, deposit) in deposits.filter(|(_, d)| !d.is_withdrawal()) {
+                insert.execute(params![
+                    key.tx,
+                    key.client,
+                    deposit.sub(),
+                    config.format(deposit.amount()),
+                    config.format(deposit.disputed_amount()),
+                    deposit.status().as_str(),
+                    deposit.timestamp().map(|ts| ts as i64),
+                ])?;
+            }
+        }
+        self.conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::account::AccountMap;
+    use crate::deposit_store::DepositStore;
+    use crate::transactions::{DepositTx, DisputeTx};
+
+    #[test]
+    fn writes_accounts_and_deposits() {
+        let mut accounts = AccountMap::new();
+        let mut deposits: HashMap<DepositKey, StoredDeposit> = HashMap::new();
+        DepositTx::new(7, 1, Decimal::new(105, 1))
+            .with_timestamp(Some(42))
+            .process(&mut accounts, &mut deposits)
+            .unwrap();
+        DisputeTx::new(7, 1)
+            .process(&mut accounts, &mut deposits)
+            .unwrap();
+        assert!(DepositStore::get(&deposits, DepositKey::new(7, 1)).is_some());
+
+        let config = Config::default();
+        let mut sink = SqliteSink::new(Connection::open_in_memory().unwrap(), &config)
+            .unwrap()
+            .with_deposits(deposits);
+        for account in accounts.into_iter_sorted() {
+            sink.write_account(&AccountOutput::new(account, &config))
+                .unwrap();
+        }
+        sink.finish().unwrap();
+        let conn = sink.conn;
+
+        let account: (u16, String, String, bool) = conn
+            .query_row(
+                "SELECT client, available, held, locked FROM accounts",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            account,
+            (7, "0.0000".to_string(), "10.5000".to_string(), false)
+        );
+
+        let deposit: (u32, String, i64) = conn
+            .query_row("SELECT tx, status, timestamp FROM deposits", [], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+            })
+            .unwrap();
+        assert_eq!(deposit, (1, "disputed".to_string(), 42));
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt::Write;
+
+use sha2::{Digest, Sha256};
+
+use crate::account::AccountMap;
+
+// Changes whenever the hashed form does, so hashes of different versions never match by accident
+const VERSION: &str = "toy-processor-state v1";
+
+// A SHA-256 commitment to the accounts, `sha256:<hex>`, for `--state-hash`: two runs that ended
+// with the same accounts print the same hash, whatever the number of workers, the order the
+// accounts were created in or the output options. Hashed is a version line, then a line per
+// account in client order, then sub-account:
+//
+//   <client>,<sub>,<available>,<held>,<status>
+//
+// with the amounts as plain decimals without trailing zeros, as `canonical` writes them.
+pub fn state_hash(accounts: &AccountMap) -> String {
+    let mut sorted: Vec<_> = accounts.iter().collect();
+    sorted.sort_by_key(|account| account.key());
+
+    let mut hasher = Sha256::new();
+    hasher.update(VERSION);
+    hasher.update("\n");
+    for account in sorted {
+        hasher.update(format!(
+            "{},{},{},{},{}\n",
+            account.client(),
+            account.sub(),
+            account.available().normalize(),
+            account.held().normalize(),
+            account.status()
+        ));
+    }
+    hasher
+        .finalize()
+        .iter()
+        .fold("sha256:".to_string(), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::transactions::DepositTx;
+
+    fn deposits(deposits: &[(u16, u32, i64)]) -> AccountMap {
+        let mut accounts = AccountMap::new();
+        let mut store = std::collections::HashMap::new();
+        for &(client, tx, amount) in deposits {
+            DepositTx::new(client, tx, Decimal::new(amount, 2))
+                .process(&mut accounts, &mut store)
+                .unwrap();
+        }
+        accounts
+    }
+
+    #[test]
+    fn hashes_the_accounts_not_their_history() {
+        let hash = state_hash(&deposits(&[(1, 1, 1000), (2, 2, 550)]));
+
+        assert!(hash.starts_with("sha256:") && hash.len() == 7 + 64);
+        assert_eq!(state_hash(&deposits(&[(2, 2, 550), (1, 1, 1000)])), hash);
+        assert_eq!(
+            state_hash(&deposits(&[(1, 1, 600), (1, 3, 400), (2, 2, 550)])),
+            hash
+        );
+        assert_ne!(state_hash(&deposits(&[(1, 1, 1000), (2, 2, 551)])), hash);
+        assert_ne!(state_hash(&deposits(&[(1, 1, 1000)])), hash);
+    }
+}
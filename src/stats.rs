@@ -0,0 +1,87 @@
+use std::fmt;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::transactions::{TransactionRow, TxType};
+
+// Counters collected by the reader loop for a single input source. Kept per source so a bad
+// partner file in a combined run stands out instead of being averaged away.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceStats {
+    source: String,
+    rows: u64,
+    rejects: u64,
+    duplicates: u64,
+    deposit_total: Decimal,
+    withdrawal_total: Decimal,
+}
+
+impl SourceStats {
+    pub fn new(source: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn record_reject(&mut self) {
+        self.rows += 1;
+        self.rejects += 1;
+    }
+
+    pub fn record_duplicate(&mut self) {
+        self.rows += 1;
+        self.duplicates += 1;
+    }
+
+    pub fn record_accepted(&mut self, row: &TransactionRow) {
+        self.rows += 1;
+        if let Some(amount) = row.amount() {
+            match row.tx_type() {
+                TxType::Deposit => self.deposit_total += amount,
+                TxType::Withdrawal => self.withdrawal_total += amount,
+                _ => {}
+            }
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub fn rejects(&self) -> u64 {
+        self.rejects
+    }
+
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    pub fn deposit_total(&self) -> Decimal {
+        self.deposit_total
+    }
+
+    pub fn withdrawal_total(&self) -> Decimal {
+        self.withdrawal_total
+    }
+}
+
+impl fmt::Display for SourceStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "source={} rows={} rejects={} duplicates={} deposits={} withdrawals={}",
+            self.source,
+            self.rows,
+            self.rejects,
+            self.duplicates,
+            self.deposit_total,
+            self.withdrawal_total
+        )
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::account::AccountMap;
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::stats::SourceStats;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Totals {
+    pub count: u64,
+    pub value: Decimal,
+}
+
+// End-of-run overview for operators: what was applied, what was turned away and why, and
+// the state the accounts were left in. Built from the merged worker metrics and the final
+// accounts, so it covers every worker.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub deposits: Totals,
+    pub withdrawals: Totals,
+    pub disputes: u64,
+    pub resolves: u64,
+    pub chargebacks: u64,
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    // Available below zero, from clawbacks or overdrafts
+    pub negative_accounts: usize,
+    pub rejected: BTreeMap<&'static str, u64>,
+    // Rows left to the dead-letter queue by a panicked worker
+    pub dead_letters: u64,
+    // Per input, to tell which file of a combined run the rejects came from
+    pub sources: Vec<SourceStats>,
+}
+
+impl Summary {
+    pub fn new(metrics: &Metrics, accounts: &AccountMap, config: &Config) -> Self {
+        let totals = |kind| {
+            // Padded to the run's precision like the account output
+            let mut value = config.round(metrics.value(kind));
+            value.rescale(config.precision);
+            Totals {
+                count: metrics.counters(kind).processed,
+                value,
+            }
+        };
+        Self {
+            deposits: totals("deposit"),
+            withdrawals: totals("withdrawal"),
+            disputes: metrics.counters("dispute").processed,
+            resolves: metrics.counters("resolve").processed,
+            chargebacks: metrics.counters("chargeback").processed,
+            accounts: accounts.len(),
+            locked_accounts: accounts.iter().filter(|a| a.locked()).count(),
+            negative_accounts: accounts.iter().filter(|a| a.in_overdraft()).count(),
+            rejected: metrics.rejections(),
+            dead_letters: metrics.dead_letters(),
+            sources: metrics.sources().to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "deposits: {} totalling {}",
+            self.deposits.count, self.deposits.value
+        )?;
+        writeln!(
+            f,
+            "withdrawals: {} totalling {}",
+            self.withdrawals.count, self.withdrawals.value
+        )?;
+        writeln!(
+            f,
+            "disputes: {}, resolves: {}, chargebacks: {}",
+            self.disputes, self.resolves, self.chargebacks
+        )?;
+        writeln!(
+            f,
+            "accounts: {}, locked: {}, negative balance: {}",
+            self.accounts, self.locked_accounts, self.negative_accounts
+        )?;
+        write!(f, "rejected: {}", self.rejected.values().sum::<u64>())?;
+        for (reason, count) in &self.rejected {
+            write!(f, "\n  {}: {}", reason, count)?;
+        }
+        // Only when it happened, like a crash it needs attention
+        if self.dead_letters > 0 {
+            write!(f, "\ndead-lettered: {}", self.dead_letters)?;
+        }
+        // A single input's numbers are the totals above
+        if self.sources.len() > 1 {
+            write!(f, "\nsources:")?;
+            for stats in &self.sources {
+                write!(
+                    f,
+                    "\n  {}: {} rows, {} rejected, {} duplicates, deposits {}, withdrawals {}",
+                    stats.source(),
+                    stats.rows(),
+                    stats.rejects(),
+                    stats.duplicates(),
+                    stats.deposit_total(),
+                    stats.withdrawal_total()
+                )?;
+            }
+        }
+        writeln!(f)
+    }
+}
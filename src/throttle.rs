@@ -0,0 +1,73 @@
+use std::time::{Duration, Instant};
+
+// Share of a second's rows that may go out at once after an idle spell
+const BURST_SECONDS: f64 = 0.1;
+
+// A token bucket holding the reader to `rate` rows a second, for `--max-tps`, so a replay
+// against shared storage doesn't overwhelm it. The bucket refills continuously and holds at
+// most a tenth of a second's rows, so after a pause the reader catches up by that much at
+// most. A row taken from an empty bucket goes into debt; the wait pays it back.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+        let burst = (rate * BURST_SECONDS).max(1.0);
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+        }
+    }
+
+    // Takes a token for one row, returning how long to wait before sending it: zero while
+    // the bucket isn't empty
+    pub fn take(&mut self) -> Duration {
+        self.take_at(Instant::now())
+    }
+
+    fn take_at(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst) - 1.0;
+        match self.tokens < 0.0 {
+            true => Duration::from_secs_f64(-self.tokens / self.rate),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_rows_to_the_rate() {
+        let mut bucket = TokenBucket::new(100);
+        let start = bucket.last;
+
+        // The burst of 10 goes out at once, the 11th waits for a token
+        for _ in 0..10 {
+            assert_eq!(bucket.take_at(start), Duration::ZERO);
+        }
+        assert_eq!(bucket.take_at(start), Duration::from_millis(10));
+
+        // Waiting it out pays the debt back exactly
+        let later = start + Duration::from_millis(10);
+        assert_eq!(bucket.take_at(later), Duration::from_millis(10));
+
+        // An idle spell refills no more than the burst
+        let idle = later + Duration::from_secs(60);
+        for _ in 0..10 {
+            assert_eq!(bucket.take_at(idle), Duration::ZERO);
+        }
+        assert!(!bucket.take_at(idle).is_zero());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::account::{AccountKey, AccountMap};
+use crate::error::Error;
+use crate::transactions::{ProcessableTx, TxContext};
+use rust_decimal::Decimal;
+
+// Operator row correcting a client's balance by a signed amount, e.g. to backfill a booking
+// the partner never sent. Unlike a correction it references no earlier transaction: the tx id
+// only names the row. Carries the reason code ops gave for it, for the audit report.
+#[derive(Debug)]
+pub struct AdjustmentTx {
+    client: u16,
+    sub: u16,
+    id: u32,
+    amount: Decimal,
+    reason: String,
+}
+
+impl AdjustmentTx {
+    pub fn new(client: u16, id: u32, amount: Decimal, reason: String) -> Self {
+        Self {
+            client,
+            sub: 0,
+            id,
+            amount,
+            reason,
+        }
+    }
+
+    pub fn with_sub(mut self, sub: u16) -> Self {
+        self.sub = sub;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    pub fn account(&self) -> AccountKey {
+        AccountKey::new(self.client, self.sub)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    // Creates the account if needed; see `Account::adjust` for what it may do to one
+    pub fn process(&self, accounts: &mut AccountMap) -> Result<(), Error> {
+        accounts.get_or_create(self.account()).adjust(self.amount)
+    }
+}
+
+impl ProcessableTx for AdjustmentTx {
+    fn client(&self) -> u16 {
+        self.client
+    }
+
+    fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        "adjustment"
+    }
+
+    fn amount(&self) -> Option<Decimal> {
+        Some(self.amount)
+    }
+
+    fn process(&self, ctx: &mut TxContext<'_>) -> Result<(), Error> {
+        AdjustmentTx::process(self, ctx.accounts)
+    }
+}
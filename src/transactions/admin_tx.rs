@@ -0,0 +1,86 @@
+use crate::account::{AccountKey, AccountMap};
+use crate::error::Error;
+use crate::transactions::{ProcessableTx, TxContext};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminAction {
+    Freeze,
+    Close,
+}
+
+// Operator row changing an account's status. The tx id only identifies the row in logs; it
+// references nothing and is not deduplicated. The account must exist.
+#[derive(Debug)]
+pub struct AdminTx {
+    client: u16,
+    sub: u16,
+    id: u32,
+    action: AdminAction,
+}
+
+impl AdminTx {
+    pub fn new(client: u16, id: u32, action: AdminAction) -> Self {
+        Self {
+            client,
+            sub: 0,
+            id,
+            action,
+        }
+    }
+
+    pub fn with_sub(mut self, sub: u16) -> Self {
+        self.sub = sub;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    pub fn account(&self) -> AccountKey {
+        AccountKey::new(self.client, self.sub)
+    }
+
+    #[allow(dead_code)]
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn action(&self) -> AdminAction {
+        self.action
+    }
+
+    pub fn process(&self, accounts: &mut AccountMap) -> Result<(), Error> {
+        let account = accounts.get_mut(self.account())?;
+        match self.action {
+            AdminAction::Freeze => account.freeze(),
+            AdminAction::Close => account.close(),
+        }
+    }
+}
+
+impl ProcessableTx for AdminTx {
+    fn client(&self) -> u16 {
+        self.client
+    }
+
+    fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        match self.action {
+            AdminAction::Freeze => "freeze",
+            AdminAction::Close => "close",
+        }
+    }
+
+    fn process(&self, ctx: &mut TxContext<'_>) -> Result<(), Error> {
+        AdminTx::process(self, ctx.accounts)
+    }
+}
@@ -0,0 +1,105 @@
+use crate::account::{AccountKey, AccountMap};
+use crate::policy::ReversalPolicy;
+use crate::transactions::{ProcessableTx, TxContext};
+use crate::{
+    deposit_store::{DepositKey, DepositStore},
+    error::Error,
+};
+
+// The card network reversed a chargeback: the charged back funds return to the client and
+// the deposit ends up resolved, as if the dispute had gone the client's way.
+#[derive(Debug)]
+pub struct ChargebackReversalTx {
+    client: u16,
+    sub: u16,
+    id: u32,
+    policy: ReversalPolicy,
+}
+
+impl ChargebackReversalTx {
+    pub fn new(client: u16, id: u32) -> Self {
+        Self {
+            client,
+            sub: 0,
+            id,
+            policy: ReversalPolicy::default(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: ReversalPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_sub(mut self, sub: u16) -> Self {
+        self.sub = sub;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    pub fn account(&self) -> AccountKey {
+        AccountKey::new(self.client, self.sub)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    // What the deposit it refers to is stored under
+    pub fn key(&self) -> DepositKey {
+        DepositKey::new(self.client, self.id)
+    }
+
+    pub fn policy(&self) -> ReversalPolicy {
+        self.policy
+    }
+
+    // State transition (set_chargeback_reversed) is the idempotency guard: a reversed deposit
+    // is resolved and cannot be reversed again. Allowed on the account the chargeback locked.
+    pub fn process(
+        &self,
+        accounts: &mut AccountMap,
+        stored_deposits: &mut (impl DepositStore + ?Sized),
+    ) -> Result<(), Error> {
+        if let Some(stored_deposit) = stored_deposits.get_mut(self.key()) {
+            stored_deposit.ensure_account_matches(self.id(), self.account())?;
+            let account = accounts.get_mut(self.account())?;
+            account.check_open()?;
+            stored_deposit.set_chargeback_reversed()?;
+            account.reverse_chargeback(
+                stored_deposit.disputed_amount(),
+                self.policy == ReversalPolicy::Unlock,
+            );
+
+            Ok(())
+        } else {
+            Err(Error::StoredDepositNotFound(self.id()))
+        }
+    }
+}
+
+impl ProcessableTx for ChargebackReversalTx {
+    fn client(&self) -> u16 {
+        self.client
+    }
+
+    fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        "chargeback_reversal"
+    }
+
+    fn process(&self, ctx: &mut TxContext<'_>) -> Result<(), Error> {
+        ChargebackReversalTx::process(self, ctx.accounts, ctx.deposits)
+    }
+}
@@ -0,0 +1,127 @@
+use crate::account::{AccountKey, AccountMap};
+use crate::transactions::{ProcessableTx, TxContext};
+use crate::{
+    deposit_store::{DepositKey, DepositStore},
+    error::Error,
+};
+use rust_decimal::Decimal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorrectionTarget {
+    Deposit,
+    Withdrawal,
+}
+
+// Signed change to an earlier deposit or withdrawal, referenced by its tx id. A negative
+// deposit correction means less was received than booked, a negative withdrawal correction
+// that less was paid out.
+#[derive(Debug)]
+pub struct CorrectionTx {
+    client: u16,
+    sub: u16,
+    id: u32,
+    amount: Decimal,
+    target: CorrectionTarget,
+}
+
+impl CorrectionTx {
+    pub fn new(client: u16, id: u32, amount: Decimal, target: CorrectionTarget) -> Self {
+        Self {
+            client,
+            sub: 0,
+            id,
+            amount,
+            target,
+        }
+    }
+
+    pub fn with_sub(mut self, sub: u16) -> Self {
+        self.sub = sub;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    pub fn account(&self) -> AccountKey {
+        AccountKey::new(self.client, self.sub)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    // What the deposit it refers to is stored under
+    pub fn key(&self) -> DepositKey {
+        DepositKey::new(self.client, self.id)
+    }
+
+    #[allow(dead_code)]
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    pub fn target(&self) -> CorrectionTarget {
+        self.target
+    }
+
+    // Both sides are checked before either is touched, so a refused correction changes nothing
+    pub fn process(
+        &self,
+        accounts: &mut AccountMap,
+        stored: &mut (impl DepositStore + ?Sized),
+    ) -> Result<(), Error> {
+        let (original, balance_delta) = match self.target {
+            CorrectionTarget::Deposit => (
+                stored
+                    .get_mut(self.key())
+                    .ok_or(Error::StoredDepositNotFound(self.id()))?,
+                self.amount,
+            ),
+            CorrectionTarget::Withdrawal => (
+                stored
+                    .get_withdrawal_mut(self.key())
+                    .ok_or(Error::StoredWithdrawalNotFound(self.id()))?,
+                -self.amount,
+            ),
+        };
+        original.ensure_account_matches(self.id(), self.account())?;
+        original.check_correction(self.id(), self.amount)?;
+
+        let account = accounts.get_mut(self.account())?;
+        account.correct(balance_delta)?;
+        original.apply_correction(self.amount);
+        Ok(())
+    }
+}
+
+impl ProcessableTx for CorrectionTx {
+    fn client(&self) -> u16 {
+        self.client
+    }
+
+    fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        match self.target {
+            CorrectionTarget::Deposit => "deposit_correction",
+            CorrectionTarget::Withdrawal => "withdrawal_correction",
+        }
+    }
+
+    fn amount(&self) -> Option<Decimal> {
+        Some(self.amount)
+    }
+
+    fn process(&self, ctx: &mut TxContext<'_>) -> Result<(), Error> {
+        CorrectionTx::process(self, ctx.accounts, ctx.deposits)
+    }
+}
@@ -1,22 +1,58 @@
-use crate::{account::AccountMap, deposit_store::DepositStore, error::Error};
+use crate::account::{AccountKey, AccountMap};
+use crate::transactions::{ProcessableTx, TxContext};
+use crate::{deposit_store::DepositStore, error::Error};
 use rust_decimal::Decimal;
 
 #[derive(Debug)]
 pub struct DepositTx {
     client: u16,
+    sub: u16,
     id: u32,
     amount: Decimal,
+    timestamp: Option<u64>,
+    // Held until a release at or after this time, see `ReleaseTx`
+    pending_until: Option<u64>,
 }
 
 impl DepositTx {
     pub fn new(client: u16, id: u32, amount: Decimal) -> Self {
-        Self { client, id, amount }
+        Self {
+            client,
+            sub: 0,
+            id,
+            amount,
+            timestamp: None,
+            pending_until: None,
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: Option<u64>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn with_pending_until(mut self, pending_until: Option<u64>) -> Self {
+        self.pending_until = pending_until;
+        self
+    }
+
+    pub fn with_sub(mut self, sub: u16) -> Self {
+        self.sub = sub;
+        self
     }
 
     pub fn client(&self) -> u16 {
         self.client
     }
 
+    pub fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    pub fn account(&self) -> AccountKey {
+        AccountKey::new(self.client, self.sub)
+    }
+
     pub fn id(&self) -> u32 {
         self.id
     }
@@ -25,14 +61,51 @@ impl DepositTx {
         self.amount
     }
 
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    pub fn pending_until(&self) -> Option<u64> {
+        self.pending_until
+    }
+
     pub fn process(
         &self,
         accounts: &mut AccountMap,
-        stored_deposits: &mut impl DepositStore,
+        stored_deposits: &mut (impl DepositStore + ?Sized),
     ) -> Result<(), Error> {
-        let account = accounts.get_or_create(self.client());
-        account.deposit(self.amount())?;
+        let account = accounts.get_or_create(self.account());
+        match self.pending_until {
+            Some(_) => account.deposit_pending(self.amount())?,
+            None => account.deposit(self.amount())?,
+        }
         stored_deposits.insert(self);
         Ok(())
     }
 }
+
+impl ProcessableTx for DepositTx {
+    fn client(&self) -> u16 {
+        self.client
+    }
+
+    fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        "deposit"
+    }
+
+    fn amount(&self) -> Option<Decimal> {
+        Some(self.amount)
+    }
+
+    fn process(&self, ctx: &mut TxContext<'_>) -> Result<(), Error> {
+        DepositTx::process(self, ctx.accounts, ctx.deposits)
+    }
+}
@@ -1,37 +1,123 @@
-use crate::{account::AccountMap, deposit_store::DepositStore, error::Error};
+use rust_decimal::Decimal;
+
+use crate::account::{AccountKey, AccountMap};
+use crate::policy::{DisputeOverdraftPolicy, LockedAccountDisputePolicy};
+use crate::transactions::{ProcessableTx, TxContext};
+use crate::{
+    deposit_store::{DepositKey, DepositStore},
+    error::Error,
+};
 
 #[derive(Debug)]
 pub struct DisputeTx {
     client: u16,
+    sub: u16,
     id: u32,
+    // Portion of the deposit to hold; the whole deposit when absent
+    amount: Option<Decimal>,
+    // Deposits timestamped before this can no longer be disputed
+    cutoff: Option<u64>,
+    overdraft: DisputeOverdraftPolicy,
+    locked: LockedAccountDisputePolicy,
 }
 
 impl DisputeTx {
     pub fn new(client: u16, id: u32) -> Self {
-        Self { client, id }
+        Self {
+            client,
+            sub: 0,
+            id,
+            amount: None,
+            cutoff: None,
+            overdraft: DisputeOverdraftPolicy::default(),
+            locked: LockedAccountDisputePolicy::default(),
+        }
     }
 
-    fn client(&self) -> u16 {
+    pub fn with_amount(mut self, amount: Option<Decimal>) -> Self {
+        self.amount = amount;
+        self
+    }
+
+    pub fn with_cutoff(mut self, cutoff: Option<u64>) -> Self {
+        self.cutoff = cutoff;
+        self
+    }
+
+    pub fn with_policies(
+        mut self,
+        overdraft: DisputeOverdraftPolicy,
+        locked: LockedAccountDisputePolicy,
+    ) -> Self {
+        self.overdraft = overdraft;
+        self.locked = locked;
+        self
+    }
+
+    pub fn with_sub(mut self, sub: u16) -> Self {
+        self.sub = sub;
+        self
+    }
+
+    pub fn client(&self) -> u16 {
         self.client
     }
 
-    fn id(&self) -> u32 {
+    pub fn account(&self) -> AccountKey {
+        AccountKey::new(self.client, self.sub)
+    }
+
+    pub fn id(&self) -> u32 {
         self.id
     }
 
+    // What the deposit it refers to is stored under
+    pub fn key(&self) -> DepositKey {
+        DepositKey::new(self.client, self.id)
+    }
+
+    #[allow(dead_code)]
+    pub fn amount(&self) -> Option<Decimal> {
+        self.amount
+    }
+
     // State transition (set_disputed) is the idempotency guard. The deposit state machine
     // rejects invalid transitions (AlreadyDisputed, etc.), preventing double-processing.
     pub fn process(
         &self,
         accounts: &mut AccountMap,
-        stored_deposits: &mut impl DepositStore,
+        stored_deposits: &mut (impl DepositStore + ?Sized),
     ) -> Result<(), Error> {
-        if let Some(stored_deposit) = stored_deposits.get_mut(self.id()) {
-            stored_deposit.ensure_client_matches(self.id(), self.client())?;
-            let account = accounts.get_mut(self.client())?;
+        if let Some(stored_deposit) = stored_deposits.get_mut(self.key()) {
+            stored_deposit.ensure_account_matches(self.id(), self.account())?;
+            if let Some(cutoff) = self.cutoff
+                && stored_deposit.is_older_than(cutoff)
+            {
+                return Err(Error::DisputeWindowExpired(self.id()));
+            }
+            let amount = self.amount.unwrap_or(stored_deposit.amount());
+            if amount > stored_deposit.amount() {
+                return Err(Error::DisputeExceedsDeposit {
+                    tx_id: self.id(),
+                    deposit: stored_deposit.amount(),
+                    requested: amount,
+                });
+            }
+            let account = accounts.get_mut(self.account())?;
+            account.check_open()?;
+            if self.locked == LockedAccountDisputePolicy::Reject && account.locked() {
+                return Err(Error::AccountLocked(self.client()));
+            }
+            if self.overdraft == DisputeOverdraftPolicy::Reject && account.available() < amount {
+                return Err(Error::InsufficientFunds {
+                    client: self.client(),
+                    available: account.available(),
+                    requested: amount,
+                });
+            }
 
-            stored_deposit.set_disputed()?;
-            account.dispute(stored_deposit.amount())?;
+            stored_deposit.set_disputed(amount)?;
+            account.dispute(amount)?;
 
             Ok(())
         } else {
@@ -39,3 +125,29 @@ impl DisputeTx {
         }
     }
 }
+
+impl ProcessableTx for DisputeTx {
+    fn client(&self) -> u16 {
+        self.client
+    }
+
+    fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        "dispute"
+    }
+
+    fn amount(&self) -> Option<Decimal> {
+        self.amount
+    }
+
+    fn process(&self, ctx: &mut TxContext<'_>) -> Result<(), Error> {
+        DisputeTx::process(self, ctx.accounts, ctx.deposits)
+    }
+}
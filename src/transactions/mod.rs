@@ -1,30 +1,87 @@
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
+mod adjustment_tx;
+mod admin_tx;
+mod chargeback_reversal_tx;
 mod chargeback_tx;
+mod correction_tx;
 mod deposit_tx;
 mod dispute_tx;
+mod processable;
+mod registry;
+mod release_tx;
 mod resolve_tx;
+mod tx_type;
+mod void_tx;
 mod withdrawal_tx;
 
+pub use adjustment_tx::AdjustmentTx;
+pub use admin_tx::{AdminAction, AdminTx};
+pub use chargeback_reversal_tx::ChargebackReversalTx;
 pub use chargeback_tx::ChargebackTx;
+pub use correction_tx::{CorrectionTarget, CorrectionTx};
 pub use deposit_tx::DepositTx;
 pub use dispute_tx::DisputeTx;
+pub use processable::{ProcessableTx, TxContext};
+pub use registry::TxRegistry;
+pub use release_tx::ReleaseTx;
 pub use resolve_tx::ResolveTx;
-pub use withdrawal_tx::WithdrawalTx;
+pub use tx_type::TxType;
+pub use void_tx::VoidTx;
+pub use withdrawal_tx::{Fee, WithdrawalTx};
 
+use crate::account::{AccountKey, AccountMap};
+use crate::amount::fits;
+use crate::config::Config;
+use crate::deposit_store::DepositStore;
 use crate::error::Error;
+use crate::policy::{DedupKey, ZeroAmountPolicy};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct TransactionRow {
     #[serde(rename = "type")]
-    tx_type: String,
+    tx_type: TxType,
     client: u16,
     tx: u32,
     amount: Option<Decimal>,
+    // Unix seconds, optional column
+    #[serde(default)]
+    timestamp: Option<u64>,
+    // Reason code of an adjustment, optional column
+    #[serde(default)]
+    reason: Option<String>,
+    // Unix seconds until which a deposit's funds are held, optional column
+    #[serde(default)]
+    pending_until: Option<u64>,
+    // Sub-account of the client, optional column; the main account when absent
+    #[serde(default)]
+    sub: Option<u16>,
+    // Line in the source file, when the reader knows it
+    #[serde(skip)]
+    line: Option<u64>,
+    // Position in the run's input, across sources, once the processor has read the row
+    #[serde(skip)]
+    seq: Option<u64>,
 }
 
 impl TransactionRow {
+    #[allow(dead_code)]
+    pub fn new(tx_type: impl Into<TxType>, client: u16, tx: u32, amount: Option<Decimal>) -> Self {
+        Self {
+            tx_type: tx_type.into(),
+            client,
+            tx,
+            amount,
+            timestamp: None,
+            reason: None,
+            pending_until: None,
+            sub: None,
+            line: None,
+            seq: None,
+        }
+    }
+
     pub fn client(&self) -> u16 {
         self.client
     }
@@ -33,7 +90,7 @@ impl TransactionRow {
         self.tx
     }
 
-    pub fn tx_type(&self) -> &str {
+    pub fn tx_type(&self) -> &TxType {
         &self.tx_type
     }
 
@@ -41,8 +98,98 @@ impl TransactionRow {
         self.amount
     }
 
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    #[allow(dead_code)]
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    #[allow(dead_code)]
+    pub fn pending_until(&self) -> Option<u64> {
+        self.pending_until
+    }
+
+    #[allow(dead_code)]
+    pub fn sub(&self) -> Option<u16> {
+        self.sub
+    }
+
+    // The account the row applies to
+    #[allow(dead_code)]
+    pub fn account(&self) -> AccountKey {
+        AccountKey::new(self.client, self.sub.unwrap_or_default())
+    }
+
+    pub fn line(&self) -> Option<u64> {
+        self.line
+    }
+
+    // Rows of a client are applied in increasing `seq`, whichever worker, batch or parser
+    // they went through; debug builds assert it
+    #[allow(dead_code)]
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    #[allow(dead_code)]
+    pub fn set_timestamp(&mut self, timestamp: Option<u64>) {
+        self.timestamp = timestamp;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_reason(&mut self, reason: Option<String>) {
+        self.reason = reason;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_pending_until(&mut self, pending_until: Option<u64>) {
+        self.pending_until = pending_until;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_sub(&mut self, sub: Option<u16>) {
+        self.sub = sub;
+    }
+
+    pub fn set_line(&mut self, line: u64) {
+        self.line = Some(line);
+    }
+
+    pub fn set_seq(&mut self, seq: u64) {
+        self.seq = Some(seq);
+    }
+
+    pub fn set_client(&mut self, client: u16) {
+        self.client = client;
+    }
+
+    pub fn set_tx_type(&mut self, tx_type: impl Into<TxType>) {
+        self.tx_type = tx_type.into();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_amount(&mut self, amount: Option<Decimal>) {
+        self.amount = amount;
+    }
+
+    // Canonical name of the row's type, or "unknown" for anything the engine won't accept
+    pub fn kind(&self) -> &'static str {
+        self.tx_type.kind()
+    }
+
+    // Identity of the row for duplicate detection
+    pub fn dedup_key(&self, key: DedupKey) -> u64 {
+        match key {
+            DedupKey::Tx => u64::from(self.tx),
+            DedupKey::ClientTx => (u64::from(self.client) << 32) | u64::from(self.tx),
+        }
+    }
+
     pub fn should_dedupe(&self) -> bool {
-        matches!(self.tx_type.as_str(), "deposit" | "withdrawal")
+        matches!(self.tx_type, TxType::Deposit | TxType::Withdrawal)
     }
 }
 
@@ -53,47 +200,275 @@ pub enum Transaction {
     Dispute(DisputeTx),
     Resolve(ResolveTx),
     Chargeback(ChargebackTx),
+    Void(VoidTx),
+    Correction(CorrectionTx),
+    ChargebackReversal(ChargebackReversalTx),
+    Admin(AdminTx),
+    Adjustment(AdjustmentTx),
+    Release(ReleaseTx),
+    // A type added through `TxRegistry::register`
+    Custom(Box<dyn ProcessableTx>),
+}
+
+impl Transaction {
+    // The built-in types keep their variants for the WAL, which needs to know what it
+    // writes; everything else goes through the trait
+    fn as_processable(&self) -> &dyn ProcessableTx {
+        match self {
+            Transaction::Deposit(t) => t,
+            Transaction::Withdrawal(t) => t,
+            Transaction::Dispute(t) => t,
+            Transaction::Resolve(t) => t,
+            Transaction::Chargeback(t) => t,
+            Transaction::Void(t) => t,
+            Transaction::Correction(t) => t,
+            Transaction::ChargebackReversal(t) => t,
+            Transaction::Admin(t) => t,
+            Transaction::Adjustment(t) => t,
+            Transaction::Release(t) => t,
+            Transaction::Custom(t) => t.as_ref(),
+        }
+    }
+
+    pub fn client(&self) -> u16 {
+        self.as_processable().client()
+    }
+
+    pub fn account(&self) -> AccountKey {
+        let tx = self.as_processable();
+        AccountKey::new(tx.client(), tx.sub())
+    }
+
+    pub fn id(&self) -> u32 {
+        self.as_processable().id()
+    }
+
+    pub fn kind(&self) -> &'static str {
+        self.as_processable().kind()
+    }
+
+    pub fn amount(&self) -> Option<Decimal> {
+        self.as_processable().amount()
+    }
+
+    // The fee a withdrawal charged, for the caller to credit to the house account
+    pub fn fee(&self) -> Option<Fee> {
+        match self {
+            Transaction::Withdrawal(t) => t.fee(),
+            _ => None,
+        }
+    }
+
+    // Replaces the overdraft limit of a withdrawal, e.g. with a per-client one. Other
+    // transactions are returned unchanged.
+    pub fn with_overdraft_limit(self, limit: Decimal) -> Self {
+        match self {
+            Transaction::Withdrawal(t) => Transaction::Withdrawal(t.with_overdraft_limit(limit)),
+            other => other,
+        }
+    }
+
+    // Moves a built-in transaction to another of its client's sub-accounts. Custom
+    // transactions are returned unchanged.
+    pub fn with_sub(self, sub: u16) -> Self {
+        match self {
+            Transaction::Deposit(t) => Transaction::Deposit(t.with_sub(sub)),
+            Transaction::Withdrawal(t) => Transaction::Withdrawal(t.with_sub(sub)),
+            Transaction::Dispute(t) => Transaction::Dispute(t.with_sub(sub)),
+            Transaction::Resolve(t) => Transaction::Resolve(t.with_sub(sub)),
+            Transaction::Chargeback(t) => Transaction::Chargeback(t.with_sub(sub)),
+            Transaction::Void(t) => Transaction::Void(t.with_sub(sub)),
+            Transaction::Correction(t) => Transaction::Correction(t.with_sub(sub)),
+            Transaction::ChargebackReversal(t) => Transaction::ChargebackReversal(t.with_sub(sub)),
+            Transaction::Admin(t) => Transaction::Admin(t.with_sub(sub)),
+            Transaction::Adjustment(t) => Transaction::Adjustment(t.with_sub(sub)),
+            Transaction::Release(t) => Transaction::Release(t.with_sub(sub)),
+            Transaction::Custom(t) => Transaction::Custom(t),
+        }
+    }
+
+    pub fn process(
+        &self,
+        accounts: &mut AccountMap,
+        deposits: &mut impl DepositStore,
+    ) -> Result<(), Error> {
+        self.as_processable()
+            .process(&mut TxContext { accounts, deposits })
+    }
 }
 
 impl TryFrom<TransactionRow> for Transaction {
     type Error = Error;
 
     fn try_from(row: TransactionRow) -> Result<Self, Self::Error> {
-        match row.tx_type.as_str() {
-            "deposit" => {
+        Transaction::from_row(row, &Config::default())
+    }
+}
+
+impl Transaction {
+    pub fn from_row(row: TransactionRow, config: &Config) -> Result<Self, Error> {
+        let sub = row.sub.unwrap_or_default();
+        match row.tx_type {
+            TxType::Deposit => {
                 if let Some(amount) = row.amount {
                     // Arguably this could be <= 0, but there might be a special case where 0 value deposits and withdrawals are valid,
-                    // opens up a spam venue but feels like that should be handled at client level if needed.
-                    if amount.is_sign_negative() {
+                    // opens up a spam venue, so callers who care can opt into `ZeroAmountPolicy::Reject`.
+                    if amount.is_sign_negative() || rejects_zero(config, amount) {
                         return Err(Error::InvalidTransactionRow(row.tx()));
                     }
-                    let amount = amount.round_dp(4);
-                    Ok(Transaction::Deposit(DepositTx::new(
-                        row.client, row.tx, amount,
-                    )))
+                    check_bounds(config, row.tx, amount)?;
+                    let amount = config.round(amount);
+                    Ok(Transaction::Deposit(
+                        DepositTx::new(row.client, row.tx, amount)
+                            .with_sub(sub)
+                            .with_timestamp(row.timestamp)
+                            .with_pending_until(row.pending_until),
+                    ))
                 } else {
                     Err(Error::InvalidTransactionRow(row.tx))
                 }
             }
-            "withdrawal" => {
+            TxType::Withdrawal => {
                 if let Some(amount) = row.amount {
-                    if amount.is_sign_negative() {
+                    if amount.is_sign_negative() || rejects_zero(config, amount) {
                         return Err(Error::InvalidTransactionRow(row.tx()));
                     }
-                    let amount = amount.round_dp(4);
-                    Ok(Transaction::Withdrawal(WithdrawalTx::new(
-                        row.client, row.tx, amount,
-                    )))
+                    check_bounds(config, row.tx, amount)?;
+                    let amount = config.round(amount);
+                    let fee = config
+                        .fee_account
+                        .map(|account| Fee {
+                            account,
+                            amount: config.round(config.withdrawal_fee.fee(amount)),
+                        })
+                        .filter(|fee| !fee.amount.is_zero());
+                    Ok(Transaction::Withdrawal(
+                        WithdrawalTx::new(row.client, row.tx, amount)
+                            .with_sub(sub)
+                            .with_timestamp(row.timestamp)
+                            .with_overdraft_limit(config.overdraft_limit)
+                            .with_fee(fee),
+                    ))
                 } else {
                     Err(Error::InvalidTransactionRow(row.tx))
                 }
             }
-            "dispute" => Ok(Transaction::Dispute(DisputeTx::new(row.client, row.tx))),
-            "resolve" => Ok(Transaction::Resolve(ResolveTx::new(row.client, row.tx))),
-            "chargeback" => Ok(Transaction::Chargeback(ChargebackTx::new(
-                row.client, row.tx,
-            ))),
+            TxType::Dispute => {
+                // An amount disputes only that part of the deposit. Checked once rounded, so
+                // one below the precision isn't taken for a dispute of nothing, which the WAL
+                // would replay as a dispute of the whole deposit.
+                let amount = match row.amount.map(|amount| config.round(amount)) {
+                    Some(amount) if amount <= Decimal::ZERO => {
+                        return Err(Error::InvalidTransactionRow(row.tx));
+                    }
+                    Some(amount) => {
+                        check_range(config, row.tx, amount)?;
+                        Some(amount)
+                    }
+                    None => None,
+                };
+                let cutoff = config
+                    .dispute_window
+                    .zip(row.timestamp)
+                    .map(|(window, now)| now.saturating_sub(window));
+                Ok(Transaction::Dispute(
+                    DisputeTx::new(row.client, row.tx)
+                        .with_sub(sub)
+                        .with_amount(amount)
+                        .with_cutoff(cutoff)
+                        .with_policies(config.dispute_overdraft, config.locked_dispute),
+                ))
+            }
+            TxType::Resolve => Ok(Transaction::Resolve(
+                ResolveTx::new(row.client, row.tx)
+                    .with_sub(sub)
+                    .with_policy(config.locked_account),
+            )),
+            TxType::Chargeback => Ok(Transaction::Chargeback(
+                ChargebackTx::new(row.client, row.tx)
+                    .with_sub(sub)
+                    .with_policy(config.locked_account),
+            )),
+            TxType::Void => Ok(Transaction::Void(
+                VoidTx::new(row.client, row.tx)
+                    .with_sub(sub)
+                    .with_policy(config.void),
+            )),
+            TxType::ChargebackReversal => Ok(Transaction::ChargebackReversal(
+                ChargebackReversalTx::new(row.client, row.tx)
+                    .with_sub(sub)
+                    .with_policy(config.chargeback_reversal),
+            )),
+            TxType::Freeze => Ok(Transaction::Admin(
+                AdminTx::new(row.client, row.tx, AdminAction::Freeze).with_sub(sub),
+            )),
+            TxType::Close => Ok(Transaction::Admin(
+                AdminTx::new(row.client, row.tx, AdminAction::Close).with_sub(sub),
+            )),
+            // Signed, with adjustments the only rows where a negative amount is meaningful
+            TxType::DepositCorrection | TxType::WithdrawalCorrection => {
+                let amount = match row.amount {
+                    Some(amount) if !amount.is_zero() => {
+                        check_bounds(config, row.tx, amount)?;
+                        config.round(amount)
+                    }
+                    _ => return Err(Error::InvalidTransactionRow(row.tx)),
+                };
+                let target = if row.tx_type == TxType::DepositCorrection {
+                    CorrectionTarget::Deposit
+                } else {
+                    CorrectionTarget::Withdrawal
+                };
+                Ok(Transaction::Correction(
+                    CorrectionTx::new(row.client, row.tx, amount, target).with_sub(sub),
+                ))
+            }
+            // Signed too, and ops must say why
+            TxType::Adjustment => match (row.amount, row.reason) {
+                (Some(amount), Some(reason)) if !amount.is_zero() && !reason.is_empty() => {
+                    check_bounds(config, row.tx, amount)?;
+                    Ok(Transaction::Adjustment(
+                        AdjustmentTx::new(row.client, row.tx, config.round(amount), reason)
+                            .with_sub(sub),
+                    ))
+                }
+                _ => Err(Error::InvalidTransactionRow(row.tx)),
+            },
+            // The hold is checked against the row's timestamp, so it needs one
+            TxType::Release => match row.timestamp {
+                Some(timestamp) => Ok(Transaction::Release(
+                    ReleaseTx::new(row.client, row.tx)
+                        .with_sub(sub)
+                        .with_timestamp(Some(timestamp)),
+                )),
+                None => Err(Error::InvalidTransactionRow(row.tx)),
+            },
             _ => Err(Error::InvalidTransactionRow(row.tx)),
         }
     }
 }
+
+// Zero amounts are accepted by default (see the note on deposits above)
+fn rejects_zero(config: &Config, amount: Decimal) -> bool {
+    config.zero_amount == ZeroAmountPolicy::Reject && amount.is_zero()
+}
+
+// Zero amounts are left to `rejects_zero`, so `--min-amount` doesn't turn them away too
+fn check_bounds(config: &Config, tx_id: u32, amount: Decimal) -> Result<(), Error> {
+    let size = amount.abs();
+    let below = config.min_amount.is_some_and(|min| size < min);
+    let above = config.max_amount.is_some_and(|max| size > max);
+    if !size.is_zero() && (below || above) {
+        return Err(Error::AmountOutOfBounds { tx_id, amount });
+    }
+    check_range(config, tx_id, amount)
+}
+
+// Amounts beyond what the engine can hold once rounded, only with `minor-units`, whatever
+// the limits
+fn check_range(config: &Config, tx_id: u32, amount: Decimal) -> Result<(), Error> {
+    match fits(config.round(amount)) {
+        true => Ok(()),
+        false => Err(Error::AmountOutOfBounds { tx_id, amount }),
+    }
+}
@@ -0,0 +1,38 @@
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::account::AccountMap;
+use crate::deposit_store::DepositStore;
+use crate::error::Error;
+
+// The state of the worker applying a transaction
+pub struct TxContext<'a> {
+    pub accounts: &'a mut AccountMap,
+    pub deposits: &'a mut dyn DepositStore,
+}
+
+// A transaction the engine can apply. A new type implements this and registers a parser for
+// its rows with `TxRegistry`; nothing else in the engine needs to know about it. Only the
+// built-in types are written to the WAL.
+pub trait ProcessableTx: fmt::Debug + Send {
+    fn client(&self) -> u16;
+
+    // The client's sub-account the transaction applies to, 0 for its main account
+    fn sub(&self) -> u16 {
+        0
+    }
+
+    fn id(&self) -> u32;
+
+    // Canonical name, used for metrics and logs
+    fn kind(&self) -> &'static str;
+
+    // The amount the row carried, if the transaction has one of its own
+    fn amount(&self) -> Option<Decimal> {
+        None
+    }
+
+    // Applies the transaction or, if it fails, leaves the state as it was
+    fn process(&self, ctx: &mut TxContext<'_>) -> Result<(), Error>;
+}
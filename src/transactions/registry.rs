@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::transactions::{ProcessableTx, Transaction, TransactionRow, TxType};
+
+// Turns a row into its transaction under the run's config
+pub type TxParser = dyn Fn(TransactionRow, &Config) -> Result<Transaction, Error> + Send + Sync;
+
+// Which row types the engine accepts and how each is parsed. The default knows the built-in
+// types; `register` adds another, or replaces a built-in one, without touching the engine:
+//
+//   registry.register("transfer", |row, _| Ok(TransferTx::new(row.client(), row.tx())));
+//
+// Registered names match ignoring ASCII case, like the built-in types.
+#[derive(Clone)]
+pub struct TxRegistry {
+    parsers: HashMap<TxType, Arc<TxParser>>,
+}
+
+impl Default for TxRegistry {
+    fn default() -> Self {
+        let builtin: Arc<TxParser> = Arc::new(Transaction::from_row);
+        Self {
+            parsers: TxType::known()
+                .iter()
+                .map(|tx_type| (tx_type.clone(), builtin.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl TxRegistry {
+    pub fn register<T, F>(&mut self, name: &str, parse: F)
+    where
+        T: ProcessableTx + 'static,
+        F: Fn(TransactionRow, &Config) -> Result<T, Error> + Send + Sync + 'static,
+    {
+        let key = match TxType::from(name) {
+            TxType::Other(raw) => TxType::Other(raw.to_ascii_lowercase()),
+            known => known,
+        };
+        let parser = move |row, config: &Config| {
+            parse(row, config).map(|tx| Transaction::Custom(Box::new(tx)))
+        };
+        self.parsers.insert(key, Arc::new(parser));
+    }
+
+    #[allow(dead_code)]
+    pub fn knows(&self, tx_type: &TxType) -> bool {
+        self.parser(tx_type).is_some()
+    }
+
+    // Rows of a type nobody registered are invalid
+    pub fn parse(&self, row: TransactionRow, config: &Config) -> Result<Transaction, Error> {
+        match self.parser(row.tx_type()) {
+            Some(parse) => parse(row, config),
+            None => Err(Error::InvalidTransactionRow(row.tx())),
+        }
+    }
+
+    // Built-in types hit the map straight away; only other names pay for lowercasing
+    fn parser(&self, tx_type: &TxType) -> Option<&Arc<TxParser>> {
+        self.parsers.get(tx_type).or_else(|| match tx_type {
+            TxType::Other(raw) => self.parsers.get(&TxType::Other(raw.to_ascii_lowercase())),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::account::AccountMap;
+    use crate::deposit_store::{DepositKey, StoredDeposit};
+    use crate::transactions::TxContext;
+
+    // Credits the client without storing a deposit, so it cannot be disputed
+    #[derive(Debug)]
+    struct BonusTx {
+        client: u16,
+        id: u32,
+        amount: Decimal,
+    }
+
+    impl ProcessableTx for BonusTx {
+        fn client(&self) -> u16 {
+            self.client
+        }
+
+        fn id(&self) -> u32 {
+            self.id
+        }
+
+        fn kind(&self) -> &'static str {
+            "bonus"
+        }
+
+        fn process(&self, ctx: &mut TxContext<'_>) -> Result<(), Error> {
+            ctx.accounts.get_or_create(self.client).deposit(self.amount)
+        }
+    }
+
+    #[test]
+    fn registered_types_are_parsed_and_applied() {
+        let mut registry = TxRegistry::default();
+        registry.register("bonus", |row, _| {
+            Ok(BonusTx {
+                client: row.client(),
+                id: row.tx(),
+                amount: row.amount().unwrap_or_default(),
+            })
+        });
+        let config = Config::default();
+        let (mut accounts, mut deposits) = (
+            AccountMap::new(),
+            HashMap::<DepositKey, StoredDeposit>::new(),
+        );
+
+        for row in [
+            TransactionRow::new("deposit", 1, 1, Some(Decimal::ONE)),
+            TransactionRow::new("Bonus", 1, 2, Some(Decimal::TEN)),
+        ] {
+            let tx = registry.parse(row, &config).unwrap();
+            tx.process(&mut accounts, &mut deposits).unwrap();
+        }
+
+        assert_eq!(accounts.get(1).unwrap().available(), Decimal::new(11, 0));
+        assert_eq!(deposits.len(), 1);
+        let refund = TransactionRow::new("refund", 1, 3, Some(Decimal::ONE));
+        assert!(!registry.knows(refund.tx_type()));
+        assert!(registry.parse(refund, &config).is_err());
+    }
+}
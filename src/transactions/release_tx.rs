@@ -0,0 +1,94 @@
+use crate::account::{AccountKey, AccountMap};
+use crate::transactions::{ProcessableTx, TxContext};
+use crate::{
+    deposit_store::{DepositKey, DepositStore},
+    error::Error,
+};
+
+// Settles a pending deposit (one with `pending_until`), moving its funds from held to
+// available, e.g. once an ACH transfer has cleared. The row's timestamp must have reached the
+// end of the hold. Applies to accounts in any state, since the money has arrived regardless.
+#[derive(Debug)]
+pub struct ReleaseTx {
+    client: u16,
+    sub: u16,
+    id: u32,
+    timestamp: Option<u64>,
+}
+
+impl ReleaseTx {
+    pub fn new(client: u16, id: u32) -> Self {
+        Self {
+            client,
+            sub: 0,
+            id,
+            timestamp: None,
+        }
+    }
+
+    // Without a timestamp the hold is not checked, for releases already accepted once
+    pub fn with_timestamp(mut self, timestamp: Option<u64>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn with_sub(mut self, sub: u16) -> Self {
+        self.sub = sub;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    pub fn account(&self) -> AccountKey {
+        AccountKey::new(self.client, self.sub)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    // What the deposit it refers to is stored under
+    pub fn key(&self) -> DepositKey {
+        DepositKey::new(self.client, self.id)
+    }
+
+    pub fn process(
+        &self,
+        accounts: &mut AccountMap,
+        stored_deposits: &mut (impl DepositStore + ?Sized),
+    ) -> Result<(), Error> {
+        let Some(stored_deposit) = stored_deposits.get_mut(self.key()) else {
+            return Err(Error::StoredDepositNotFound(self.id()));
+        };
+        stored_deposit.ensure_account_matches(self.id(), self.account())?;
+        let account = accounts.get_mut(self.account())?;
+        stored_deposit.set_released(self.id(), self.timestamp)?;
+        account.release(stored_deposit.amount());
+        Ok(())
+    }
+}
+
+impl ProcessableTx for ReleaseTx {
+    fn client(&self) -> u16 {
+        self.client
+    }
+
+    fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        "release"
+    }
+
+    fn process(&self, ctx: &mut TxContext<'_>) -> Result<(), Error> {
+        ReleaseTx::process(self, ctx.accounts, ctx.deposits)
+    }
+}
@@ -1,39 +1,96 @@
-use crate::{account::AccountMap, deposit_store::DepositStore, error::Error};
+use crate::account::{AccountKey, AccountMap};
+use crate::policy::AccountPolicy;
+use crate::transactions::{ProcessableTx, TxContext};
+use crate::{
+    deposit_store::{DepositKey, DepositStore},
+    error::Error,
+};
 
 #[derive(Debug)]
 pub struct ResolveTx {
     client: u16,
+    sub: u16,
     id: u32,
+    policy: AccountPolicy,
 }
 impl ResolveTx {
     pub fn new(client: u16, id: u32) -> Self {
-        Self { client, id }
+        Self {
+            client,
+            sub: 0,
+            id,
+            policy: AccountPolicy::default(),
+        }
     }
 
-    fn client(&self) -> u16 {
+    pub fn with_policy(mut self, policy: AccountPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_sub(mut self, sub: u16) -> Self {
+        self.sub = sub;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn client(&self) -> u16 {
         self.client
     }
 
-    fn id(&self) -> u32 {
+    pub fn account(&self) -> AccountKey {
+        AccountKey::new(self.client, self.sub)
+    }
+
+    pub fn id(&self) -> u32 {
         self.id
     }
 
+    // What the deposit it refers to is stored under
+    pub fn key(&self) -> DepositKey {
+        DepositKey::new(self.client, self.id)
+    }
+
     // State transition (set_resolved) is the idempotency guard. The deposit state machine
     // rejects invalid transitions (AlreadyResolved, etc.), preventing double-processing.
     pub fn process(
         &self,
         accounts: &mut AccountMap,
-        stored_deposits: &mut impl DepositStore,
+        stored_deposits: &mut (impl DepositStore + ?Sized),
     ) -> Result<(), Error> {
-        if let Some(stored_deposit) = stored_deposits.get_mut(self.id()) {
-            stored_deposit.ensure_client_matches(self.id(), self.client())?;
+        if let Some(stored_deposit) = stored_deposits.get_mut(self.key()) {
+            stored_deposit.ensure_account_matches(self.id(), self.account())?;
+            let account = accounts.get_mut(self.account())?;
+            // A refused resolve must leave the dispute open
+            account.check_resolve(self.policy)?;
             stored_deposit.set_resolved()?;
 
-            let account = accounts.get_mut(self.client())?;
-            account.resolve(stored_deposit.amount())?;
+            account.resolve(stored_deposit.disputed_amount(), self.policy)?;
             Ok(())
         } else {
             Err(Error::StoredDepositNotFound(self.id()))
         }
     }
 }
+
+impl ProcessableTx for ResolveTx {
+    fn client(&self) -> u16 {
+        self.client
+    }
+
+    fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        "resolve"
+    }
+
+    fn process(&self, ctx: &mut TxContext<'_>) -> Result<(), Error> {
+        ResolveTx::process(self, ctx.accounts, ctx.deposits)
+    }
+}
@@ -0,0 +1,145 @@
+use std::fmt;
+
+use serde::Deserialize;
+use serde::de::{self, Deserializer, Visitor};
+
+// The `type` field of a row. The types the engine accepts are recognised ignoring ASCII case,
+// so `Deposit` and `DEPOSIT` are deposits, straight from the raw bytes so fast readers can
+// classify a row without allocating. Anything else is kept verbatim in `Other`, for
+// transformers to rename and for rules and logs to see.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TxType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Void,
+    ChargebackReversal,
+    DepositCorrection,
+    WithdrawalCorrection,
+    Freeze,
+    Close,
+    Adjustment,
+    Release,
+    Other(String),
+}
+
+const KNOWN: [TxType; 13] = [
+    TxType::Deposit,
+    TxType::Withdrawal,
+    TxType::Dispute,
+    TxType::Resolve,
+    TxType::Chargeback,
+    TxType::Void,
+    TxType::ChargebackReversal,
+    TxType::DepositCorrection,
+    TxType::WithdrawalCorrection,
+    TxType::Freeze,
+    TxType::Close,
+    TxType::Adjustment,
+    TxType::Release,
+];
+
+impl TxType {
+    // Every type the engine accepts out of the box
+    pub fn known() -> &'static [TxType] {
+        &KNOWN
+    }
+
+    // One of the known types, or None
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        KNOWN
+            .iter()
+            .find(|known| bytes.eq_ignore_ascii_case(known.kind().as_bytes()))
+            .cloned()
+    }
+
+    // The canonical lowercase name, or the raw text of an unknown type
+    pub fn as_str(&self) -> &str {
+        match self {
+            TxType::Other(raw) => raw,
+            known => known.kind(),
+        }
+    }
+
+    // The canonical name, or "unknown" for anything the engine won't accept
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TxType::Deposit => "deposit",
+            TxType::Withdrawal => "withdrawal",
+            TxType::Dispute => "dispute",
+            TxType::Resolve => "resolve",
+            TxType::Chargeback => "chargeback",
+            TxType::Void => "void",
+            TxType::ChargebackReversal => "chargeback_reversal",
+            TxType::DepositCorrection => "deposit_correction",
+            TxType::WithdrawalCorrection => "withdrawal_correction",
+            TxType::Freeze => "freeze",
+            TxType::Close => "close",
+            TxType::Adjustment => "adjustment",
+            TxType::Release => "release",
+            TxType::Other(_) => "unknown",
+        }
+    }
+}
+
+impl From<&str> for TxType {
+    fn from(s: &str) -> Self {
+        TxType::from_bytes(s.as_bytes()).unwrap_or_else(|| TxType::Other(s.to_string()))
+    }
+}
+
+impl From<String> for TxType {
+    fn from(s: String) -> Self {
+        TxType::from_bytes(s.as_bytes()).unwrap_or(TxType::Other(s))
+    }
+}
+
+impl fmt::Display for TxType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for TxType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TxTypeVisitor;
+
+        impl Visitor<'_> for TxTypeVisitor {
+            type Value = TxType;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a transaction type")
+            }
+
+            // Borrowed from the reader's buffer, so known types never allocate
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<TxType, E> {
+                Ok(TxType::from(s))
+            }
+
+            fn visit_string<E: de::Error>(self, s: String) -> Result<TxType, E> {
+                Ok(TxType::from(s))
+            }
+        }
+
+        deserializer.deserialize_str(TxTypeVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_known_types_ignoring_case() {
+        assert_eq!(TxType::from("Deposit"), TxType::Deposit);
+        assert_eq!(
+            TxType::from("CHARGEBACK_REVERSAL"),
+            TxType::ChargebackReversal
+        );
+        assert_eq!(TxType::from("refund"), TxType::Other("refund".to_string()));
+        assert_eq!(TxType::from("Refund").as_str(), "Refund");
+        assert_eq!(TxType::from("deposits").kind(), "unknown");
+    }
+}
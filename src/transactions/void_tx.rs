@@ -0,0 +1,104 @@
+use crate::account::{AccountKey, AccountMap};
+use crate::policy::VoidPolicy;
+use crate::transactions::{ProcessableTx, TxContext};
+use crate::{
+    deposit_store::{DepositKey, DepositStore},
+    error::Error,
+};
+
+// Administrative removal of a deposit. The stored deposit is kept, marked voided, so the
+// record survives for audit and can never be disputed again.
+#[derive(Debug)]
+pub struct VoidTx {
+    client: u16,
+    sub: u16,
+    id: u32,
+    policy: VoidPolicy,
+}
+
+impl VoidTx {
+    pub fn new(client: u16, id: u32) -> Self {
+        Self {
+            client,
+            sub: 0,
+            id,
+            policy: VoidPolicy::default(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: VoidPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_sub(mut self, sub: u16) -> Self {
+        self.sub = sub;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    pub fn account(&self) -> AccountKey {
+        AccountKey::new(self.client, self.sub)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    // What the deposit it refers to is stored under
+    pub fn key(&self) -> DepositKey {
+        DepositKey::new(self.client, self.id)
+    }
+
+    pub fn policy(&self) -> VoidPolicy {
+        self.policy
+    }
+
+    // State transition (set_voided) is the idempotency guard. Disputed deposits have to be
+    // resolved first so held funds are never orphaned.
+    pub fn process(
+        &self,
+        accounts: &mut AccountMap,
+        stored_deposits: &mut (impl DepositStore + ?Sized),
+    ) -> Result<(), Error> {
+        if let Some(stored_deposit) = stored_deposits.get_mut(self.key()) {
+            stored_deposit.ensure_account_matches(self.id(), self.account())?;
+            let account = accounts.get_mut(self.account())?;
+            account.check_open()?;
+            stored_deposit.set_voided()?;
+            if self.policy == VoidPolicy::Reverse {
+                account.void(stored_deposit.amount())?;
+            }
+
+            Ok(())
+        } else {
+            Err(Error::StoredDepositNotFound(self.id()))
+        }
+    }
+}
+
+impl ProcessableTx for VoidTx {
+    fn client(&self) -> u16 {
+        self.client
+    }
+
+    fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        "void"
+    }
+
+    fn process(&self, ctx: &mut TxContext<'_>) -> Result<(), Error> {
+        VoidTx::process(self, ctx.accounts, ctx.deposits)
+    }
+}
@@ -1,24 +1,74 @@
-use crate::{account::AccountMap, error::Error};
+use crate::account::{AccountKey, AccountMap};
+use crate::transactions::{ProcessableTx, TxContext};
+use crate::{deposit_store::DepositStore, error::Error};
 use rust_decimal::Decimal;
 
+// A fee charged with a withdrawal and owed to the house account. The engine debits it with
+// the withdrawal; crediting `account` is up to the caller, since with several workers the
+// house account may live on another one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fee {
+    pub account: u16,
+    pub amount: Decimal,
+}
+
 #[derive(Debug)]
 pub struct WithdrawalTx {
     client: u16,
-    #[allow(dead_code)]
+    sub: u16,
     id: u32,
     amount: Decimal,
+    timestamp: Option<u64>,
+    overdraft_limit: Decimal,
+    fee: Option<Fee>,
 }
 
 impl WithdrawalTx {
     pub fn new(client: u16, id: u32, amount: Decimal) -> Self {
-        Self { client, id, amount }
+        Self {
+            client,
+            sub: 0,
+            id,
+            amount,
+            timestamp: None,
+            overdraft_limit: Decimal::ZERO,
+            fee: None,
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: Option<u64>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    // How far below zero this withdrawal may take available
+    pub fn with_overdraft_limit(mut self, limit: Decimal) -> Self {
+        self.overdraft_limit = limit;
+        self
+    }
+
+    pub fn with_fee(mut self, fee: Option<Fee>) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    pub fn with_sub(mut self, sub: u16) -> Self {
+        self.sub = sub;
+        self
     }
 
     pub fn client(&self) -> u16 {
         self.client
     }
 
-    #[allow(dead_code)]
+    pub fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    pub fn account(&self) -> AccountKey {
+        AccountKey::new(self.client, self.sub)
+    }
+
     pub fn id(&self) -> u32 {
         self.id
     }
@@ -27,9 +77,55 @@ impl WithdrawalTx {
         self.amount
     }
 
-    pub fn process(&self, accounts: &mut AccountMap) -> Result<(), Error> {
-        let account = accounts.get_or_create(self.client());
-        account.withdraw(self.amount())?;
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    pub fn overdraft_limit(&self) -> Decimal {
+        self.overdraft_limit
+    }
+
+    pub fn fee(&self) -> Option<Fee> {
+        self.fee
+    }
+
+    pub fn process(
+        &self,
+        accounts: &mut AccountMap,
+        stored: &mut (impl DepositStore + ?Sized),
+    ) -> Result<(), Error> {
+        let account = accounts.get_or_create(self.account());
+        // One debit, so the fee is only taken if the withdrawal goes through and the
+        // withdrawal only if the fee is covered too
+        let fee = self.fee.map_or(Decimal::ZERO, |fee| fee.amount);
+        account.withdraw(self.amount() + fee, self.overdraft_limit)?;
+        stored.insert_withdrawal(self);
         Ok(())
     }
 }
+
+impl ProcessableTx for WithdrawalTx {
+    fn client(&self) -> u16 {
+        self.client
+    }
+
+    fn sub(&self) -> u16 {
+        self.sub
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        "withdrawal"
+    }
+
+    fn amount(&self) -> Option<Decimal> {
+        Some(self.amount)
+    }
+
+    fn process(&self, ctx: &mut TxContext<'_>) -> Result<(), Error> {
+        WithdrawalTx::process(self, ctx.accounts, ctx.deposits)
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::transactions::{TransactionRow, TxType};
+
+// Hook applied to every parsed row before validation and dedup, for rewriting partner-specific
+// conventions into the engine's. Returning None drops the row.
+pub trait RowTransformer: Send + Sync {
+    fn transform(&self, row: TransactionRow) -> Option<TransactionRow>;
+}
+
+impl<F> RowTransformer for F
+where
+    F: Fn(TransactionRow) -> Option<TransactionRow> + Send + Sync,
+{
+    fn transform(&self, row: TransactionRow) -> Option<TransactionRow> {
+        self(row)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MappingRow {
+    field: String,
+    from: String,
+    to: String,
+}
+
+// Built-in transformer driven by a `field,from,to` CSV, e.g.
+//
+//   field,from,to
+//   client,9001,1
+//   type,DEP,deposit
+//   type,REFUND,deposit
+//
+// `client` rewrites client ids (legacy id mapping), `type` rewrites transaction type names.
+#[derive(Debug, Default)]
+pub struct MappingTransformer {
+    clients: HashMap<u16, u16>,
+    types: HashMap<String, TxType>,
+}
+
+impl MappingTransformer {
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, Error> {
+        let mut mapping = Self::default();
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        for result in rdr.deserialize() {
+            let entry: MappingRow = result?;
+            match entry.field.as_str() {
+                "client" => {
+                    let parse = |s: &str| {
+                        s.parse::<u16>().map_err(|_| {
+                            Error::InvalidArgument(format!("invalid client in mapping: {}", s))
+                        })
+                    };
+                    mapping
+                        .clients
+                        .insert(parse(&entry.from)?, parse(&entry.to)?);
+                }
+                "type" => {
+                    mapping.types.insert(entry.from, TxType::from(entry.to));
+                }
+                other => {
+                    return Err(Error::InvalidArgument(format!(
+                        "unknown mapping field {}",
+                        other
+                    )));
+                }
+            }
+        }
+        Ok(mapping)
+    }
+}
+
+impl RowTransformer for MappingTransformer {
+    fn transform(&self, mut row: TransactionRow) -> Option<TransactionRow> {
+        if let Some(&client) = self.clients.get(&row.client()) {
+            row.set_client(client);
+        }
+        if let Some(tx_type) = self.types.get(row.tx_type().as_str()) {
+            row.set_tx_type(tx_type.clone());
+        }
+        Some(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn maps_clients_and_types() {
+        let mapping = MappingTransformer::from_reader(
+            "field,from,to\nclient,9001,1\ntype,DEP,deposit\n".as_bytes(),
+        )
+        .unwrap();
+
+        let row = mapping
+            .transform(TransactionRow::new("DEP", 9001, 7, Some(Decimal::ONE)))
+            .unwrap();
+
+        assert_eq!(row.client(), 1);
+        assert_eq!(row.tx_type(), &TxType::Deposit);
+        assert_eq!(row.tx(), 7);
+    }
+
+    #[test]
+    fn closures_are_transformers() {
+        let drop_client_2 = |row: TransactionRow| (row.client() != 2).then_some(row);
+
+        assert!(
+            drop_client_2
+                .transform(TransactionRow::new("deposit", 2, 1, None))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn unknown_field_rejected() {
+        let result = MappingTransformer::from_reader("field,from,to\ntx,1,2\n".as_bytes());
+
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+}
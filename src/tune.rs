@@ -0,0 +1,106 @@
+use std::fmt;
+use std::thread;
+
+use crate::config::Config;
+use crate::processor::ProcessorBuilder;
+use crate::transactions::TransactionRow;
+
+// Rows read up front for the trials; enough to get past thread start-up noise
+pub const DEFAULT_SAMPLE_ROWS: usize = 200_000;
+const CHANNEL_CAPACITIES: [usize; 3] = [1_000, 10_000, 100_000];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TuneResult {
+    pub workers: usize,
+    pub channel_capacity: usize,
+    pub rows_per_sec: f64,
+}
+
+impl fmt::Display for TuneResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "workers={} channel_capacity={} rows_per_sec={:.0}",
+            self.workers, self.channel_capacity, self.rows_per_sec
+        )
+    }
+}
+
+// Worker counts worth trying on this machine: powers of two up to the core count, plus the
+// core count itself
+pub fn worker_candidates() -> Vec<usize> {
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut candidates: Vec<usize> = (0..)
+        .map(|shift| 1 << shift)
+        .take_while(|n| *n < cores)
+        .collect();
+    candidates.push(cores);
+    candidates
+}
+
+// Runs `sample` through an isolated engine once per combination of `workers` and channel
+// capacity (or only `fixed_capacity` when the user pinned it) and returns the fastest. Worker
+// count and queues are fixed for the lifetime of a run, since clients are sharded by worker,
+// so the trials happen before the real run rather than during it.
+pub fn tune(
+    sample: &[TransactionRow],
+    config: &Config,
+    workers: &[usize],
+    fixed_capacity: Option<usize>,
+) -> TuneResult {
+    let capacities = match fixed_capacity {
+        Some(capacity) => vec![capacity],
+        None => CHANNEL_CAPACITIES.to_vec(),
+    };
+
+    let mut best: Option<TuneResult> = None;
+    for &worker_count in workers {
+        for &channel_capacity in &capacities {
+            let rows = sample.iter().cloned().map(Ok);
+            let Ok(output) = ProcessorBuilder::new()
+                .config(*config)
+                .workers(worker_count)
+                .channel_capacity(channel_capacity)
+                .build()
+                .run("auto-tune", rows)
+            else {
+                continue;
+            };
+            let trial = TuneResult {
+                workers: worker_count,
+                channel_capacity,
+                rows_per_sec: output.metrics.rows_per_sec(),
+            };
+            if best.is_none_or(|b| trial.rows_per_sec > b.rows_per_sec) {
+                best = Some(trial);
+            }
+        }
+    }
+
+    best.unwrap_or(TuneResult {
+        workers: workers.first().copied().unwrap_or(1),
+        channel_capacity: capacities[0],
+        rows_per_sec: 0.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn picks_one_of_the_candidates() {
+        let sample: Vec<TransactionRow> = (0..1_000)
+            .map(|tx| TransactionRow::new("deposit", (tx % 10) as u16, tx, Some(Decimal::ONE)))
+            .collect();
+
+        let result = tune(&sample, &Config::default(), &[1, 2], None);
+        assert!([1, 2].contains(&result.workers));
+        assert!(CHANNEL_CAPACITIES.contains(&result.channel_capacity));
+
+        let pinned = tune(&sample, &Config::default(), &[2], Some(64));
+        assert_eq!((pinned.workers, pinned.channel_capacity), (2, 64));
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::transactions::{Transaction, TransactionRow, TxType};
+use crate::transform::RowTransformer;
+
+// One row that would not make it into the engine
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Issue {
+    pub source: String,
+    pub line: u64,
+    // Missing when the row could not be parsed
+    pub tx: Option<u32>,
+    pub reason: &'static str,
+    pub detail: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub rows: u64,
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    pub fn passed(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    // CSV, one issue per row: source,line,tx,reason,detail
+    pub fn write(&self, sink: impl Write) -> Result<(), Error> {
+        let mut wtr = csv::Writer::from_writer(sink);
+        if self.issues.is_empty() {
+            wtr.write_record(["source", "line", "tx", "reason", "detail"])?;
+        }
+        for issue in &self.issues {
+            wtr.serialize(issue)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rows={} invalid={}", self.rows, self.issues.len())
+    }
+}
+
+// Checks every row of `sources` the way the engine would before applying it: parsing, the
+// transformer, type and amount validation under `config`, and deposit/withdrawal ids seen
+// before in any source. Duplicates are found exactly whatever the run's dedup strategy. No
+// account is touched, so business failures such as insufficient funds are not reported.
+pub fn validate<S, I>(
+    sources: S,
+    transformer: Option<&dyn RowTransformer>,
+    config: &Config,
+) -> ValidationReport
+where
+    S: IntoIterator<Item = (String, I)>,
+    I: IntoIterator<Item = Result<TransactionRow, csv::Error>>,
+{
+    let mut report = ValidationReport::default();
+    // First source and line of each deposit/withdrawal key
+    let mut seen: HashMap<u64, (String, u64)> = HashMap::new();
+
+    for (source, rows) in sources {
+        let mut record = 0u64;
+        for result in rows {
+            record += 1;
+            report.rows += 1;
+            let mut issue = |line, tx, reason, detail| {
+                report.issues.push(Issue {
+                    source: source.clone(),
+                    line,
+                    tx,
+                    reason,
+                    detail,
+                })
+            };
+            let row = match result {
+                Ok(row) => row,
+                Err(e) => {
+                    let line = e.position().map_or(record + 1, |p| p.line());
+                    issue(line, None, "parse_error", e.to_string());
+                    continue;
+                }
+            };
+            let line = row.line().unwrap_or(record + 1);
+            let row = match transformer {
+                Some(transformer) => match transformer.transform(row) {
+                    Some(row) => row,
+                    None => continue,
+                },
+                None => row,
+            };
+
+            let tx = row.tx();
+            if let Err((reason, detail)) = check(&row, config) {
+                issue(line, Some(tx), reason, detail);
+                continue;
+            }
+            if row.should_dedupe() {
+                let key = row.dedup_key(config.dedup_key);
+                if let Some((first_source, first_line)) = seen.get(&key) {
+                    let detail = format!("tx {} first seen at {}:{}", tx, first_source, first_line);
+                    issue(line, Some(tx), "duplicate", detail);
+                } else {
+                    seen.insert(key, (source.clone(), line));
+                }
+            }
+        }
+    }
+    report
+}
+
+// Why the engine would refuse the row before looking at any account
+fn check(row: &TransactionRow, config: &Config) -> Result<(), (&'static str, String)> {
+    let needs_amount = matches!(row.tx_type(), TxType::Deposit | TxType::Withdrawal);
+    match (row.tx_type(), row.amount()) {
+        (TxType::Other(raw), _) => Err(("unknown_type", format!("unknown type {:?}", raw))),
+        (_, None) if needs_amount => Err((
+            "missing_amount",
+            format!("{} without an amount", row.kind()),
+        )),
+        (_, Some(amount)) if needs_amount && amount.is_sign_negative() => {
+            Err(("negative_amount", format!("{} of {}", row.kind(), amount)))
+        }
+        _ => Transaction::from_row(row.clone(), config)
+            .map(|_| ())
+            .map_err(|e| (e.reason(), e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::numbered_rows;
+
+    #[test]
+    fn reports_each_invalid_row_with_its_line() {
+        let input = "type,client,tx,amount
+deposit,1,1,10
+refund,1,2,5
+deposit,x,3,1
+withdrawal,1,4,-2
+withdrawal,1,5,
+deposit,1,1,3
+dispute,1,1,";
+        let rows = numbered_rows(csv::ReaderBuilder::new().from_reader(input.as_bytes())).unwrap();
+
+        let report = validate([("in.csv".to_string(), rows)], None, &Config::default());
+
+        let found: Vec<_> = report
+            .issues
+            .iter()
+            .map(|issue| (issue.line, issue.reason))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (3, "unknown_type"),
+                (4, "parse_error"),
+                (5, "negative_amount"),
+                (6, "missing_amount"),
+                (7, "duplicate"),
+            ]
+        );
+        assert_eq!(report.rows, 7);
+        assert_eq!(report.issues[4].detail, "tx 1 first seen at in.csv:2");
+    }
+}
@@ -0,0 +1,118 @@
+use quickcheck::{Arbitrary, Gen, QuickCheck};
+use rust_decimal::Decimal;
+use toy_processor::TransactionRow;
+use toy_processor::account::{Account, AccountMap, AccountOutput};
+use toy_processor::canonical::{AccountWriter, TransactionWriter};
+use toy_processor::config::Config;
+
+// Read the way the CLI reads input
+fn reader(csv: &[u8]) -> csv::Reader<&[u8]> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(csv)
+}
+
+fn decimal(g: &mut Gen) -> Decimal {
+    Decimal::new(i64::arbitrary(g), u32::arbitrary(g) % 29)
+}
+
+// A row as it comes out of the parser: the reader trims fields, so the type never carries
+// surrounding whitespace, but it may hold commas, quotes and newlines
+#[derive(Debug, Clone)]
+struct ParsedRow(TransactionRow);
+
+impl Arbitrary for ParsedRow {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let kinds = ["deposit", "withdrawal", "dispute", "resolve", "chargeback"];
+        let kind = if bool::arbitrary(g) {
+            g.choose(&kinds).unwrap().to_string()
+        } else {
+            String::arbitrary(g).trim().to_string()
+        };
+        let amount = bool::arbitrary(g).then(|| decimal(g));
+        let mut csv = b"type,client,tx,amount,timestamp\n".to_vec();
+        let mut writer = csv::Writer::from_writer(&mut csv);
+        writer
+            .write_record([
+                kind,
+                u16::arbitrary(g).to_string(),
+                u32::arbitrary(g).to_string(),
+                amount.map(|a| a.to_string()).unwrap_or_default(),
+                Option::<u64>::arbitrary(g)
+                    .map(|ts| ts.to_string())
+                    .unwrap_or_default(),
+            ])
+            .unwrap();
+        drop(writer);
+        ParsedRow(reader(&csv).deserialize().next().unwrap().unwrap())
+    }
+}
+
+fn rows_round_trip(rows: Vec<ParsedRow>) -> bool {
+    let rows: Vec<TransactionRow> = rows.into_iter().map(|r| r.0).collect();
+    let mut writer = TransactionWriter::new(Vec::new()).unwrap();
+    for row in &rows {
+        writer.write(row).unwrap();
+    }
+    let csv = writer.into_inner().unwrap();
+
+    let reparsed: Vec<TransactionRow> = reader(&csv)
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    // Equal decimals may differ in scale, so compare the canonical text too
+    let mut rewritten = TransactionWriter::new(Vec::new()).unwrap();
+    for row in &reparsed {
+        rewritten.write(row).unwrap();
+    }
+    reparsed == rows && rewritten.into_inner().unwrap() == csv
+}
+
+#[test]
+fn transaction_rows_round_trip() {
+    QuickCheck::new()
+        .tests(500)
+        .quickcheck(rows_round_trip as fn(Vec<ParsedRow>) -> bool);
+}
+
+fn accounts_round_trip(balances: Vec<(u16, i64, i64, bool)>, precision: u8) -> bool {
+    let config = Config {
+        precision: u32::from(precision % 9),
+        ..Config::default()
+    };
+    // Balances are i64 ten-thousandths with `minor-units`, so keep their sums in range
+    let shift = if cfg!(feature = "minor-units") { 16 } else { 0 };
+    let mut accounts = AccountMap::new();
+    for (client, available, held, lock) in balances {
+        let (available, held) = (available >> shift, held >> shift);
+        let account = accounts.get_or_create(client);
+        account.deposit(Decimal::new(available, 4)).ok();
+        account.dispute(Decimal::new(held, 4)).ok();
+        if lock {
+            account.chargeback(Decimal::ZERO, Default::default()).ok();
+        }
+    }
+    let outputs: Vec<AccountOutput> = accounts
+        .into_iter_sorted()
+        .map(|account: Account| AccountOutput::new(account, &config))
+        .collect();
+
+    let mut writer = AccountWriter::new(Vec::new()).unwrap();
+    for output in &outputs {
+        writer.write(output).unwrap();
+    }
+    let csv = writer.into_inner().unwrap();
+
+    let reparsed: Vec<AccountOutput> = reader(&csv)
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    reparsed == outputs
+}
+
+#[test]
+fn account_outputs_round_trip() {
+    QuickCheck::new()
+        .tests(200)
+        .quickcheck(accounts_round_trip as fn(Vec<(u16, i64, i64, bool)>, u8) -> bool);
+}
@@ -1,10 +1,27 @@
-use std::process::Command;
+use std::process::{Command, Output};
 
-fn run_test(fixture: &str, expected: &str) {
-    let output = Command::new("./target/debug/toy-processor")
-        .arg(format!("tests/fixtures/{}.csv", fixture))
+fn run(fixture: &str, args: &[&str]) -> Output {
+    run_file(&format!("{}.csv", fixture), args)
+}
+
+fn run_file(file: &str, args: &[&str]) -> Output {
+    run_args(args, &[&format!("tests/fixtures/{}", file)])
+}
+
+fn run_args(args: &[&str], inputs: &[&str]) -> Output {
+    Command::new("./target/debug/toy-processor")
+        .args(args)
+        .args(inputs)
         .output()
-        .expect("Failed to execute binary");
+        .expect("Failed to execute binary")
+}
+
+fn run_test(fixture: &str, expected: &str) {
+    run_test_with_args(fixture, &[], expected);
+}
+
+fn run_test_with_args(fixture: &str, args: &[&str], expected: &str) {
+    let output = run(fixture, args);
 
     let stdout = String::from_utf8_lossy(&output.stdout);
     assert_eq!(stdout.trim(), expected.trim(), "Fixture: {}", fixture);
@@ -20,6 +37,170 @@ fn basic_deposit_withdraw() {
     );
 }
 
+#[test]
+fn config_file_is_overridden_by_flags() {
+    let config = ["--config", "tests/fixtures/run.toml"];
+    run_test_with_args(
+        "basic_deposit_withdraw",
+        &config,
+        "client,available,held,total,locked
+1,85.00,0.00,85.00,false
+2,50.00,0.00,50.00,false",
+    );
+    run_test_with_args(
+        "basic_deposit_withdraw",
+        &[&config[..], &["--precision", "4"]].concat(),
+        "client,available,held,total,locked
+1,85.0000,0.0000,85.0000,false
+2,50.0000,0.0000,50.0000,false",
+    );
+    let output = run(
+        "basic_deposit_withdraw",
+        &["--config", "tests/fixtures/missing.toml"],
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn client_filter() {
+    run_test_with_args(
+        "basic_deposit_withdraw",
+        &["--client", "2"],
+        "client,available,held,total,locked
+2,50.0000,0.0000,50.0000,false",
+    );
+    run_test_with_args(
+        "basic_deposit_withdraw",
+        &["--clients", "2,1,7"],
+        "client,available,held,total,locked
+1,85.0000,0.0000,85.0000,false
+2,50.0000,0.0000,50.0000,false",
+    );
+}
+
+#[test]
+fn dedupe_key_policies() {
+    // Clients 1 and 2 both use tx 1 while client 1's is disputed; client 2 then replays its
+    // row. Each client keeps its own deposit with tx 1, on a shared worker or not, so client
+    // 1's is still there to resolve.
+    for workers in ["1", "2"] {
+        run_test_with_args(
+            "reused_tx_ids",
+            &["--workers", workers, "--assert-invariants"],
+            "client,available,held,total,locked
+1,10.0000,0.0000,10.0000,false
+2,20.0000,0.0000,20.0000,false",
+        );
+    }
+    // Keyed on the tx id alone, client 2's deposits are duplicates
+    run_test_with_args(
+        "reused_tx_ids",
+        &["--dedupe-key", "tx", "--assert-invariants"],
+        "client,available,held,total,locked
+1,10.0000,0.0000,10.0000,false",
+    );
+}
+
+// Filtered rows never reach dedup, so client 1's tx 1 does not shadow client 2's
+#[test]
+fn client_lists_filter_before_dedup() {
+    let expected = "client,available,held,total,locked
+2,20.0000,0.0000,20.0000,false";
+    for list in [
+        "--only-clients=tests/fixtures/clients/incident.txt",
+        "--exclude-clients=tests/fixtures/clients/exclude.txt",
+    ] {
+        let (flag, path) = list.split_once('=').unwrap();
+        run_test_with_args(
+            "reused_tx_ids",
+            &["--dedupe-key", "tx", flag, path],
+            expected,
+        );
+    }
+}
+
+// Three disputed deposits; charging back the first locks the account before the other two settle
+#[test]
+fn locked_account_settle_policy() {
+    run_test(
+        "locked_settlement",
+        "client,available,held,total,locked
+1,50.0000,0.0000,50.0000,true",
+    );
+}
+
+#[test]
+fn locked_account_resolve_only_policy() {
+    run_test_with_args(
+        "locked_settlement",
+        &["--locked-account", "resolve-only"],
+        "client,available,held,total,locked
+1,50.0000,30.0000,80.0000,true",
+    );
+}
+
+#[test]
+fn locked_account_frozen_policy() {
+    run_test_with_args(
+        "locked_settlement",
+        &["--locked-account", "frozen"],
+        "client,available,held,total,locked
+1,0.0000,80.0000,80.0000,true",
+    );
+}
+
+#[test]
+fn corrections_adjust_original_transactions() {
+    // Deposit cut by 10 and 5 of the withdrawal returned: 100 - 10 - 40 + 5 = 55. Taking 95
+    // off a deposit now worth 90, or 36 off a withdrawal now worth 35, is refused, as is
+    // correcting a disputed deposit.
+    run_test(
+        "corrections",
+        "client,available,held,total,locked
+1,55.0000,0.0000,55.0000,false
+2,0.0000,20.0000,20.0000,false",
+    );
+}
+
+#[test]
+fn chargeback_reversal_returns_funds() {
+    // The second reversal of tx 1 and the reversal of the never charged back tx 3 are refused
+    run_test(
+        "chargeback_reversal",
+        "client,available,held,total,locked
+1,150.0000,0.0000,150.0000,true
+2,30.0000,0.0000,30.0000,false",
+    );
+    run_test_with_args(
+        "chargeback_reversal",
+        &["--chargeback-reversal", "unlock"],
+        "client,available,held,total,locked
+1,150.0000,0.0000,150.0000,false
+2,30.0000,0.0000,30.0000,false",
+    );
+}
+
+#[test]
+fn frozen_and_closed_accounts() {
+    // Frozen client 1 takes a deposit but not a withdrawal, closed client 2 refuses a deposit
+    // and a dispute, and locked client 3 can't be frozen
+    run_test(
+        "account_status",
+        "client,available,held,total,locked,status
+1,110.0000,0.0000,110.0000,false,frozen
+2,50.0000,0.0000,50.0000,true,closed
+3,0.0000,0.0000,0.0000,true,locked",
+    );
+    run_test_with_args(
+        "account_status",
+        &["--output-compat", "v1"],
+        "client,available,held,total,locked
+1,110.0000,0.0000,110.0000,false
+2,50.0000,0.0000,50.0000,true
+3,0.0000,0.0000,0.0000,true",
+    );
+}
+
 #[test]
 fn whitespace_handling() {
     run_test(
@@ -29,6 +210,218 @@ fn whitespace_handling() {
     );
 }
 
+#[test]
+fn utf8_bom_header() {
+    run_test(
+        "utf8_bom",
+        "client,available,held,total,locked
+1,100.0000,0.0000,100.0000,false",
+    );
+}
+
+#[test]
+fn columns_in_any_order() {
+    // Columns the engine doesn't read, like `memo`, are ignored
+    run_test(
+        "reordered_columns",
+        "client,available,held,total,locked
+1,70.0000,0.0000,70.0000,false
+2,5.0000,0.0000,5.0000,false",
+    );
+}
+
+#[cfg(feature = "encoding")]
+#[test]
+fn utf16_input_is_transcoded() {
+    // Windows export: UTF-16LE with BOM and CRLF line endings
+    run_test_with_args(
+        "utf16le_bom",
+        &["--encoding", "auto"],
+        "client,available,held,total,locked
+1,100.0000,0.0000,100.0000,false",
+    );
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn gzip_input() {
+    let output = run_file("basic_deposit_withdraw.csv.gz", &[]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "client,available,held,total,locked
+1,85.0000,0.0000,85.0000,false
+2,50.0000,0.0000,50.0000,false"
+    );
+}
+
+#[cfg(feature = "avro")]
+#[test]
+fn avro_input() {
+    let output = run_file("basic_deposit_withdraw.avro", &["--format", "avro"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "client,available,held,total,locked
+1,85.0000,0.0000,85.0000,false
+2,50.0000,0.0000,50.0000,false"
+    );
+    // The file is not CSV
+    let output = run_file("basic_deposit_withdraw.avro", &[]);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("85.0000"));
+}
+
+// Tx 2 can't be counted in ten-thousandths at all, tx 5 would take client 3 past it
+#[cfg(feature = "minor-units")]
+#[test]
+fn minor_units_reject_amounts_out_of_range() {
+    for workers in ["1", "4"] {
+        let output = run(
+            "minor_units_overflow",
+            &["--workers", workers, "--summary", "-"],
+        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            "client,available,held,total,locked
+1,15.0000,0.0000,15.0000,false
+3,900000000000000.0000,0.0000,900000000000000.0000,false"
+        );
+        assert!(stderr.contains("amount_out_of_bounds: 1"));
+        assert!(stderr.contains("balance_overflow: 1"));
+    }
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_input() {
+    let output = run_file("basic_deposit_withdraw.csv.zst", &[]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "client,available,held,total,locked
+1,85.0000,0.0000,85.0000,false
+2,50.0000,0.0000,50.0000,false"
+    );
+}
+
+#[test]
+fn type_names_are_case_insensitive() {
+    run_test(
+        "mixed_case_types",
+        "client,available,held,total,locked
+1,12.5000,0.0000,12.5000,false",
+    );
+}
+
+#[test]
+fn progress_reports_final_counts_on_stderr() {
+    let output = run("insufficient_funds", &["--progress"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(output.status.success());
+    let last = stderr
+        .lines()
+        .rfind(|l| l.starts_with("progress:"))
+        .unwrap();
+    assert!(last.starts_with("progress: rows=2 rejected=1 "), "{}", last);
+    assert!(last.contains("read=100.0%"), "{}", last);
+}
+
+#[test]
+fn error_log_limit_counts_the_rest() {
+    let output = Command::new("./target/debug/toy-processor")
+        .args(["--error-log-limit", "1", "tests/fixtures/canary_broken.csv"])
+        .env("RUST_LOG", "error")
+        .output()
+        .expect("Failed to execute binary");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(output.status.success());
+    // Two invalid rows, the second only noted as suppressed
+    assert_eq!(stderr.matches("Failed to convert transaction").count(), 2);
+    assert!(stderr.contains("(further invalid_row errors are counted, not logged)"));
+    assert!(stderr.contains("2 invalid_row errors in total, 1 not logged"));
+    assert_eq!(stderr.matches("Failed to parse CSV row").count(), 1);
+}
+
+#[test]
+fn summary_report_counts_and_reasons() {
+    let path = std::env::temp_dir().join(format!("toy-processor-{}.summary", std::process::id()));
+    let output = run("corrections", &["--summary", path.to_str().unwrap()]);
+    let summary = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        summary,
+        "deposits: 2 totalling 120.0000
+withdrawals: 1 totalling 40.0000
+disputes: 1, resolves: 0, chargebacks: 0
+accounts: 2, locked: 0, negative balance: 0
+rejected: 3
+  correction_exceeds_original: 2
+  invalid_state: 1
+"
+    );
+}
+
+#[test]
+fn summary_and_metrics_break_down_sources() {
+    let dir = std::env::temp_dir();
+    let summary = dir.join(format!(
+        "toy-processor-{}.sources.summary",
+        std::process::id()
+    ));
+    let metrics = dir.join(format!("toy-processor-{}.sources.json", std::process::id()));
+    let output = run_args(
+        &[
+            "--summary",
+            summary.to_str().unwrap(),
+            "--metrics-json",
+            metrics.to_str().unwrap(),
+        ],
+        &[
+            "tests/fixtures/basic_deposit_withdraw.csv",
+            "tests/fixtures/next_day.csv",
+        ],
+    );
+    let text = std::fs::read_to_string(&summary).unwrap();
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&metrics).unwrap()).unwrap();
+    std::fs::remove_file(&summary).unwrap();
+    std::fs::remove_file(&metrics).unwrap();
+
+    assert!(output.status.success());
+    assert!(text.ends_with(
+        "sources:
+  tests/fixtures/basic_deposit_withdraw.csv: 4 rows, 0 rejected, 0 duplicates, deposits 160, withdrawals 25
+  tests/fixtures/next_day.csv: 4 rows, 0 rejected, 0 duplicates, deposits 20, withdrawals 0
+"
+    ));
+    let sources = json["sources"].as_array().unwrap();
+    assert_eq!(sources.len(), 2);
+    assert_eq!(sources[1]["source"], "tests/fixtures/next_day.csv");
+    assert_eq!(sources[1]["rows"], 4);
+    assert_eq!(sources[0]["deposit_total"], "160");
+}
+
+#[test]
+fn mmap_reader_matches_buffered() {
+    for fixture in [
+        "basic_deposit_withdraw",
+        "whitespace",
+        "utf8_bom",
+        "corrections",
+        "mixed_case_types",
+        "reordered_columns",
+    ] {
+        let buffered = run(fixture, &[]);
+        let mapped = run(fixture, &["--mmap"]);
+        assert!(mapped.status.success(), "Fixture: {}", fixture);
+        assert_eq!(mapped.stdout, buffered.stdout, "Fixture: {}", fixture);
+    }
+}
+
 #[test]
 fn precision_4_decimals() {
     run_test(
@@ -38,6 +431,40 @@ fn precision_4_decimals() {
     );
 }
 
+#[test]
+fn precision_and_rounding_flags() {
+    // 10.123456789 and 5.00001 truncated to 2dp on the way in
+    run_test_with_args(
+        "precision",
+        &["--precision", "2", "--rounding", "truncate"],
+        "client,available,held,total,locked
+1,15.12,0.00,15.12,false",
+    );
+}
+
+#[test]
+fn output_compat_v1_ignores_precision() {
+    run_test_with_args(
+        "precision",
+        &["--precision", "2", "--output-compat", "v1"],
+        "client,available,held,total,locked
+1,15.1200,0.0000,15.1200,false",
+    );
+}
+
+#[test]
+fn sort_by_total_descending() {
+    // Clients 1 and 2 tie on total and stay in client order
+    run_test_with_args(
+        "partial_dispute",
+        &["--sort-by", "total", "--desc"],
+        "client,available,held,total,locked
+3,20.0000,0.0000,20.0000,false
+1,10.0000,0.0000,10.0000,true
+2,10.0000,0.0000,10.0000,false",
+    );
+}
+
 #[test]
 fn dispute_then_resolve_returns_funds() {
     run_test(
@@ -56,6 +483,46 @@ fn dispute_then_chargeback_locks_account() {
     );
 }
 
+#[test]
+fn partial_dispute_holds_only_disputed_amount() {
+    // Client 1: 40 of 100 disputed, the undisputed 60 stays withdrawable, chargeback takes 40
+    // Client 2: dispute larger than the deposit is rejected
+    // Client 3: resolve releases the 5 that were held
+    run_test(
+        "partial_dispute",
+        "client,available,held,total,locked
+1,10.0000,0.0000,10.0000,true
+2,10.0000,0.0000,10.0000,false
+3,20.0000,0.0000,20.0000,false",
+    );
+}
+
+#[test]
+fn void_reverses_deposit_and_blocks_disputes() {
+    // Client 1: voided deposit can't be disputed afterwards
+    // Client 2: void refused while disputed, accepted after the resolve
+    // Client 3: reversal after a withdrawal leaves available negative
+    run_test(
+        "void",
+        "client,available,held,total,locked
+1,100.0000,0.0000,100.0000,false
+2,0.0000,0.0000,0.0000,false
+3,-15.0000,0.0000,-15.0000,false",
+    );
+}
+
+#[test]
+fn void_retain_policy_keeps_balances() {
+    run_test_with_args(
+        "void",
+        &["--void", "retain"],
+        "client,available,held,total,locked
+1,150.0000,0.0000,150.0000,false
+2,30.0000,0.0000,30.0000,false
+3,5.0000,0.0000,5.0000,false",
+    );
+}
+
 #[test]
 fn locked_account_rejects_deposit() {
     // After chargeback, account is locked - subsequent deposit should be rejected
@@ -78,6 +545,24 @@ fn insufficient_funds_rejected() {
     );
 }
 
+#[test]
+fn overdraft_limits() {
+    // Client 1 may go 60 below zero; client 2's own limit of 15 refuses its second withdrawal
+    run_test_with_args(
+        "overdraft",
+        &[
+            "--overdraft-limit",
+            "60",
+            "--overdraft-limits",
+            "tests/fixtures/overdraft_limits.csv",
+        ],
+        "client,available,held,total,locked,overdraft
+1,-50.0000,0.0000,-50.0000,false,true
+2,-15.0000,0.0000,-15.0000,false,true
+3,10.0000,0.0000,10.0000,false,false",
+    );
+}
+
 #[test]
 fn dispute_nonexistent_tx_ignored() {
     // Disputing a tx that doesn't exist should be ignored (logged as error)
@@ -101,6 +586,297 @@ fn negative_balance_clawback() {
     );
 }
 
+#[test]
+fn negative_balance_reject_policy() {
+    // Same input, but disputes that would overdraw the account are refused
+    run_test_with_args(
+        "negative_balance_clawback",
+        &["--dispute-overdraft", "reject"],
+        "client,available,held,total,locked
+1,20.0000,0.0000,20.0000,false",
+    );
+}
+
+#[test]
+fn dispute_window_rejects_old_deposits() {
+    // Deposit 1 is ~92 days older than its dispute, outside a 90 day window
+    run_test_with_args(
+        "dispute_window",
+        &["--dispute-window", "90d"],
+        "client,available,held,total,locked
+1,100.0000,50.0000,150.0000,false",
+    );
+}
+
+#[test]
+fn timestamps_ignored_without_dispute_window() {
+    run_test(
+        "dispute_window",
+        "client,available,held,total,locked
+1,0.0000,150.0000,150.0000,false",
+    );
+}
+
+#[test]
+fn as_of_ignores_later_rows() {
+    // Only the first deposit predates the cut-off
+    run_test_with_args(
+        "dispute_window",
+        &["--as-of", "7999999"],
+        "client,available,held,total,locked
+1,100.0000,0.0000,100.0000,false",
+    );
+    // Inclusive: rows at the cut-off still count, the second dispute does not
+    run_test_with_args(
+        "dispute_window",
+        &["--as-of", "8000000"],
+        "client,available,held,total,locked
+1,50.0000,100.0000,150.0000,false",
+    );
+}
+
+#[test]
+fn pending_deposits_are_held_until_released() {
+    // Client 1's deposit can't be spent or disputed before its release; a release without a
+    // timestamp is invalid
+    run_test_with_args(
+        "pending_deposits",
+        &[],
+        "client,available,held,total,locked
+1,90.0000,0.0000,90.0000,false
+2,0.0000,70.0000,70.0000,false",
+    );
+    // The cut-off settles the deposit whose hold is over by then, not the later one
+    run_test_with_args(
+        "pending_deposits",
+        &["--as-of", "6000"],
+        "client,available,held,total,locked
+1,90.0000,0.0000,90.0000,false
+2,50.0000,20.0000,70.0000,false",
+    );
+}
+
+#[test]
+fn sub_accounts_keep_their_own_balances() {
+    // Client 1's sub-account 1 can't draw on the main account, and only a dispute naming the
+    // sub-account reaches its deposit; the chargeback locks the sub-account alone
+    run_test_with_args(
+        "sub_accounts",
+        &[],
+        "client,sub,available,held,total,locked
+1,0,105.0000,0.0000,105.0000,false
+1,1,0.0000,0.0000,0.0000,true
+2,3,7.0000,0.0000,7.0000,false",
+    );
+    // Sharded by client, so a single worker settles it the same
+    run_test_with_args(
+        "sub_accounts",
+        &["--verify-deterministic"],
+        "client,sub,available,held,total,locked
+1,0,105.0000,0.0000,105.0000,false
+1,1,0.0000,0.0000,0.0000,true
+2,3,7.0000,0.0000,7.0000,false",
+    );
+
+    let strict = run("sub_accounts", &["--strict"]);
+    assert!(!strict.status.success());
+    let stderr = String::from_utf8_lossy(&strict.stderr);
+    assert!(stderr.contains("RowFailed { line: 4, source: SubAccountMismatch"));
+}
+
+#[test]
+fn require_monotonic_catches_rows_out_of_order() {
+    // Only client 1's withdrawal goes back in time; the untimestamped row is never checked
+    for args in [&[][..], &["--require-monotonic", "warn"]] {
+        run_test_with_args(
+            "out_of_order",
+            args,
+            "client,available,held,total,locked
+1,85.0000,0.0000,85.0000,false
+2,20.0000,0.0000,20.0000,false",
+        );
+    }
+    run_test_with_args(
+        "out_of_order",
+        &["--require-monotonic", "reject"],
+        "client,available,held,total,locked
+1,115.0000,0.0000,115.0000,false
+2,20.0000,0.0000,20.0000,false",
+    );
+
+    let strict = run(
+        "out_of_order",
+        &["--require-monotonic", "reject", "--strict"],
+    );
+    assert!(!strict.status.success());
+    let stderr = String::from_utf8_lossy(&strict.stderr);
+    assert!(stderr.contains("RowFailed { line: 4, source: OutOfOrder"));
+}
+
+#[test]
+fn withdrawal_fees_go_to_the_house_account() {
+    // Client 2 can't cover 10 plus its fee, so neither is taken
+    run_test_with_args(
+        "withdrawal_fee",
+        &["--withdrawal-fee", "0.5%", "--fee-account", "99"],
+        "client,available,held,total,locked
+1,49.7500,0.0000,49.7500,false
+2,0.9550,0.0000,0.9550,false
+99,0.2950,0.0000,0.2950,false",
+    );
+    // A fixed fee, paid to an account that also withdraws
+    run_test_with_args(
+        "withdrawal_fee",
+        &["--withdrawal-fee", "1", "--fee-account", "1"],
+        "client,available,held,total,locked
+1,51.0000,0.0000,51.0000,false
+2,0.0000,0.0000,0.0000,false",
+    );
+
+    let output = run("withdrawal_fee", &["--withdrawal-fee", "1"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn interest_accrues_on_available() {
+    run_test_with_args(
+        "basic_deposit_withdraw",
+        &["--accrue-interest", "1%"],
+        "client,available,held,total,locked
+1,85.8500,0.0000,85.8500,false
+2,50.5000,0.0000,50.5000,false",
+    );
+}
+
+#[test]
+fn deposit_cap_evicts_oldest() {
+    run_test(
+        "deposit_cap",
+        "client,available,held,total,locked
+1,0.0000,30.0000,30.0000,false",
+    );
+    // Deposit 1 is evicted by deposit 2, so its dispute finds nothing
+    let metrics =
+        std::env::temp_dir().join(format!("toy-processor-{}.cap.json", std::process::id()));
+    let output = run(
+        "deposit_cap",
+        &[
+            "--max-deposits-per-worker",
+            "1",
+            "--metrics-json",
+            metrics.to_str().unwrap(),
+        ],
+    );
+    let json = std::fs::read_to_string(&metrics).unwrap();
+    std::fs::remove_file(&metrics).unwrap();
+
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "client,available,held,total,locked
+1,10.0000,20.0000,30.0000,false"
+    );
+    assert!(json.contains("\"evicted_deposits\": 1"));
+}
+
+#[test]
+fn json_output_format() {
+    run_test_with_args(
+        "basic_deposit_withdraw",
+        &["--output-format", "json"],
+        r#"[
+  {"client":1,"available":"85.0000","held":"0.0000","total":"85.0000","locked":false},
+  {"client":2,"available":"50.0000","held":"0.0000","total":"50.0000","locked":false}
+]"#,
+    );
+    run_test_with_args(
+        "basic_deposit_withdraw",
+        &["--output-format", "json", "--client", "9"],
+        "[]",
+    );
+}
+
+#[test]
+fn multiple_inputs_share_state() {
+    // Day 2 charges back a deposit made on day 1
+    let expected = "client,available,held,total,locked
+1,85.5000,0.0000,85.5000,false
+2,0.0000,0.0000,0.0000,true
+";
+    let day1 = "tests/fixtures/daily/2024-01-01.csv";
+    let day2 = "tests/fixtures/daily/2024-01-02.csv";
+    for (args, inputs) in [
+        (&[][..], &[day1, day2][..]),
+        (&[], &["tests/fixtures/daily/*.csv"]),
+        (&["--input-dir", "tests/fixtures/daily"], &[]),
+    ] {
+        let output = run_args(args, inputs);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    }
+
+    // The other way round the chargeback finds nothing to charge back
+    let output = run_args(&[], &[day2, day1]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("2,50.0000,0.0000,50.0000,false"));
+}
+
+#[test]
+fn verify_deterministic_matches_single_worker() {
+    let output = run_args(
+        &[
+            "--verify-deterministic",
+            "--withdrawal-fee",
+            "1",
+            "--fee-account",
+            "9",
+        ],
+        &["tests/fixtures/daily/*.csv"],
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,locked
+1,84.5000,0.0000,84.5000,false
+2,0.0000,0.0000,0.0000,true
+9,1.0000,0.0000,1.0000,false
+"
+    );
+
+    let capped = run_file(
+        "deposit_cap.csv",
+        &["--verify-deterministic", "--max-deposits-per-worker", "2"],
+    );
+    assert!(!capped.status.success());
+}
+
+// Clawback and partial disputes leave held and negative totals the invariants must allow
+#[test]
+fn invariants_hold_across_fixtures() {
+    for (fixture, args) in [
+        ("partial_dispute", &[][..]),
+        (
+            "locked_settlement",
+            &["--locked-account", "resolve-only"][..],
+        ),
+        ("negative_balance_clawback", &[][..]),
+        (
+            "negative_balance_clawback",
+            &["--dispute-overdraft", "reject"][..],
+        ),
+        ("adjustments", &[][..]),
+        ("pending_deposits", &[][..]),
+        ("pending_deposits", &["--as-of", "6000"][..]),
+    ] {
+        let args = [&["--assert-invariants"][..], args].concat();
+        let output = run(fixture, &args);
+        assert!(
+            output.status.success(),
+            "{} {:?}: {}",
+            fixture,
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
 #[test]
 fn double_dispute_idempotent() {
     // Disputing same tx twice - second dispute should be rejected by state machine
@@ -131,3 +907,554 @@ fn negative_amount_rejected() {
 1,100.0000,0.0000,100.0000,false",
     );
 }
+
+#[test]
+fn mapping_file_rewrites_legacy_rows() {
+    // Legacy client 9001 and DEP/WDL type codes mapped onto client 1 deposits/withdrawals
+    run_test_with_args(
+        "legacy_ids",
+        &["--mapping", "tests/fixtures/legacy_mapping.csv"],
+        "client,available,held,total,locked
+1,75.0000,0.0000,75.0000,false",
+    );
+}
+
+#[test]
+fn canary_aborts_broken_file() {
+    // 3 of the first 4 rows are malformed, well above the default 5% reject threshold
+    let output = run("canary_broken", &["--canary", "4"]);
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn strict_mode_reports_first_bad_line() {
+    // Line 3 has an unknown type, line 4 an unparsable client; the earlier line wins
+    let output = run("canary_broken", &["--strict"]);
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("RowFailed { line: 3,"));
+}
+
+#[test]
+fn strict_mode_aborts_on_failed_transaction() {
+    let output = run("insufficient_funds", &["--strict"]);
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("RowFailed { line: 3, source: InsufficientFunds"));
+}
+
+#[test]
+fn canary_within_threshold_proceeds() {
+    // Only the first row is sampled, so the full run goes ahead and skips the bad rows as usual
+    run_test_with_args(
+        "canary_broken",
+        &["--canary", "1"],
+        "client,available,held,total,locked
+1,90.0000,0.0000,90.0000,false",
+    );
+}
+
+#[test]
+fn wal_replay_matches_original_run() {
+    let wal = std::env::temp_dir().join(format!("toy-processor-{}.wal", std::process::id()));
+    let wal = wal.to_str().unwrap();
+
+    let original = run("negative_balance_clawback", &["--wal", wal]);
+    let replayed = Command::new("./target/debug/toy-processor")
+        .args(["replay", wal])
+        .output()
+        .expect("Failed to execute binary");
+    std::fs::remove_file(wal).ok();
+
+    assert!(replayed.status.success());
+    assert_eq!(original.stdout, replayed.stdout);
+}
+
+// Client 1's dispute rounds to nothing and is rejected, client 2's to the smallest unit, in
+// the run and in a replay of its WAL alike
+#[test]
+fn sub_precision_dispute_is_rejected_and_replayed_alike() {
+    let wal = std::env::temp_dir().join(format!("toy-processor-{}.sub.wal", std::process::id()));
+    let wal = wal.to_str().unwrap();
+
+    let original = run("sub_precision_dispute", &["--wal", wal]);
+    let replayed = Command::new("./target/debug/toy-processor")
+        .args(["replay", wal])
+        .output()
+        .expect("Failed to execute binary");
+    std::fs::remove_file(wal).ok();
+
+    let expected = "client,available,held,total,locked
+1,10.0000,0.0000,10.0000,false
+2,9.9999,0.0001,10.0000,false";
+    assert_eq!(String::from_utf8_lossy(&original.stdout).trim(), expected);
+    assert!(replayed.status.success());
+    assert_eq!(String::from_utf8_lossy(&replayed.stdout).trim(), expected);
+}
+
+// Adjustments reach the locked client 2 and create client 3, but not once 3 is closed; the
+// one without a reason code never parses, so it is not in the report
+#[test]
+fn adjustments_apply_to_locked_accounts_and_are_reported() {
+    let report =
+        std::env::temp_dir().join(format!("toy-processor-{}.adjustments", std::process::id()));
+    let report = report.to_str().unwrap();
+
+    run_test_with_args(
+        "adjustments",
+        &["--adjustments", report],
+        "client,available,held,total,locked,status
+1,97.5000,0.0000,97.5000,false,active
+2,20.0000,0.0000,20.0000,true,locked
+3,7.5000,0.0000,7.5000,true,closed",
+    );
+    let written = std::fs::read_to_string(report).unwrap();
+    std::fs::remove_file(report).ok();
+
+    // Workers append in parallel, so only each client's lines are in order
+    let mut lines: Vec<_> = written.lines().collect();
+    lines[1..].sort_by_key(|line| line.split(',').nth(1));
+    assert_eq!(
+        lines,
+        [
+            "tx,client,amount,reason,outcome,available,held",
+            "2,1,-2.5000,FX_FIX,applied,97.5000,0.0000",
+            "4,2,20.0000,CHARGEBACK_WON,applied,20.0000,0.0000",
+            "5,3,7.5000,BACKFILL,applied,7.5000,0.0000",
+            "8,3,1.0000,LATE_FEE,account_closed,7.5000,0.0000",
+        ]
+    );
+}
+
+#[test]
+fn ledger_journals_applied_transactions() {
+    let ledger = std::env::temp_dir().join(format!("toy-processor-{}.ledger", std::process::id()));
+    let ledger = ledger.to_str().unwrap();
+
+    let output = run("dispute_chargeback", &["--ledger", ledger]);
+    let journal = std::fs::read_to_string(ledger).unwrap();
+    std::fs::remove_file(ledger).ok();
+
+    assert!(output.status.success());
+    assert_eq!(
+        journal,
+        "entry,tx,type,account,debit,credit
+1,1,deposit,settlement,100.0000,
+1,1,deposit,client:1:available,,100.0000
+2,2,deposit,settlement,50.0000,
+2,2,deposit,client:1:available,,50.0000
+3,1,dispute,client:1:available,100.0000,
+3,1,dispute,client:1:held,,100.0000
+4,1,chargeback,client:1:held,100.0000,
+4,1,chargeback,settlement,,100.0000
+"
+    );
+}
+
+#[test]
+fn seen_store_skips_rows_from_earlier_runs() {
+    let store = std::env::temp_dir().join(format!("toy-processor-{}.seen", std::process::id()));
+    let store = store.to_str().unwrap();
+
+    let first = run("basic_deposit_withdraw", &["--seen-store", store]);
+    let second = run("basic_deposit_withdraw", &["--seen-store", store]);
+    let mismatched = run(
+        "basic_deposit_withdraw",
+        &["--seen-store", store, "--dedup", "exact"],
+    );
+    std::fs::remove_file(store).ok();
+
+    assert!(String::from_utf8_lossy(&first.stdout).contains("1,85.0000"));
+    // Every row was applied by the first run, so there are no accounts to write
+    assert!(second.status.success());
+    assert!(second.stdout.is_empty());
+    assert!(!mismatched.status.success());
+}
+
+#[test]
+fn next_day_starts_from_the_previous_run() {
+    let dir = std::env::temp_dir();
+    let path = |name: &str| {
+        let file = dir.join(format!("toy-processor-{}.{}", std::process::id(), name));
+        file.to_str().unwrap().to_string()
+    };
+    let (accounts, deposits, next) = (path("accounts.csv"), path("deposits.csv"), path("next.csv"));
+
+    let first = run(
+        "basic_deposit_withdraw",
+        &["--output", &accounts, "--save-deposits", &deposits],
+    );
+    let second = run(
+        "next_day",
+        &[
+            "--load-accounts",
+            &accounts,
+            "--load-deposits",
+            &deposits,
+            "--save-deposits",
+            &next,
+        ],
+    );
+    let saved = std::fs::read_to_string(&next);
+    let without_deposits = run("next_day", &["--load-accounts", &accounts]);
+    for file in [&accounts, &deposits, &next] {
+        std::fs::remove_file(file).ok();
+    }
+
+    assert!(first.status.success());
+    // Day 1's deposits 4 and 2 are disputed, 2 charged back
+    assert_eq!(
+        String::from_utf8_lossy(&second.stdout).trim(),
+        "client,available,held,total,locked
+1,75.0000,10.0000,85.0000,false
+2,20.0000,0.0000,20.0000,true"
+    );
+    let saved = saved.unwrap();
+    assert!(saved.contains("2,deposit,2,0,50,50,chargedback,,"));
+    assert!(saved.contains("4,deposit,1,0,10,10,disputed,,"));
+    assert!(saved.contains("5,deposit,2,0,20,0,clear,,"));
+    // Unknown deposits can't be disputed
+    assert!(String::from_utf8_lossy(&without_deposits.stdout).contains("1,85.0000,0.0000"));
+}
+
+#[test]
+fn bloom_filter_is_carried_between_runs() {
+    let filter = std::env::temp_dir().join(format!("toy-processor-{}.bloom", std::process::id()));
+    let filter = filter.to_str().unwrap();
+    let small = [
+        "--expected-transactions",
+        "1000",
+        "--bloom-fp-rate",
+        "0.001",
+    ];
+
+    let first = run(
+        "basic_deposit_withdraw",
+        &[&small[..], &["--bloom-save", filter]].concat(),
+    );
+    let second = run("basic_deposit_withdraw", &["--bloom-load", filter]);
+    let resized = run(
+        "basic_deposit_withdraw",
+        &[&small[..], &["--bloom-load", filter]].concat(),
+    );
+    let exact = run(
+        "basic_deposit_withdraw",
+        &["--dedup", "exact", "--bloom-save", filter],
+    );
+    std::fs::remove_file(filter).ok();
+
+    assert!(String::from_utf8_lossy(&first.stdout).contains("1,85.0000"));
+    assert!(second.status.success());
+    assert!(second.stdout.is_empty());
+    assert!(!resized.status.success());
+    assert!(!exact.status.success());
+}
+
+#[test]
+fn quarantine_recovers_after_unbalanced_quote() {
+    // Without recovery the open quote swallows every following row
+    let quarantine =
+        std::env::temp_dir().join(format!("toy-processor-{}.quarantine", std::process::id()));
+    let quarantine = quarantine.to_str().unwrap();
+
+    run_test_with_args(
+        "unbalanced_quote",
+        &["--quarantine", quarantine],
+        "client,available,held,total,locked
+1,105.0000,0.0000,105.0000,false",
+    );
+    let quarantined = std::fs::read_to_string(quarantine).unwrap();
+    std::fs::remove_file(quarantine).ok();
+
+    assert_eq!(quarantined, "deposit,1,\"2,10\n");
+}
+
+#[test]
+fn generated_data_is_processable() {
+    let generated = Command::new("./target/debug/toy-processor")
+        .args([
+            "generate",
+            "--rows",
+            "2000",
+            "--clients",
+            "50",
+            "--seed",
+            "7",
+        ])
+        .output()
+        .expect("Failed to execute binary");
+    assert!(generated.status.success());
+
+    let csv = std::env::temp_dir().join(format!("toy-processor-{}.csv", std::process::id()));
+    std::fs::write(&csv, &generated.stdout).unwrap();
+    let output = Command::new("./target/debug/toy-processor")
+        .arg(&csv)
+        .output()
+        .expect("Failed to execute binary");
+    std::fs::remove_file(&csv).ok();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout.lines().next(),
+        Some("client,available,held,total,locked")
+    );
+    assert_eq!(stdout.lines().count(), 51);
+}
+
+#[test]
+fn diff_reports_changed_accounts() {
+    let output = Command::new("./target/debug/toy-processor")
+        .args([
+            "diff",
+            "tests/fixtures/snapshots/before.csv",
+            "tests/fixtures/snapshots/after.csv",
+        ])
+        .output()
+        .expect("Failed to execute binary");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,change
+2,-5.0000,0.0000,-5.0000,locked
+3,1.0000,-1.0000,0.0000,
+4,1.2500,0.0000,1.2500,new
+"
+    );
+
+    let missing = run_args(&["diff"], &["tests/fixtures/snapshots/before.csv"]);
+    assert!(!missing.status.success());
+}
+
+#[test]
+fn validate_only_reports_invalid_rows() {
+    let output = run("canary_broken", &["--validate-only"]);
+
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        r#"source,line,tx,reason,detail
+tests/fixtures/canary_broken.csv,3,2,unknown_type,"unknown type ""depositt"""
+tests/fixtures/canary_broken.csv,4,,parse_error,"CSV deserialize error: record 3 (line: 4, byte: 58): field 1: invalid digit found in string"
+tests/fixtures/canary_broken.csv,5,4,missing_amount,deposit without an amount
+"#
+    );
+
+    let clean = run("dispute_chargeback", &["--validate-only"]);
+    assert!(clean.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&clean.stdout),
+        "source,line,tx,reason,detail\n"
+    );
+}
+
+#[test]
+#[cfg(not(feature = "s3"))]
+fn s3_input_needs_the_feature() {
+    let output = run_args(&["--input", "s3://ledger/tx.csv"], &[]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("needs the `s3` feature"));
+}
+
+// Written to a side file and renamed, so a failed run leaves the previous output alone
+#[test]
+fn output_file_is_replaced_whole() {
+    let path = std::env::temp_dir().join(format!("toy-processor-{}.out.csv", std::process::id()));
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "previous\n").unwrap();
+
+    let failed = run("insufficient_funds", &["--strict", "--output", path]);
+    assert!(!failed.status.success());
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "previous\n");
+
+    let output = run("basic_deposit_withdraw", &["--output", path]);
+    let written = std::fs::read_to_string(path).unwrap();
+    std::fs::remove_file(path).ok();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        written,
+        "client,available,held,total,locked
+1,85.0000,0.0000,85.0000,false
+2,50.0000,0.0000,50.0000,false
+"
+    );
+    assert!(!std::path::Path::new(&format!("{}.partial", path)).exists());
+}
+
+#[test]
+fn follow_rewrites_output_as_rows_are_appended() {
+    use std::io::Write;
+
+    let dir = std::env::temp_dir();
+    let input = dir.join(format!("toy-processor-{}.follow.csv", std::process::id()));
+    let output = dir.join(format!("toy-processor-{}.follow.out", std::process::id()));
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,10\n").unwrap();
+    let mut child = Command::new("./target/debug/toy-processor")
+        .args(["--follow", "--snapshot-interval", "1", "--output"])
+        .arg(format!("file:{}", output.display()))
+        .arg(&input)
+        .spawn()
+        .expect("Failed to execute binary");
+    // The output is replaced whole, so it is either the old snapshot or the new one
+    let wait_for = |expected: &str| {
+        (0..100).any(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::read_to_string(&output).is_ok_and(|out| out == expected)
+        })
+    };
+
+    let first = wait_for("client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n");
+    let mut appender = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&input)
+        .unwrap();
+    appender
+        .write_all(b"withdrawal,1,2,4\ndeposit,2,3,1\n")
+        .unwrap();
+    let second = wait_for(
+        "client,available,held,total,locked\n1,6.0000,0.0000,6.0000,false\n2,1.0000,0.0000,1.0000,false\n",
+    );
+    child.kill().unwrap();
+    child.wait().unwrap();
+    std::fs::remove_file(&input).ok();
+    std::fs::remove_file(&output).ok();
+
+    assert!(first, "first snapshot");
+    assert!(second, "snapshot after the append");
+}
+
+#[cfg(unix)]
+#[test]
+fn listen_unix_applies_rows_sent_to_the_socket() {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+
+    let dir = std::env::temp_dir();
+    let socket = dir.join(format!("toy-processor-{}.listen.sock", std::process::id()));
+    let output = dir.join(format!("toy-processor-{}.listen.out", std::process::id()));
+    let mut child = Command::new("./target/debug/toy-processor")
+        .args(["--snapshot-interval", "1", "--listen-unix"])
+        .arg(&socket)
+        .arg("--output")
+        .arg(format!("file:{}", output.display()))
+        .spawn()
+        .expect("Failed to execute binary");
+    let connect = || {
+        (0..100).find_map(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            UnixStream::connect(&socket).ok()
+        })
+    };
+
+    let mut first = connect().expect("socket bound");
+    first
+        .write_all(b"type,client,tx,amount\ndeposit,1,1,10\n")
+        .unwrap();
+    let mut second = connect().expect("socket bound");
+    second
+        .write_all(b"client,tx,type,amount\n2,3,deposit,1\n")
+        .unwrap();
+    first.write_all(b"withdrawal,1,2,4\n").unwrap();
+    drop((first, second));
+    let expected = "client,available,held,total,locked\n1,6.0000,0.0000,6.0000,false\n2,1.0000,0.0000,1.0000,false\n";
+    let applied = (0..100).any(|_| {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        std::fs::read_to_string(&output).is_ok_and(|out| out == expected)
+    });
+    child.kill().unwrap();
+    child.wait().unwrap();
+    std::fs::remove_file(&socket).ok();
+    std::fs::remove_file(&output).ok();
+
+    assert!(applied, "snapshot with every connection's rows");
+}
+
+#[test]
+fn alerts_are_posted_to_the_webhook() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    // Answers every post and hands over its body
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+    let webhook = std::thread::spawn(move || {
+        let mut bodies = Vec::new();
+        for stream in listener.incoming().take(2) {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            bodies.push(String::from_utf8(body).unwrap());
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+        }
+        bodies
+    });
+
+    run_test_with_args(
+        "negative_balance_clawback",
+        &[
+            "--workers",
+            "1",
+            "--alert-below",
+            "0",
+            "--alert-held-above",
+            "50",
+            "--alert-webhook",
+            &url,
+        ],
+        &String::from_utf8(run("negative_balance_clawback", &[]).stdout).unwrap(),
+    );
+    // The dispute crosses both thresholds at once
+    assert_eq!(
+        webhook.join().unwrap(),
+        [
+            r#"{"alert":"available_below","client":1,"sub":0,"tx":1,"available":"-80.0000","held":"100.0000","threshold":"0.0000"}"#,
+            r#"{"alert":"held_above","client":1,"sub":0,"tx":1,"available":"-80.0000","held":"100.0000","threshold":"50.0000"}"#,
+        ]
+    );
+}
+
+#[test]
+fn state_hash_is_the_same_for_the_same_accounts() {
+    let hash_of = |fixture: &str, args: &[&str]| {
+        let output = run(fixture, args);
+        assert!(output.status.success());
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .lines()
+            .find_map(|line| line.strip_prefix("state-hash: ").map(str::to_string))
+            .expect("a state hash")
+    };
+    let hash = |args: &[&str]| hash_of("dispute_chargeback", args);
+
+    let hash_one = hash(&["--state-hash", "--workers", "1"]);
+    assert!(hash_one.starts_with("sha256:"));
+    assert_eq!(
+        hash(&["--state-hash", "--workers", "4", "--precision", "4"]),
+        hash_one
+    );
+    assert_eq!(hash(&["--state-hash", "--output-format", "json"]), hash_one);
+    assert_eq!(hash(&["--state-hash", "--clients", "2"]), hash_one);
+    assert_ne!(hash_of("dispute_resolve", &["--state-hash"]), hash_one);
+}
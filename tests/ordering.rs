@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use toy_processor::account::{Account, AccountMap};
+use toy_processor::error::Error;
+use toy_processor::policy::DedupStrategy;
+use toy_processor::rule::{RowRule, RuleDecision};
+use toy_processor::{ProcessorBuilder, TransactionRow};
+
+// Interleaves many clients so every worker sees a mix, with small channels so the reader
+// and workers constantly hand over. Tx ids are shuffled relative to input position, so an
+// id-sorted result can't pass by accident.
+fn interleaved(rows: u32, clients: u16) -> Vec<TransactionRow> {
+    let mut state = 0x9e37_79b9_u32;
+    (0..rows)
+        .map(|i| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let client = (state % u32::from(clients)) as u16;
+            let tx = i.wrapping_mul(2_654_435_761);
+            TransactionRow::new("deposit", client, tx, Some(Decimal::ONE))
+        })
+        .collect()
+}
+
+#[test]
+fn sharded_pipeline_preserves_per_client_order() {
+    let mut expected: std::collections::HashMap<u16, Vec<u32>> = Default::default();
+    for row in interleaved(50_000, 97) {
+        expected.entry(row.client()).or_default().push(row.tx());
+    }
+
+    for workers in [1, 3, 4, 8] {
+        let output = ProcessorBuilder::new()
+            .workers(workers)
+            .channel_capacity(16)
+            // A bloom false positive would drop a row and break the comparison
+            .dedup(DedupStrategy::Exact)
+            .record_order(true)
+            .build()
+            .run("ordering", interleaved(50_000, 97).into_iter().map(Ok))
+            .unwrap();
+
+        assert_eq!(
+            output.applied_order.unwrap(),
+            expected,
+            "workers={}",
+            workers
+        );
+    }
+}
+
+// Delays the four busy clients, which all shard to worker 0 of 4, so that queue backs up
+// while the others drain theirs
+struct SlowBusyClients;
+
+impl RowRule for SlowBusyClients {
+    fn evaluate(&self, row: TransactionRow, _: Option<&Account>) -> Result<RuleDecision, Error> {
+        if row.client().is_multiple_of(4) {
+            std::thread::sleep(Duration::from_micros(20));
+        }
+        Ok(RuleDecision::Accept(row))
+    }
+}
+
+// Most rows belong to clients 0, 4, 8 and 12. Every tenth row of a client disputes its
+// previous deposit and the next one resolves it, so moved clients need their deposits.
+fn skewed(rows: u32) -> Vec<TransactionRow> {
+    // Rows and latest deposit per client
+    let mut seen: std::collections::HashMap<u16, (u32, u32)> = Default::default();
+    (0..rows)
+        .map(|i| {
+            let client = if i.is_multiple_of(5) {
+                (i / 5 % 40) as u16
+            } else {
+                (i % 4 * 4) as u16
+            };
+            let (count, deposit) = seen.entry(client).or_default();
+            *count += 1;
+            match *count % 10 {
+                9 => TransactionRow::new("dispute", client, *deposit, None),
+                0 => TransactionRow::new("resolve", client, *deposit, None),
+                _ => {
+                    *deposit = i;
+                    TransactionRow::new("deposit", client, i, Some(Decimal::ONE))
+                }
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn rebalancing_preserves_per_client_order_and_state() {
+    let run = |rebalance: bool| {
+        ProcessorBuilder::new()
+            .workers(4)
+            .channel_capacity(16)
+            .dedup(DedupStrategy::Exact)
+            .record_order(true)
+            .rebalance(rebalance)
+            .rule(SlowBusyClients)
+            .build()
+            .run("skewed", skewed(10_000).into_iter().map(Ok))
+            .unwrap()
+    };
+    let balances = |accounts: &AccountMap| -> Vec<(u16, Decimal, Decimal)> {
+        (0..40)
+            .filter_map(|client| accounts.get(client))
+            .map(|a| (a.client(), a.available(), a.held()))
+            .collect()
+    };
+
+    let fixed = run(false);
+    let rebalanced = run(true);
+
+    assert!(rebalanced.metrics.rebalanced_clients() > 0);
+    assert_eq!(rebalanced.applied_order, fixed.applied_order);
+    assert_eq!(balances(&rebalanced.accounts), balances(&fixed.accounts));
+}
@@ -0,0 +1,60 @@
+#![cfg(feature = "testkit")]
+
+use toy_processor::ProcessorBuilder;
+use toy_processor::account::AccountMap;
+use toy_processor::config::Config;
+use toy_processor::policy::DedupStrategy;
+use toy_processor::testkit::{self, Oracle, SequenceConfig};
+use toy_processor::transactions::{TransactionRow, TxType};
+
+// A bloom false positive in one but not the other would be a difference of their own
+fn config() -> Config {
+    Config {
+        dedup: DedupStrategy::Exact,
+        fee_account: Some(99),
+        withdrawal_fee: "1%".parse().unwrap(),
+        ..Config::default()
+    }
+}
+
+fn sharded(rows: &[TransactionRow]) -> AccountMap {
+    let rows = rows.iter().cloned().map(Ok);
+    ProcessorBuilder::new()
+        .config(config())
+        .workers(4)
+        .channel_capacity(8)
+        .build()
+        .run("testkit", rows)
+        .unwrap()
+        .accounts
+}
+
+#[test]
+fn sharded_processor_matches_the_oracle() {
+    let sequences = SequenceConfig {
+        rows: 500,
+        ..SequenceConfig::default()
+    };
+
+    if let Err(counterexample) = testkit::check(&config(), sequences, 0..50, sharded) {
+        panic!("{}", counterexample);
+    }
+}
+
+// An engine that drops disputes is caught, on a prefix ending in the first dispute that held
+// anything
+#[test]
+fn counterexamples_are_shrunk() {
+    let skips_disputes = |rows: &[TransactionRow]| {
+        let kept = rows.iter().filter(|row| *row.tx_type() != TxType::Dispute);
+        Oracle::run(config(), kept.cloned()).unwrap()
+    };
+
+    let counterexample =
+        testkit::check(&config(), SequenceConfig::default(), 0..10, skips_disputes).unwrap_err();
+
+    assert_eq!(counterexample.seed, 0);
+    let last = counterexample.rows.last().unwrap();
+    assert_eq!(*last.tx_type(), TxType::Dispute);
+    assert!(!counterexample.deltas.is_empty());
+}